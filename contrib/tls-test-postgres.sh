@@ -0,0 +1,24 @@
+#!/bin/sh
+# Starts a throwaway PostgreSQL server with a self-signed certificate for the ignored TLS tests in
+# src/tls.rs. The certificate is created as described in "Creating Certificates" in the PostgreSQL
+# documentation. Needs initdb, pg_ctl and openssl, and must not be run as root (initdb refuses to).
+#
+#   contrib/tls-test-postgres.sh [DIRECTORY] [PORT]
+#
+# prints the command running the tests and the command stopping the server.
+set -eu
+
+dir="${1:-$(mktemp -d)}"
+port="${2:-54329}"
+
+initdb --pgdata="$dir/data" --username=postgres --auth=trust >/dev/null
+openssl req -new -x509 -days 365 -nodes -text -subj "/CN=localhost" \
+    -out "$dir/data/server.crt" -keyout "$dir/data/server.key" 2>/dev/null
+chmod 600 "$dir/data/server.key"
+pg_ctl --pgdata="$dir/data" --log="$dir/postgres.log" --wait \
+    -o "-c ssl=on -c port=$port -c listen_addresses=localhost -c unix_socket_directories=$dir" start >/dev/null
+
+echo "run the tests with:"
+echo "  BEEPEE_TLS_TEST_DB='host=localhost port=$port user=postgres dbname=postgres' BEEPEE_TLS_TEST_CA='$dir/data/server.crt' cargo test tls:: -- --ignored"
+echo "stop the server with:"
+echo "  pg_ctl --pgdata='$dir/data' stop"
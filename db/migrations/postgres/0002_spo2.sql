@@ -0,0 +1,4 @@
+ALTER TABLE beepee.measurements ADD COLUMN IF NOT EXISTS spo2_percent integer NULL DEFAULT NULL;
+
+ALTER TABLE beepee.measurements DROP CONSTRAINT IF EXISTS measurements_check;
+ALTER TABLE beepee.measurements ADD CONSTRAINT measurements_check CHECK (systolic_mmhg >= 0 AND diastolic_mmhg >= 0 AND pulse_bpm >= 0 AND (spo2_percent IS NULL OR spo2_percent BETWEEN 0 AND 100));
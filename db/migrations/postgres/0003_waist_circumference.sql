@@ -0,0 +1,4 @@
+ALTER TABLE beepee.mass_measurements ADD COLUMN IF NOT EXISTS waist_circum_cm numeric(6, 2) NULL DEFAULT NULL;
+
+ALTER TABLE beepee.mass_measurements DROP CONSTRAINT IF EXISTS mass_measurements_check;
+ALTER TABLE beepee.mass_measurements ADD CONSTRAINT mass_measurements_check CHECK (mass_kg >= 0 AND (waist_circum_cm IS NULL OR waist_circum_cm >= 0));
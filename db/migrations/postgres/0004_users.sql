@@ -0,0 +1,41 @@
+CREATE SEQUENCE beepee.users_id_seq AS bigint START WITH 1;
+
+CREATE TABLE beepee.users
+( id bigint NOT NULL DEFAULT nextval('beepee.users_id_seq')
+, "name" varchar(256) NOT NULL
+, CONSTRAINT users_pkey PRIMARY KEY (id)
+, CONSTRAINT users_name_key UNIQUE ("name")
+);
+
+-- measurements recorded before users were introduced belong to the default user
+INSERT INTO beepee.users ("name") VALUES ('default');
+
+ALTER TABLE beepee.measurements ADD COLUMN user_id bigint NULL;
+UPDATE beepee.measurements SET user_id = (SELECT id FROM beepee.users WHERE "name" = 'default');
+ALTER TABLE beepee.measurements ALTER COLUMN user_id SET NOT NULL;
+ALTER TABLE beepee.measurements ADD CONSTRAINT measurements_user_id_fkey FOREIGN KEY (user_id) REFERENCES beepee.users (id);
+CREATE INDEX measurements_user_id_timestamp_idx ON beepee.measurements (user_id, "timestamp", id);
+
+ALTER TABLE beepee.mass_measurements ADD COLUMN user_id bigint NULL;
+UPDATE beepee.mass_measurements SET user_id = (SELECT id FROM beepee.users WHERE "name" = 'default');
+ALTER TABLE beepee.mass_measurements ALTER COLUMN user_id SET NOT NULL;
+ALTER TABLE beepee.mass_measurements ADD CONSTRAINT mass_measurements_user_id_fkey FOREIGN KEY (user_id) REFERENCES beepee.users (id);
+CREATE INDEX mass_measurements_user_id_timestamp_idx ON beepee.mass_measurements (user_id, "timestamp", id);
+
+ALTER TABLE beepee.body_temperature_measurements ADD COLUMN user_id bigint NULL;
+UPDATE beepee.body_temperature_measurements SET user_id = (SELECT id FROM beepee.users WHERE "name" = 'default');
+ALTER TABLE beepee.body_temperature_measurements ALTER COLUMN user_id SET NOT NULL;
+ALTER TABLE beepee.body_temperature_measurements ADD CONSTRAINT body_temperature_measurements_user_id_fkey FOREIGN KEY (user_id) REFERENCES beepee.users (id);
+CREATE INDEX body_temperature_measurements_user_id_timestamp_idx ON beepee.body_temperature_measurements (user_id, "timestamp", id);
+
+ALTER TABLE beepee.blood_sugar_measurements ADD COLUMN user_id bigint NULL;
+UPDATE beepee.blood_sugar_measurements SET user_id = (SELECT id FROM beepee.users WHERE "name" = 'default');
+ALTER TABLE beepee.blood_sugar_measurements ALTER COLUMN user_id SET NOT NULL;
+ALTER TABLE beepee.blood_sugar_measurements ADD CONSTRAINT blood_sugar_measurements_user_id_fkey FOREIGN KEY (user_id) REFERENCES beepee.users (id);
+CREATE INDEX blood_sugar_measurements_user_id_timestamp_idx ON beepee.blood_sugar_measurements (user_id, "timestamp", id);
+
+ALTER TABLE beepee.long_term_blood_sugar_measurements ADD COLUMN user_id bigint NULL;
+UPDATE beepee.long_term_blood_sugar_measurements SET user_id = (SELECT id FROM beepee.users WHERE "name" = 'default');
+ALTER TABLE beepee.long_term_blood_sugar_measurements ALTER COLUMN user_id SET NOT NULL;
+ALTER TABLE beepee.long_term_blood_sugar_measurements ADD CONSTRAINT long_term_blood_sugar_measurements_user_id_fkey FOREIGN KEY (user_id) REFERENCES beepee.users (id);
+CREATE INDEX long_term_blood_sugar_measurements_user_id_timestamp_idx ON beepee.long_term_blood_sugar_measurements (user_id, "timestamp", id);
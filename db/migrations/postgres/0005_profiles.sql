@@ -0,0 +1,16 @@
+ALTER TABLE beepee.users ADD COLUMN birth_date date NULL DEFAULT NULL;
+ALTER TABLE beepee.users ADD COLUMN sex character varying(16) NULL DEFAULT NULL;
+ALTER TABLE beepee.users ADD CONSTRAINT users_sex_check CHECK (sex IS NULL OR sex IN ('female', 'male'));
+
+CREATE SEQUENCE beepee.body_heights_id_seq AS bigint START WITH 1;
+
+CREATE TABLE beepee.body_heights
+( id bigint NOT NULL DEFAULT nextval('beepee.body_heights_id_seq')
+, user_id bigint NOT NULL
+, effective_date date NOT NULL
+, height_cm integer NOT NULL
+, CONSTRAINT body_heights_pkey PRIMARY KEY (id)
+, CONSTRAINT body_heights_user_id_effective_date_key UNIQUE (user_id, effective_date)
+, CONSTRAINT body_heights_check CHECK (height_cm > 0)
+, CONSTRAINT body_heights_user_id_fkey FOREIGN KEY (user_id) REFERENCES beepee.users (id)
+);
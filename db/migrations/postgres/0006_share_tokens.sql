@@ -0,0 +1,19 @@
+CREATE SEQUENCE beepee.share_tokens_id_seq AS bigint START WITH 1;
+
+CREATE TABLE beepee.share_tokens
+( id bigint NOT NULL DEFAULT nextval('beepee.share_tokens_id_seq')
+, user_id bigint NOT NULL
+, token_sha256 character varying(64) NOT NULL
+, description character varying(256) NOT NULL
+, scopes character varying(256) NOT NULL
+, range_start timestamp with time zone NULL
+, range_end timestamp with time zone NULL
+, created_at timestamp with time zone NOT NULL
+, expires_at timestamp with time zone NOT NULL
+, revoked_at timestamp with time zone NULL
+, CONSTRAINT share_tokens_pkey PRIMARY KEY (id)
+, CONSTRAINT share_tokens_token_sha256_key UNIQUE (token_sha256)
+, CONSTRAINT share_tokens_user_id_fkey FOREIGN KEY (user_id) REFERENCES beepee.users (id)
+);
+
+CREATE INDEX share_tokens_user_id_idx ON beepee.share_tokens (user_id, created_at);
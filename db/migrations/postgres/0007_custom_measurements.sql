@@ -0,0 +1,20 @@
+CREATE SEQUENCE beepee.custom_measurements_id_seq AS bigint START WITH 1;
+
+CREATE TABLE beepee.custom_measurements
+( id bigint NOT NULL DEFAULT nextval('beepee.custom_measurements_id_seq')
+, user_id bigint NOT NULL
+, type_name character varying(256) NOT NULL
+, "timestamp" timestamp with time zone NOT NULL
+, CONSTRAINT custom_measurements_pkey PRIMARY KEY (id)
+, CONSTRAINT custom_measurements_user_id_fkey FOREIGN KEY (user_id) REFERENCES beepee.users (id)
+);
+
+CREATE INDEX custom_measurements_user_id_type_name_timestamp_idx ON beepee.custom_measurements (user_id, type_name, "timestamp", id);
+
+CREATE TABLE beepee.custom_measurement_values
+( measurement_id bigint NOT NULL
+, field_key character varying(256) NOT NULL
+, "value" numeric(16, 4) NOT NULL
+, CONSTRAINT custom_measurement_values_pkey PRIMARY KEY (measurement_id, field_key)
+, CONSTRAINT custom_measurement_values_measurement_id_fkey FOREIGN KEY (measurement_id) REFERENCES beepee.custom_measurements (id) ON DELETE CASCADE
+);
@@ -0,0 +1,15 @@
+CREATE SEQUENCE beepee.sessions_id_seq AS bigint START WITH 1;
+
+CREATE TABLE beepee.sessions
+( id bigint NOT NULL DEFAULT nextval('beepee.sessions_id_seq')
+, user_id bigint NOT NULL
+, session_sha256 character varying(64) NOT NULL
+, token_key character varying(256) NOT NULL
+, created_at timestamp with time zone NOT NULL
+, expires_at timestamp with time zone NOT NULL
+, CONSTRAINT sessions_pkey PRIMARY KEY (id)
+, CONSTRAINT sessions_session_sha256_key UNIQUE (session_sha256)
+, CONSTRAINT sessions_user_id_fkey FOREIGN KEY (user_id) REFERENCES beepee.users (id)
+);
+
+CREATE INDEX sessions_expires_at_idx ON beepee.sessions (expires_at);
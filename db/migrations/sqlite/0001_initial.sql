@@ -0,0 +1,58 @@
+-- timestamps are stored as RFC 3339 strings in UTC with microsecond precision (which sort correctly);
+-- decimal values are stored as strings with two decimal places
+
+CREATE TABLE measurements
+( id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT
+, "timestamp" TEXT NOT NULL
+, systolic_mmhg INTEGER NOT NULL
+, diastolic_mmhg INTEGER NOT NULL
+, pulse_bpm INTEGER NOT NULL
+, spo2_percent INTEGER NULL DEFAULT NULL
+, CHECK (systolic_mmhg >= 0 AND diastolic_mmhg >= 0 AND pulse_bpm >= 0 AND (spo2_percent IS NULL OR spo2_percent BETWEEN 0 AND 100))
+);
+CREATE INDEX measurements_timestamp_idx ON measurements ("timestamp", id);
+
+CREATE TABLE mass_measurements
+( id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT
+, "timestamp" TEXT NOT NULL
+, mass_kg TEXT NOT NULL
+, waist_circum_cm TEXT NULL DEFAULT NULL
+, CHECK (CAST(mass_kg AS REAL) >= 0 AND (waist_circum_cm IS NULL OR CAST(waist_circum_cm AS REAL) >= 0))
+);
+CREATE INDEX mass_measurements_timestamp_idx ON mass_measurements ("timestamp", id);
+
+CREATE TABLE body_temperature_locations
+( id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT
+, "name" TEXT NOT NULL
+);
+
+CREATE TABLE body_temperature_measurements
+( id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT
+, "timestamp" TEXT NOT NULL
+, location_id INTEGER NOT NULL REFERENCES body_temperature_locations (id)
+, temperature_celsius TEXT NOT NULL
+, CHECK (CAST(temperature_celsius AS REAL) >= -273.15)
+);
+CREATE INDEX body_temperature_measurements_timestamp_idx ON body_temperature_measurements ("timestamp", id);
+
+CREATE TABLE blood_sugar_measurements
+( id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT
+, "timestamp" TEXT NOT NULL
+, sugar_mmol_per_l TEXT NOT NULL
+);
+CREATE INDEX blood_sugar_measurements_timestamp_idx ON blood_sugar_measurements ("timestamp", id);
+
+CREATE TABLE long_term_blood_sugar_measurements
+( id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT
+, "timestamp" TEXT NOT NULL
+, hba1c_mmol_per_mol TEXT NOT NULL
+);
+CREATE INDEX long_term_blood_sugar_measurements_timestamp_idx ON long_term_blood_sugar_measurements ("timestamp", id);
+
+-- there is no user interface for managing locations, so start out with the sample locations
+INSERT INTO body_temperature_locations ("name") VALUES
+('rectum'),
+('mouth'),
+('armpit'),
+('ear'),
+('forehead');
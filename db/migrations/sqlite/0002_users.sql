@@ -0,0 +1,29 @@
+CREATE TABLE users
+( id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT
+, "name" TEXT NOT NULL UNIQUE
+);
+
+-- measurements recorded before users were introduced belong to the default user, which is the
+-- first user and therefore has ID 1; SQLite cannot add a column with both a foreign key and a
+-- non-NULL default, so the reference to the users table is not enforced
+INSERT INTO users (id, "name") VALUES (1, 'default');
+
+ALTER TABLE measurements ADD COLUMN user_id INTEGER NOT NULL DEFAULT 1;
+DROP INDEX measurements_timestamp_idx;
+CREATE INDEX measurements_user_id_timestamp_idx ON measurements (user_id, "timestamp", id);
+
+ALTER TABLE mass_measurements ADD COLUMN user_id INTEGER NOT NULL DEFAULT 1;
+DROP INDEX mass_measurements_timestamp_idx;
+CREATE INDEX mass_measurements_user_id_timestamp_idx ON mass_measurements (user_id, "timestamp", id);
+
+ALTER TABLE body_temperature_measurements ADD COLUMN user_id INTEGER NOT NULL DEFAULT 1;
+DROP INDEX body_temperature_measurements_timestamp_idx;
+CREATE INDEX body_temperature_measurements_user_id_timestamp_idx ON body_temperature_measurements (user_id, "timestamp", id);
+
+ALTER TABLE blood_sugar_measurements ADD COLUMN user_id INTEGER NOT NULL DEFAULT 1;
+DROP INDEX blood_sugar_measurements_timestamp_idx;
+CREATE INDEX blood_sugar_measurements_user_id_timestamp_idx ON blood_sugar_measurements (user_id, "timestamp", id);
+
+ALTER TABLE long_term_blood_sugar_measurements ADD COLUMN user_id INTEGER NOT NULL DEFAULT 1;
+DROP INDEX long_term_blood_sugar_measurements_timestamp_idx;
+CREATE INDEX long_term_blood_sugar_measurements_user_id_timestamp_idx ON long_term_blood_sugar_measurements (user_id, "timestamp", id);
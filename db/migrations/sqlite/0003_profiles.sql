@@ -0,0 +1,13 @@
+-- dates are stored as ISO 8601 strings (YYYY-MM-DD)
+
+ALTER TABLE users ADD COLUMN birth_date TEXT NULL DEFAULT NULL;
+ALTER TABLE users ADD COLUMN sex TEXT NULL DEFAULT NULL CHECK (sex IS NULL OR sex IN ('female', 'male'));
+
+CREATE TABLE body_heights
+( id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT
+, user_id INTEGER NOT NULL REFERENCES users (id)
+, effective_date TEXT NOT NULL
+, height_cm INTEGER NOT NULL
+, UNIQUE (user_id, effective_date)
+, CHECK (height_cm > 0)
+);
@@ -0,0 +1,14 @@
+CREATE TABLE share_tokens
+( id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT
+, user_id INTEGER NOT NULL REFERENCES users (id)
+, token_sha256 TEXT NOT NULL UNIQUE
+, description TEXT NOT NULL
+, scopes TEXT NOT NULL
+, range_start TEXT NULL
+, range_end TEXT NULL
+, created_at TEXT NOT NULL
+, expires_at TEXT NOT NULL
+, revoked_at TEXT NULL
+);
+
+CREATE INDEX share_tokens_user_id_idx ON share_tokens (user_id, created_at);
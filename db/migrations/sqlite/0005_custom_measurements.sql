@@ -0,0 +1,15 @@
+CREATE TABLE custom_measurements
+( id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT
+, user_id INTEGER NOT NULL REFERENCES users (id)
+, type_name TEXT NOT NULL
+, "timestamp" TEXT NOT NULL
+);
+
+CREATE INDEX custom_measurements_user_id_type_name_timestamp_idx ON custom_measurements (user_id, type_name, "timestamp", id);
+
+CREATE TABLE custom_measurement_values
+( measurement_id INTEGER NOT NULL REFERENCES custom_measurements (id) ON DELETE CASCADE
+, field_key TEXT NOT NULL
+, "value" TEXT NOT NULL
+, PRIMARY KEY (measurement_id, field_key)
+);
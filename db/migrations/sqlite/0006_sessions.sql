@@ -0,0 +1,10 @@
+CREATE TABLE sessions
+( id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT
+, user_id INTEGER NOT NULL REFERENCES users (id)
+, session_sha256 TEXT NOT NULL UNIQUE
+, token_key TEXT NOT NULL
+, created_at TEXT NOT NULL
+, expires_at TEXT NOT NULL
+);
+
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
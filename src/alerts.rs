@@ -0,0 +1,537 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use http::header::{CONTENT_TYPE, HOST, USER_AGENT};
+use http_body_util::Full;
+use hyper::{Method, Request, StatusCode};
+use hyper::body::Bytes;
+use hyper_util::rt::tokio::TokioIo;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use lettre::address::AddressError;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use log::{error, info, warn};
+use num_rational::Rational32;
+use rustls::pki_types::ServerName;
+use serde::Serialize;
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsConnector;
+use url::{Host, Url};
+
+use crate::config::{AlertAction, AlertMetric, AlertRule, SmtpConfig, SmtpTls};
+use crate::measurement::Measurement;
+use crate::numerism::r32_to_decimal;
+use crate::tls::{TlsSetupError, make_https_client_config};
+
+
+/// How long an action may take before it is abandoned.
+const ACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+#[derive(Debug)]
+pub(crate) enum AlertError {
+    UnknownAction(String),
+    RunningCommand(io::Error),
+    CommandFailed(ExitStatus),
+    InvalidUrl(String),
+    Connecting(io::Error),
+    TlsSetup(TlsSetupError),
+    InvalidServerName(String),
+    TlsHandshake(io::Error),
+    Http(hyper::Error),
+    WebhookStatus(StatusCode),
+    SmtpNotConfigured,
+    InvalidAddress(AddressError),
+    BuildingMessage(lettre::error::Error),
+    Smtp(lettre::transport::smtp::Error),
+    TimedOut,
+}
+impl fmt::Display for AlertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertError::UnknownAction(name)
+                => write!(f, "unknown action {:?}", name),
+            AlertError::RunningCommand(e)
+                => write!(f, "error running command: {}", e),
+            AlertError::CommandFailed(status)
+                => write!(f, "command failed: {}", status),
+            AlertError::InvalidUrl(url)
+                => write!(f, "invalid webhook URL {:?}", url),
+            AlertError::Connecting(e)
+                => write!(f, "error connecting to webhook: {}", e),
+            AlertError::TlsSetup(e)
+                => write!(f, "{}", e),
+            AlertError::InvalidServerName(name)
+                => write!(f, "invalid TLS server name {:?}", name),
+            AlertError::TlsHandshake(e)
+                => write!(f, "TLS handshake with webhook failed: {}", e),
+            AlertError::Http(e)
+                => write!(f, "error calling webhook: {}", e),
+            AlertError::WebhookStatus(status)
+                => write!(f, "webhook responded with status {}", status),
+            AlertError::SmtpNotConfigured
+                => write!(f, "smtp is not configured"),
+            AlertError::InvalidAddress(e)
+                => write!(f, "invalid email address: {}", e),
+            AlertError::BuildingMessage(e)
+                => write!(f, "error building email: {}", e),
+            AlertError::Smtp(e)
+                => write!(f, "error sending email: {}", e),
+            AlertError::TimedOut
+                => write!(f, "timed out after {} seconds", ACTION_TIMEOUT.as_secs()),
+        }
+    }
+}
+impl Error for AlertError {
+}
+
+
+/// An alert rule that has been triggered by a new measurement.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+pub(crate) struct Alert {
+    pub user: String,
+    pub rule: String,
+    pub metric: AlertMetric,
+    /// The value as a decimal number.
+    pub value: String,
+    pub unit: &'static str,
+    #[serde(with = "crate::ser_de::serde_datetime_local")] pub timestamp: DateTime<Local>,
+    /// The number of consecutive alarming readings, including this one.
+    pub consecutive: usize,
+    pub message: String,
+}
+impl Alert {
+    pub fn new(
+        user: String,
+        rule: &AlertRule,
+        value: Rational32,
+        timestamp: DateTime<Local>,
+    ) -> Self {
+        let value = r32_to_decimal(value, rule.metric.decimal_places());
+        let mut message = format!(
+            "{}: {} of {} {} at {}",
+            rule.name,
+            rule.metric.title(),
+            value,
+            rule.metric.unit(),
+            timestamp.format("%Y-%m-%d %H:%M"),
+        );
+        if rule.consecutive > 1 {
+            message.push_str(&format!(" ({} consecutive readings)", rule.consecutive));
+        }
+        Self {
+            user,
+            rule: rule.name.clone(),
+            metric: rule.metric,
+            value,
+            unit: rule.metric.unit(),
+            timestamp,
+            consecutive: rule.consecutive,
+            message,
+        }
+    }
+
+    /// The environment variables describing the alert to a command.
+    fn environment(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("BEEPEE_ALERT_USER", self.user.clone()),
+            ("BEEPEE_ALERT_RULE", self.rule.clone()),
+            ("BEEPEE_ALERT_METRIC", self.metric.name().to_owned()),
+            ("BEEPEE_ALERT_VALUE", self.value.clone()),
+            ("BEEPEE_ALERT_UNIT", self.unit.to_owned()),
+            ("BEEPEE_ALERT_TIMESTAMP", self.timestamp.to_rfc3339()),
+            ("BEEPEE_ALERT_MESSAGE", self.message.clone()),
+        ]
+    }
+}
+
+
+/// The rules triggered by the newest of the given measurements, which must be ordered from oldest
+/// to newest. A rule is triggered if the newest measurement and enough of the preceding ones
+/// containing the rule's metric are alarming.
+pub(crate) fn triggered_alerts<M: Measurement>(user: &str, rules: &[AlertRule], measurements: &[M]) -> Vec<(AlertRule, Alert)> {
+    let newest = match measurements.last() {
+        Some(n) => n,
+        None => return Vec::new(),
+    };
+
+    let mut alerts = Vec::new();
+    for rule in rules {
+        let value = match newest.value(rule.metric.field_key()) {
+            Some(v) => v,
+            None => continue,
+        };
+        let alarming_count = measurements.iter()
+            .rev()
+            .filter_map(|m| m.value(rule.metric.field_key()))
+            .take(rule.consecutive)
+            .filter(|v| rule.is_alarming(*v))
+            .count();
+        if alarming_count == rule.consecutive {
+            alerts.push((rule.clone(), Alert::new(user.to_owned(), rule, value, newest.timestamp())));
+        }
+    }
+    alerts
+}
+
+
+async fn run_command(command: &[String], alert: &Alert) -> Result<(), AlertError> {
+    let status = Command::new(&command[0])
+        .args(&command[1..])
+        .envs(alert.environment())
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .status().await
+        .map_err(AlertError::RunningCommand)?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(AlertError::CommandFailed(status))
+    }
+}
+
+async fn send_http_request<I>(io: I, request: Request<Full<Bytes>>) -> Result<StatusCode, AlertError>
+    where I: hyper::rt::Read + hyper::rt::Write + Send + Unpin + 'static {
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(io).await
+        .map_err(AlertError::Http)?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            error!("error on webhook connection: {}", e);
+        }
+    });
+    let response = sender.send_request(request).await
+        .map_err(AlertError::Http)?;
+    Ok(response.status())
+}
+
+async fn call_webhook(url: &str, alert: &Alert) -> Result<(), AlertError> {
+    let invalid_url = || AlertError::InvalidUrl(url.to_owned());
+    let parsed_url: Url = url.parse()
+        .map_err(|_| invalid_url())?;
+    let host = match parsed_url.host() {
+        Some(Host::Domain(d)) => d.to_owned(),
+        Some(Host::Ipv4(a)) => a.to_string(),
+        Some(Host::Ipv6(a)) => a.to_string(),
+        None => return Err(invalid_url()),
+    };
+    let port = parsed_url.port_or_known_default()
+        .ok_or_else(invalid_url)?;
+    let authority = &parsed_url[url::Position::BeforeHost..url::Position::AfterPort];
+    let path_and_query = &parsed_url[url::Position::BeforePath..url::Position::AfterQuery];
+
+    let body = serde_json::to_vec(alert)
+        .expect("alert can be serialized");
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(path_and_query)
+        .header(HOST, authority)
+        .header(CONTENT_TYPE, "application/json")
+        .header(USER_AGENT, "beepee")
+        .body(Full::new(Bytes::from(body)))
+        .map_err(|_| invalid_url())?;
+
+    let tcp_stream = TcpStream::connect((host.as_str(), port)).await
+        .map_err(AlertError::Connecting)?;
+    let status = match parsed_url.scheme() {
+        "http" => send_http_request(TokioIo::new(tcp_stream), request).await?,
+        "https" => {
+            let client_config = make_https_client_config()
+                .map_err(AlertError::TlsSetup)?;
+            let server_name = ServerName::try_from(host.clone())
+                .map_err(|_| AlertError::InvalidServerName(host.clone()))?;
+            let tls_stream = TlsConnector::from(Arc::new(client_config))
+                .connect(server_name, tcp_stream).await
+                .map_err(AlertError::TlsHandshake)?;
+            send_http_request(TokioIo::new(tls_stream), request).await?
+        },
+        _ => return Err(invalid_url()),
+    };
+
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(AlertError::WebhookStatus(status))
+    }
+}
+
+async fn send_email(smtp: &SmtpConfig, to: &str, alert: &Alert) -> Result<(), AlertError> {
+    let message = Message::builder()
+        .from(smtp.from.parse().map_err(AlertError::InvalidAddress)?)
+        .to(to.parse().map_err(AlertError::InvalidAddress)?)
+        .subject(format!("beepee alert: {}", alert.rule))
+        .header(ContentType::TEXT_PLAIN)
+        .body(format!("{}\n\nUser: {}\n", alert.message, alert.user))
+        .map_err(AlertError::BuildingMessage)?;
+
+    let mut builder = match smtp.tls {
+        SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host),
+        SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)
+            .map_err(AlertError::Smtp)?,
+        SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)
+            .map_err(AlertError::Smtp)?,
+    };
+    if let Some(port) = smtp.port {
+        builder = builder.port(port);
+    }
+    if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+        builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+
+    builder.build()
+        .send(message).await
+        .map_err(AlertError::Smtp)?;
+    Ok(())
+}
+
+/// Runs an alert action, giving up after `ACTION_TIMEOUT`.
+pub(crate) async fn run_action(action: &AlertAction, smtp: Option<&SmtpConfig>, alert: &Alert) -> Result<(), AlertError> {
+    let action_future = async {
+        match action {
+            AlertAction::Command(command) => run_command(command, alert).await,
+            AlertAction::Webhook(url) => call_webhook(url, alert).await,
+            AlertAction::Email(to) => {
+                let smtp = smtp.ok_or(AlertError::SmtpNotConfigured)?;
+                send_email(smtp, to, alert).await
+            },
+        }
+    };
+    tokio::time::timeout(ACTION_TIMEOUT, action_future).await
+        .map_err(|_| AlertError::TimedOut)?
+}
+
+/// The tasks running the actions of raised alerts. Dropping them lets the actions finish in the
+/// background, which only works while the runtime keeps running (as it does in the server).
+pub(crate) struct AlertActions(Vec<JoinHandle<()>>);
+impl AlertActions {
+    pub fn none() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Waits until all actions have finished or timed out.
+    pub async fn finish(self) {
+        for handle in self.0 {
+            if let Err(e) = handle.await {
+                error!("alert action task failed: {}", e);
+            }
+        }
+    }
+}
+
+/// Logs the alerts and runs their actions in the background.
+pub(crate) fn raise_alerts(
+    alerts: Vec<(AlertRule, Alert)>,
+    actions: &BTreeMap<String, AlertAction>,
+    smtp: Option<&SmtpConfig>,
+) -> AlertActions {
+    let mut handles = Vec::new();
+    for (rule, alert) in alerts {
+        warn!("alert for user {:?}: {}", alert.user, alert.message);
+        for action_name in &rule.actions {
+            let action = actions.get(action_name).cloned();
+            let smtp = smtp.cloned();
+            let alert = alert.clone();
+            let action_name = action_name.clone();
+            let handle = tokio::spawn(async move {
+                let result = match action {
+                    Some(a) => run_action(&a, smtp.as_ref(), &alert).await,
+                    None => Err(AlertError::UnknownAction(action_name.clone())),
+                };
+                match result {
+                    Ok(()) => info!("ran action {:?} for alert {:?}", action_name, alert.rule),
+                    Err(e) => error!("error running action {:?} for alert {:?}: {}", action_name, alert.rule, e),
+                }
+            });
+            handles.push(handle);
+        }
+    }
+    AlertActions(handles)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::Infallible;
+
+    use chrono::TimeZone;
+    use hyper::Response;
+    use hyper::body::Incoming;
+    use hyper::service::service_fn;
+    use http_body_util::BodyExt;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    use crate::model::BloodPressureMeasurement;
+
+    fn rule(at_least: Option<i32>, at_most: Option<i32>, consecutive: usize) -> AlertRule {
+        AlertRule {
+            name: String::from("test"),
+            metric: AlertMetric::Systolic,
+            at_least: at_least.map(Rational32::from_integer),
+            at_most: at_most.map(Rational32::from_integer),
+            consecutive,
+            actions: Vec::new(),
+        }
+    }
+
+    fn bp(minute: u32, systolic_mmhg: i32) -> BloodPressureMeasurement {
+        let timestamp = Local.with_ymd_and_hms(2024, 3, 1, 8, minute, 0).unwrap();
+        BloodPressureMeasurement::new(-1, timestamp, systolic_mmhg, 80, 60, None)
+    }
+
+    fn test_alert() -> Alert {
+        Alert::new(
+            String::from("alice"),
+            &rule(Some(140), None, 2),
+            Rational32::from_integer(150),
+            Local.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn rules_evaluated() {
+        let measurements = vec![bp(0, 150), bp(1, 120), bp(2, 145), bp(3, 140)];
+        assert_eq!(triggered_alerts("alice", &[rule(Some(140), None, 1)], &measurements).len(), 1);
+        assert_eq!(triggered_alerts("alice", &[rule(Some(140), None, 2)], &measurements).len(), 1);
+        assert_eq!(triggered_alerts("alice", &[rule(Some(140), None, 3)], &measurements).len(), 0);
+        assert_eq!(triggered_alerts("alice", &[rule(Some(141), None, 1)], &measurements).len(), 0);
+        assert_eq!(triggered_alerts("alice", &[rule(None, Some(140), 1)], &measurements).len(), 1);
+        assert_eq!(triggered_alerts("alice", &[rule(Some(140), None, 5)], &measurements).len(), 0);
+
+        // SpO2 is missing from all measurements
+        let spo2_rule = AlertRule { metric: AlertMetric::Spo2, ..rule(None, Some(100), 1) };
+        assert_eq!(triggered_alerts("alice", &[spo2_rule], &measurements).len(), 0);
+
+        let alerts = triggered_alerts("alice", &[rule(Some(140), None, 2)], &measurements);
+        assert_eq!(alerts[0].1.message, "test: systolic blood pressure of 140 mmHg at 2024-03-01 08:03 (2 consecutive readings)");
+    }
+
+    #[tokio::test]
+    async fn command_action() {
+        let output_path = std::env::temp_dir()
+            .join(format!("beepee-alert-test-{}", std::process::id()));
+        let action = AlertAction::Command(vec![
+            String::from("sh"),
+            String::from("-c"),
+            String::from("printf '%s %s' \"$BEEPEE_ALERT_RULE\" \"$BEEPEE_ALERT_VALUE\" > \"$0\""),
+            output_path.to_string_lossy().into_owned(),
+        ]);
+        run_action(&action, None, &test_alert()).await.unwrap();
+        let output = std::fs::read_to_string(&output_path).unwrap();
+        std::fs::remove_file(&output_path).unwrap();
+        assert_eq!(output, "test 150");
+
+        let failing_action = AlertAction::Command(vec![String::from("false")]);
+        assert!(matches!(
+            run_action(&failing_action, None, &test_alert()).await,
+            Err(AlertError::CommandFailed(_)),
+        ));
+    }
+
+    #[tokio::test]
+    async fn webhook_action() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let sender = sender.clone();
+                let service = service_fn(move |req: Request<Incoming>| {
+                    let sender = sender.clone();
+                    async move {
+                        let path = req.uri().path().to_owned();
+                        let body = req.into_body().collect().await.unwrap().to_bytes();
+                        let status = if path == "/hook" { 204 } else { 404 };
+                        sender.send((path, body)).unwrap();
+                        Ok::<_, Infallible>(Response::builder().status(status).body(Full::new(Bytes::new())).unwrap())
+                    }
+                });
+                tokio::spawn(hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service));
+            }
+        });
+
+        let action = AlertAction::Webhook(format!("http://{}/hook?key=value", address));
+        run_action(&action, None, &test_alert()).await.unwrap();
+        let (path, body) = receiver.recv().await.unwrap();
+        assert_eq!(path, "/hook");
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["rule"], "test");
+        assert_eq!(json["metric"], "systolic");
+        assert_eq!(json["value"], "150");
+        assert_eq!(json["user"], "alice");
+
+        let missing_action = AlertAction::Webhook(format!("http://{}/missing", address));
+        assert!(matches!(
+            run_action(&missing_action, None, &test_alert()).await,
+            Err(AlertError::WebhookStatus(StatusCode::NOT_FOUND)),
+        ));
+    }
+
+    #[tokio::test]
+    async fn email_action() {
+        // a minimal SMTP server that accepts a single message
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read_half, mut write_half) = stream.into_split();
+            let mut lines = BufReader::new(read_half).lines();
+            let mut transcript = Vec::new();
+            write_half.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                transcript.push(line.clone());
+                let command = line.to_ascii_uppercase();
+                let reply: &[u8] = if command.starts_with("EHLO") || command.starts_with("HELO") {
+                    b"250 localhost\r\n"
+                } else if command == "DATA" {
+                    b"354 go ahead\r\n"
+                } else if command == "." {
+                    b"250 queued\r\n"
+                } else if command == "QUIT" {
+                    write_half.write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                } else if command.starts_with("MAIL") || command.starts_with("RCPT") {
+                    b"250 ok\r\n"
+                } else {
+                    // message content
+                    continue;
+                };
+                write_half.write_all(reply).await.unwrap();
+            }
+            transcript
+        });
+
+        let smtp = SmtpConfig {
+            host: String::from("127.0.0.1"),
+            port: Some(port),
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+            from: String::from("beepee <beepee@example.com>"),
+        };
+        let action = AlertAction::Email(String::from("alice@example.com"));
+        assert!(matches!(
+            run_action(&action, None, &test_alert()).await,
+            Err(AlertError::SmtpNotConfigured),
+        ));
+        run_action(&action, Some(&smtp), &test_alert()).await.unwrap();
+
+        let transcript = server.await.unwrap();
+        assert!(transcript.contains(&String::from("MAIL FROM:<beepee@example.com>")));
+        assert!(transcript.contains(&String::from("RCPT TO:<alice@example.com>")));
+        assert!(transcript.contains(&String::from("Subject: beepee alert: test")));
+    }
+}
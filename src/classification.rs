@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+
+use crate::model::BloodPressureMeasurement;
+
+
+/// The guideline according to which blood pressure is classified.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) enum BloodPressureGuideline {
+    /// 2018 ESC/ESH Guidelines for the management of arterial hypertension.
+    #[default]
+    #[serde(rename = "esc-esh-2018")]
+    EscEsh2018,
+
+    /// 2017 ACC/AHA Guideline for the Prevention, Detection, Evaluation, and Management of High
+    /// Blood Pressure in Adults.
+    #[serde(rename = "acc-aha-2017")]
+    AccAha2017,
+}
+impl BloodPressureGuideline {
+    /// The categories of this guideline, from lowest to highest blood pressure.
+    pub fn categories(&self) -> &'static [BloodPressureCategory] {
+        match self {
+            Self::EscEsh2018 => &[
+                BloodPressureCategory::Optimal,
+                BloodPressureCategory::Normal,
+                BloodPressureCategory::HighNormal,
+                BloodPressureCategory::Grade1Hypertension,
+                BloodPressureCategory::Grade2Hypertension,
+                BloodPressureCategory::Grade3Hypertension,
+            ],
+            Self::AccAha2017 => &[
+                BloodPressureCategory::Normal,
+                BloodPressureCategory::Elevated,
+                BloodPressureCategory::Stage1Hypertension,
+                BloodPressureCategory::Stage2Hypertension,
+            ],
+        }
+    }
+
+    /// The category of the given blood pressure. If the systolic and the diastolic pressure fall into
+    /// different categories, the higher category applies.
+    pub fn classify(&self, systolic_mmhg: i32, diastolic_mmhg: i32) -> BloodPressureCategory {
+        match self {
+            Self::EscEsh2018 => {
+                if systolic_mmhg >= 180 || diastolic_mmhg >= 110 {
+                    BloodPressureCategory::Grade3Hypertension
+                } else if systolic_mmhg >= 160 || diastolic_mmhg >= 100 {
+                    BloodPressureCategory::Grade2Hypertension
+                } else if systolic_mmhg >= 140 || diastolic_mmhg >= 90 {
+                    BloodPressureCategory::Grade1Hypertension
+                } else if systolic_mmhg >= 130 || diastolic_mmhg >= 85 {
+                    BloodPressureCategory::HighNormal
+                } else if systolic_mmhg >= 120 || diastolic_mmhg >= 80 {
+                    BloodPressureCategory::Normal
+                } else {
+                    BloodPressureCategory::Optimal
+                }
+            },
+            Self::AccAha2017 => {
+                if systolic_mmhg >= 140 || diastolic_mmhg >= 90 {
+                    BloodPressureCategory::Stage2Hypertension
+                } else if systolic_mmhg >= 130 || diastolic_mmhg >= 80 {
+                    BloodPressureCategory::Stage1Hypertension
+                } else if systolic_mmhg >= 120 {
+                    BloodPressureCategory::Elevated
+                } else {
+                    BloodPressureCategory::Normal
+                }
+            },
+        }
+    }
+
+    pub fn classify_measurement(&self, measurement: &BloodPressureMeasurement) -> BloodPressureCategory {
+        self.classify(measurement.systolic_mmhg, measurement.diastolic_mmhg)
+    }
+
+    /// The number of the given measurements in each category of this guideline.
+    pub fn count_categories(&self, measurements: &[BloodPressureMeasurement]) -> Vec<(BloodPressureCategory, usize)> {
+        self.categories().iter()
+            .map(|category| {
+                let count = measurements.iter()
+                    .filter(|m| self.classify_measurement(m) == *category)
+                    .count();
+                (*category, count)
+            })
+            .collect()
+    }
+}
+
+
+/// A blood pressure category of one of the guidelines. `Normal` is part of both guidelines, but
+/// with different limits.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) enum BloodPressureCategory {
+    #[serde(rename = "optimal")] Optimal,
+    #[serde(rename = "normal")] Normal,
+    #[serde(rename = "high-normal")] HighNormal,
+    #[serde(rename = "grade-1-hypertension")] Grade1Hypertension,
+    #[serde(rename = "grade-2-hypertension")] Grade2Hypertension,
+    #[serde(rename = "grade-3-hypertension")] Grade3Hypertension,
+    #[serde(rename = "elevated")] Elevated,
+    #[serde(rename = "stage-1-hypertension")] Stage1Hypertension,
+    #[serde(rename = "stage-2-hypertension")] Stage2Hypertension,
+}
+impl BloodPressureCategory {
+    /// The identifier of the category, as used in the API and as CSS class.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Optimal => "optimal",
+            Self::Normal => "normal",
+            Self::HighNormal => "high-normal",
+            Self::Grade1Hypertension => "grade-1-hypertension",
+            Self::Grade2Hypertension => "grade-2-hypertension",
+            Self::Grade3Hypertension => "grade-3-hypertension",
+            Self::Elevated => "elevated",
+            Self::Stage1Hypertension => "stage-1-hypertension",
+            Self::Stage2Hypertension => "stage-2-hypertension",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Self::Optimal => "optimal",
+            Self::Normal => "normal",
+            Self::HighNormal => "high normal",
+            Self::Grade1Hypertension => "grade 1 hypertension",
+            Self::Grade2Hypertension => "grade 2 hypertension",
+            Self::Grade3Hypertension => "grade 3 hypertension",
+            Self::Elevated => "elevated",
+            Self::Stage1Hypertension => "stage 1 hypertension",
+            Self::Stage2Hypertension => "stage 2 hypertension",
+        }
+    }
+}
+
+
+/// A blood pressure measurement along with its category, as output by the API.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
+pub(crate) struct ClassifiedBloodPressureMeasurement {
+    #[serde(flatten)]
+    pub measurement: BloodPressureMeasurement,
+    pub category: BloodPressureCategory,
+}
+impl ClassifiedBloodPressureMeasurement {
+    pub fn new(
+        guideline: BloodPressureGuideline,
+        measurement: BloodPressureMeasurement,
+    ) -> Self {
+        Self {
+            measurement,
+            category: guideline.classify_measurement(&measurement),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn esc_esh_2018() {
+        let guideline = BloodPressureGuideline::EscEsh2018;
+        assert_eq!(guideline.classify(119, 79), BloodPressureCategory::Optimal);
+        assert_eq!(guideline.classify(120, 70), BloodPressureCategory::Normal);
+        assert_eq!(guideline.classify(110, 85), BloodPressureCategory::HighNormal);
+        assert_eq!(guideline.classify(145, 80), BloodPressureCategory::Grade1Hypertension);
+        assert_eq!(guideline.classify(150, 105), BloodPressureCategory::Grade2Hypertension);
+        assert_eq!(guideline.classify(180, 60), BloodPressureCategory::Grade3Hypertension);
+    }
+
+    #[test]
+    fn acc_aha_2017() {
+        let guideline = BloodPressureGuideline::AccAha2017;
+        assert_eq!(guideline.classify(119, 79), BloodPressureCategory::Normal);
+        assert_eq!(guideline.classify(125, 79), BloodPressureCategory::Elevated);
+        assert_eq!(guideline.classify(125, 80), BloodPressureCategory::Stage1Hypertension);
+        assert_eq!(guideline.classify(140, 70), BloodPressureCategory::Stage2Hypertension);
+    }
+}
@@ -1,18 +1,39 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
 use std::fs::File;
 use std::io::Read;
+use std::iter::FromIterator;
 use std::path::PathBuf;
 
+use lettre::message::Mailbox;
+use log::{error, info, warn};
+use num_rational::Rational32;
 use once_cell::sync::OnceCell;
 use serde::{Serialize, Deserialize};
 use tokio::sync::RwLock;
 use toml;
+use url::Url;
 
 use crate::ServerError;
+use crate::classification::BloodPressureGuideline;
+use crate::model::{ShareToken, TimeRange, User};
+use crate::token::{TokenHash, sha256_hex, tokens_equal};
 
 
 pub(crate) static CONFIG_PATH: OnceCell<PathBuf> = OnceCell::new();
 pub(crate) static CONFIG: OnceCell<RwLock<Config>> = OnceCell::new();
 
+/// Configuration keys that are only read on startup; changing them when reloading has no effect
+/// until the program is restarted.
+const RESTART_KEYS: [&str; 8] = [
+    "storage", "db_conn_string", "db_pool", "db_tls", "sqlite_path", "http_listen",
+    "migrate_on_startup", "height_cm",
+];
+
+/// Configuration keys whose values are not logged when they change.
+const SECRET_KEYS: [&str; 4] = ["db_conn_string", "auth_tokens", "alert_actions", "smtp"];
+
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub(crate) struct Hours {
@@ -24,26 +45,707 @@ pub(crate) struct Hours {
 }
 
 
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(default)]
+pub(crate) struct DbPoolConfig {
+    pub max_size: usize,
+    pub wait_timeout_ms: Option<u64>,
+    pub create_timeout_ms: Option<u64>,
+    pub recycle_timeout_ms: Option<u64>,
+}
+impl Default for DbPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 16,
+            wait_timeout_ms: Some(10_000),
+            create_timeout_ms: Some(10_000),
+            recycle_timeout_ms: Some(5_000),
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum StorageBackend {
+    /// Store measurements in a PostgreSQL database (`db_conn_string`).
+    #[default]
+    Postgres,
+
+    /// Store measurements in an SQLite database file (`sqlite_path`).
+    Sqlite,
+
+    /// Only keep measurements in memory; they are lost when the program exits. Meant for testing.
+    Memory,
+}
+
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum DbSslMode {
+    /// Never use TLS.
+    Disable,
+
+    /// Use TLS if the server supports it, without verifying its certificate.
+    Prefer,
+
+    /// Always use TLS, without verifying the server's certificate.
+    Require,
+
+    /// Always use TLS and verify that the server's certificate has been issued by a trusted CA.
+    VerifyCa,
+
+    /// Always use TLS and verify that the server's certificate has been issued by a trusted CA for
+    /// the host name being connected to.
+    VerifyFull,
+}
+
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(default)]
+pub(crate) struct DbTlsConfig {
+    /// Overrides the `sslmode` of the connection string. If unset, the connection string's mode
+    /// applies.
+    pub ssl_mode: Option<DbSslMode>,
+
+    /// PEM file with the CA certificates to trust. If unset, the system's certificate store is used.
+    /// A server certificate contained in the file is trusted as is, without checking the host name.
+    pub ca_file: Option<PathBuf>,
+
+    /// PEM file with the client certificate chain to authenticate with.
+    pub client_cert_file: Option<PathBuf>,
+
+    /// PEM file with the private key of the client certificate.
+    pub client_key_file: Option<PathBuf>,
+}
+
+
+/// A value of a measurement that alert rules can be defined for.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum AlertMetric {
+    Systolic,
+    Diastolic,
+    Pulse,
+    Spo2,
+    Mass,
+    Bmi,
+    WaistCircumference,
+    Temperature,
+    /// Blood sugar in mmol/l.
+    Sugar,
+    /// HbA1c in mmol/mol.
+    LongTermSugar,
+}
+impl AlertMetric {
+    /// The scope of the measurements containing this value.
+    pub fn scope(&self) -> Scope {
+        match self {
+            AlertMetric::Systolic|AlertMetric::Diastolic|AlertMetric::Pulse|AlertMetric::Spo2
+                => Scope::Bp,
+            AlertMetric::Mass|AlertMetric::Bmi|AlertMetric::WaistCircumference
+                => Scope::Mass,
+            AlertMetric::Temperature => Scope::Temperature,
+            AlertMetric::Sugar => Scope::Sugar,
+            AlertMetric::LongTermSugar => Scope::LongTermSugar,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertMetric::Systolic => "systolic",
+            AlertMetric::Diastolic => "diastolic",
+            AlertMetric::Pulse => "pulse",
+            AlertMetric::Spo2 => "spo2",
+            AlertMetric::Mass => "mass",
+            AlertMetric::Bmi => "bmi",
+            AlertMetric::WaistCircumference => "waist-circumference",
+            AlertMetric::Temperature => "temperature",
+            AlertMetric::Sugar => "sugar",
+            AlertMetric::LongTermSugar => "long-term-sugar",
+        }
+    }
+
+    /// The key of the measurement field containing this value.
+    pub fn field_key(&self) -> &'static str {
+        match self {
+            AlertMetric::Systolic => "systolic_mmhg",
+            AlertMetric::Diastolic => "diastolic_mmhg",
+            AlertMetric::Pulse => "pulse_bpm",
+            AlertMetric::Spo2 => "spo2_percent",
+            AlertMetric::Mass => "mass_kg",
+            AlertMetric::Bmi => "bmi",
+            AlertMetric::WaistCircumference => "waist_circum_cm",
+            AlertMetric::Temperature => "temperature_celsius",
+            AlertMetric::Sugar => "sugar_mmol_per_l",
+            AlertMetric::LongTermSugar => "hba1c_mmol_per_mol",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            AlertMetric::Systolic => "systolic blood pressure",
+            AlertMetric::Diastolic => "diastolic blood pressure",
+            AlertMetric::Pulse => "pulse",
+            AlertMetric::Spo2 => "SpO2",
+            AlertMetric::Mass => "body mass",
+            AlertMetric::Bmi => "BMI",
+            AlertMetric::WaistCircumference => "waist circumference",
+            AlertMetric::Temperature => "body temperature",
+            AlertMetric::Sugar => "blood sugar",
+            AlertMetric::LongTermSugar => "HbA1c",
+        }
+    }
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            AlertMetric::Systolic|AlertMetric::Diastolic => "mmHg",
+            AlertMetric::Pulse => "/min",
+            AlertMetric::Spo2 => "%",
+            AlertMetric::Mass => "kg",
+            AlertMetric::Bmi => "kg/m²",
+            AlertMetric::WaistCircumference => "cm",
+            AlertMetric::Temperature => "°C",
+            AlertMetric::Sugar => "mmol/l",
+            AlertMetric::LongTermSugar => "mmol/mol",
+        }
+    }
+
+    /// The number of decimal places with which values are reported.
+    pub fn decimal_places(&self) -> usize {
+        match self {
+            AlertMetric::Systolic|AlertMetric::Diastolic|AlertMetric::Pulse|AlertMetric::Spo2
+                |AlertMetric::LongTermSugar
+                => 0,
+            AlertMetric::Mass|AlertMetric::Bmi|AlertMetric::WaistCircumference
+                |AlertMetric::Temperature|AlertMetric::Sugar
+                => 1,
+        }
+    }
+}
+
+
+/// Raises an alert when a new measurement is at or beyond a threshold.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub(crate) struct AlertRule {
+    pub name: String,
+    pub metric: AlertMetric,
+    /// Values at or above this are alarming.
+    #[serde(default, with = "crate::ser_de::serde_rat32_number_opt")]
+    pub at_least: Option<Rational32>,
+    /// Values at or below this are alarming.
+    #[serde(default, with = "crate::ser_de::serde_rat32_number_opt")]
+    pub at_most: Option<Rational32>,
+    /// How many of the most recent values, including the new one, must be alarming.
+    #[serde(default = "default_consecutive")]
+    pub consecutive: usize,
+    /// The names of the entries in `alert_actions` to run.
+    pub actions: Vec<String>,
+}
+impl AlertRule {
+    pub fn is_alarming(&self, value: Rational32) -> bool {
+        self.at_least.map(|al| value >= al).unwrap_or(false)
+            || self.at_most.map(|am| value <= am).unwrap_or(false)
+    }
+}
+
+fn default_consecutive() -> usize {
+    1
+}
+
+
+/// What to do when an alert is raised.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum AlertAction {
+    /// Runs the program (the first element) with the arguments (the other elements). The alert is
+    /// described in the environment variables `BEEPEE_ALERT_*`.
+    Command(Vec<String>),
+    /// Sends the alert as JSON in a POST request to the URL.
+    Webhook(String),
+    /// Sends the alert by email to the address via the server configured in `smtp`.
+    Email(String),
+}
+
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum SmtpTls {
+    /// Unencrypted, e.g. for a mail server on the same host.
+    None,
+    /// Upgrade the connection using the STARTTLS command (port 587 by default).
+    #[default]
+    Starttls,
+    /// Connect using TLS (port 465 by default).
+    Tls,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub(crate) struct SmtpConfig {
+    pub host: String,
+    /// Defaults to the port matching `tls`.
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// The sender address, optionally with a name (`beepee <beepee@example.com>`).
+    pub from: String,
+}
+
+
+/// A numeric value of a custom measurement type.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub(crate) struct CustomField {
+    /// Identifies the value in forms and the API; lowercase letters, digits and underscores.
+    pub key: String,
+    /// Shown in the table headers; defaults to the key.
+    pub title: Option<String>,
+    #[serde(default)]
+    pub unit: String,
+    /// The lowest value that is accepted.
+    #[serde(default, with = "crate::ser_de::serde_rat32_number_opt")]
+    pub min: Option<Rational32>,
+    /// The highest value that is accepted.
+    #[serde(default, with = "crate::ser_de::serde_rat32_number_opt")]
+    pub max: Option<Rational32>,
+    /// The number of decimal places to which values are rounded (at most
+    /// `MAX_CUSTOM_DECIMAL_PLACES`).
+    #[serde(default)]
+    pub decimal_places: usize,
+    /// Whether measurements may lack this value.
+    #[serde(default)]
+    pub optional: bool,
+}
+impl CustomField {
+    pub fn title(&self) -> &str {
+        self.title.as_deref().unwrap_or(&self.key)
+    }
+
+    /// The smallest increment of the value, as the `step` of number inputs.
+    pub fn step(&self) -> String {
+        if self.decimal_places == 0 {
+            "1".to_owned()
+        } else {
+            format!("0.{}1", "0".repeat(self.decimal_places - 1))
+        }
+    }
+}
+
+/// A kind of measurement that is defined in the configuration instead of the code.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub(crate) struct CustomMeasurementType {
+    /// Identifies the type in URLs and the database; lowercase letters, digits and hyphens.
+    pub name: String,
+    /// Shown as the heading of the list page; defaults to the name.
+    pub title: Option<String>,
+    pub fields: Vec<CustomField>,
+}
+impl CustomMeasurementType {
+    pub fn title(&self) -> &str {
+        self.title.as_deref().unwrap_or(&self.name)
+    }
+
+    /// The list page of the type relative to the base URL.
+    pub fn page(&self) -> String {
+        format!("custom-{}", self.name)
+    }
+
+    /// The scope that tokens need to read or write measurements of this type.
+    pub fn scope(&self) -> Scope {
+        Scope::Custom(self.name.clone())
+    }
+}
+
+/// The largest number of decimal places that values of custom measurements can have.
+pub(crate) const MAX_CUSTOM_DECIMAL_PLACES: usize = 4;
+
+
+/// A kind of data that a token can be allowed to read or write.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) enum Scope {
+    Bp,
+    Mass,
+    Temperature,
+    Sugar,
+    LongTermSugar,
+    /// The birth date, sex and heights of the user.
+    Profile,
+    /// The measurements of all the types in `custom_measurement_types`.
+    AllCustom,
+    /// The measurements of the type in `custom_measurement_types` with the given name; given as
+    /// `custom:<name>` in the configuration.
+    Custom(String),
+}
+impl Scope {
+    /// The scopes that do not depend on the configured custom measurement types.
+    pub const ALL: [Scope; 7] = [
+        Scope::Bp, Scope::Mass, Scope::Temperature, Scope::Sugar, Scope::LongTermSugar, Scope::Profile,
+        Scope::AllCustom,
+    ];
+
+    /// The name of the scope, as used in the configuration.
+    pub fn name(&self) -> Cow<'static, str> {
+        match self {
+            Scope::Bp => Cow::Borrowed("bp"),
+            Scope::Mass => Cow::Borrowed("mass"),
+            Scope::Temperature => Cow::Borrowed("temperature"),
+            Scope::Sugar => Cow::Borrowed("sugar"),
+            Scope::LongTermSugar => Cow::Borrowed("long-term-sugar"),
+            Scope::Profile => Cow::Borrowed("profile"),
+            Scope::AllCustom => Cow::Borrowed("custom"),
+            Scope::Custom(type_name) => Cow::Owned(format!("custom:{}", type_name)),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(type_name) = name.strip_prefix("custom:") {
+            return if type_name.is_empty() {
+                None
+            } else {
+                Some(Scope::Custom(type_name.to_owned()))
+            };
+        }
+        Scope::ALL.iter()
+            .find(|s| s.name() == name)
+            .cloned()
+    }
+
+    /// Whether this scope is about custom measurements.
+    pub fn is_custom(&self) -> bool {
+        matches!(self, Scope::AllCustom|Scope::Custom(_))
+    }
+}
+impl TryFrom<String> for Scope {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Scope::from_name(&value)
+            .ok_or_else(|| format!("unknown scope {:?}", value))
+    }
+}
+impl From<Scope> for String {
+    fn from(value: Scope) -> Self {
+        value.name().into_owned()
+    }
+}
+
+
+/// A set of scopes, given in the configuration either as a list or as `true` (all scopes) or `false`
+/// (no scopes).
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(from = "ScopesValue", into = "Vec<Scope>")]
+pub(crate) struct Scopes(BTreeSet<Scope>);
+impl Scopes {
+    pub fn all() -> Self {
+        Self(Scope::ALL.iter().cloned().collect())
+    }
+
+    pub fn none() -> Self {
+        Self(BTreeSet::new())
+    }
+
+    /// Whether the set contains the scope; the measurements of each custom type are also contained
+    /// in the scope of all custom measurements.
+    pub fn contains(&self, scope: &Scope) -> bool {
+        self.0.contains(scope)
+            || (matches!(scope, Scope::Custom(_)) && self.0.contains(&Scope::AllCustom))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Scope> + '_ {
+        self.0.iter()
+    }
+
+    /// The names of the scopes separated by commas.
+    pub fn to_names_string(&self) -> String {
+        self.iter()
+            .map(|s| s.name())
+            .collect::<Vec<Cow<str>>>()
+            .join(",")
+    }
+
+    /// Parses the output of `to_names_string`, returning `None` if a name is unknown.
+    pub fn from_names_string(names: &str) -> Option<Self> {
+        names.split(',')
+            .filter(|n| !n.is_empty())
+            .map(Scope::from_name)
+            .collect()
+    }
+}
+impl FromIterator<Scope> for Scopes {
+    fn from_iter<I: IntoIterator<Item = Scope>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+impl From<ScopesValue> for Scopes {
+    fn from(value: ScopesValue) -> Self {
+        match value {
+            ScopesValue::All(true) => Self::all(),
+            ScopesValue::All(false) => Self::none(),
+            ScopesValue::List(scopes) => Self(scopes.into_iter().collect()),
+        }
+    }
+}
+impl From<Scopes> for Vec<Scope> {
+    fn from(value: Scopes) -> Self {
+        value.0.into_iter().collect()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScopesValue {
+    All(bool),
+    List(Vec<Scope>),
+}
+
+
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub(crate) struct AuthToken {
-    pub token: String,
-    pub write: bool,
+    /// The token in plain text. Exactly one of `token` and `token_hash` must be set.
+    pub token: Option<String>,
+    /// The hash of the token, as output by the `generate-token` command.
+    pub token_hash: Option<TokenHash>,
+    /// The scopes that can be read; all scopes if not set.
+    #[serde(default = "Scopes::all")]
+    pub read: Scopes,
+    /// The scopes that can be written. Scopes that can be written can also be read.
+    pub write: Scopes,
+    #[serde(default = "default_user")]
+    pub user: String,
+    /// Limits the measurements that can be read; only share tokens are limited.
+    #[serde(skip)]
+    pub range: TimeRange,
+}
+impl AuthToken {
+    /// The read-only token that is used when a share token is presented.
+    pub fn from_share_token(user: &User, share_token: &ShareToken) -> Self {
+        Self {
+            token: None,
+            token_hash: None,
+            read: share_token.scopes.clone(),
+            write: Scopes::none(),
+            user: user.name.clone(),
+            range: share_token.range,
+        }
+    }
+
+    pub fn can_read(&self, scope: Scope) -> bool {
+        self.read.contains(&scope) || self.write.contains(&scope)
+    }
+
+    pub fn can_write(&self, scope: Scope) -> bool {
+        self.write.contains(&scope)
+    }
+
+    /// Whether the measurements of at least one custom type can be read.
+    pub fn can_read_any_custom(&self) -> bool {
+        self.read.iter()
+            .chain(self.write.iter())
+            .any(|s| s.is_custom())
+    }
+
+    /// Whether any scope can be written; share tokens can only be managed with such tokens.
+    pub fn can_write_any(&self) -> bool {
+        !self.write.is_empty()
+    }
+
+
+    /// Whether the given value is this token. The comparison takes constant time.
+    pub fn matches(&self, value: &str) -> bool {
+        match (&self.token, &self.token_hash) {
+            (_, Some(hash)) => hash.verify(value),
+            (Some(token), None) => tokens_equal(token, value),
+            (None, None) => false,
+        }
+    }
+
+    /// Identifies the token in the sessions started with it: the configured hash or the SHA-256
+    /// hash of the token in hexadecimal. Share tokens have no key; they are identified by their
+    /// hash in storage.
+    pub fn session_key(&self) -> Option<String> {
+        match (&self.token, &self.token_hash) {
+            (_, Some(hash)) => Some(hash.to_string()),
+            (Some(token), None) => Some(sha256_hex(token)),
+            (None, None) => None,
+        }
+    }
 }
 
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub(crate) struct Config {
-    pub db_conn_string: String,
+    #[serde(default)]
+    pub storage: StorageBackend,
+    pub db_conn_string: Option<String>,
+    #[serde(default)]
+    pub db_pool: DbPoolConfig,
+    #[serde(default)]
+    pub db_tls: DbTlsConfig,
+    pub sqlite_path: Option<PathBuf>,
     pub http_listen: String,
     pub auth_tokens: Vec<AuthToken>,
     pub base_url: String,
     pub hours: Hours,
+    /// Deprecated in favor of the height history in the user profile; recorded as the height of the
+    /// default user if that user has no height yet.
     pub height_cm: Option<i32>,
     pub default_temperature_location_id: i64,
+    #[serde(default = "default_export_decimal_places")]
+    pub export_decimal_places: usize,
+    #[serde(default = "default_migrate_on_startup")]
+    pub migrate_on_startup: bool,
+    /// How long the sessions started by the login form remain valid.
+    #[serde(default = "default_session_days")]
+    pub session_days: u32,
+    #[serde(default)]
+    pub bp_guideline: BloodPressureGuideline,
+    #[serde(default)]
+    pub alert_rules: Vec<AlertRule>,
+    /// The actions that alert rules can run, by name.
+    #[serde(default)]
+    pub alert_actions: BTreeMap<String, AlertAction>,
+    /// The mail server used by email actions.
+    pub smtp: Option<SmtpConfig>,
+    #[serde(default)]
+    pub custom_measurement_types: Vec<CustomMeasurementType>,
 }
 
+impl Config {
+    pub fn validate(&self) -> Result<(), ServerError> {
+        for (index, auth_token) in self.auth_tokens.iter().enumerate() {
+            if auth_token.token.is_some() == auth_token.token_hash.is_some() {
+                return Err(ServerError::InvalidConfig(format!(
+                    "auth token {} (user {:?}) must have exactly one of token and token_hash",
+                    index, auth_token.user,
+                )));
+            }
+        }
 
-pub(crate) async fn load_config() -> Result<(), ServerError> {
+        for (name, action) in &self.alert_actions {
+            let invalid = |problem: &str| ServerError::InvalidConfig(format!("alert action {:?} {}", name, problem));
+            match action {
+                AlertAction::Command(command) => {
+                    if command.is_empty() {
+                        return Err(invalid("has an empty command"));
+                    }
+                },
+                AlertAction::Webhook(url) => {
+                    let parsed_url: Url = url.parse()
+                        .map_err(|e| invalid(&format!("has an invalid URL: {}", e)))?;
+                    if parsed_url.scheme() != "http" && parsed_url.scheme() != "https" {
+                        return Err(invalid("must have an http or https URL"));
+                    }
+                },
+                AlertAction::Email(address) => {
+                    if self.smtp.is_none() {
+                        return Err(invalid("sends email, but smtp is not configured"));
+                    }
+                    address.parse::<Mailbox>()
+                        .map_err(|e| invalid(&format!("has an invalid email address: {}", e)))?;
+                },
+            }
+        }
+        if let Some(smtp) = &self.smtp {
+            smtp.from.parse::<Mailbox>()
+                .map_err(|e| ServerError::InvalidConfig(format!("invalid smtp sender address: {}", e)))?;
+        }
+
+        for rule in &self.alert_rules {
+            let invalid = |problem: &str| ServerError::InvalidConfig(format!("alert rule {:?} {}", rule.name, problem));
+            if rule.at_least.is_none() && rule.at_most.is_none() {
+                return Err(invalid("needs at_least or at_most"));
+            }
+            if rule.consecutive == 0 {
+                return Err(invalid("needs at least one consecutive reading"));
+            }
+            for action_name in &rule.actions {
+                if !self.alert_actions.contains_key(action_name) {
+                    return Err(invalid(&format!("refers to unknown action {:?}", action_name)));
+                }
+            }
+        }
+
+        let mut type_names = BTreeSet::new();
+        for measurement_type in &self.custom_measurement_types {
+            let invalid = |problem: &str| ServerError::InvalidConfig(format!("custom measurement type {:?} {}", measurement_type.name, problem));
+            let name_valid = measurement_type.name.chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            if measurement_type.name.is_empty() || !name_valid {
+                return Err(invalid("must have a name consisting of lowercase letters, digits and hyphens"));
+            }
+            if !type_names.insert(&measurement_type.name) {
+                return Err(invalid("is defined more than once"));
+            }
+            if measurement_type.fields.is_empty() {
+                return Err(invalid("needs at least one field"));
+            }
+
+            let mut field_keys = BTreeSet::new();
+            for field in &measurement_type.fields {
+                let key_valid = field.key.chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+                if field.key.is_empty() || !key_valid {
+                    return Err(invalid(&format!("has field {:?} whose key does not consist of lowercase letters, digits and underscores", field.key)));
+                }
+                if !field_keys.insert(&field.key) {
+                    return Err(invalid(&format!("has field {:?} more than once", field.key)));
+                }
+                if let (Some(min), Some(max)) = (field.min, field.max) {
+                    if min > max {
+                        return Err(invalid(&format!("has field {:?} whose min is greater than its max", field.key)));
+                    }
+                }
+                if field.decimal_places > MAX_CUSTOM_DECIMAL_PLACES {
+                    return Err(invalid(&format!("has field {:?} with more than {} decimal places", field.key, MAX_CUSTOM_DECIMAL_PLACES)));
+                }
+            }
+        }
+        for (index, auth_token) in self.auth_tokens.iter().enumerate() {
+            for scope in auth_token.read.iter().chain(auth_token.write.iter()) {
+                if let Scope::Custom(type_name) = scope {
+                    if self.custom_measurement_type(type_name).is_none() {
+                        return Err(ServerError::InvalidConfig(format!(
+                            "auth token {} (user {:?}) has a scope of the unknown custom measurement type {:?}",
+                            index, auth_token.user, type_name,
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn custom_measurement_type(&self, name: &str) -> Option<&CustomMeasurementType> {
+        self.custom_measurement_types.iter()
+            .find(|mt| mt.name == name)
+    }
+}
+
+fn default_export_decimal_places() -> usize {
+    2
+}
+
+fn default_migrate_on_startup() -> bool {
+    true
+}
+
+fn default_session_days() -> u32 {
+    30
+}
+
+/// The user owning all measurements recorded before users were introduced.
+pub(crate) fn default_user() -> String {
+    "default".to_owned()
+}
+
+
+fn read_config() -> Result<Config, ServerError> {
     let path = CONFIG_PATH
         .get().expect("configuration path missing");
 
@@ -56,6 +758,12 @@ pub(crate) async fn load_config() -> Result<(), ServerError> {
         toml::from_str(&config_str)
             .map_err(|e| ServerError::ParsingConfigFile(e))?
     };
+    config.validate()?;
+    Ok(config)
+}
+
+pub(crate) async fn load_config() -> Result<(), ServerError> {
+    let config = read_config()?;
 
     match CONFIG.get() {
         Some(cg) => {
@@ -71,3 +779,220 @@ pub(crate) async fn load_config() -> Result<(), ServerError> {
 
     Ok(())
 }
+
+/// Reads the configuration file again and replaces the current configuration with it, logging the
+/// keys that have changed. If the new configuration is invalid, the current one is kept.
+pub(crate) async fn reload_config() -> Result<(), ServerError> {
+    let mut new_config = read_config()?;
+
+    let mut config_guard = CONFIG
+        .get().expect("config is set")
+        .write().await;
+    match changed_keys(&config_guard, &new_config) {
+        Ok(changes) => {
+            if changes.is_empty() {
+                info!("configuration unchanged");
+            }
+            for change in &changes {
+                if SECRET_KEYS.contains(&change.key.as_str()) {
+                    info!("configuration key {} changed", change.key);
+                } else {
+                    info!(
+                        "configuration key {} changed from {} to {}",
+                        change.key, display_config_value(&change.old_value), display_config_value(&change.new_value),
+                    );
+                }
+                if RESTART_KEYS.contains(&change.key.as_str()) {
+                    warn!("changing configuration key {} requires a restart", change.key);
+                }
+            }
+        },
+        Err(e) => error!("failed to compare configurations: {}", e),
+    }
+
+    // keep what is in effect until the restart
+    new_config.storage = config_guard.storage;
+    new_config.db_conn_string = config_guard.db_conn_string.clone();
+    new_config.db_pool = config_guard.db_pool;
+    new_config.db_tls = config_guard.db_tls.clone();
+    new_config.sqlite_path = config_guard.sqlite_path.clone();
+    new_config.http_listen = config_guard.http_listen.clone();
+    new_config.migrate_on_startup = config_guard.migrate_on_startup;
+    new_config.height_cm = config_guard.height_cm;
+    *config_guard = new_config;
+    Ok(())
+}
+
+/// A top-level configuration key whose value differs between two configurations.
+#[derive(Clone, Debug, PartialEq)]
+struct ConfigChange {
+    key: String,
+    /// `None` if the key is not set.
+    old_value: Option<toml::Value>,
+    new_value: Option<toml::Value>,
+}
+
+fn changed_keys(old_config: &Config, new_config: &Config) -> Result<Vec<ConfigChange>, toml::ser::Error> {
+    let old_table = toml::Table::try_from(old_config)?;
+    let new_table = toml::Table::try_from(new_config)?;
+    let keys: BTreeSet<&String> = old_table.keys()
+        .chain(new_table.keys())
+        .collect();
+    let changed = keys.into_iter()
+        .filter(|key| old_table.get(*key) != new_table.get(*key))
+        .map(|key| ConfigChange {
+            key: key.clone(),
+            old_value: old_table.get(key).cloned(),
+            new_value: new_table.get(key).cloned(),
+        })
+        .collect();
+    Ok(changed)
+}
+
+fn display_config_value(value: &Option<toml::Value>) -> String {
+    match value {
+        Some(v) => v.to_string(),
+        None => "(not set)".to_owned(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG_TOML: &str = r#"
+        storage = "memory"
+        http_listen = "127.0.0.1:8000"
+        base_url = "http://beepee.example/"
+        default_temperature_location_id = 1
+        auth_tokens = [{ token = "one", write = true }]
+
+        [hours]
+        morning_start = 5
+        morning_end = 13
+        midday_start = 11
+        midday_end = 20
+        evening_start = 17
+    "#;
+
+    #[test]
+    fn changed_keys_listed() {
+        let old_config: Config = toml::from_str(CONFIG_TOML).unwrap();
+        assert_eq!(changed_keys(&old_config, &old_config).unwrap(), Vec::new());
+
+        let mut new_config = old_config.clone();
+        new_config.hours.morning_start = 6;
+        new_config.auth_tokens[0].token = Some("two".to_owned());
+        new_config.height_cm = Some(180);
+        let keys: Vec<String> = changed_keys(&old_config, &new_config).unwrap()
+            .into_iter()
+            .map(|change| change.key)
+            .collect();
+        assert_eq!(keys, vec!["auth_tokens", "height_cm", "hours"]);
+    }
+
+    #[test]
+    fn alert_rules_validated() {
+        let alerts_toml = r#"
+            [[alert_rules]]
+            name = "high blood pressure"
+            metric = "systolic"
+            at_least = 160
+            consecutive = 3
+            actions = ["notify"]
+
+            [[alert_rules]]
+            name = "fever"
+            metric = "temperature"
+            at_least = "38.5"
+            actions = ["notify"]
+
+            [alert_actions]
+            notify = { command = ["notify-send", "beepee"] }
+        "#;
+        let config: Config = toml::from_str(&format!("{}{}", CONFIG_TOML, alerts_toml)).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.alert_rules[0].at_least, Some(Rational32::from_integer(160)));
+        assert_eq!(config.alert_rules[1].at_least, Some(Rational32::new(385, 10)));
+        assert_eq!(config.alert_rules[1].consecutive, 1);
+
+        let mut invalid_config = config.clone();
+        invalid_config.alert_rules[0].actions.push("missing".to_owned());
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = config.clone();
+        invalid_config.alert_rules[0].at_least = None;
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = config;
+        invalid_config.alert_actions.insert("mail".to_owned(), AlertAction::Email("alice@example.com".to_owned()));
+        assert!(invalid_config.validate().is_err());
+    }
+
+    #[test]
+    fn custom_measurement_types_validated() {
+        let custom_toml = r#"
+            [[custom_measurement_types]]
+            name = "peak-flow"
+            title = "peak expiratory flow"
+            fields = [{ key = "pef", unit = "l/min", min = 50, max = 900 }]
+
+            [[custom_measurement_types]]
+            name = "inr"
+            fields = [{ key = "inr", min = "0.5", max = 10, decimal_places = 1 }]
+        "#;
+        let config: Config = toml::from_str(&format!("{}{}", CONFIG_TOML, custom_toml)).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.custom_measurement_type("inr").unwrap().fields[0].min, Some(Rational32::new(1, 2)));
+        assert_eq!(config.custom_measurement_type("inr").unwrap().title(), "inr");
+        assert_eq!(config.custom_measurement_type("pain"), None);
+
+        let mut invalid_config = config.clone();
+        invalid_config.custom_measurement_types[1].name = "peak-flow".to_owned();
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = config.clone();
+        invalid_config.custom_measurement_types[0].name = "Peak Flow".to_owned();
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = config.clone();
+        invalid_config.custom_measurement_types[0].fields.clear();
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = config.clone();
+        invalid_config.custom_measurement_types[0].fields[0].min = Some(Rational32::from_integer(1000));
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = config.clone();
+        invalid_config.custom_measurement_types[1].fields[0].decimal_places = 5;
+        assert!(invalid_config.validate().is_err());
+
+        let mut scoped_config = config;
+        scoped_config.auth_tokens[0].write = Scopes::from_names_string("bp,custom:inr").unwrap();
+        scoped_config.validate().unwrap();
+        scoped_config.auth_tokens[0].write = Scopes::from_names_string("custom:pain").unwrap();
+        assert!(scoped_config.validate().is_err());
+    }
+
+    #[test]
+    fn custom_scopes() {
+        let token: AuthToken = toml::from_str(r#"
+            token = "one"
+            read = ["bp", "custom:inr"]
+            write = ["custom:peak-flow"]
+        "#).unwrap();
+        assert_eq!(token.read.to_names_string(), "bp,custom:inr");
+        assert!(token.can_read(Scope::Custom("inr".to_owned())));
+        assert!(token.can_write(Scope::Custom("peak-flow".to_owned())));
+        assert!(!token.can_write(Scope::Custom("inr".to_owned())));
+        assert!(!token.can_read(Scope::Custom("pain".to_owned())));
+        assert!(!token.can_read(Scope::AllCustom));
+        assert!(token.can_read_any_custom());
+
+        let all_custom = Scopes::from_names_string("custom").unwrap();
+        assert!(all_custom.contains(&Scope::Custom("pain".to_owned())));
+        assert_eq!(Scopes::from_names_string("custom:"), None);
+        assert!(toml::from_str::<AuthToken>("token = \"one\"\nwrite = [\"blood\"]").is_err());
+    }
+}
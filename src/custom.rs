@@ -0,0 +1,398 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::fmt;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use http_body_util::Full;
+use hyper::{Request, Response};
+use hyper::body::{Body, Bytes};
+use num_rational::Rational32;
+
+use crate::{
+    ClientError, CustomEditTemplate, CustomListTemplate, CustomTypesTemplate, DEFAULT_LOOKBACK_DAYS,
+    check_timestamp_not_future, get_form_r32, get_form_timestamp_not_future, handle_measurement_request,
+    respond_403_scope, respond_404, respond_json_error, respond_template,
+};
+use crate::config::{AuthToken, CONFIG, Config, CustomMeasurementType, Scope};
+use crate::database::DatabaseError;
+use crate::measurement::{MeasurementEndpoint, MeasurementField, MeasurementType, Record};
+use crate::model::{CustomMeasurement, Page, TimeRange, User};
+use crate::numerism::{r32_from_decimal, r32_to_decimal};
+use crate::statistics::MeasurementStatistics;
+use crate::storage::Storage;
+
+
+/// The page or API endpoint of a custom measurement type at the given path, along with the name of
+/// the type.
+///
+/// The pages of a type are at `/custom-<name>`, `/edit-custom-<name>` and `/delete-custom-<name>`;
+/// the API endpoints at `/api/custom/<name>` and `/api/custom/<name>/statistics`.
+pub(crate) fn custom_endpoint(path: &str) -> Option<(MeasurementEndpoint, &str)> {
+    let (endpoint, name) = if let Some(name) = path.strip_prefix("/custom-") {
+        (MeasurementEndpoint::List, name)
+    } else if let Some(name) = path.strip_prefix("/edit-custom-") {
+        (MeasurementEndpoint::Edit, name)
+    } else if let Some(name) = path.strip_prefix("/delete-custom-") {
+        (MeasurementEndpoint::Delete, name)
+    } else if let Some(api_path) = path.strip_prefix("/api/custom/") {
+        match api_path.strip_suffix("/statistics") {
+            Some(name) => (MeasurementEndpoint::ApiStatistics, name),
+            None => (MeasurementEndpoint::Api, api_path),
+        }
+    } else {
+        return None;
+    };
+    if name.is_empty() || name.contains('/') {
+        None
+    } else {
+        Some((endpoint, name))
+    }
+}
+
+
+/// The fields of the measurement type, for calculating statistics.
+fn measurement_fields(measurement_type: &CustomMeasurementType) -> Vec<MeasurementField> {
+    measurement_type.fields.iter()
+        .map(|f| MeasurementField {
+            key: Cow::Owned(f.key.clone()),
+            class: Cow::Owned(f.key.clone()),
+            unit: Cow::Owned(f.unit.clone()),
+            decimal_places: f.decimal_places,
+        })
+        .collect()
+}
+
+pub(crate) fn calculate_statistics(measurement_type: &CustomMeasurementType, measurements: &[CustomMeasurement]) -> MeasurementStatistics {
+    let all_values: Vec<Vec<Option<Rational32>>> = measurements.iter()
+        .map(|m| {
+            measurement_type.fields.iter()
+                .map(|f| m.values.get(&f.key).copied())
+                .collect()
+        })
+        .collect();
+    MeasurementStatistics::calculate_values(&measurement_fields(measurement_type), &all_values)
+}
+
+/// Rounds the value to the given number of decimal places. Values that cannot be represented with
+/// that many decimal places are returned unchanged.
+fn round_value(value: Rational32, decimal_places: usize) -> Rational32 {
+    r32_from_decimal(&r32_to_decimal(value, decimal_places))
+        .unwrap_or(value)
+}
+
+/// Rounds the values of the measurement to the precision of their fields and checks them against
+/// the measurement type.
+pub(crate) fn validate(measurement_type: &CustomMeasurementType, measurement: &mut CustomMeasurement) -> Result<(), ClientError> {
+    check_timestamp_not_future("timestamp", measurement.timestamp)?;
+    for key in measurement.values.keys() {
+        if !measurement_type.fields.iter().any(|f| &f.key == key) {
+            return Err(ClientError::UnknownKey(key.clone()));
+        }
+    }
+    for field in &measurement_type.fields {
+        let value = match measurement.values.get_mut(&field.key) {
+            Some(v) => v,
+            None => {
+                if field.optional {
+                    continue;
+                }
+                return Err(ClientError::MissingValue(field.key.clone()));
+            },
+        };
+        *value = round_value(*value, field.decimal_places);
+        if let Some(min) = field.min {
+            if *value < min {
+                return Err(ClientError::RationalValueTooLow(field.key.clone(), *value, min));
+            }
+        }
+        if let Some(max) = field.max {
+            if *value > max {
+                return Err(ClientError::RationalValueTooHigh(field.key.clone(), *value, max));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads and validates a measurement entered into the form on the list or edit page.
+pub(crate) fn from_form(measurement_type: &CustomMeasurementType, req_kv: &HashMap<String, String>, default_timestamp: DateTime<Local>) -> Result<CustomMeasurement, ClientError> {
+    let timestamp = get_form_timestamp_not_future(req_kv, "timestamp")?
+        .unwrap_or(default_timestamp);
+    let mut values = BTreeMap::new();
+    for field in &measurement_type.fields {
+        if let Some(value) = get_form_r32(req_kv, &field.key)? {
+            values.insert(field.key.clone(), value);
+        }
+    }
+    let mut measurement = CustomMeasurement::new(-1, timestamp, values);
+    validate(measurement_type, &mut measurement)?;
+    Ok(measurement)
+}
+
+
+/// The custom measurement type with the given name, if it is configured.
+async fn get_measurement_type(name: &str) -> Option<CustomMeasurementType> {
+    CONFIG
+        .get().expect("config is set")
+        .read().await
+        .custom_measurement_type(name)
+        .cloned()
+}
+
+async fn respond_json_404_type(name: &str) -> Result<Response<Full<Bytes>>, Infallible> {
+    respond_json_error(404, "not-found", None, format!("no custom measurement type {:?}", name)).await
+}
+
+/// The configured custom measurement types.
+pub(crate) async fn custom_measurement_types() -> Vec<CustomMeasurementType> {
+    CONFIG
+        .get().expect("config is set")
+        .read().await
+        .custom_measurement_types
+        .clone()
+}
+
+/// Renders the overview of the custom measurement types whose measurements the token can read.
+pub(crate) async fn get_custom_types(token: &AuthToken) -> Result<Response<Full<Bytes>>, Infallible> {
+    if !token.can_read_any_custom() {
+        return respond_403_scope().await;
+    }
+
+    let measurement_types = custom_measurement_types().await
+        .into_iter()
+        .filter(|mt| token.can_read(mt.scope()))
+        .collect();
+    let template = CustomTypesTemplate {
+        token: token.clone(),
+        measurement_types,
+    };
+    respond_template(
+        &template,
+        200,
+        &HashMap::new(),
+    ).await
+}
+
+
+impl Record for CustomMeasurement {
+    fn id(&self) -> i64 { self.id }
+    fn set_id(&mut self, id: i64) { self.id = id; }
+    fn timestamp(&self) -> DateTime<Local> { self.timestamp }
+}
+
+#[async_trait]
+impl MeasurementType for CustomMeasurementType {
+    type Measurement = CustomMeasurement;
+    type Json = CustomMeasurement;
+
+    fn scope(&self) -> Scope { CustomMeasurementType::scope(self) }
+    fn api_page_noslash(&self) -> String { format!("api/custom/{}", self.name) }
+    fn list_page_noslash(&self) -> String { self.page() }
+    fn lookback_days(&self) -> i64 { DEFAULT_LOOKBACK_DAYS }
+
+    fn validate(&self, measurement: &mut CustomMeasurement) -> Result<(), ClientError> {
+        validate(self, measurement)
+    }
+
+    fn read_form(&self, req_kv: &HashMap<String, String>, default_timestamp: DateTime<Local>) -> Result<CustomMeasurement, ClientError> {
+        from_form(self, req_kv, default_timestamp)
+    }
+
+    fn to_json(&self, measurement: CustomMeasurement, _config: &Config) -> CustomMeasurement {
+        measurement
+    }
+
+    fn statistics(&self, measurements: &[CustomMeasurement]) -> MeasurementStatistics {
+        calculate_statistics(self, measurements)
+    }
+
+    async fn complete(&self, _storage: &dyn Storage, _user_id: i64, _measurements: &mut [CustomMeasurement]) -> Result<(), DatabaseError> {
+        Ok(())
+    }
+
+    async fn add(&self, storage: &dyn Storage, user_id: i64, measurement: &CustomMeasurement) -> Result<i64, DatabaseError> {
+        storage.add_custom_measurement(user_id, &self.name, measurement).await
+    }
+
+    async fn update(&self, storage: &dyn Storage, user_id: i64, measurement: &CustomMeasurement) -> Result<(), DatabaseError> {
+        storage.update_custom_measurement(user_id, &self.name, measurement).await
+    }
+
+    async fn remove(&self, storage: &dyn Storage, user_id: i64, measurement_id: i64) -> Result<(), DatabaseError> {
+        storage.remove_custom_measurement(user_id, &self.name, measurement_id).await
+    }
+
+    async fn get(&self, storage: &dyn Storage, user_id: i64, measurement_id: i64) -> Result<Option<CustomMeasurement>, DatabaseError> {
+        storage.get_custom_measurement(user_id, &self.name, measurement_id).await
+    }
+
+    async fn get_range(&self, storage: &dyn Storage, user_id: i64, range: &TimeRange, page: &Page) -> Result<Vec<CustomMeasurement>, DatabaseError> {
+        storage.get_custom_measurements(user_id, &self.name, range, page).await
+    }
+
+    async fn check_alerts(&self, _user: &User, _new_measurement: &CustomMeasurement) {
+        // alert rules only apply to the values of the built-in types
+    }
+
+    async fn respond_list(
+        &self,
+        token: &AuthToken,
+        range: TimeRange,
+        mut measurements: Vec<CustomMeasurement>,
+        statistics: Option<MeasurementStatistics>,
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        measurements.reverse();
+        let template = CustomListTemplate {
+            token: token.clone(),
+            range,
+            measurement_type: self.clone(),
+            measurements,
+            statistics,
+        };
+        respond_template(
+            &template,
+            200,
+            &HashMap::new(),
+        ).await
+    }
+
+    async fn respond_edit(&self, measurement: CustomMeasurement) -> Result<Response<Full<Bytes>>, Infallible> {
+        let template = CustomEditTemplate {
+            measurement_type: self.clone(),
+            measurement,
+        };
+        respond_template(
+            &template,
+            200,
+            &HashMap::new(),
+        ).await
+    }
+}
+
+/// Handles a request to one of the pages or API endpoints of the custom measurement type with the
+/// given name.
+pub(crate) async fn handle_custom_request<B>(
+    endpoint: MeasurementEndpoint,
+    type_name: &str,
+    req: Request<B>,
+    token: &AuthToken,
+    user: &User,
+    query_kv: &HashMap<String, String>,
+) -> Result<Response<Full<Bytes>>, Infallible>
+    where B: Body, B::Error: fmt::Display {
+    let measurement_type = match get_measurement_type(type_name).await {
+        Some(mt) => mt,
+        None => {
+            return match endpoint {
+                MeasurementEndpoint::Api|MeasurementEndpoint::ApiStatistics => respond_json_404_type(type_name).await,
+                MeasurementEndpoint::List|MeasurementEndpoint::Edit|MeasurementEndpoint::Delete => respond_404().await,
+            };
+        },
+    };
+
+    handle_measurement_request(&measurement_type, endpoint, req, token, user, query_kv).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    use crate::config::CustomField;
+
+    fn peak_flow() -> CustomMeasurementType {
+        CustomMeasurementType {
+            name: "peak-flow".to_owned(),
+            title: None,
+            fields: vec![
+                CustomField {
+                    key: "pef".to_owned(),
+                    title: None,
+                    unit: "l/min".to_owned(),
+                    min: Some(Rational32::from_integer(50)),
+                    max: Some(Rational32::from_integer(900)),
+                    decimal_places: 0,
+                    optional: false,
+                },
+                CustomField {
+                    key: "variability".to_owned(),
+                    title: None,
+                    unit: "%".to_owned(),
+                    min: None,
+                    max: None,
+                    decimal_places: 1,
+                    optional: true,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn endpoints_by_path() {
+        assert_eq!(custom_endpoint("/custom-peak-flow"), Some((MeasurementEndpoint::List, "peak-flow")));
+        assert_eq!(custom_endpoint("/edit-custom-inr"), Some((MeasurementEndpoint::Edit, "inr")));
+        assert_eq!(custom_endpoint("/delete-custom-inr"), Some((MeasurementEndpoint::Delete, "inr")));
+        assert_eq!(custom_endpoint("/api/custom/inr"), Some((MeasurementEndpoint::Api, "inr")));
+        assert_eq!(custom_endpoint("/api/custom/inr/statistics"), Some((MeasurementEndpoint::ApiStatistics, "inr")));
+        assert_eq!(custom_endpoint("/api/custom/inr/other"), None);
+        assert_eq!(custom_endpoint("/custom"), None);
+        assert_eq!(custom_endpoint("/custom-"), None);
+        assert_eq!(custom_endpoint("/edit-bp"), None);
+    }
+
+    #[test]
+    fn values_validated_and_rounded() {
+        let measurement_type = peak_flow();
+        let timestamp = Local.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap();
+        let measurement = |values: &[(&str, Rational32)]| {
+            let values = values.iter()
+                .map(|(k, v)| ((*k).to_owned(), *v))
+                .collect();
+            CustomMeasurement::new(-1, timestamp, values)
+        };
+
+        let mut valid = measurement(&[("pef", Rational32::new(9001, 20)), ("variability", Rational32::new(1234, 100))]);
+        validate(&measurement_type, &mut valid).unwrap();
+        assert_eq!(valid.values["pef"], Rational32::from_integer(450));
+        assert_eq!(valid.values["variability"], Rational32::new(123, 10));
+        validate(&measurement_type, &mut measurement(&[("pef", Rational32::from_integer(50))])).unwrap();
+
+        match validate(&measurement_type, &mut measurement(&[("pef", Rational32::from_integer(901))])) {
+            Err(ClientError::RationalValueTooHigh(key, _, _)) => assert_eq!(key, "pef"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match validate(&measurement_type, &mut measurement(&[("pef", Rational32::from_integer(49))])) {
+            Err(ClientError::RationalValueTooLow(key, _, _)) => assert_eq!(key, "pef"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match validate(&measurement_type, &mut measurement(&[("variability", Rational32::from_integer(5))])) {
+            Err(ClientError::MissingValue(key)) => assert_eq!(key, "pef"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match validate(&measurement_type, &mut measurement(&[("pef", Rational32::from_integer(400)), ("fev1", Rational32::from_integer(3))])) {
+            Err(ClientError::UnknownKey(key)) => assert_eq!(key, "fev1"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn statistics_of_fields() {
+        let measurement_type = peak_flow();
+        let timestamp = Local.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap();
+        let measurements: Vec<CustomMeasurement> = [400, 500].iter()
+            .map(|pef| {
+                let values = vec![("pef".to_owned(), Rational32::from_integer(*pef))].into_iter().collect();
+                CustomMeasurement::new(-1, timestamp, values)
+            })
+            .collect();
+        let statistics = calculate_statistics(&measurement_type, &measurements);
+        assert_eq!(statistics.count, 2);
+        assert_eq!(statistics.fields[0].1.unwrap().median, 450.0);
+        assert_eq!(statistics.fields[1].1, None);
+        assert_eq!(statistics.rows()[1].cells[0], (String::from("pef"), String::from("500")));
+    }
+}
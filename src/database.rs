@@ -1,48 +1,237 @@
-use chrono::{Duration, Local};
-use log::error;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use deadpool_postgres::{
+    BuildError, GenericClient, Manager, ManagerConfig, Object, Pool, PoolError, RecyclingMethod,
+    Runtime,
+};
 use num_rational::Rational32;
-use tokio;
-use tokio_postgres::{self, Client, NoTls};
+use tokio::task::JoinError;
+use tokio_postgres::{self, NoTls};
+use tokio_postgres::types::ToSql;
 
-use crate::config::CONFIG;
+use crate::config::{Config, DbSslMode, Scopes};
+use crate::migrations::{MigrationError, check_postgres_schema_version, run_postgres_migrations};
 use crate::model::{
-    BloodPressureMeasurement, BloodSugarMeasurement, BodyMassMeasurement, BodyTemperatureLocation,
-    BodyTemperatureMeasurement, LongTermBloodSugarMeasurement,
+    BodyHeight, BodyTemperatureLocation, CustomMeasurement, Page, Profile, Session, ShareToken,
+    TimeRange, User,
 };
 use crate::numerism::r32_from_decimal;
+use crate::storage::{ColumnType, ColumnValue, MeasurementRow, MeasurementTable, Storage};
+use crate::tls::{TlsSetupError, apply_ssl_mode, make_tls_connect};
+
+
+#[derive(Debug)]
+pub(crate) enum DatabaseError {
+    MissingConfigValue(&'static str),
+    ParsingConnString(tokio_postgres::Error),
+    SettingUpTls(TlsSetupError),
+    BuildingPool(BuildError),
+    Pool(PoolError),
+    Postgres(tokio_postgres::Error),
+    Sqlite(rusqlite::Error),
+    SqliteTask(JoinError),
+    /// A value of a measurement row is missing or of an unexpected type or size.
+    InvalidColumnValue(usize),
+}
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::MissingConfigValue(key)
+                => write!(f, "configuration value {:?} is required for the selected storage backend", key),
+            DatabaseError::ParsingConnString(e)
+                => write!(f, "error parsing database connection string: {}", e),
+            DatabaseError::SettingUpTls(e)
+                => write!(f, "error setting up TLS for database connections: {}", e),
+            DatabaseError::BuildingPool(e)
+                => write!(f, "error building database connection pool: {}", e),
+            DatabaseError::Pool(e)
+                => write!(f, "error obtaining database connection from pool: {}", e),
+            DatabaseError::Postgres(e)
+                => write!(f, "database error: {}", e),
+            DatabaseError::Sqlite(e)
+                => write!(f, "SQLite error: {}", e),
+            DatabaseError::SqliteTask(e)
+                => write!(f, "SQLite task failed: {}", e),
+            DatabaseError::InvalidColumnValue(index)
+                => write!(f, "value {} of a measurement row is missing or invalid", index),
+        }
+    }
+}
+impl Error for DatabaseError {
+}
+impl From<PoolError> for DatabaseError {
+    fn from(e: PoolError) -> Self {
+        DatabaseError::Pool(e)
+    }
+}
+impl From<tokio_postgres::Error> for DatabaseError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        DatabaseError::Postgres(e)
+    }
+}
+impl From<rusqlite::Error> for DatabaseError {
+    fn from(e: rusqlite::Error) -> Self {
+        DatabaseError::Sqlite(e)
+    }
+}
+impl From<JoinError> for DatabaseError {
+    fn from(e: JoinError) -> Self {
+        DatabaseError::SqliteTask(e)
+    }
+}
+
+
+pub(crate) struct PostgresStorage {
+    pool: Pool,
+}
+impl PostgresStorage {
+    /// Sets up the database connection pool according to the configuration.
+    pub fn new(config: &Config) -> Result<Self, DatabaseError> {
+        let conn_string = config.db_conn_string.as_ref()
+            .ok_or(DatabaseError::MissingConfigValue("db_conn_string"))?;
+        let mut pg_config: tokio_postgres::Config = conn_string.parse()
+            .map_err(DatabaseError::ParsingConnString)?;
+        let ssl_mode = apply_ssl_mode(&config.db_tls, &mut pg_config);
+        let manager_config = ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        };
+        let manager = if ssl_mode == DbSslMode::Disable {
+            Manager::from_config(pg_config, NoTls, manager_config)
+        } else {
+            let tls_connect = make_tls_connect(&config.db_tls, ssl_mode)
+                .map_err(DatabaseError::SettingUpTls)?;
+            Manager::from_config(pg_config, tls_connect, manager_config)
+        };
+
+        let pool_config = &config.db_pool;
+        let pool = Pool::builder(manager)
+            .max_size(pool_config.max_size)
+            .wait_timeout(pool_config.wait_timeout_ms.map(Duration::from_millis))
+            .create_timeout(pool_config.create_timeout_ms.map(Duration::from_millis))
+            .recycle_timeout(pool_config.recycle_timeout_ms.map(Duration::from_millis))
+            .runtime(Runtime::Tokio1)
+            .build()
+            .map_err(DatabaseError::BuildingPool)?;
+
+        Ok(Self {
+            pool,
+        })
+    }
 
+    async fn connect(&self) -> Result<Object, DatabaseError> {
+        let client = self.pool
+            .get().await?;
+        Ok(client)
+    }
+}
 
-async fn get_conn_string() -> String {
-    CONFIG
-        .get().expect("config not set")
-        .read().await
-        .db_conn_string
-        .clone()
+
+/// Reads a share token from the columns starting at the given index.
+fn share_token_from_row(row: &tokio_postgres::Row, first_index: usize) -> ShareToken {
+    let scopes_string: String = row.get(first_index + 3);
+    ShareToken {
+        id: row.get(first_index),
+        token_sha256: row.get(first_index + 1),
+        description: row.get(first_index + 2),
+        scopes: Scopes::from_names_string(&scopes_string)
+            .expect("parsing scopes failed"),
+        range: TimeRange::new(row.get(first_index + 4), row.get(first_index + 5)),
+        created: row.get(first_index + 6),
+        expires: row.get(first_index + 7),
+        revoked: row.get(first_index + 8),
+    }
 }
 
-async fn connect() -> Result<Client, tokio_postgres::Error> {
-    let conn_string = get_conn_string()
-        .await;
+/// The columns of the measurement table to select; decimals are read as strings to keep them exact.
+fn measurement_select_columns(table: &MeasurementTable) -> String {
+    let mut columns = String::from("id, \"timestamp\"");
+    for column in table.columns {
+        match column.column_type {
+            ColumnType::Integer => columns.push_str(&format!(", CAST({0} AS bigint) {0}", column.name)),
+            ColumnType::Decimal => columns.push_str(&format!(", CAST({0} AS character varying(128)) {0}", column.name)),
+        }
+    }
+    columns
+}
 
-    let (client, connection) = tokio_postgres::connect(&conn_string, NoTls)
-        .await?;
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            error!("connection error: {}", e);
+/// The SQL expressions for the values of a measurement row, with parameters numbered from
+/// `first_param`, along with the parameters. Decimals are passed as numerator and denominator.
+fn measurement_value_params(table: &MeasurementTable, row: &MeasurementRow, first_param: usize) -> (Vec<String>, Vec<Option<i64>>) {
+    let mut expressions = Vec::with_capacity(table.columns.len());
+    let mut params = Vec::new();
+    for (column, value) in table.columns.iter().zip(&row.values) {
+        let param = first_param + params.len();
+        match column.column_type {
+            ColumnType::Integer => {
+                expressions.push(format!("CAST(${} AS bigint)", param));
+                params.push(match value {
+                    Some(ColumnValue::Integer(i)) => Some(*i),
+                    _ => None,
+                });
+            },
+            ColumnType::Decimal => {
+                expressions.push(format!("(CAST(CAST(${} AS bigint) AS numeric) / CAST(CAST(${} AS bigint) AS numeric))", param, param + 1));
+                let (numer, denom) = match value {
+                    Some(ColumnValue::Decimal(d)) => (Some(i64::from(*d.numer())), Some(i64::from(*d.denom()))),
+                    _ => (None, None),
+                };
+                params.push(numer);
+                params.push(denom);
+            },
         }
-    });
+    }
+    (expressions, params)
+}
 
-    Ok(client)
+/// Reads a measurement row selected with the columns from `measurement_select_columns`.
+fn measurement_row_from_row(table: &MeasurementTable, row: &tokio_postgres::Row) -> Result<MeasurementRow, DatabaseError> {
+    let mut values = Vec::with_capacity(table.columns.len());
+    for (index, column) in table.columns.iter().enumerate() {
+        let value = match column.column_type {
+            ColumnType::Integer => {
+                let integer: Option<i64> = row.get(index + 2);
+                integer.map(ColumnValue::Integer)
+            },
+            ColumnType::Decimal => {
+                let decimal_string: Option<String> = row.get(index + 2);
+                decimal_string
+                    .map(|ds| r32_from_decimal(&ds)
+                        .map(ColumnValue::Decimal)
+                        .map_err(|_| DatabaseError::InvalidColumnValue(index)))
+                    .transpose()?
+            },
+        };
+        values.push(value);
+    }
+    Ok(MeasurementRow {
+        id: row.get(0),
+        timestamp: row.get(1),
+        values,
+    })
 }
 
-pub(crate) async fn add_blood_pressure_measurement(measurement: &BloodPressureMeasurement) -> Result<i64, tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+async fn insert_measurement<C: GenericClient>(client: &C, user_id: i64, table: &MeasurementTable, measurement: &MeasurementRow) -> Result<i64, DatabaseError> {
+    let (value_expressions, value_params) = measurement_value_params(table, measurement, 2);
+    let column_names: Vec<&str> = table.columns.iter()
+        .map(|c| c.name)
+        .collect();
+    let query = format!(
+        "INSERT INTO beepee.{} (\"timestamp\", {}, user_id) VALUES ($1, {}, ${}) RETURNING id",
+        table.name, column_names.join(", "), value_expressions.join(", "), value_params.len() + 2,
+    );
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![&measurement.timestamp];
+    params.extend(value_params.iter().map(|p| p as &(dyn ToSql + Sync)));
+    params.push(&user_id);
 
     let row = client
         .query_one(
-            "INSERT INTO beepee.measurements (\"timestamp\", systolic_mmhg, diastolic_mmhg, pulse_bpm, spo2_percent) VALUES ($1, $2, $3, $4, $5) RETURNING id",
-            &[&measurement.timestamp, &measurement.systolic_mmhg, &measurement.diastolic_mmhg, &measurement.pulse_bpm, &measurement.spo2_percent],
+            &client.prepare_cached(&query).await?,
+            &params,
         )
         .await?;
     let measurement_id: i64 = row.get(0);
@@ -50,441 +239,557 @@ pub(crate) async fn add_blood_pressure_measurement(measurement: &BloodPressureMe
     Ok(measurement_id)
 }
 
-pub(crate) async fn remove_blood_pressure_measurement(measurement_id: i64) -> Result<(), tokio_postgres::Error> {
-    let client = connect()
-        .await?;
-
-    client
-        .execute(
-            "DELETE FROM beepee.measurements WHERE id = $1",
-            &[&measurement_id],
-        )
-        .await?;
-
+async fn insert_custom_values<C: GenericClient>(client: &C, measurement_id: i64, values: &BTreeMap<String, Rational32>) -> Result<(), DatabaseError> {
+    let statement = client.prepare_cached("INSERT INTO beepee.custom_measurement_values (measurement_id, field_key, \"value\") VALUES ($1, $2, (CAST(CAST($3 AS int) AS numeric) / CAST(CAST($4 AS int) AS numeric)))").await?;
+    for (field_key, value) in values {
+        client
+            .execute(
+                &statement,
+                &[&measurement_id, field_key, &value.numer(), &value.denom()],
+            )
+            .await?;
+    }
     Ok(())
 }
 
-pub(crate) async fn update_blood_pressure_measurement(measurement: &BloodPressureMeasurement) -> Result<(), tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+/// Assembles custom measurements from the rows of their values.
+fn custom_measurements_from_rows(rows: Vec<tokio_postgres::Row>) -> Vec<CustomMeasurement> {
+    let value_rows = rows.into_iter()
+        .map(|row| {
+            let field_key: Option<String> = row.get(2);
+            let value_string: Option<String> = row.get(3);
+            let value = value_string.map(|vs| r32_from_decimal(&vs)
+                .expect("parsing custom value failed"));
+            (row.get(0), row.get(1), field_key.zip(value))
+        });
+    CustomMeasurement::from_value_rows(value_rows)
+}
 
-    client
-        .execute(
-            "UPDATE beepee.measurements SET \"timestamp\"=$1, systolic_mmhg=$2, diastolic_mmhg=$3, pulse_bpm=$4, spo2_percent=$5 WHERE id=$6",
-            &[&measurement.timestamp, &measurement.systolic_mmhg, &measurement.diastolic_mmhg, &measurement.pulse_bpm, &measurement.spo2_percent, &measurement.id],
-        )
-        .await?;
 
-    Ok(())
-}
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn run_migrations(&self) -> Result<Vec<i32>, MigrationError> {
+        let mut client = self.connect()
+            .await?;
 
-pub(crate) async fn get_recent_blood_pressure_measurements(ago: Duration) -> Result<Vec<BloodPressureMeasurement>, tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        run_postgres_migrations(&mut client)
+            .await
+    }
 
-    let start_time = Local::now() - ago;
+    async fn check_schema_version(&self) -> Result<(), MigrationError> {
+        let client = self.connect()
+            .await?;
 
-    let rows = client
-        .query(
-            "SELECT id, \"timestamp\", systolic_mmhg, diastolic_mmhg, pulse_bpm, spo2_percent FROM beepee.measurements WHERE \"timestamp\" >= $1 ORDER BY \"timestamp\"",
-            &[&start_time],
-        )
-        .await?;
-    let mut ret = Vec::new();
-    for row in rows {
-        ret.push(BloodPressureMeasurement::new(
+        check_postgres_schema_version(&client)
+            .await
+    }
+
+    async fn get_or_add_user(&self, name: &str) -> Result<User, DatabaseError> {
+        let client = self.connect()
+            .await?;
+
+        let select_statement = client.prepare_cached("SELECT id, \"name\" FROM beepee.users WHERE \"name\" = $1").await?;
+        let mut row_opt = client
+            .query_opt(&select_statement, &[&name])
+            .await?;
+        if row_opt.is_none() {
+            // another request might be adding the same user concurrently
+            client
+                .execute(
+                    &client.prepare_cached("INSERT INTO beepee.users (\"name\") VALUES ($1) ON CONFLICT (\"name\") DO NOTHING").await?,
+                    &[&name],
+                )
+                .await?;
+            row_opt = client
+                .query_opt(&select_statement, &[&name])
+                .await?;
+        }
+        let row = row_opt
+            .expect("user missing after insertion");
+
+        Ok(User::new(
             row.get(0),
             row.get(1),
-            row.get(2),
-            row.get(3),
-            row.get(4),
-            row.get(5),
-        ));
+        ))
     }
 
-    Ok(ret)
-}
+    async fn get_profile(&self, user_id: i64) -> Result<Profile, DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-pub(crate) async fn add_mass_measurement(measurement: &BodyMassMeasurement) -> Result<i64, tokio_postgres::Error> {
-    let client = connect()
-        .await?;
-
-    let row = if let Some(circum) = &measurement.waist_circum_cm {
-        client
+        let user_row = client
             .query_one(
-                "INSERT INTO beepee.mass_measurements (\"timestamp\", mass_kg, waist_circum_cm) VALUES ($1, (CAST(CAST($2 AS int) AS numeric(6, 2)) / CAST(CAST($3 AS int) AS numeric(6, 2))), (CAST(CAST($4 AS int) AS numeric(6, 2)) / CAST(CAST($5 AS int) AS numeric(6, 2)))) RETURNING id",
-                &[&measurement.timestamp, &measurement.mass_kg.numer(), &measurement.mass_kg.denom(), &circum.numer(), &circum.denom()],
+                &client.prepare_cached("SELECT birth_date, sex FROM beepee.users WHERE id = $1").await?,
+                &[&user_id],
             )
-            .await?
-    } else {
-        client
-            .query_one(
-                "INSERT INTO beepee.mass_measurements (\"timestamp\", mass_kg, waist_circum_cm) VALUES ($1, (CAST(CAST($2 AS int) AS numeric(6, 2)) / CAST(CAST($3 AS int) AS numeric(6, 2))), NULL) RETURNING id",
-                &[&measurement.timestamp, &measurement.mass_kg.numer(), &measurement.mass_kg.denom()],
+            .await?;
+        let sex_string: Option<String> = user_row.get(1);
+        let sex = sex_string.map(|s|
+            s.parse()
+                .expect("parsing sex failed")
+        );
+
+        let height_rows = client
+            .query(
+                &client.prepare_cached("SELECT id, effective_date, height_cm FROM beepee.body_heights WHERE user_id = $1 ORDER BY effective_date").await?,
+                &[&user_id],
             )
-            .await?
-    };
-    let measurement_id: i64 = row.get(0);
+            .await?;
+        let mut heights = Vec::new();
+        for row in height_rows {
+            heights.push(BodyHeight::new(
+                row.get(0),
+                row.get(1),
+                row.get(2),
+            ));
+        }
 
-    Ok(measurement_id)
-}
+        Ok(Profile {
+            birth_date: user_row.get(0),
+            sex,
+            heights,
+        })
+    }
 
-pub(crate) async fn remove_mass_measurement(measurement_id: i64) -> Result<(), tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+    async fn update_profile(&self, user_id: i64, profile: &Profile) -> Result<(), DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-    client
-        .execute(
-            "DELETE FROM beepee.mass_measurements WHERE id = $1",
-            &[&measurement_id],
-        )
-        .await?;
+        client
+            .execute(
+                &client.prepare_cached("UPDATE beepee.users SET birth_date=$1, sex=$2 WHERE id=$3").await?,
+                &[&profile.birth_date, &profile.sex.map(|s| s.as_str()), &user_id],
+            )
+            .await?;
 
-    Ok(())
-}
+        Ok(())
+    }
 
-pub(crate) async fn update_mass_measurement(measurement: &BodyMassMeasurement) -> Result<(), tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+    async fn set_height(&self, user_id: i64, height: &BodyHeight) -> Result<i64, DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-    if let Some(circum) = &measurement.waist_circum_cm {
-        client
-            .execute(
-                "UPDATE beepee.mass_measurements SET \"timestamp\"=$1, mass_kg=(CAST(CAST($2 AS int) AS numeric(6, 2)) / CAST(CAST($3 AS int) AS numeric(6, 2))), waist_circum_cm=(CAST(CAST($4 AS int) AS numeric(6, 2)) / CAST(CAST($5 AS int) AS numeric(6, 2))) WHERE id=$6",
-                &[&measurement.timestamp, &measurement.mass_kg.numer(), &measurement.mass_kg.denom(), &circum.numer(), &circum.denom(), &measurement.id],
+        let row = client
+            .query_one(
+                &client.prepare_cached("INSERT INTO beepee.body_heights (user_id, effective_date, height_cm) VALUES ($1, $2, $3) ON CONFLICT (user_id, effective_date) DO UPDATE SET height_cm = EXCLUDED.height_cm RETURNING id").await?,
+                &[&user_id, &height.effective_date, &height.height_cm],
             )
-            .await?
-    } else {
+            .await?;
+        let height_id: i64 = row.get(0);
+
+        Ok(height_id)
+    }
+
+    async fn remove_height(&self, user_id: i64, height_id: i64) -> Result<(), DatabaseError> {
+        let client = self.connect()
+            .await?;
+
         client
             .execute(
-                "UPDATE beepee.mass_measurements SET \"timestamp\"=$1, mass_kg=(CAST(CAST($2 AS int) AS numeric(6, 2)) / CAST(CAST($3 AS int) AS numeric(6, 2))), waist_circum_cm=NULL WHERE id=$4",
-                &[&measurement.timestamp, &measurement.mass_kg.numer(), &measurement.mass_kg.denom(), &measurement.id],
+                &client.prepare_cached("DELETE FROM beepee.body_heights WHERE id = $1 AND user_id = $2").await?,
+                &[&height_id, &user_id],
             )
-            .await?
-    };
+            .await?;
 
-    Ok(())
-}
+        Ok(())
+    }
 
-pub(crate) async fn get_recent_mass_measurements(ago: Duration) -> Result<Vec<BodyMassMeasurement>, tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+    async fn add_share_token(&self, user_id: i64, share_token: &ShareToken) -> Result<i64, DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-    let height_cm: Option<i32> = {
-        let config_guard = CONFIG
-            .get().expect("initial config not set")
-            .read().await;
-        config_guard.height_cm
-    };
-    let height_m = height_cm
-        .map(|h| Rational32::new(h, 100));
-    let square_height_m2 = height_m
-        .map(|h| h * h);
-
-    let start_time = Local::now() - ago;
-
-    let rows = client
-        .query(
-            "SELECT id, \"timestamp\", CAST(mass_kg AS character varying(128)) mass_kg, CAST(waist_circum_cm AS character varying(128)) waist_circum_cm FROM beepee.mass_measurements WHERE \"timestamp\" >= $1 ORDER BY \"timestamp\"",
-            &[&start_time],
-        )
-        .await?;
-    let mut ret = Vec::new();
-    for row in rows {
-        let mass_string: String = row.get(2);
-        let mass_kg: Rational32 = r32_from_decimal(&mass_string)
-            .expect("parsing mass failed");
-        let circum_string: Option<String> = row.get(3);
-        let circum_cm: Option<Rational32> = circum_string.map(|s|
-            r32_from_decimal(&s)
-                .expect("parsing circumference failed")
-        );
-        let bmi: Option<Rational32> = square_height_m2.map(|sqh|
-            mass_kg / sqh
-        );
-        ret.push(BodyMassMeasurement::new(
-            row.get(0),
-            row.get(1),
-            mass_kg,
-            circum_cm,
-            bmi,
-        ));
+        let row = client
+            .query_one(
+                &client.prepare_cached("INSERT INTO beepee.share_tokens (token_sha256, description, scopes, range_start, range_end, created_at, expires_at, revoked_at, user_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id").await?,
+                &[
+                    &share_token.token_sha256, &share_token.description, &share_token.scopes.to_names_string(),
+                    &share_token.range.start, &share_token.range.end, &share_token.created,
+                    &share_token.expires, &share_token.revoked, &user_id,
+                ],
+            )
+            .await?;
+        let share_token_id: i64 = row.get(0);
+
+        Ok(share_token_id)
     }
 
-    Ok(ret)
-}
+    async fn get_share_tokens(&self, user_id: i64) -> Result<Vec<ShareToken>, DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-pub(crate) async fn add_temperature_location(loc: &BodyTemperatureLocation) -> Result<i64, tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        let rows = client
+            .query(
+                &client.prepare_cached("SELECT id, token_sha256, description, scopes, range_start, range_end, created_at, expires_at, revoked_at FROM beepee.share_tokens WHERE user_id = $1 ORDER BY created_at DESC, id DESC").await?,
+                &[&user_id],
+            )
+            .await?;
+        let mut share_tokens = Vec::new();
+        for row in rows {
+            share_tokens.push(share_token_from_row(&row, 0));
+        }
 
-    let row = client
-        .query_one(
-            "INSERT INTO beepee.body_temperature_locations (\"name\") VALUES ($1) RETURNING id",
-            &[&loc.name],
-        )
-        .await?;
-    let loc_id: i64 = row.get(0);
+        Ok(share_tokens)
+    }
 
-    Ok(loc_id)
-}
+    async fn get_share_token_by_hash(&self, token_sha256: &str) -> Result<Option<(User, ShareToken)>, DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-pub(crate) async fn remove_temperature_location(loc_id: i64) -> Result<(), tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        let row_opt = client
+            .query_opt(
+                &client.prepare_cached("SELECT u.id, u.\"name\", st.id, st.token_sha256, st.description, st.scopes, st.range_start, st.range_end, st.created_at, st.expires_at, st.revoked_at FROM beepee.share_tokens st INNER JOIN beepee.users u ON u.id = st.user_id WHERE st.token_sha256 = $1").await?,
+                &[&token_sha256],
+            )
+            .await?;
+        let user_and_share_token = row_opt.map(|row| (
+            User::new(
+                row.get(0),
+                row.get(1),
+            ),
+            share_token_from_row(&row, 2),
+        ));
 
-    client
-        .execute(
-            "DELETE FROM beepee.body_temperature_locations WHERE id = $1",
-            &[&loc_id],
-        )
-        .await?;
+        Ok(user_and_share_token)
+    }
 
-    Ok(())
-}
+    async fn revoke_share_token(&self, user_id: i64, share_token_id: i64, revoked: DateTime<Local>) -> Result<(), DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-pub(crate) async fn update_temperature_location(loc: &BodyTemperatureLocation) -> Result<(), tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        client
+            .execute(
+                &client.prepare_cached("UPDATE beepee.share_tokens SET revoked_at=$1 WHERE id=$2 AND user_id=$3 AND revoked_at IS NULL").await?,
+                &[&revoked, &share_token_id, &user_id],
+            )
+            .await?;
 
-    client
-        .execute(
-            "UPDATE beepee.body_temperature_locations SET \"name\"=$1 WHERE id=$2",
-            &[&loc.name, &loc.id],
-        )
-        .await?;
+        Ok(())
+    }
 
-    Ok(())
-}
+    async fn add_session(&self, user_id: i64, session: &Session) -> Result<i64, DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-pub(crate) async fn get_temperature_locations() -> Result<Vec<BodyTemperatureLocation>, tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        let row = client
+            .query_one(
+                &client.prepare_cached("INSERT INTO beepee.sessions (session_sha256, token_key, created_at, expires_at, user_id) VALUES ($1, $2, $3, $4, $5) RETURNING id").await?,
+                &[&session.session_sha256, &session.token_key, &session.created, &session.expires, &user_id],
+            )
+            .await?;
+        let session_id: i64 = row.get(0);
 
-    let rows = client
-        .query(
-            "SELECT id, \"name\" FROM beepee.body_temperature_locations ORDER BY \"name\"",
-            &[],
-        )
-        .await?;
-    let mut ret = Vec::new();
-    for row in rows {
-        ret.push(BodyTemperatureLocation::new(
-            row.get(0),
-            row.get(1),
+        Ok(session_id)
+    }
+
+    async fn get_session_by_hash(&self, session_sha256: &str) -> Result<Option<(User, Session)>, DatabaseError> {
+        let client = self.connect()
+            .await?;
+
+        let row_opt = client
+            .query_opt(
+                &client.prepare_cached("SELECT u.id, u.\"name\", s.id, s.session_sha256, s.token_key, s.created_at, s.expires_at FROM beepee.sessions s INNER JOIN beepee.users u ON u.id = s.user_id WHERE s.session_sha256 = $1").await?,
+                &[&session_sha256],
+            )
+            .await?;
+        let user_and_session = row_opt.map(|row| (
+            User::new(
+                row.get(0),
+                row.get(1),
+            ),
+            Session {
+                id: row.get(2),
+                session_sha256: row.get(3),
+                token_key: row.get(4),
+                created: row.get(5),
+                expires: row.get(6),
+            },
         ));
+
+        Ok(user_and_session)
     }
 
-    Ok(ret)
-}
+    async fn remove_session(&self, session_sha256: &str) -> Result<(), DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-pub(crate) async fn add_temperature_measurement(measurement: &BodyTemperatureMeasurement) -> Result<i64, tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        client
+            .execute(
+                &client.prepare_cached("DELETE FROM beepee.sessions WHERE session_sha256 = $1").await?,
+                &[&session_sha256],
+            )
+            .await?;
 
-    let row = client
-        .query_one(
-            "INSERT INTO beepee.body_temperature_measurements (\"timestamp\", location_id, temperature_celsius) VALUES ($1, $2, (CAST(CAST($3 AS int) AS numeric(6, 2)) / CAST(CAST($4 AS int) AS numeric(6, 2)))) RETURNING id",
-            &[&measurement.timestamp, &measurement.location_id, &measurement.temperature_celsius.numer(), &measurement.temperature_celsius.denom()],
-        )
-        .await?;
-    let measurement_id: i64 = row.get(0);
+        Ok(())
+    }
 
-    Ok(measurement_id)
-}
+    async fn remove_expired_sessions(&self, now: DateTime<Local>) -> Result<(), DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-pub(crate) async fn remove_temperature_measurement(measurement_id: i64) -> Result<(), tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        client
+            .execute(
+                &client.prepare_cached("DELETE FROM beepee.sessions WHERE expires_at <= $1").await?,
+                &[&now],
+            )
+            .await?;
 
-    client
-        .execute(
-            "DELETE FROM beepee.body_temperature_measurements WHERE id = $1",
-            &[&measurement_id],
-        )
-        .await?;
+        Ok(())
+    }
 
-    Ok(())
-}
+    async fn add_measurements(&self, user_id: i64, table: &MeasurementTable, rows: &[MeasurementRow]) -> Result<Vec<i64>, DatabaseError> {
+        let mut client = self.connect()
+            .await?;
+        let transaction = client
+            .transaction().await?;
+
+        let mut measurement_ids = Vec::with_capacity(rows.len());
+        for row in rows {
+            let measurement_id = insert_measurement(&transaction, user_id, table, row)
+                .await?;
+            measurement_ids.push(measurement_id);
+        }
 
-pub(crate) async fn update_temperature_measurement(measurement: &BodyTemperatureMeasurement) -> Result<(), tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        transaction
+            .commit().await?;
+        Ok(measurement_ids)
+    }
 
-    client
-        .execute(
-            "UPDATE beepee.body_temperature_measurements SET \"timestamp\"=$1, location_id=$2, temperature_celsius=(CAST(CAST($3 AS int) AS numeric(6, 2)) / CAST(CAST($4 AS int) AS numeric(6, 2))) WHERE id=$5",
-            &[&measurement.timestamp, &measurement.location_id, &measurement.temperature_celsius.numer(), &measurement.temperature_celsius.denom(), &measurement.id],
-        )
-        .await?;
+    async fn remove_measurement(&self, user_id: i64, table: &MeasurementTable, measurement_id: i64) -> Result<(), DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-    Ok(())
-}
+        let query = format!("DELETE FROM beepee.{} WHERE id = $1 AND user_id = $2", table.name);
+        client
+            .execute(
+                &client.prepare_cached(&query).await?,
+                &[&measurement_id, &user_id],
+            )
+            .await?;
 
-pub(crate) async fn get_recent_temperature_measurements(ago: Duration) -> Result<Vec<BodyTemperatureMeasurement>, tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        Ok(())
+    }
 
-    let start_time = Local::now() - ago;
+    async fn update_measurement(&self, user_id: i64, table: &MeasurementTable, row: &MeasurementRow) -> Result<(), DatabaseError> {
+        let client = self.connect()
+            .await?;
+
+        let (value_expressions, value_params) = measurement_value_params(table, row, 2);
+        let assignments: Vec<String> = table.columns.iter()
+            .zip(&value_expressions)
+            .map(|(c, e)| format!("{}={}", c.name, e))
+            .collect();
+        let query = format!(
+            "UPDATE beepee.{} SET \"timestamp\"=$1, {} WHERE id=${} AND user_id=${}",
+            table.name, assignments.join(", "), value_params.len() + 2, value_params.len() + 3,
+        );
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&row.timestamp];
+        params.extend(value_params.iter().map(|p| p as &(dyn ToSql + Sync)));
+        params.push(&row.id);
+        params.push(&user_id);
 
-    let rows = client
-        .query(
-            "SELECT id, \"timestamp\", location_id, CAST(temperature_celsius AS character varying(128)) temperature_celsius FROM beepee.body_temperature_measurements WHERE \"timestamp\" >= $1 ORDER BY \"timestamp\"",
-            &[&start_time],
-        )
-        .await?;
-    let mut ret = Vec::new();
-    for row in rows {
-        let temperature_string: String = row.get(3);
-        let temperature_celsius: Rational32 = r32_from_decimal(&temperature_string)
-            .expect("parsing temperature failed");
-        ret.push(BodyTemperatureMeasurement::new(
-            row.get(0),
-            row.get(1),
-            row.get(2),
-            temperature_celsius,
-        ));
+        client
+            .execute(
+                &client.prepare_cached(&query).await?,
+                &params,
+            )
+            .await?;
+
+        Ok(())
     }
 
-    Ok(ret)
-}
+    async fn get_measurement(&self, user_id: i64, table: &MeasurementTable, measurement_id: i64) -> Result<Option<MeasurementRow>, DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-pub(crate) async fn add_blood_sugar_measurement(measurement: &BloodSugarMeasurement) -> Result<i64, tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        let query = format!(
+            "SELECT {} FROM beepee.{} WHERE id = $1 AND user_id = $2",
+            measurement_select_columns(table), table.name,
+        );
+        let row_opt = client
+            .query_opt(
+                &client.prepare_cached(&query).await?,
+                &[&measurement_id, &user_id],
+            )
+            .await?;
 
-    let row = client
-        .query_one(
-            "INSERT INTO beepee.blood_sugar_measurements (\"timestamp\", sugar_mmol_per_l) VALUES ($1, (CAST(CAST($2 AS int) AS numeric(6, 2)) / CAST(CAST($3 AS int) AS numeric(6, 2)))) RETURNING id",
-            &[&measurement.timestamp, &measurement.sugar_mmol_per_l.numer(), &measurement.sugar_mmol_per_l.denom()],
-        )
-        .await?;
-    let measurement_id: i64 = row.get(0);
+        row_opt
+            .map(|row| measurement_row_from_row(table, &row))
+            .transpose()
+    }
 
-    Ok(measurement_id)
-}
+    async fn get_measurements(&self, user_id: i64, table: &MeasurementTable, range: &TimeRange, page: &Page) -> Result<Vec<MeasurementRow>, DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-pub(crate) async fn remove_blood_sugar_measurement(measurement_id: i64) -> Result<(), tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        let query = format!(
+            "SELECT {} FROM beepee.{} WHERE ($1::timestamptz IS NULL OR \"timestamp\" >= $1) AND ($2::timestamptz IS NULL OR \"timestamp\" < $2) AND ($3::timestamptz IS NULL OR (\"timestamp\", id) > ($3, $4::bigint)) AND user_id = $6 ORDER BY \"timestamp\", id LIMIT $5::bigint",
+            measurement_select_columns(table), table.name,
+        );
+        let rows = client
+            .query(
+                &client.prepare_cached(&query).await?,
+                &[&range.start, &range.end, &page.after_timestamp(), &page.after_id(), &page.limit, &user_id],
+            )
+            .await?;
 
-    client
-        .execute(
-            "DELETE FROM beepee.blood_sugar_measurements WHERE id = $1",
-            &[&measurement_id],
-        )
-        .await?;
+        rows.iter()
+            .map(|row| measurement_row_from_row(table, row))
+            .collect()
+    }
 
-    Ok(())
-}
+    async fn add_temperature_location(&self, loc: &BodyTemperatureLocation) -> Result<i64, DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-pub(crate) async fn update_blood_sugar_measurement(measurement: &BloodSugarMeasurement) -> Result<(), tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        let row = client
+            .query_one(
+                &client.prepare_cached("INSERT INTO beepee.body_temperature_locations (\"name\") VALUES ($1) RETURNING id").await?,
+                &[&loc.name],
+            )
+            .await?;
+        let loc_id: i64 = row.get(0);
 
-    client
-        .execute(
-            "UPDATE beepee.blood_sugar_measurements SET \"timestamp\"=$1, sugar_mmol_per_l=(CAST(CAST($2 AS int) AS numeric(6, 2)) / CAST(CAST($3 AS int) AS numeric(6, 2))) WHERE id=$4",
-            &[&measurement.timestamp, &measurement.sugar_mmol_per_l.numer(), &measurement.sugar_mmol_per_l.denom(), &measurement.id],
-        )
-        .await?;
+        Ok(loc_id)
+    }
 
-    Ok(())
-}
+    async fn remove_temperature_location(&self, loc_id: i64) -> Result<(), DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-pub(crate) async fn get_recent_blood_sugar_measurements(ago: Duration) -> Result<Vec<BloodSugarMeasurement>, tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        client
+            .execute(
+                &client.prepare_cached("DELETE FROM beepee.body_temperature_locations WHERE id = $1").await?,
+                &[&loc_id],
+            )
+            .await?;
 
-    let start_time = Local::now() - ago;
+        Ok(())
+    }
 
-    let rows = client
-        .query(
-            "SELECT id, \"timestamp\", CAST(sugar_mmol_per_l AS character varying(128)) sugar_mmol_per_l FROM beepee.blood_sugar_measurements WHERE \"timestamp\" >= $1 ORDER BY \"timestamp\"",
-            &[&start_time],
-        )
-        .await?;
-    let mut ret = Vec::new();
-    for row in rows {
-        let temperature_string: String = row.get(2);
-        let temperature_celsius: Rational32 = r32_from_decimal(&temperature_string)
-            .expect("parsing temperature failed");
-        ret.push(BloodSugarMeasurement::new(
-            row.get(0),
-            row.get(1),
-            temperature_celsius,
-        ));
+    async fn update_temperature_location(&self, loc: &BodyTemperatureLocation) -> Result<(), DatabaseError> {
+        let client = self.connect()
+            .await?;
+
+        client
+            .execute(
+                &client.prepare_cached("UPDATE beepee.body_temperature_locations SET \"name\"=$1 WHERE id=$2").await?,
+                &[&loc.name, &loc.id],
+            )
+            .await?;
+
+        Ok(())
     }
 
-    Ok(ret)
-}
+    async fn get_temperature_locations(&self) -> Result<Vec<BodyTemperatureLocation>, DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-pub(crate) async fn add_long_term_blood_sugar_measurement(measurement: &LongTermBloodSugarMeasurement) -> Result<i64, tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        let rows = client
+            .query(
+                &client.prepare_cached("SELECT id, \"name\" FROM beepee.body_temperature_locations ORDER BY \"name\"").await?,
+                &[],
+            )
+            .await?;
+        let mut ret = Vec::new();
+        for row in rows {
+            ret.push(BodyTemperatureLocation::new(
+                row.get(0),
+                row.get(1),
+            ));
+        }
 
-    let row = client
-        .query_one(
-            "INSERT INTO beepee.long_term_blood_sugar_measurements (\"timestamp\", hba1c_mmol_per_mol) VALUES ($1, (CAST(CAST($2 AS int) AS numeric(6, 2)) / CAST(CAST($3 AS int) AS numeric(6, 2)))) RETURNING id",
-            &[&measurement.timestamp, &measurement.hba1c_mmol_per_mol.numer(), &measurement.hba1c_mmol_per_mol.denom()],
-        )
-        .await?;
-    let measurement_id: i64 = row.get(0);
+        Ok(ret)
+    }
 
-    Ok(measurement_id)
-}
+    async fn add_custom_measurement(&self, user_id: i64, type_name: &str, measurement: &CustomMeasurement) -> Result<i64, DatabaseError> {
+        let mut client = self.connect()
+            .await?;
+        let transaction = client
+            .transaction().await?;
 
-pub(crate) async fn remove_long_term_blood_sugar_measurement(measurement_id: i64) -> Result<(), tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        let row = transaction
+            .query_one(
+                &transaction.prepare_cached("INSERT INTO beepee.custom_measurements (user_id, type_name, \"timestamp\") VALUES ($1, $2, $3) RETURNING id").await?,
+                &[&user_id, &type_name, &measurement.timestamp],
+            )
+            .await?;
+        let measurement_id: i64 = row.get(0);
+        insert_custom_values(&transaction, measurement_id, &measurement.values)
+            .await?;
+
+        transaction
+            .commit().await?;
+        Ok(measurement_id)
+    }
 
-    client
-        .execute(
-            "DELETE FROM beepee.long_term_blood_sugar_measurements WHERE id = $1",
-            &[&measurement_id],
-        )
-        .await?;
+    async fn remove_custom_measurement(&self, user_id: i64, type_name: &str, measurement_id: i64) -> Result<(), DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-    Ok(())
-}
+        // the values are deleted along with the measurement
+        client
+            .execute(
+                &client.prepare_cached("DELETE FROM beepee.custom_measurements WHERE id = $1 AND user_id = $2 AND type_name = $3").await?,
+                &[&measurement_id, &user_id, &type_name],
+            )
+            .await?;
 
-pub(crate) async fn update_long_term_blood_sugar_measurement(measurement: &LongTermBloodSugarMeasurement) -> Result<(), tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        Ok(())
+    }
 
-    client
-        .execute(
-            "UPDATE beepee.long_term_blood_sugar_measurements SET \"timestamp\"=$1, hba1c_mmol_per_mol=(CAST(CAST($2 AS int) AS numeric(6, 2)) / CAST(CAST($3 AS int) AS numeric(6, 2))) WHERE id=$4",
-            &[&measurement.timestamp, &measurement.hba1c_mmol_per_mol.numer(), &measurement.hba1c_mmol_per_mol.denom(), &measurement.id],
-        )
-        .await?;
+    async fn update_custom_measurement(&self, user_id: i64, type_name: &str, measurement: &CustomMeasurement) -> Result<(), DatabaseError> {
+        let mut client = self.connect()
+            .await?;
+        let transaction = client
+            .transaction().await?;
 
-    Ok(())
-}
+        let updated_count = transaction
+            .execute(
+                &transaction.prepare_cached("UPDATE beepee.custom_measurements SET \"timestamp\"=$1 WHERE id=$2 AND user_id=$3 AND type_name=$4").await?,
+                &[&measurement.timestamp, &measurement.id, &user_id, &type_name],
+            )
+            .await?;
+        if updated_count > 0 {
+            transaction
+                .execute(
+                    &transaction.prepare_cached("DELETE FROM beepee.custom_measurement_values WHERE measurement_id = $1").await?,
+                    &[&measurement.id],
+                )
+                .await?;
+            insert_custom_values(&transaction, measurement.id, &measurement.values)
+                .await?;
+        }
 
-pub(crate) async fn get_recent_long_term_blood_sugar_measurements(ago: Duration) -> Result<Vec<LongTermBloodSugarMeasurement>, tokio_postgres::Error> {
-    let client = connect()
-        .await?;
+        transaction
+            .commit().await?;
+        Ok(())
+    }
 
-    let start_time = Local::now() - ago;
+    async fn get_custom_measurement(&self, user_id: i64, type_name: &str, measurement_id: i64) -> Result<Option<CustomMeasurement>, DatabaseError> {
+        let client = self.connect()
+            .await?;
 
-    let rows = client
-        .query(
-            "SELECT id, \"timestamp\", CAST(hba1c_mmol_per_mol AS character varying(128)) hba1c_mmol_per_mol FROM beepee.long_term_blood_sugar_measurements WHERE \"timestamp\" >= $1 ORDER BY \"timestamp\"",
-            &[&start_time],
-        )
-        .await?;
-    let mut ret = Vec::new();
-    for row in rows {
-        let hba1c_mmol_per_mol_string: String = row.get(2);
-        let hba1c_mmol_per_mol: Rational32 = r32_from_decimal(&hba1c_mmol_per_mol_string)
-            .expect("parsing temperature failed");
-        ret.push(LongTermBloodSugarMeasurement::new(
-            row.get(0),
-            row.get(1),
-            hba1c_mmol_per_mol,
-        ));
+        let rows = client
+            .query(
+                &client.prepare_cached("SELECT m.id, m.\"timestamp\", v.field_key, CAST(v.\"value\" AS character varying(128)) \"value\" FROM beepee.custom_measurements m LEFT JOIN beepee.custom_measurement_values v ON v.measurement_id = m.id WHERE m.id = $1 AND m.user_id = $2 AND m.type_name = $3").await?,
+                &[&measurement_id, &user_id, &type_name],
+            )
+            .await?;
+
+        Ok(custom_measurements_from_rows(rows).pop())
     }
 
-    Ok(ret)
+    async fn get_custom_measurements(&self, user_id: i64, type_name: &str, range: &TimeRange, page: &Page) -> Result<Vec<CustomMeasurement>, DatabaseError> {
+        let client = self.connect()
+            .await?;
+
+        let rows = client
+            .query(
+                &client.prepare_cached("SELECT m.id, m.\"timestamp\", v.field_key, CAST(v.\"value\" AS character varying(128)) \"value\" FROM (SELECT id, \"timestamp\" FROM beepee.custom_measurements WHERE ($1::timestamptz IS NULL OR \"timestamp\" >= $1) AND ($2::timestamptz IS NULL OR \"timestamp\" < $2) AND ($3::timestamptz IS NULL OR (\"timestamp\", id) > ($3, $4::bigint)) AND user_id = $6 AND type_name = $7 ORDER BY \"timestamp\", id LIMIT $5::bigint) m LEFT JOIN beepee.custom_measurement_values v ON v.measurement_id = m.id ORDER BY m.\"timestamp\", m.id").await?,
+                &[&range.start, &range.end, &page.after_timestamp(), &page.after_id(), &page.limit, &user_id, &type_name],
+            )
+            .await?;
+
+        Ok(custom_measurements_from_rows(rows))
+    }
 }
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local, SecondsFormat};
+use num_rational::Rational32;
+
+use crate::model::{
+    BloodPressureMeasurement, BloodSugarMeasurement, BodyMassMeasurement, BodyTemperatureLocation,
+    BodyTemperatureMeasurement, LongTermBloodSugarMeasurement,
+};
+use crate::numerism::r32_to_decimal;
+
+
+fn timestamp_to_csv(timestamp: &DateTime<Local>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, false)
+}
+
+fn opt_to_csv<T: ToString>(value: Option<T>) -> String {
+    value
+        .map(|v| v.to_string())
+        .unwrap_or_default()
+}
+
+fn write_csv(header: &[&str], rows: Vec<Vec<String>>) -> Result<String, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(header)?;
+    for row in rows {
+        writer.write_record(&row)?;
+    }
+    let bytes = writer.into_inner()
+        .map_err(|e| e.into_error())?;
+    Ok(String::from_utf8(bytes).expect("CSV writer produced invalid UTF-8"))
+}
+
+
+pub(crate) fn blood_pressure_to_csv(measurements: &[BloodPressureMeasurement]) -> Result<String, csv::Error> {
+    let rows = measurements.iter()
+        .map(|m| vec![
+            m.id.to_string(),
+            timestamp_to_csv(&m.timestamp),
+            m.systolic_mmhg.to_string(),
+            m.diastolic_mmhg.to_string(),
+            m.pulse_bpm.to_string(),
+            opt_to_csv(m.spo2_percent),
+        ])
+        .collect();
+    write_csv(
+        &["id", "timestamp", "systolic_mmhg", "diastolic_mmhg", "pulse_bpm", "spo2_percent"],
+        rows,
+    )
+}
+
+pub(crate) fn mass_to_csv(measurements: &[BodyMassMeasurement], decimal_places: usize) -> Result<String, csv::Error> {
+    let to_decimal = |r: Rational32| r32_to_decimal(r, decimal_places);
+    let rows = measurements.iter()
+        .map(|m| vec![
+            m.id.to_string(),
+            timestamp_to_csv(&m.timestamp),
+            to_decimal(m.mass_kg),
+            opt_to_csv(m.waist_circum_cm.map(to_decimal)),
+            opt_to_csv(m.bmi.map(to_decimal)),
+        ])
+        .collect();
+    write_csv(
+        &["id", "timestamp", "mass_kg", "waist_circum_cm", "bmi"],
+        rows,
+    )
+}
+
+pub(crate) fn temperature_to_csv(measurements: &[BodyTemperatureMeasurement], locations: &[BodyTemperatureLocation], decimal_places: usize) -> Result<String, csv::Error> {
+    let location_id_to_name: HashMap<i64, &String> = locations.iter()
+        .map(|btl| (btl.id, &btl.name))
+        .collect();
+    let rows = measurements.iter()
+        .map(|m| vec![
+            m.id.to_string(),
+            timestamp_to_csv(&m.timestamp),
+            m.location_id.to_string(),
+            opt_to_csv(location_id_to_name.get(&m.location_id)),
+            r32_to_decimal(m.temperature_celsius, decimal_places),
+        ])
+        .collect();
+    write_csv(
+        &["id", "timestamp", "location_id", "location_name", "temperature_celsius"],
+        rows,
+    )
+}
+
+pub(crate) fn sugar_to_csv(measurements: &[BloodSugarMeasurement], decimal_places: usize) -> Result<String, csv::Error> {
+    let rows = measurements.iter()
+        .map(|m| vec![
+            m.id.to_string(),
+            timestamp_to_csv(&m.timestamp),
+            r32_to_decimal(m.sugar_mmol_per_l, decimal_places),
+            r32_to_decimal(m.sugar_mg_per_dl(), decimal_places),
+        ])
+        .collect();
+    write_csv(
+        &["id", "timestamp", "sugar_mmol_per_l", "sugar_mg_per_dl"],
+        rows,
+    )
+}
+
+pub(crate) fn long_term_sugar_to_csv(measurements: &[LongTermBloodSugarMeasurement], decimal_places: usize) -> Result<String, csv::Error> {
+    let rows = measurements.iter()
+        .map(|m| vec![
+            m.id.to_string(),
+            timestamp_to_csv(&m.timestamp),
+            r32_to_decimal(m.hba1c_mmol_per_mol, decimal_places),
+            r32_to_decimal(m.hba1c_dcct_percent(), decimal_places),
+        ])
+        .collect();
+    write_csv(
+        &["id", "timestamp", "hba1c_mmol_per_mol", "hba1c_dcct_percent"],
+        rows,
+    )
+}
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Local, SecondsFormat};
+use num_rational::Rational32;
+use serde_json::{json, Value};
+
+use crate::config::Scope;
+use crate::database::DatabaseError;
+use crate::measurement::{BuiltInType, MeasurementType};
+use crate::model::{
+    BloodPressureMeasurement, BloodSugarMeasurement, BodyMassMeasurement, BodyTemperatureLocation,
+    BodyTemperatureMeasurement, LongTermBloodSugarMeasurement, Page, TimeRange,
+};
+use crate::numerism::r32_to_decimal;
+use crate::storage::Storage;
+
+
+const LOINC_SYSTEM: &str = "http://loinc.org";
+const UCUM_SYSTEM: &str = "http://unitsofmeasure.org";
+const OBSERVATION_CATEGORY_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/observation-category";
+
+
+fn timestamp_to_fhir(timestamp: &DateTime<Local>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, false)
+}
+
+fn loinc_code(codes_and_displays: &[(&str, &str)]) -> Value {
+    let codings: Vec<Value> = codes_and_displays.iter()
+        .map(|(code, display)| json!({
+            "system": LOINC_SYSTEM,
+            "code": code,
+            "display": display,
+        }))
+        .collect();
+    json!({
+        "coding": codings,
+        "text": codes_and_displays[0].1,
+    })
+}
+
+fn category(code: &str, display: &str) -> Value {
+    json!([{
+        "coding": [{
+            "system": OBSERVATION_CATEGORY_SYSTEM,
+            "code": code,
+            "display": display,
+        }],
+    }])
+}
+
+fn quantity(value: Value, unit: &str, ucum_code: &str) -> Value {
+    json!({
+        "value": value,
+        "unit": unit,
+        "system": UCUM_SYSTEM,
+        "code": ucum_code,
+    })
+}
+
+fn observation(id: String, category: Value, code: Value, timestamp: &DateTime<Local>) -> Value {
+    json!({
+        "resourceType": "Observation",
+        "id": id,
+        "status": "final",
+        "category": category,
+        "code": code,
+        "effectiveDateTime": timestamp_to_fhir(timestamp),
+    })
+}
+
+
+/// A FHIR R4 bundle of observations.
+pub(crate) struct FhirBundle {
+    decimal_places: usize,
+    resources: Vec<Value>,
+}
+impl FhirBundle {
+    pub fn new(decimal_places: usize) -> Self {
+        Self {
+            decimal_places,
+            resources: Vec::new(),
+        }
+    }
+
+    fn decimal(&self, value: Rational32) -> Value {
+        // round-trip through the decimal representation to limit the number of decimal places
+        let decimal_string = r32_to_decimal(value, self.decimal_places);
+        let decimal: f64 = decimal_string.parse()
+            .expect("formatted decimal is not a valid number");
+        json!(decimal)
+    }
+
+    pub fn add_blood_pressure(&mut self, measurements: &[BloodPressureMeasurement]) {
+        for m in measurements {
+            let mut panel = observation(
+                format!("bp-{}", m.id),
+                category("vital-signs", "Vital Signs"),
+                loinc_code(&[("85354-9", "Blood pressure panel with all children optional")]),
+                &m.timestamp,
+            );
+            panel["component"] = json!([
+                {
+                    "code": loinc_code(&[("8480-6", "Systolic blood pressure")]),
+                    "valueQuantity": quantity(json!(m.systolic_mmhg), "mmHg", "mm[Hg]"),
+                },
+                {
+                    "code": loinc_code(&[("8462-4", "Diastolic blood pressure")]),
+                    "valueQuantity": quantity(json!(m.diastolic_mmhg), "mmHg", "mm[Hg]"),
+                },
+            ]);
+            self.resources.push(panel);
+
+            let mut pulse = observation(
+                format!("pulse-{}", m.id),
+                category("vital-signs", "Vital Signs"),
+                loinc_code(&[("8867-4", "Heart rate")]),
+                &m.timestamp,
+            );
+            pulse["valueQuantity"] = quantity(json!(m.pulse_bpm), "/min", "/min");
+            self.resources.push(pulse);
+
+            if let Some(spo2_percent) = m.spo2_percent {
+                let mut spo2 = observation(
+                    format!("spo2-{}", m.id),
+                    category("vital-signs", "Vital Signs"),
+                    loinc_code(&[
+                        ("59408-5", "Oxygen saturation in Arterial blood by Pulse oximetry"),
+                        ("2708-6", "Oxygen saturation in Arterial blood"),
+                    ]),
+                    &m.timestamp,
+                );
+                spo2["valueQuantity"] = quantity(json!(spo2_percent), "%", "%");
+                self.resources.push(spo2);
+            }
+        }
+    }
+
+    pub fn add_mass(&mut self, measurements: &[BodyMassMeasurement]) {
+        for m in measurements {
+            let mut mass = observation(
+                format!("mass-{}", m.id),
+                category("vital-signs", "Vital Signs"),
+                loinc_code(&[("29463-7", "Body weight")]),
+                &m.timestamp,
+            );
+            mass["valueQuantity"] = quantity(self.decimal(m.mass_kg), "kg", "kg");
+            self.resources.push(mass);
+
+            if let Some(waist_circum_cm) = m.waist_circum_cm {
+                let mut waist = observation(
+                    format!("waist-{}", m.id),
+                    category("vital-signs", "Vital Signs"),
+                    loinc_code(&[("8280-0", "Waist Circumference at umbilicus by Tape measure")]),
+                    &m.timestamp,
+                );
+                waist["valueQuantity"] = quantity(self.decimal(waist_circum_cm), "cm", "cm");
+                self.resources.push(waist);
+            }
+        }
+    }
+
+    pub fn add_temperature(&mut self, measurements: &[BodyTemperatureMeasurement], locations: &[BodyTemperatureLocation]) {
+        let location_id_to_name: HashMap<i64, &String> = locations.iter()
+            .map(|btl| (btl.id, &btl.name))
+            .collect();
+        for m in measurements {
+            let mut temperature = observation(
+                format!("temperature-{}", m.id),
+                category("vital-signs", "Vital Signs"),
+                loinc_code(&[("8310-5", "Body temperature")]),
+                &m.timestamp,
+            );
+            temperature["valueQuantity"] = quantity(self.decimal(m.temperature_celsius), "\u{B0}C", "Cel");
+            if let Some(location_name) = location_id_to_name.get(&m.location_id) {
+                temperature["bodySite"] = json!({
+                    "text": location_name,
+                });
+            }
+            self.resources.push(temperature);
+        }
+    }
+
+    pub fn add_sugar(&mut self, measurements: &[BloodSugarMeasurement]) {
+        for m in measurements {
+            let mut sugar = observation(
+                format!("sugar-{}", m.id),
+                category("laboratory", "Laboratory"),
+                loinc_code(&[("15074-8", "Glucose [Moles/volume] in Blood")]),
+                &m.timestamp,
+            );
+            sugar["valueQuantity"] = quantity(self.decimal(m.sugar_mmol_per_l), "mmol/L", "mmol/L");
+            self.resources.push(sugar);
+        }
+    }
+
+    pub fn add_long_term_sugar(&mut self, measurements: &[LongTermBloodSugarMeasurement]) {
+        for m in measurements {
+            let mut hba1c = observation(
+                format!("long-term-sugar-{}", m.id),
+                category("laboratory", "Laboratory"),
+                loinc_code(&[("59261-8", "Hemoglobin A1c/Hemoglobin.total in Blood by IFCC protocol")]),
+                &m.timestamp,
+            );
+            hba1c["valueQuantity"] = quantity(self.decimal(m.hba1c_mmol_per_mol), "mmol/mol", "mmol/mol");
+            self.resources.push(hba1c);
+        }
+    }
+
+    pub fn to_json(&self, timestamp: &DateTime<Local>) -> Value {
+        let entries: Vec<Value> = self.resources.iter()
+            .map(|r| json!({
+                "resource": r,
+            }))
+            .collect();
+        json!({
+            "resourceType": "Bundle",
+            "type": "collection",
+            "timestamp": timestamp_to_fhir(timestamp),
+            "entry": entries,
+        })
+    }
+}
+
+
+/// A measurement type whose measurements are exported into FHIR bundles.
+#[async_trait]
+pub(crate) trait FhirExport: Send + Sync {
+    /// The scope that tokens need to export measurements of this type.
+    fn scope(&self) -> Scope;
+
+    /// Adds the user's measurements within the range to the bundle.
+    async fn add_to_bundle(&self, bundle: &mut FhirBundle, storage: &dyn Storage, user_id: i64, range: &TimeRange) -> Result<(), DatabaseError>;
+}
+
+#[async_trait]
+impl FhirExport for BuiltInType<BloodPressureMeasurement> {
+    fn scope(&self) -> Scope { MeasurementType::scope(self) }
+
+    async fn add_to_bundle(&self, bundle: &mut FhirBundle, storage: &dyn Storage, user_id: i64, range: &TimeRange) -> Result<(), DatabaseError> {
+        let measurements = self.get_range(storage, user_id, range, &Page::all()).await?;
+        bundle.add_blood_pressure(&measurements);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FhirExport for BuiltInType<BodyMassMeasurement> {
+    fn scope(&self) -> Scope { MeasurementType::scope(self) }
+
+    async fn add_to_bundle(&self, bundle: &mut FhirBundle, storage: &dyn Storage, user_id: i64, range: &TimeRange) -> Result<(), DatabaseError> {
+        let measurements = self.get_range(storage, user_id, range, &Page::all()).await?;
+        bundle.add_mass(&measurements);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FhirExport for BuiltInType<BodyTemperatureMeasurement> {
+    fn scope(&self) -> Scope { MeasurementType::scope(self) }
+
+    async fn add_to_bundle(&self, bundle: &mut FhirBundle, storage: &dyn Storage, user_id: i64, range: &TimeRange) -> Result<(), DatabaseError> {
+        let measurements = self.get_range(storage, user_id, range, &Page::all()).await?;
+        let locations = storage.get_temperature_locations().await?;
+        bundle.add_temperature(&measurements, &locations);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FhirExport for BuiltInType<BloodSugarMeasurement> {
+    fn scope(&self) -> Scope { MeasurementType::scope(self) }
+
+    async fn add_to_bundle(&self, bundle: &mut FhirBundle, storage: &dyn Storage, user_id: i64, range: &TimeRange) -> Result<(), DatabaseError> {
+        let measurements = self.get_range(storage, user_id, range, &Page::all()).await?;
+        bundle.add_sugar(&measurements);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FhirExport for BuiltInType<LongTermBloodSugarMeasurement> {
+    fn scope(&self) -> Scope { MeasurementType::scope(self) }
+
+    async fn add_to_bundle(&self, bundle: &mut FhirBundle, storage: &dyn Storage, user_id: i64, range: &TimeRange) -> Result<(), DatabaseError> {
+        let measurements = self.get_range(storage, user_id, range, &Page::all()).await?;
+        bundle.add_long_term_sugar(&measurements);
+        Ok(())
+    }
+}
+
+/// The measurement types exported into FHIR bundles, in the order of their observations.
+pub(crate) static FHIR_EXPORTS: [&dyn FhirExport; 5] = [
+    &BuiltInType::<BloodPressureMeasurement>::new(),
+    &BuiltInType::<BodyMassMeasurement>::new(),
+    &BuiltInType::<BodyTemperatureMeasurement>::new(),
+    &BuiltInType::<BloodSugarMeasurement>::new(),
+    &BuiltInType::<LongTermBloodSugarMeasurement>::new(),
+];
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blood_pressure_panel() {
+        let timestamp = Local::now();
+        let mut bundle = FhirBundle::new(2);
+        bundle.add_blood_pressure(&[BloodPressureMeasurement::new(7, timestamp, 120, 80, 60, None)]);
+        let json = bundle.to_json(&timestamp);
+
+        let entries = json["entry"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        let panel = &entries[0]["resource"];
+        assert_eq!(panel["id"], "bp-7");
+        assert_eq!(panel["code"]["coding"][0]["code"], "85354-9");
+        assert_eq!(panel["component"][0]["code"]["coding"][0]["code"], "8480-6");
+        assert_eq!(panel["component"][0]["valueQuantity"]["value"], 120);
+        assert_eq!(panel["component"][1]["code"]["coding"][0]["code"], "8462-4");
+        assert_eq!(panel["component"][1]["valueQuantity"]["value"], 80);
+        let pulse = &entries[1]["resource"];
+        assert_eq!(pulse["code"]["coding"][0]["code"], "8867-4");
+        assert_eq!(pulse["valueQuantity"]["value"], 60);
+    }
+
+    #[test]
+    fn decimal_values_rounded() {
+        let timestamp = Local::now();
+        let mut bundle = FhirBundle::new(1);
+        bundle.add_sugar(&[BloodSugarMeasurement::new(1, timestamp, Rational32::new(100, 18))]);
+        let json = bundle.to_json(&timestamp);
+
+        let quantity = &json["entry"][0]["resource"]["valueQuantity"];
+        assert_eq!(quantity["value"], 5.6);
+        assert_eq!(quantity["code"], "mmol/L");
+    }
+}
@@ -33,3 +33,7 @@ pub(crate) fn time_of_day_ms(timestamp: &DateTime<Local>) -> Result<u32, askama:
 pub(crate) fn time(timestamp: &DateTime<Local>) -> Result<String, askama::Error> {
     Ok(timestamp.format("%H:%M").to_string())
 }
+
+pub(crate) fn datetime_local(timestamp: &DateTime<Local>) -> Result<String, askama::Error> {
+    Ok(timestamp.format("%Y-%m-%dT%H:%M:%S").to_string())
+}
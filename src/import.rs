@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::{ClientError, check_alerts};
+use crate::alerts::{Alert, AlertActions};
+use crate::config::Scope;
+use crate::database::DatabaseError;
+use crate::measurement::Measurement;
+use crate::model::{
+    BloodPressureMeasurement, BloodSugarMeasurement, BodyMassMeasurement, BodyTemperatureMeasurement,
+    LongTermBloodSugarMeasurement, Page, TimeRange, User,
+};
+use crate::storage::storage;
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum ImportKind {
+    BloodPressure,
+    Mass,
+    Temperature,
+    Sugar,
+    LongTermSugar,
+}
+impl ImportKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bp" => Some(Self::BloodPressure),
+            "mass" => Some(Self::Mass),
+            "temperature" => Some(Self::Temperature),
+            "sugar" => Some(Self::Sugar),
+            "long-term-sugar" => Some(Self::LongTermSugar),
+            _ => None,
+        }
+    }
+
+    /// The scope needed to import measurements of this kind.
+    pub fn scope(&self) -> Scope {
+        match self {
+            Self::BloodPressure => Scope::Bp,
+            Self::Mass => Scope::Mass,
+            Self::Temperature => Scope::Temperature,
+            Self::Sugar => Scope::Sugar,
+            Self::LongTermSugar => Scope::LongTermSugar,
+        }
+    }
+
+    pub fn names() -> Vec<String> {
+        vec![
+            "bp".to_owned(), "mass".to_owned(), "temperature".to_owned(), "sugar".to_owned(),
+            "long-term-sugar".to_owned(),
+        ]
+    }
+}
+
+
+#[derive(Debug)]
+pub(crate) enum ImportError {
+    ReadingHeaders(csv::Error),
+    Database(DatabaseError),
+}
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::ReadingHeaders(e)
+                => write!(f, "error reading CSV headers: {}", e),
+            ImportError::Database(e)
+                => write!(f, "database error: {}", e),
+        }
+    }
+}
+impl Error for ImportError {
+}
+
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+pub(crate) struct ImportRowError {
+    pub line: u64,
+    pub error: &'static str,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+pub(crate) struct ImportReport {
+    pub dry_run: bool,
+    pub row_count: usize,
+    pub valid_row_count: usize,
+    pub inserted_count: usize,
+    pub errors: Vec<ImportRowError>,
+    /// The alerts raised for the newest imported measurement.
+    pub alerts: Vec<Alert>,
+}
+
+
+struct CsvRow {
+    line: u64,
+    values: Result<HashMap<String, String>, ClientError>,
+}
+
+
+/// Translates the columns written by the CSV export into the keys understood by the form parsers.
+fn translate_export_columns(kind: ImportKind, values: &mut HashMap<String, String>) {
+    let mut rename_value = |from_key: &str, to_key: &str, unit: Option<(&str, &str)>| {
+        if values.contains_key(to_key) {
+            return;
+        }
+        let value = match values.get(from_key) {
+            Some(v) if !v.is_empty() => v.clone(),
+            _ => return,
+        };
+        values.insert(to_key.to_owned(), value);
+        if let Some((unit_key, unit_value)) = unit {
+            values.insert(unit_key.to_owned(), unit_value.to_owned());
+        }
+    };
+
+    match kind {
+        ImportKind::BloodPressure|ImportKind::Mass => {},
+        ImportKind::Temperature => {
+            rename_value("location_id", "location", None);
+        },
+        ImportKind::Sugar => {
+            rename_value("sugar_mmol_per_l", "sugar_value", Some(("sugar_unit_key", "mmol-per-l")));
+            rename_value("sugar_mg_per_dl", "sugar_value", Some(("sugar_unit_key", "mg-per-dl")));
+        },
+        ImportKind::LongTermSugar => {
+            rename_value("hba1c_mmol_per_mol", "hba1c_value", Some(("hba1c_unit_key", "mmol-per-mol")));
+            rename_value("hba1c_dcct_percent", "hba1c_value", Some(("hba1c_unit_key", "dcct-percent")));
+        },
+    }
+}
+
+fn read_csv_rows(kind: ImportKind, csv_data: &[u8]) -> Result<Vec<CsvRow>, ImportError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(csv_data);
+    let headers = reader.headers()
+        .map_err(ImportError::ReadingHeaders)?
+        .clone();
+
+    let mut rows = Vec::new();
+    for record_res in reader.records() {
+        let row = match record_res {
+            Ok(record) => {
+                let line = record.position()
+                    .map(|p| p.line())
+                    .unwrap_or(0);
+                let mut values: HashMap<String, String> = headers.iter()
+                    .zip(record.iter())
+                    .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                    .collect();
+                translate_export_columns(kind, &mut values);
+
+                // imported measurements must not silently default to the current time
+                let has_timestamp = values.get("timestamp")
+                    .map(|t| !t.is_empty())
+                    .unwrap_or(false);
+                if has_timestamp {
+                    CsvRow { line, values: Ok(values) }
+                } else {
+                    CsvRow { line, values: Err(ClientError::MissingValue("timestamp".to_owned())) }
+                }
+            },
+            Err(e) => {
+                let line = e.position()
+                    .map(|p| p.line())
+                    .unwrap_or(0);
+                CsvRow { line, values: Err(ClientError::FailedToParseCsv(e)) }
+            },
+        };
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn add_row_error(report: &mut ImportReport, line: u64, error: ClientError) {
+    report.errors.push(ImportRowError {
+        line,
+        error: error.kind(),
+        key: error.key().map(|k| k.to_owned()),
+        message: error.to_string(),
+    });
+}
+
+/// Imports measurements of the given kind from CSV data for the given user. Each row is validated in the same manner as
+/// a submitted form; all valid rows are then added in a single transaction unless `dry_run` is set.
+///
+/// Errors in individual rows are collected in the report instead of failing the import. The alert rules
+/// are evaluated against the newest imported measurement, as if it had just been entered; the older
+/// ones are history by then. Imports of old data, which is older than a stored measurement, raise no
+/// alerts. The tasks running the actions of the raised alerts are returned along with the report.
+pub(crate) async fn import_csv(user: &User, kind: ImportKind, csv_data: &[u8], dry_run: bool) -> Result<(ImportReport, AlertActions), ImportError> {
+    let rows = read_csv_rows(kind, csv_data)?;
+    let mut report = ImportReport {
+        dry_run,
+        row_count: rows.len(),
+        valid_row_count: 0,
+        inserted_count: 0,
+        errors: Vec::new(),
+        alerts: Vec::new(),
+    };
+    let now = Local::now();
+
+    let alert_actions = match kind {
+        ImportKind::BloodPressure => import_rows::<BloodPressureMeasurement>(user, rows, now, dry_run, &mut report).await?,
+        ImportKind::Mass => import_rows::<BodyMassMeasurement>(user, rows, now, dry_run, &mut report).await?,
+        ImportKind::Temperature => import_rows::<BodyTemperatureMeasurement>(user, rows, now, dry_run, &mut report).await?,
+        ImportKind::Sugar => import_rows::<BloodSugarMeasurement>(user, rows, now, dry_run, &mut report).await?,
+        ImportKind::LongTermSugar => import_rows::<LongTermBloodSugarMeasurement>(user, rows, now, dry_run, &mut report).await?,
+    };
+
+    Ok((report, alert_actions))
+}
+
+/// Validates the rows as measurements of the type and adds the valid ones.
+async fn import_rows<M: Measurement>(user: &User, rows: Vec<CsvRow>, now: DateTime<Local>, dry_run: bool, report: &mut ImportReport) -> Result<AlertActions, ImportError> {
+    let mut lines = Vec::new();
+    let mut parsed_measurements = Vec::new();
+    for row in rows {
+        match row.values.and_then(|v| M::from_form(&v, now)) {
+            Ok(m) => {
+                lines.push(row.line);
+                parsed_measurements.push(m);
+            },
+            Err(e) => add_row_error(report, row.line, e),
+        }
+    }
+
+    // checked in a dry run too, so that it predicts the outcome of the real import
+    let reference_results = M::check_references(storage(), &parsed_measurements).await
+        .map_err(ImportError::Database)?;
+    let mut measurements = Vec::new();
+    for ((line, measurement), result) in lines.into_iter().zip(parsed_measurements).zip(reference_results) {
+        match result {
+            Ok(()) => measurements.push(measurement),
+            Err(e) => add_row_error(report, line, e),
+        }
+    }
+    report.valid_row_count = measurements.len();
+    if dry_run {
+        return Ok(AlertActions::none());
+    }
+
+    // alerts are only raised for data that is newer than what has been recorded before
+    let newest_timestamp = measurements.iter()
+        .map(|m| m.timestamp())
+        .max();
+    let is_newest_data = match newest_timestamp {
+        Some(timestamp) => {
+            let later_range = TimeRange::new(Some(timestamp), None);
+            M::get_range(storage(), user.id, &later_range, &Page::new(None, Some(1))).await
+                .map_err(ImportError::Database)?
+                .is_empty()
+        },
+        None => false,
+    };
+
+    let measurement_ids = M::add_all(storage(), user.id, &measurements).await
+        .map_err(ImportError::Database)?;
+    report.inserted_count = measurement_ids.len();
+    for (measurement, measurement_id) in measurements.iter_mut().zip(measurement_ids) {
+        measurement.set_id(measurement_id);
+    }
+    if !is_newest_data {
+        return Ok(AlertActions::none());
+    }
+    let newest = measurements.iter()
+        .max_by_key(|m| (m.timestamp(), m.id()));
+    let (alerts, alert_actions) = match newest {
+        Some(newest_measurement) => check_alerts(user, newest_measurement).await,
+        None => (Vec::new(), AlertActions::none()),
+    };
+    report.alerts = alerts;
+    Ok(alert_actions)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_columns_translated() {
+        let csv_data = b"id,timestamp,sugar_mmol_per_l,sugar_mg_per_dl\n1,2024-01-01T08:00:00+01:00,5.50,99.00\n";
+        let rows = read_csv_rows(ImportKind::Sugar, csv_data).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].line, 2);
+        let values = rows[0].values.as_ref().unwrap();
+        assert_eq!(values["sugar_value"], "5.50");
+        assert_eq!(values["sugar_unit_key"], "mmol-per-l");
+    }
+
+    #[test]
+    fn missing_timestamp_rejected() {
+        let csv_data = b"systolic_mmhg,diastolic_mmhg,pulse_bpm\n120,80,60\n";
+        let rows = read_csv_rows(ImportKind::BloodPressure, csv_data).unwrap();
+        assert_eq!(rows.len(), 1);
+        match &rows[0].values {
+            Err(ClientError::MissingValue(key)) => assert_eq!(key, "timestamp"),
+            other => panic!("unexpected result {:?}", other.as_ref().map(|_| ())),
+        }
+    }
+}
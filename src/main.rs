@@ -1,13 +1,27 @@
+mod alerts;
+mod classification;
 mod config;
+mod custom;
 mod database;
+mod export;
+mod fhir;
 mod filters;
+mod import;
+mod measurement;
+mod memory;
+mod migrations;
 mod model;
 mod numerism;
 mod ser_de;
+mod sqlite;
+mod statistics;
+mod storage;
+mod tls;
+mod token;
 
 
-use std::collections::{BTreeMap, HashMap};
-use std::convert::Infallible;
+use std::collections::HashMap;
+use std::convert::{Infallible, TryFrom};
 use std::error::Error;
 use std::ffi::OsString;
 use std::fmt;
@@ -17,41 +31,85 @@ use std::process::ExitCode;
 use std::result::Result;
 
 use askama::Template;
-use chrono::{Duration, Local, Timelike};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveTime};
 use env_logger;
 use form_urlencoded;
+use http::header::{AUTHORIZATION, COOKIE};
 use http::request::Parts;
 use http_body_util::{BodyExt, Full};
-use hyper::{Method, Request, Response};
-use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use hyper::body::{Body, Bytes};
 use hyper::service::service_fn;
 use hyper_util::rt::tokio::{TokioExecutor, TokioIo};
-use log::error;
+use log::{error, info};
 use num_rational::Rational32;
 use num_traits::Zero;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::signal::unix::{SignalKind, signal};
 use toml;
 use url::Url;
 
-use crate::config::{AuthToken, CONFIG, CONFIG_PATH, load_config};
-use crate::database::{
-    add_blood_pressure_measurement, add_blood_sugar_measurement,
-    add_long_term_blood_sugar_measurement, add_mass_measurement, add_temperature_measurement,
-    get_recent_blood_pressure_measurements, get_recent_blood_sugar_measurements,
-    get_recent_long_term_blood_sugar_measurements, get_recent_mass_measurements,
-    get_recent_temperature_measurements, get_temperature_locations,
+use crate::alerts::{Alert, AlertActions, raise_alerts, triggered_alerts};
+use crate::classification::{BloodPressureCategory, BloodPressureGuideline};
+use crate::config::{
+    AuthToken, CONFIG, CONFIG_PATH, CustomMeasurementType, Scope, default_user, load_config,
+    reload_config,
 };
+use crate::custom::{custom_endpoint, custom_measurement_types, get_custom_types, handle_custom_request};
+use crate::database::DatabaseError;
+use crate::export::{
+    blood_pressure_to_csv, long_term_sugar_to_csv, mass_to_csv, sugar_to_csv, temperature_to_csv,
+};
+use crate::fhir::{FHIR_EXPORTS, FhirBundle};
+use crate::import::{ImportError, ImportKind, import_csv};
+use crate::measurement::{
+    BuiltInType, Measurement, MeasurementEndpoint, MeasurementType, Record, measurement_endpoint,
+};
+use crate::migrations::MigrationError;
 use crate::model::{
-    DailyBloodPressureMeasurements, BloodPressureMeasurement, BloodSugarMeasurement,
-    BodyMassMeasurement, BodyTemperatureLocation, BodyTemperatureMeasurement,
-    LongTermBloodSugarMeasurement, MeasurementStatistics, SUGAR_MG_PER_DL_IN_MMOL_PER_L,
+    DailyBloodPressureMeasurements, BloodPressureMeasurement, BloodSugarMeasurement, BodyHeight,
+    BodyMassMeasurement, BodyTemperatureLocation, BodyTemperatureMeasurement, CustomMeasurement,
+    LongTermBloodSugarMeasurement, Page, PageCursor, ParsePageCursorError,
+    Profile, Session, Sex, ShareToken, TimeRange, User,
 };
 use crate::numerism::{ParseRationalError, r32_from_decimal};
+use crate::ser_de::{ParseTimestampError, local_from_naive, parse_timestamp};
+use crate::statistics::MeasurementStatistics;
+use crate::storage::{init_storage, storage};
+use crate::token::{generate_token, generate_token_value, sha256_hex};
 
 
 static ABSOLUTE_ZERO_CELSIUS: Lazy<Rational32> = Lazy::new(|| Rational32::new(-27315, 100));
+const DEFAULT_LOOKBACK_DAYS: i64 = 3*31;
+const LONG_TERM_SUGAR_LOOKBACK_DAYS: i64 = 3*365;
+const API_DEFAULT_PAGE_SIZE: i32 = 100;
+const API_MAX_PAGE_SIZE: i32 = 1000;
+const MAX_SHARE_DAYS: i32 = 365;
+/// How far back alert rules look for consecutive readings.
+const ALERT_HISTORY_DAYS: i64 = 365;
+
+/// The cookie that keeps browsers logged in. It contains the random ID of a session stored on the
+/// server, which ends when the browser logs out, the session expires or the token that has been
+/// used to log in is removed from the configuration or revoked.
+const SESSION_COOKIE_NAME: &str = "beepee_session";
+
+/// The page showing the data of each scope: its name as passed to `output_links`, its URL relative
+/// to the base URL and its title.
+const SCOPE_PAGES: [(Scope, &str, &str, &str); 7] = [
+    (Scope::Bp, "bp", "./", "blood pressure"),
+    (Scope::Mass, "mass", "mass", "body mass"),
+    (Scope::Temperature, "temperature", "temperature", "body temperature"),
+    (Scope::Sugar, "sugar", "sugar", "blood sugar"),
+    (Scope::LongTermSugar, "long-term-sugar", "long-term-sugar", "long-term blood sugar"),
+    (Scope::AllCustom, "custom", "custom", "other measurements"),
+    (Scope::Profile, "profile", "profile", "profile"),
+];
+
 static STATIC_PATH_RE: Lazy<Regex> = Lazy::new(|| Regex::new("^/static/([a-z0-9-._]+)$").unwrap());
 
 
@@ -71,7 +129,15 @@ pub(crate) enum ServerError {
     OpeningConfigFile(std::io::Error),
     ReadingConfigFile(std::io::Error),
     ParsingConfigFile(toml::de::Error),
+    InvalidConfig(String),
     ParsingListenAddress(AddrParseError),
+    InvalidCommandLine(String),
+    ListeningForSignals(std::io::Error),
+    GeneratingToken(ring::error::Unspecified),
+    ReadingImportFile(std::io::Error),
+    Importing(ImportError),
+    SettingUpDatabase(DatabaseError),
+    Migrating(MigrationError),
 }
 impl fmt::Display for ServerError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -82,8 +148,24 @@ impl fmt::Display for ServerError {
                 => write!(f, "error reading config file: {}", e),
             ServerError::ParsingConfigFile(e)
                 => write!(f, "error parsing config file: {}", e),
+            ServerError::InvalidConfig(e)
+                => write!(f, "invalid configuration: {}", e),
             ServerError::ParsingListenAddress(e)
                 => write!(f, "error parsing listen address: {}", e),
+            ServerError::InvalidCommandLine(e)
+                => write!(f, "invalid command line: {}", e),
+            ServerError::ListeningForSignals(e)
+                => write!(f, "error listening for signals: {}", e),
+            ServerError::GeneratingToken(e)
+                => write!(f, "error generating token: {}", e),
+            ServerError::ReadingImportFile(e)
+                => write!(f, "error reading import file: {}", e),
+            ServerError::Importing(e)
+                => write!(f, "error importing: {}", e),
+            ServerError::SettingUpDatabase(e)
+                => write!(f, "error setting up database: {}", e),
+            ServerError::Migrating(e)
+                => write!(f, "error migrating database schema: {}", e),
         }
     }
 }
@@ -100,7 +182,76 @@ pub(crate) enum ClientError {
     RationalValueZeroOrLess(String, Rational32),
     IntValueTooHigh(String, i32, i32),
     RationalValueTooLow(String, Rational32, Rational32),
+    RationalValueTooHigh(String, Rational32, Rational32),
+    UnknownKey(String),
     ValueIsInvalidOption(String, String, Vec<String>),
+    FailedToParseTimestampValue(String, String, ParseTimestampError),
+    FailedToParseDateValue(String, String, chrono::ParseError),
+    TimestampInFuture(String, DateTime<Local>),
+    FailedToParseJson(serde_json::Error),
+    IdMismatch(i64, i64),
+    ConflictingValues(String, String),
+    EmptyTimeRange(DateTime<Local>, DateTime<Local>),
+    TimeRangeOutOfBounds(String),
+    FailedToParseCursorValue(String, String, ParsePageCursorError),
+    FailedToParseCsv(csv::Error),
+    UnknownLocation(i64),
+}
+impl ClientError {
+    /// A short machine-readable identifier of the kind of error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientError::MissingValue(_) => "missing-value",
+            ClientError::FailedToParseIntValue(_, _, _) => "invalid-int-value",
+            ClientError::FailedToParseRationalValue(_, _, _) => "invalid-rational-value",
+            ClientError::IntValueZeroOrLess(_, _) => "value-zero-or-less",
+            ClientError::RationalValueZeroOrLess(_, _) => "value-zero-or-less",
+            ClientError::IntValueTooHigh(_, _, _) => "value-too-high",
+            ClientError::RationalValueTooLow(_, _, _) => "value-too-low",
+            ClientError::RationalValueTooHigh(_, _, _) => "value-too-high",
+            ClientError::UnknownKey(_) => "unknown-key",
+            ClientError::ValueIsInvalidOption(_, _, _) => "invalid-option",
+            ClientError::FailedToParseTimestampValue(_, _, _) => "invalid-timestamp",
+            ClientError::FailedToParseDateValue(_, _, _) => "invalid-date",
+            ClientError::TimestampInFuture(_, _) => "timestamp-in-future",
+            ClientError::FailedToParseJson(_) => "invalid-json",
+            ClientError::IdMismatch(_, _) => "id-mismatch",
+            ClientError::ConflictingValues(_, _) => "conflicting-values",
+            ClientError::EmptyTimeRange(_, _) => "empty-time-range",
+            ClientError::TimeRangeOutOfBounds(_) => "time-range-out-of-bounds",
+            ClientError::FailedToParseCursorValue(_, _, _) => "invalid-cursor",
+            ClientError::FailedToParseCsv(_) => "invalid-csv",
+            ClientError::UnknownLocation(_) => "unknown-location",
+        }
+    }
+
+    /// The key of the offending value, if the error pertains to a single value.
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            ClientError::MissingValue(key)
+                | ClientError::FailedToParseIntValue(key, _, _)
+                | ClientError::FailedToParseRationalValue(key, _, _)
+                | ClientError::IntValueZeroOrLess(key, _)
+                | ClientError::RationalValueZeroOrLess(key, _)
+                | ClientError::IntValueTooHigh(key, _, _)
+                | ClientError::RationalValueTooLow(key, _, _)
+                | ClientError::RationalValueTooHigh(key, _, _)
+                | ClientError::UnknownKey(key)
+                | ClientError::ValueIsInvalidOption(key, _, _)
+                | ClientError::FailedToParseTimestampValue(key, _, _)
+                | ClientError::FailedToParseDateValue(key, _, _)
+                | ClientError::TimestampInFuture(key, _)
+                | ClientError::FailedToParseCursorValue(key, _, _)
+                | ClientError::TimeRangeOutOfBounds(key)
+                => Some(key),
+            ClientError::FailedToParseJson(_) => None,
+            ClientError::FailedToParseCsv(_) => None,
+            ClientError::IdMismatch(_, _) => Some("id"),
+            ClientError::ConflictingValues(key, _) => Some(key),
+            ClientError::EmptyTimeRange(_, _) => Some("to"),
+            ClientError::UnknownLocation(_) => Some("location_id"),
+        }
+    }
 }
 impl fmt::Display for ClientError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -119,8 +270,34 @@ impl fmt::Display for ClientError {
                 => write!(f, "value {} for key {:?} is too high (> {})", value, key, max),
             ClientError::RationalValueTooLow(key, value, min)
                 => write!(f, "value {} for key {:?} is too low (< {})", value, key, min),
+            ClientError::RationalValueTooHigh(key, value, max)
+                => write!(f, "value {} for key {:?} is too high (> {})", value, key, max),
+            ClientError::UnknownKey(key)
+                => write!(f, "unknown key {:?}", key),
             ClientError::ValueIsInvalidOption(key, value, valid_options)
                 => write!(f, "value {} for key {:?} is not a valid option; valid options are {:?}", value, key, valid_options),
+            ClientError::FailedToParseTimestampValue(key, value, err)
+                => write!(f, "failed to parse value {:?} for key {:?} as a timestamp: {}", value, key, err),
+            ClientError::FailedToParseDateValue(key, value, err)
+                => write!(f, "failed to parse value {:?} for key {:?} as a date: {}", value, key, err),
+            ClientError::TimestampInFuture(key, value)
+                => write!(f, "timestamp {} for key {:?} is in the future", value, key),
+            ClientError::FailedToParseJson(err)
+                => write!(f, "failed to parse JSON: {}", err),
+            ClientError::IdMismatch(body_id, query_id)
+                => write!(f, "ID {} in request body does not match ID {} in request URI", body_id, query_id),
+            ClientError::ConflictingValues(key, other_key)
+                => write!(f, "key {:?} cannot be combined with key {:?}", key, other_key),
+            ClientError::EmptyTimeRange(start, end)
+                => write!(f, "time range from {} to {} is empty", start, end),
+            ClientError::TimeRangeOutOfBounds(key)
+                => write!(f, "value for key {:?} extends the time range beyond the supported dates", key),
+            ClientError::FailedToParseCursorValue(key, value, err)
+                => write!(f, "failed to parse value {:?} for key {:?} as a cursor: {}", value, key, err),
+            ClientError::FailedToParseCsv(err)
+                => write!(f, "failed to parse CSV: {}", err),
+            ClientError::UnknownLocation(location_id)
+                => write!(f, "unknown temperature location {}", location_id),
         }
     }
 }
@@ -128,6 +305,20 @@ impl Error for ClientError {
 }
 
 
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+struct JsonError<'a> {
+    error: &'a str,
+    key: Option<&'a str>,
+    message: String,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+struct JsonPage<'a, T> {
+    measurements: &'a [T],
+    next: Option<String>,
+}
+
+
 #[derive(Template)]
 #[template(path = "400.html")]
 struct Error400Template {
@@ -138,10 +329,20 @@ struct Error400Template {
 #[template(path = "403.html")]
 struct Error403Template;
 
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate {
+    failed: bool,
+}
+
 #[derive(Template)]
 #[template(path = "403_ro.html")]
 struct Error403ReadOnlyTemplate;
 
+#[derive(Template)]
+#[template(path = "403_scope.html")]
+struct Error403ScopeTemplate;
+
 #[derive(Template)]
 #[template(path = "404.html")]
 struct Error404Template;
@@ -162,9 +363,13 @@ struct RedirectTemplate {
 #[template(path = "list.html")]
 struct ListTemplate {
     token: AuthToken,
+    range: TimeRange,
     measurements: Vec<BloodPressureMeasurement>,
     days_and_measurements: Vec<DailyBloodPressureMeasurements>,
-    statistics: Option<MeasurementStatistics<BloodPressureMeasurement>>,
+    statistics: Option<MeasurementStatistics>,
+    guideline: BloodPressureGuideline,
+    /// The number of measurements in each category of the guideline.
+    category_counts: Vec<(BloodPressureCategory, usize)>,
 }
 impl ListTemplate {
     fn measurements_with_spo2(&self) -> impl Iterator<Item = &BloodPressureMeasurement> {
@@ -178,18 +383,20 @@ impl ListTemplate {
 #[template(path = "mass_list.html")]
 struct MassListTemplate {
     token: AuthToken,
+    range: TimeRange,
     measurements: Vec<BodyMassMeasurement>,
-    statistics: Option<MeasurementStatistics<BodyMassMeasurement>>,
+    statistics: Option<MeasurementStatistics>,
 }
 
 #[derive(Template)]
 #[template(path = "temperature_list.html")]
 struct TemperatureListTemplate {
     token: AuthToken,
+    range: TimeRange,
     measurements: Vec<BodyTemperatureMeasurement>,
     temperature_locations: Vec<BodyTemperatureLocation>,
     default_temperature_location_id: i64,
-    statistics: Option<MeasurementStatistics<BodyTemperatureMeasurement>>,
+    statistics: Option<MeasurementStatistics>,
 }
 impl TemperatureListTemplate {
     fn location_id_to_name(&self) -> HashMap<i64, &String> {
@@ -204,16 +411,119 @@ impl TemperatureListTemplate {
 #[template(path = "sugar_list.html")]
 struct SugarListTemplate {
     token: AuthToken,
+    range: TimeRange,
     measurements: Vec<BloodSugarMeasurement>,
-    statistics: Option<MeasurementStatistics<BloodSugarMeasurement>>,
+    statistics: Option<MeasurementStatistics>,
 }
 
 #[derive(Template)]
 #[template(path = "long_term_sugar_list.html")]
 struct LongTermSugarListTemplate {
     token: AuthToken,
+    range: TimeRange,
     measurements: Vec<LongTermBloodSugarMeasurement>,
-    statistics: Option<MeasurementStatistics<LongTermBloodSugarMeasurement>>,
+    statistics: Option<MeasurementStatistics>,
+}
+
+
+#[derive(Template)]
+#[template(path = "custom_types.html")]
+struct CustomTypesTemplate {
+    token: AuthToken,
+    measurement_types: Vec<CustomMeasurementType>,
+}
+
+#[derive(Template)]
+#[template(path = "custom_list.html")]
+struct CustomListTemplate {
+    token: AuthToken,
+    range: TimeRange,
+    measurement_type: CustomMeasurementType,
+    measurements: Vec<CustomMeasurement>,
+    statistics: Option<MeasurementStatistics>,
+}
+
+
+#[derive(Template)]
+#[template(path = "edit.html")]
+struct EditTemplate {
+    measurement: BloodPressureMeasurement,
+}
+
+#[derive(Template)]
+#[template(path = "mass_edit.html")]
+struct MassEditTemplate {
+    measurement: BodyMassMeasurement,
+}
+
+#[derive(Template)]
+#[template(path = "temperature_edit.html")]
+struct TemperatureEditTemplate {
+    measurement: BodyTemperatureMeasurement,
+    temperature_locations: Vec<BodyTemperatureLocation>,
+}
+
+#[derive(Template)]
+#[template(path = "sugar_edit.html")]
+struct SugarEditTemplate {
+    measurement: BloodSugarMeasurement,
+}
+
+#[derive(Template)]
+#[template(path = "long_term_sugar_edit.html")]
+struct LongTermSugarEditTemplate {
+    measurement: LongTermBloodSugarMeasurement,
+}
+
+#[derive(Template)]
+#[template(path = "custom_edit.html")]
+struct CustomEditTemplate {
+    measurement_type: CustomMeasurementType,
+    measurement: CustomMeasurement,
+}
+
+#[derive(Template)]
+#[template(path = "profile.html")]
+struct ProfileTemplate {
+    token: AuthToken,
+    user: User,
+    profile: Profile,
+}
+impl ProfileTemplate {
+    fn sex_str(&self) -> &'static str {
+        self.profile.sex
+            .map(|s| s.as_str())
+            .unwrap_or("")
+    }
+}
+
+#[derive(Template)]
+#[template(path = "shares.html")]
+struct SharesTemplate {
+    token: AuthToken,
+    share_tokens: Vec<ShareToken>,
+    /// The link of a share token that has just been created; it cannot be shown again later.
+    new_link: Option<String>,
+    now: DateTime<Local>,
+    /// The configured custom measurement types, each of which can also be shared on its own.
+    custom_types: Vec<CustomMeasurementType>,
+}
+impl SharesTemplate {
+    /// The names and titles of the scopes that can be shared with this token.
+    fn shareable_scopes(&self) -> Vec<(String, String)> {
+        let custom_scopes = self.custom_types.iter()
+            .map(|mt| (mt.scope(), mt.title()));
+        SCOPE_PAGES.iter()
+            .map(|(scope, _page, _href, title)| (scope.clone(), *title))
+            .chain(custom_scopes)
+            .filter(|(scope, _title)| self.token.can_read(scope.clone()))
+            .map(|(scope, title)| (scope.name().into_owned(), title.to_owned()))
+            .collect()
+    }
+
+    fn is_active(&self, share_token: &ShareToken) -> bool {
+        share_token.is_active_at(self.now)
+    }
 }
 
 
@@ -271,12 +581,32 @@ fn respond_500() -> Result<Response<Full<Bytes>>, Infallible> {
 
     // can't do much except unwrap/expect here, as this *is* the error handler
     let response = Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
         .header("Content-Type", "text/html; charset=utf-8")
         .body(body)
         .expect("failed to create response");
     Ok(response)
 }
 
+/// The counterpart of `respond_500` for the API and import endpoints.
+fn respond_json_500() -> Result<Response<Full<Bytes>>, Infallible> {
+    let error = JsonError {
+        error: "internal-error",
+        key: None,
+        message: "something went wrong; tell the people responsible to check the logs".to_owned(),
+    };
+
+    // as in respond_500, this is the error handler
+    let json = serde_json::to_string(&error)
+        .expect("failed to serialize error");
+    let response = Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(json)))
+        .expect("failed to create response");
+    Ok(response)
+}
+
 async fn respond_400(error: ClientError) -> Result<Response<Full<Bytes>>, Infallible> {
     let template = Error400Template {
         error,
@@ -311,6 +641,20 @@ async fn respond_403_ro() -> Result<Response<Full<Bytes>>, Infallible> {
     ).await
 }
 
+async fn respond_403_scope() -> Result<Response<Full<Bytes>>, Infallible> {
+    let template = Error403ScopeTemplate;
+    let mut headers = HashMap::new();
+    headers.insert(
+        "Forbidden-Reason".to_owned(),
+        "token-scope".to_owned(),
+    );
+    respond_template(
+        &template,
+        403,
+        &headers,
+    ).await
+}
+
 async fn respond_404() -> Result<Response<Full<Bytes>>, Infallible> {
     let template = Error404Template;
     respond_template(
@@ -339,10 +683,184 @@ async fn respond_405(allowed_methods: &[Method]) -> Result<Response<Full<Bytes>>
     ).await
 }
 
+async fn respond_csv(file_name: &str, csv_result: Result<String, csv::Error>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let csv = match csv_result {
+        Ok(c) => c,
+        Err(e) => {
+            error!("error exporting {} as CSV: {}", file_name, e);
+            return respond_500();
+        },
+    };
+
+    respond_attachment(file_name, "text/csv; charset=utf-8", csv).await
+}
+
+async fn respond_attachment(file_name: &str, content_type: &str, body: String) -> Result<Response<Full<Bytes>>, Infallible> {
+    let response_res = Response::builder()
+        .status(200)
+        .header("Content-Type", content_type)
+        .header("Content-Disposition", format!("attachment; filename=\"{}\"", file_name))
+        .body(Full::new(Bytes::from(body)));
+    match response_res {
+        Ok(r) => Ok(r),
+        Err(e) => {
+            error!("failed to construct response for {}: {}", file_name, e);
+            respond_500()
+        },
+    }
+}
+
+async fn respond_json<T: Serialize + ?Sized>(value: &T, status: u16) -> Result<Response<Full<Bytes>>, Infallible> {
+    let json = match serde_json::to_string(value) {
+        Ok(j) => j,
+        Err(e) => {
+            error!("error serializing value to JSON: {}", e);
+            return respond_json_500();
+        },
+    };
+
+    let response_res = Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(json)));
+    match response_res {
+        Ok(r) => Ok(r),
+        Err(e) => {
+            error!("failed to create response: {}", e);
+            respond_json_500()
+        },
+    }
+}
+
+/// Responds with a page of measurements, each with its cursor. `measurements` may contain one
+/// measurement more than the page's limit, signalling that a next page exists.
+async fn respond_json_page<T: Serialize>(
+    page_uri_noslash: &str,
+    query_kv: &HashMap<String, String>,
+    page: &Page,
+    mut measurements: Vec<(PageCursor, T)>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let limit = page.limit
+        .and_then(|l| usize::try_from(l).ok())
+        .unwrap_or(measurements.len());
+
+    let mut next = None;
+    if measurements.len() > limit && limit > 0 {
+        let base_uri: Url = {
+            let base_uri_str = &CONFIG
+                .get().expect("cannot get config")
+                .read().await
+                .base_url;
+            match base_uri_str.parse() {
+                Ok(bus) => bus,
+                Err(e) => {
+                    error!("failed to parse URI {:?}: {}", base_uri_str, e);
+                    return respond_json_500();
+                },
+            }
+        };
+
+        let cursor = measurements[limit - 1].0;
+        let mut keys: Vec<&String> = query_kv.keys()
+            .filter(|k| k.as_str() != "cursor")
+            .collect();
+        keys.sort();
+        let mut query_serializer = form_urlencoded::Serializer::new(String::new());
+        for key in keys {
+            query_serializer.append_pair(key, &query_kv[key]);
+        }
+        query_serializer.append_pair("cursor", &cursor.to_string());
+        let next_uri_noslash = format!("{}?{}", page_uri_noslash, query_serializer.finish());
+
+        match base_uri.join(&next_uri_noslash) {
+            Ok(nu) => next = Some(nu.to_string()),
+            Err(e) => {
+                error!("failed to join {} and {}: {}", base_uri, next_uri_noslash, e);
+                return respond_json_500();
+            },
+        }
+    }
+
+    measurements.truncate(limit);
+    let json_measurements: Vec<T> = measurements.into_iter()
+        .map(|(_cursor, m)| m)
+        .collect();
+    let json_page = JsonPage {
+        measurements: &json_measurements,
+        next,
+    };
+    respond_json(&json_page, 200).await
+}
+
+async fn respond_measurement_json<T: MeasurementType>(measurement_type: &T, measurement: T::Measurement, status: u16) -> Result<Response<Full<Bytes>>, Infallible> {
+    let json_measurement = {
+        let config_guard = CONFIG
+            .get().expect("config is set")
+            .read().await;
+        measurement_type.to_json(measurement, &config_guard)
+    };
+    respond_json(&json_measurement, status).await
+}
+
+async fn respond_json_error(status: u16, kind: &str, key: Option<&str>, message: String) -> Result<Response<Full<Bytes>>, Infallible> {
+    let error = JsonError {
+        error: kind,
+        key,
+        message,
+    };
+    respond_json(&error, status).await
+}
+
+async fn respond_json_400(error: ClientError) -> Result<Response<Full<Bytes>>, Infallible> {
+    respond_json_error(400, error.kind(), error.key(), error.to_string()).await
+}
+
+async fn respond_json_403_ro() -> Result<Response<Full<Bytes>>, Infallible> {
+    let mut response = respond_json_error(403, "token-read-only", None, "the token does not allow entering new data".to_owned()).await?;
+    response.headers_mut().insert(
+        "Forbidden-Reason",
+        http::HeaderValue::from_static("token-read-only"),
+    );
+    Ok(response)
+}
+
+async fn respond_json_403_scope() -> Result<Response<Full<Bytes>>, Infallible> {
+    let mut response = respond_json_error(403, "token-scope", None, "the token does not allow access to these data".to_owned()).await?;
+    response.headers_mut().insert(
+        "Forbidden-Reason",
+        http::HeaderValue::from_static("token-scope"),
+    );
+    Ok(response)
+}
+
+async fn respond_json_404(measurement_id: i64) -> Result<Response<Full<Bytes>>, Infallible> {
+    respond_json_error(404, "not-found", Some("id"), format!("no measurement with ID {}", measurement_id)).await
+}
+
+async fn respond_204() -> Result<Response<Full<Bytes>>, Infallible> {
+    let response_res = Response::builder()
+        .status(204)
+        .body(Full::new(Bytes::new()));
+    match response_res {
+        Ok(r) => Ok(r),
+        Err(e) => {
+            error!("failed to create response: {}", e);
+            respond_json_500()
+        },
+    }
+}
+
 async fn redirect_to_self(parts: Parts) -> Result<Response<Full<Bytes>>, Infallible> {
     let req_uri_string = parts.uri.to_string();
     let req_uri_noslash = req_uri_string.trim_start_matches('/');
+    redirect_to(req_uri_noslash).await
+}
+
+async fn redirect_to(req_uri_noslash: &str) -> Result<Response<Full<Bytes>>, Infallible> {
+    redirect_to_with_headers(req_uri_noslash, HashMap::new()).await
+}
 
+async fn redirect_to_with_headers(req_uri_noslash: &str, mut headers: HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
     let base_uri: Url = {
         let base_uri_str = &CONFIG
             .get().expect("cannot get config")
@@ -368,7 +886,6 @@ async fn redirect_to_self(parts: Parts) -> Result<Response<Full<Bytes>>, Infalli
     let template = RedirectTemplate {
         url: page_uri_string.clone(),
     };
-    let mut headers = HashMap::new();
     headers.insert(String::from("Location"), page_uri_string);
 
     respond_template(
@@ -378,521 +895,420 @@ async fn redirect_to_self(parts: Parts) -> Result<Response<Full<Bytes>>, Infalli
     ).await
 }
 
-async fn get_index(token: &AuthToken) -> Result<Response<Full<Bytes>>, Infallible> {
-    let mut recent_measurements = match get_recent_blood_pressure_measurements(Duration::days(3*31)).await {
+async fn get_list<T: MeasurementType>(measurement_type: &T, token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let range = match get_time_range(token, query_kv, measurement_type.lookback_days()) {
+        Ok(r) => r,
+        Err(e) => return respond_400(e).await,
+    };
+    let mut recent_measurements = match measurement_type.get_range(storage(), user.id, &range, &Page::all()).await {
         Ok(rm) => rm,
         Err(e) => {
             error!("error obtaining recent measurements: {}", e);
             return respond_500();
         },
     };
-    recent_measurements.sort_by_key(|m| m.timestamp);
-
-    // group measurements by day
-    let hours = {
-        let config_guard = CONFIG
-            .get().unwrap()
-            .read().await;
-        config_guard.hours
-    };
-    let mut day_to_measurements: BTreeMap<String, DailyBloodPressureMeasurements> = BTreeMap::new();
-    let mut max_measurement: Option<BloodPressureMeasurement> = None;
-    let mut min_measurement: Option<BloodPressureMeasurement> = None;
-    for measurement in &recent_measurements {
-        if let Some(mm) = &mut max_measurement {
-            *mm = mm.values_max(&measurement);
-        } else {
-            max_measurement = Some(measurement.clone());
-        }
-
-        if let Some(mm) = &mut min_measurement {
-            *mm = mm.values_min(&measurement);
-        } else {
-            min_measurement = Some(measurement.clone());
-        }
-
-        let mut day = measurement.timestamp.date_naive();
-        if measurement.timestamp.hour() < hours.morning_start {
-            // count this as (the evening of) the previous day
-            day = day.pred_opt().expect("no previous day?!");
-        }
+    recent_measurements.sort_by_key(|m| m.timestamp());
 
-        let date_string = day.format("%Y-%m-%d").to_string();
-
-        let entry = day_to_measurements
-            .entry(date_string.clone())
-            .or_insert_with(|| DailyBloodPressureMeasurements::new_empty(date_string));
-
-        let this_hour = measurement.timestamp.hour();
-
-        if this_hour < hours.morning_start && entry.evening.is_none() {
-            // night (previous day)
-            entry.evening = Some(measurement.clone());
-        } else if this_hour >= hours.morning_start && this_hour < hours.morning_end && entry.morning.is_none() {
-            // morning
-            entry.morning = Some(measurement.clone());
-        } else if this_hour >= hours.midday_start && this_hour < hours.midday_end && entry.midday.is_none() {
-            // midday
-            entry.midday = Some(measurement.clone());
-        } else if this_hour >= hours.evening_start && entry.evening.is_none() {
-            // night
-            entry.evening = Some(measurement.clone());
-        } else {
-            entry.other.push(measurement.clone());
-        }
-    }
+    let statistics = if recent_measurements.is_empty() {
+        None
+    } else {
+        Some(measurement_type.statistics(&recent_measurements))
+    };
 
-    let days_and_measurements: Vec<DailyBloodPressureMeasurements> = day_to_measurements
-        .values()
-        .rev()
-        .map(|v| v.clone())
-        .collect();
+    measurement_type.respond_list(token, range, recent_measurements, statistics).await
+}
 
-    let statistics = if recent_measurements.len() > 0 {
-        // calculate percentiles
-        let average = BloodPressureMeasurement::average(&recent_measurements);
-        let quasi_q1 = BloodPressureMeasurement::quasi_n_tile(&recent_measurements, 1, 4);
-        let quasi_q2 = BloodPressureMeasurement::quasi_n_tile(&recent_measurements, 1, 2);
-        let quasi_q3 = BloodPressureMeasurement::quasi_n_tile(&recent_measurements, 3, 4);
-
-        Some(MeasurementStatistics {
-            maximum: max_measurement.unwrap(),
-            minimum: min_measurement.unwrap(),
-            average,
-            quasi_q1,
-            quasi_q2,
-            quasi_q3,
-        })
-    } else {
-        None
+async fn get_api<T: MeasurementType>(measurement_type: &T, token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let range = match get_time_range(token, query_kv, measurement_type.lookback_days()) {
+        Ok(r) => r,
+        Err(e) => return respond_json_400(e).await,
+    };
+    let page = match get_api_page(query_kv) {
+        Ok(p) => p,
+        Err(e) => return respond_json_400(e).await,
+    };
+    let measurements = match measurement_type.get_range(storage(), user.id, &range, &page_with_lookahead(&page)).await {
+        Ok(ms) => ms,
+        Err(e) => {
+            error!("error obtaining measurements: {}", e);
+            return respond_json_500();
+        },
     };
 
-    let template = ListTemplate {
-        token: token.clone(),
-        measurements: recent_measurements,
-        days_and_measurements,
-        statistics,
+    let json_measurements: Vec<(PageCursor, T::Json)> = {
+        let config_guard = CONFIG
+            .get().expect("config is set")
+            .read().await;
+        measurements.into_iter()
+            .map(|m| (PageCursor::new(m.timestamp(), m.id()), measurement_type.to_json(m, &config_guard)))
+            .collect()
     };
 
-    respond_template(
-        &template,
-        200,
-        &HashMap::new(),
-    ).await
+    respond_json_page(&measurement_type.api_page_noslash(), query_kv, &page, json_measurements).await
 }
 
-async fn get_mass(token: &AuthToken) -> Result<Response<Full<Bytes>>, Infallible> {
-    let mut recent_measurements = match get_recent_mass_measurements(Duration::days(3*31)).await {
-        Ok(rm) => rm,
+async fn get_api_statistics<T: MeasurementType>(measurement_type: &T, token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let range = match get_time_range(token, query_kv, measurement_type.lookback_days()) {
+        Ok(r) => r,
+        Err(e) => return respond_json_400(e).await,
+    };
+    let measurements = match measurement_type.get_range(storage(), user.id, &range, &Page::all()).await {
+        Ok(ms) => ms,
         Err(e) => {
-            error!("error obtaining recent measurements: {}", e);
-            return respond_500();
+            error!("error obtaining measurements: {}", e);
+            return respond_json_500();
         },
     };
-    recent_measurements.sort_by_key(|m| m.timestamp);
-    recent_measurements.reverse();
 
-    let mut max_measurement: Option<BodyMassMeasurement> = None;
-    let mut min_measurement: Option<BodyMassMeasurement> = None;
-    for measurement in &recent_measurements {
-        if let Some(mm) = &mut max_measurement {
-            *mm = mm.values_max(&measurement);
-        } else {
-            max_measurement = Some(measurement.clone());
-        }
+    respond_json(&measurement_type.statistics(&measurements), 200).await
+}
 
-        if let Some(mm) = &mut min_measurement {
-            *mm = mm.values_min(&measurement);
-        } else {
-            min_measurement = Some(measurement.clone());
-        }
+async fn post_list<T: MeasurementType, B>(measurement_type: &T, req: Request<B>, token: &AuthToken, user: &User) -> Result<Response<Full<Bytes>>, Infallible>
+    where B: Body, B::Error: fmt::Display {
+    if !token.can_write(measurement_type.scope()) {
+        return respond_403_ro().await;
     }
 
-    let statistics = if recent_measurements.len() > 0 {
-        let average = BodyMassMeasurement::average(&recent_measurements);
-        let quasi_q1 = BodyMassMeasurement::quasi_n_tile(&recent_measurements, 1, 4);
-        let quasi_q2 = BodyMassMeasurement::quasi_n_tile(&recent_measurements, 1, 2);
-        let quasi_q3 = BodyMassMeasurement::quasi_n_tile(&recent_measurements, 3, 4);
-
-        Some(MeasurementStatistics {
-            minimum: min_measurement.unwrap(),
-            maximum: max_measurement.unwrap(),
-            average,
-            quasi_q1,
-            quasi_q3,
-            quasi_q2,
-        })
-    } else {
-        None
+    let (req_parts, req_body) = req.into_parts();
+    let req_kv = match collect_form(req_body).await {
+        Ok(kv) => kv,
+        Err(e) => {
+            error!("error reading request bytes: {}", e);
+            return respond_500();
+        },
     };
 
-    let template = MassListTemplate {
-        token: token.clone(),
-        measurements: recent_measurements,
-        statistics,
+    let mut new_measurement = match measurement_type.read_form(&req_kv, Local::now()) {
+        Ok(nm) => nm,
+        Err(e) => {
+            return respond_400(e).await;
+        },
     };
-    respond_template(
-        &template,
-        200,
-        &HashMap::new(),
-    ).await
-}
+    let reference_results = match measurement_type.check_references(storage(), std::slice::from_ref(&new_measurement)).await {
+        Ok(rr) => rr,
+        Err(e) => {
+            error!("error checking references of measurement: {}", e);
+            return respond_500();
+        },
+    };
+    if let Some(Err(e)) = reference_results.into_iter().next() {
+        return respond_400(e).await;
+    }
 
-async fn get_temperature(token: &AuthToken) -> Result<Response<Full<Bytes>>, Infallible> {
-    let mut recent_measurements = match get_recent_temperature_measurements(Duration::days(3*31)).await {
-        Ok(rm) => rm,
+    match measurement_type.add(storage(), user.id, &new_measurement).await {
+        Ok(mi) => new_measurement.set_id(mi),
         Err(e) => {
-            error!("error obtaining recent measurements: {}", e);
+            error!("error adding measurement: {}", e);
             return respond_500();
         },
     };
-    recent_measurements.sort_by_key(|m| m.timestamp);
-    recent_measurements.reverse();
+    measurement_type.check_alerts(user, &new_measurement).await;
 
-    let mut max_measurement: Option<BodyTemperatureMeasurement> = None;
-    let mut min_measurement: Option<BodyTemperatureMeasurement> = None;
-    for measurement in &recent_measurements {
-        if let Some(mm) = &mut max_measurement {
-            *mm = mm.values_max(&measurement);
-        } else {
-            max_measurement = Some(measurement.clone());
-        }
+    redirect_to_self(req_parts).await
+}
 
-        if let Some(mm) = &mut min_measurement {
-            *mm = mm.values_min(&measurement);
-        } else {
-            min_measurement = Some(measurement.clone());
-        }
+async fn get_edit<T: MeasurementType>(measurement_type: &T, token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    if !token.can_write(measurement_type.scope()) {
+        return respond_403_ro().await;
     }
 
-    let temperature_locations = match get_temperature_locations().await {
-        Ok(l) => l,
+    let measurement_id = match get_req_form_i64(query_kv, "id") {
+        Ok(mi) => mi,
+        Err(e) => return respond_400(e).await,
+    };
+    let measurement = match measurement_type.get(storage(), user.id, measurement_id).await {
+        Ok(Some(m)) => m,
+        Ok(None) => return respond_404().await,
         Err(e) => {
-            error!("error obtaining temperature locations: {}", e);
+            error!("error obtaining measurement {}: {}", measurement_id, e);
             return respond_500();
-        }
+        },
     };
 
-    let statistics = if recent_measurements.len() > 0 {
-        let average = BodyTemperatureMeasurement::average(&recent_measurements);
-        let quasi_q1 = BodyTemperatureMeasurement::quasi_n_tile(&recent_measurements, 1, 4);
-        let quasi_q2 = BodyTemperatureMeasurement::quasi_n_tile(&recent_measurements, 1, 2);
-        let quasi_q3 = BodyTemperatureMeasurement::quasi_n_tile(&recent_measurements, 3, 4);
+    measurement_type.respond_edit(measurement).await
+}
 
-        Some(MeasurementStatistics {
-            minimum: min_measurement.unwrap(),
-            maximum: max_measurement.unwrap(),
-            average,
-            quasi_q1,
-            quasi_q3,
-            quasi_q2,
-        })
-    } else {
-        None
-    };
-
-    let default_temperature_location_id = {
-        let config = CONFIG
-            .get().unwrap()
-            .read().await;
-        config.default_temperature_location_id
-    };
+async fn post_edit<T: MeasurementType, B>(measurement_type: &T, req: Request<B>, token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible>
+    where B: Body, B::Error: fmt::Display {
+    if !token.can_write(measurement_type.scope()) {
+        return respond_403_ro().await;
+    }
 
-    let template = TemperatureListTemplate {
-        token: token.clone(),
-        measurements: recent_measurements,
-        temperature_locations,
-        default_temperature_location_id,
-        statistics,
+    let measurement_id = match get_req_form_i64(query_kv, "id") {
+        Ok(mi) => mi,
+        Err(e) => return respond_400(e).await,
     };
-    respond_template(
-        &template,
-        200,
-        &HashMap::new(),
-    ).await
-}
-
-async fn get_sugar(token: &AuthToken) -> Result<Response<Full<Bytes>>, Infallible> {
-    let mut recent_measurements = match get_recent_blood_sugar_measurements(Duration::days(3*31)).await {
-        Ok(rm) => rm,
+    let old_measurement = match measurement_type.get(storage(), user.id, measurement_id).await {
+        Ok(Some(m)) => m,
+        Ok(None) => return respond_404().await,
         Err(e) => {
-            error!("error obtaining recent measurements: {}", e);
+            error!("error obtaining measurement {}: {}", measurement_id, e);
             return respond_500();
         },
     };
-    recent_measurements.sort_by_key(|m| m.timestamp);
-    recent_measurements.reverse();
-
-    let mut max_measurement: Option<BloodSugarMeasurement> = None;
-    let mut min_measurement: Option<BloodSugarMeasurement> = None;
-    for measurement in &recent_measurements {
-        if let Some(mm) = &mut max_measurement {
-            *mm = mm.values_max(&measurement);
-        } else {
-            max_measurement = Some(measurement.clone());
-        }
-
-        if let Some(mm) = &mut min_measurement {
-            *mm = mm.values_min(&measurement);
-        } else {
-            min_measurement = Some(measurement.clone());
-        }
-    }
 
-    let statistics = if recent_measurements.len() > 0 {
-        let average = BloodSugarMeasurement::average(&recent_measurements);
-        let quasi_q1 = BloodSugarMeasurement::quasi_n_tile(&recent_measurements, 1, 4);
-        let quasi_q2 = BloodSugarMeasurement::quasi_n_tile(&recent_measurements, 1, 2);
-        let quasi_q3 = BloodSugarMeasurement::quasi_n_tile(&recent_measurements, 3, 4);
-
-        Some(MeasurementStatistics {
-            minimum: min_measurement.unwrap(),
-            maximum: max_measurement.unwrap(),
-            average,
-            quasi_q1,
-            quasi_q3,
-            quasi_q2,
-        })
-    } else {
-        None
+    let req_kv = match collect_form(req.into_body()).await {
+        Ok(kv) => kv,
+        Err(e) => {
+            error!("error reading request bytes: {}", e);
+            return respond_500();
+        },
     };
 
-    let template = SugarListTemplate {
-        token: token.clone(),
-        measurements: recent_measurements,
-        statistics,
+    let mut new_measurement = match measurement_type.read_form(&req_kv, old_measurement.timestamp()) {
+        Ok(nm) => nm,
+        Err(e) => {
+            return respond_400(e).await;
+        },
     };
-    respond_template(
-        &template,
-        200,
-        &HashMap::new(),
-    ).await
-}
-
-async fn get_long_term_sugar(token: &AuthToken) -> Result<Response<Full<Bytes>>, Infallible> {
-    let mut recent_measurements = match get_recent_long_term_blood_sugar_measurements(Duration::days(3*365)).await {
-        Ok(rm) => rm,
+    new_measurement.set_id(old_measurement.id());
+    let reference_results = match measurement_type.check_references(storage(), std::slice::from_ref(&new_measurement)).await {
+        Ok(rr) => rr,
         Err(e) => {
-            error!("error obtaining recent measurements: {}", e);
+            error!("error checking references of measurement {}: {}", measurement_id, e);
             return respond_500();
         },
     };
-    recent_measurements.sort_by_key(|m| m.timestamp);
-    recent_measurements.reverse();
-
-    let mut max_measurement: Option<LongTermBloodSugarMeasurement> = None;
-    let mut min_measurement: Option<LongTermBloodSugarMeasurement> = None;
-    for measurement in &recent_measurements {
-        if let Some(mm) = &mut max_measurement {
-            *mm = mm.values_max(&measurement);
-        } else {
-            max_measurement = Some(measurement.clone());
-        }
+    if let Some(Err(e)) = reference_results.into_iter().next() {
+        return respond_400(e).await;
+    }
 
-        if let Some(mm) = &mut min_measurement {
-            *mm = mm.values_min(&measurement);
-        } else {
-            min_measurement = Some(measurement.clone());
-        }
+    if let Err(e) = measurement_type.update(storage(), user.id, &new_measurement).await {
+        error!("error updating measurement {}: {}", measurement_id, e);
+        return respond_500();
     }
 
-    let statistics = if recent_measurements.len() > 0 {
-        let average = LongTermBloodSugarMeasurement::average(&recent_measurements);
-        let quasi_q1 = LongTermBloodSugarMeasurement::quasi_n_tile(&recent_measurements, 1, 4);
-        let quasi_q2 = LongTermBloodSugarMeasurement::quasi_n_tile(&recent_measurements, 1, 2);
-        let quasi_q3 = LongTermBloodSugarMeasurement::quasi_n_tile(&recent_measurements, 3, 4);
+    redirect_to(&measurement_type.list_page_noslash()).await
+}
 
-        Some(MeasurementStatistics {
-            minimum: min_measurement.unwrap(),
-            maximum: max_measurement.unwrap(),
-            average,
-            quasi_q1,
-            quasi_q3,
-            quasi_q2,
-        })
-    } else {
-        None
-    };
+async fn post_delete<T: MeasurementType>(measurement_type: &T, token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    if !token.can_write(measurement_type.scope()) {
+        return respond_403_ro().await;
+    }
 
-    let template = LongTermSugarListTemplate {
-        token: token.clone(),
-        measurements: recent_measurements,
-        statistics,
+    let measurement_id = match get_req_form_i64(query_kv, "id") {
+        Ok(mi) => mi,
+        Err(e) => return respond_400(e).await,
     };
-    respond_template(
-        &template,
-        200,
-        &HashMap::new(),
-    ).await
-}
+    if let Err(e) = measurement_type.remove(storage(), user.id, measurement_id).await {
+        error!("error removing measurement {}: {}", measurement_id, e);
+        return respond_500();
+    }
 
-async fn get_api_bp() -> Result<Response<Full<Bytes>>, Infallible> {
-    let mut recent_measurements = match get_recent_blood_pressure_measurements(Duration::days(3*31)).await {
-        Ok(rm) => rm,
-        Err(e) => {
-            error!("error obtaining recent measurements: {}", e);
-            return respond_500();
-        },
-    };
+    redirect_to(&measurement_type.list_page_noslash()).await
+}
 
-    recent_measurements.sort_by_key(|m| m.timestamp);
+async fn post_api<T: MeasurementType, B>(measurement_type: &T, req: Request<B>, token: &AuthToken, user: &User) -> Result<Response<Full<Bytes>>, Infallible>
+    where B: Body, B::Error: fmt::Display {
+    if !token.can_write(measurement_type.scope()) {
+        return respond_json_403_ro().await;
+    }
 
-    // make it a JSON
-    let recent_json = match serde_json::to_string(&recent_measurements) {
-        Ok(rj) => rj,
+    let req_body_bytes = match collect_bytes(req.into_body()).await {
+        Ok(b) => b,
         Err(e) => {
-            error!("error serializing recent measurements to JSON: {}", e);
-            return respond_500();
+            error!("error reading request bytes: {}", e);
+            return respond_json_500();
         },
     };
-
-    // spit it out
-    let response_res = Response::builder()
-        .status(200)
-        .header("Content-Type", "application/json")
-        .body(Full::new(Bytes::from(recent_json)));
-    match response_res {
-        Ok(r) => Ok(r),
+    let mut new_measurement: T::Measurement = match get_measurement_from_json(&req_body_bytes, -1, Local::now()) {
+        Ok(nm) => nm,
+        Err(e) => return respond_json_400(e).await,
+    };
+    if let Err(e) = measurement_type.validate(&mut new_measurement) {
+        return respond_json_400(e).await;
+    }
+    let reference_results = match measurement_type.check_references(storage(), std::slice::from_ref(&new_measurement)).await {
+        Ok(rr) => rr,
         Err(e) => {
-            error!("failed to create response: {}", e);
-            return respond_500();
+            error!("error checking references of measurement: {}", e);
+            return respond_json_500();
         },
+    };
+    if let Some(Err(e)) = reference_results.into_iter().next() {
+        return respond_json_400(e).await;
+    }
+    if let Err(e) = measurement_type.complete(storage(), user.id, std::slice::from_mut(&mut new_measurement)).await {
+        error!("error completing measurement: {}", e);
+        return respond_json_500();
     }
-}
 
-async fn get_api_mass() -> Result<Response<Full<Bytes>>, Infallible> {
-    let mut recent_measurements = match get_recent_mass_measurements(Duration::days(3*31)).await {
-        Ok(rm) => rm,
+    match measurement_type.add(storage(), user.id, &new_measurement).await {
+        Ok(mi) => new_measurement.set_id(mi),
         Err(e) => {
-            error!("error obtaining recent measurements: {}", e);
-            return respond_500();
+            error!("error adding measurement: {}", e);
+            return respond_json_500();
         },
     };
+    measurement_type.check_alerts(user, &new_measurement).await;
 
-    recent_measurements.sort_by_key(|m| m.timestamp);
+    respond_measurement_json(measurement_type, new_measurement, 201).await
+}
+
+async fn put_api<T: MeasurementType, B>(measurement_type: &T, req: Request<B>, token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible>
+    where B: Body, B::Error: fmt::Display {
+    if !token.can_write(measurement_type.scope()) {
+        return respond_json_403_ro().await;
+    }
 
-    // make it a JSON
-    let recent_json = match serde_json::to_string(&recent_measurements) {
-        Ok(rj) => rj,
+    let measurement_id = match get_req_form_i64(query_kv, "id") {
+        Ok(mi) => mi,
+        Err(e) => return respond_json_400(e).await,
+    };
+    let old_measurement = match measurement_type.get(storage(), user.id, measurement_id).await {
+        Ok(Some(m)) => m,
+        Ok(None) => return respond_json_404(measurement_id).await,
         Err(e) => {
-            error!("error serializing recent measurements to JSON: {}", e);
-            return respond_500();
+            error!("error obtaining measurement {}: {}", measurement_id, e);
+            return respond_json_500();
         },
     };
 
-    // spit it out
-    let response_res = Response::builder()
-        .status(200)
-        .header("Content-Type", "application/json")
-        .body(Full::new(Bytes::from(recent_json)));
-    match response_res {
-        Ok(r) => Ok(r),
+    let req_body_bytes = match collect_bytes(req.into_body()).await {
+        Ok(b) => b,
         Err(e) => {
-            error!("failed to create response: {}", e);
-            return respond_500();
+            error!("error reading request bytes: {}", e);
+            return respond_json_500();
         },
+    };
+    let mut new_measurement: T::Measurement = match get_measurement_from_json(&req_body_bytes, old_measurement.id(), old_measurement.timestamp()) {
+        Ok(nm) => nm,
+        Err(e) => return respond_json_400(e).await,
+    };
+    if new_measurement.id() != measurement_id {
+        return respond_json_400(ClientError::IdMismatch(new_measurement.id(), measurement_id)).await;
     }
-}
-
-async fn get_api_temperature() -> Result<Response<Full<Bytes>>, Infallible> {
-    let mut recent_measurements = match get_recent_temperature_measurements(Duration::days(3*31)).await {
-        Ok(rm) => rm,
+    if let Err(e) = measurement_type.validate(&mut new_measurement) {
+        return respond_json_400(e).await;
+    }
+    let reference_results = match measurement_type.check_references(storage(), std::slice::from_ref(&new_measurement)).await {
+        Ok(rr) => rr,
         Err(e) => {
-            error!("error obtaining recent measurements: {}", e);
-            return respond_500();
+            error!("error checking references of measurement {}: {}", measurement_id, e);
+            return respond_json_500();
         },
     };
+    if let Some(Err(e)) = reference_results.into_iter().next() {
+        return respond_json_400(e).await;
+    }
+    if let Err(e) = measurement_type.complete(storage(), user.id, std::slice::from_mut(&mut new_measurement)).await {
+        error!("error completing measurement {}: {}", measurement_id, e);
+        return respond_json_500();
+    }
 
-    recent_measurements.sort_by_key(|m| m.timestamp);
+    if let Err(e) = measurement_type.update(storage(), user.id, &new_measurement).await {
+        error!("error updating measurement {}: {}", measurement_id, e);
+        return respond_json_500();
+    }
 
-    // make it a JSON
-    let recent_json = match serde_json::to_string(&recent_measurements) {
-        Ok(rj) => rj,
-        Err(e) => {
-            error!("error serializing recent measurements to JSON: {}", e);
-            return respond_500();
-        },
-    };
+    respond_measurement_json(measurement_type, new_measurement, 200).await
+}
 
-    // spit it out
-    let response_res = Response::builder()
-        .status(200)
-        .header("Content-Type", "application/json")
-        .body(Full::new(Bytes::from(recent_json)));
-    match response_res {
-        Ok(r) => Ok(r),
-        Err(e) => {
-            error!("failed to create response: {}", e);
-            return respond_500();
-        },
+async fn delete_api<T: MeasurementType>(measurement_type: &T, token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    if !token.can_write(measurement_type.scope()) {
+        return respond_json_403_ro().await;
     }
-}
 
-async fn get_api_sugar() -> Result<Response<Full<Bytes>>, Infallible> {
-    let mut recent_measurements = match get_recent_blood_sugar_measurements(Duration::days(3*31)).await {
-        Ok(rm) => rm,
+    let measurement_id = match get_req_form_i64(query_kv, "id") {
+        Ok(mi) => mi,
+        Err(e) => return respond_json_400(e).await,
+    };
+    match measurement_type.get(storage(), user.id, measurement_id).await {
+        Ok(Some(_)) => {},
+        Ok(None) => return respond_json_404(measurement_id).await,
         Err(e) => {
-            error!("error obtaining recent measurements: {}", e);
-            return respond_500();
+            error!("error obtaining measurement {}: {}", measurement_id, e);
+            return respond_json_500();
         },
     };
+    if let Err(e) = measurement_type.remove(storage(), user.id, measurement_id).await {
+        error!("error removing measurement {}: {}", measurement_id, e);
+        return respond_json_500();
+    }
 
-    recent_measurements.sort_by_key(|m| m.timestamp);
+    respond_204().await
+}
 
-    // make it a JSON
-    let recent_json = match serde_json::to_string(&recent_measurements) {
-        Ok(rj) => rj,
-        Err(e) => {
-            error!("error serializing recent measurements to JSON: {}", e);
-            return respond_500();
+/// Handles a request to one of the pages or API endpoints of a measurement type.
+async fn handle_measurement_request<T: MeasurementType, B>(
+    measurement_type: &T,
+    endpoint: MeasurementEndpoint,
+    req: Request<B>,
+    token: &AuthToken,
+    user: &User,
+    query_kv: &HashMap<String, String>,
+) -> Result<Response<Full<Bytes>>, Infallible>
+    where B: Body, B::Error: fmt::Display {
+    match endpoint {
+        MeasurementEndpoint::List => {
+            if req.method() == Method::GET {
+                get_list(measurement_type, token, user, query_kv).await
+            } else if req.method() == Method::POST {
+                post_list(measurement_type, req, token, user).await
+            } else {
+                respond_405(&[Method::GET, Method::POST]).await
+            }
         },
-    };
-
-    // spit it out
-    let response_res = Response::builder()
-        .status(200)
-        .header("Content-Type", "application/json")
-        .body(Full::new(Bytes::from(recent_json)));
-    match response_res {
-        Ok(r) => Ok(r),
-        Err(e) => {
-            error!("failed to create response: {}", e);
-            return respond_500();
+        MeasurementEndpoint::Edit => {
+            if req.method() == Method::GET {
+                get_edit(measurement_type, token, user, query_kv).await
+            } else if req.method() == Method::POST {
+                post_edit(measurement_type, req, token, user, query_kv).await
+            } else {
+                respond_405(&[Method::GET, Method::POST]).await
+            }
+        },
+        MeasurementEndpoint::Delete => {
+            if req.method() == Method::POST {
+                post_delete(measurement_type, token, user, query_kv).await
+            } else {
+                respond_405(&[Method::POST]).await
+            }
+        },
+        MeasurementEndpoint::Api => {
+            if req.method() == Method::GET {
+                get_api(measurement_type, token, user, query_kv).await
+            } else if req.method() == Method::POST {
+                post_api(measurement_type, req, token, user).await
+            } else if req.method() == Method::PUT {
+                put_api(measurement_type, req, token, user, query_kv).await
+            } else if req.method() == Method::DELETE {
+                delete_api(measurement_type, token, user, query_kv).await
+            } else {
+                respond_405(&[Method::GET, Method::POST, Method::PUT, Method::DELETE]).await
+            }
+        },
+        MeasurementEndpoint::ApiStatistics => {
+            if req.method() == Method::GET {
+                get_api_statistics(measurement_type, token, user, query_kv).await
+            } else {
+                respond_405(&[Method::GET]).await
+            }
         },
     }
 }
 
-async fn get_api_long_term_sugar() -> Result<Response<Full<Bytes>>, Infallible> {
-    let mut recent_measurements = match get_recent_long_term_blood_sugar_measurements(Duration::days(3*365)).await {
-        Ok(rm) => rm,
-        Err(e) => {
-            error!("error obtaining recent measurements: {}", e);
-            return respond_500();
-        },
-    };
-
-    recent_measurements.sort_by_key(|m| m.timestamp);
+fn check_i32_gt0(key: &str, value: i32) -> Result<(), ClientError> {
+    if value < 0 {
+        Err(ClientError::IntValueZeroOrLess(String::from(key), value))
+    } else {
+        Ok(())
+    }
+}
 
-    // make it a JSON
-    let recent_json = match serde_json::to_string(&recent_measurements) {
-        Ok(rj) => rj,
-        Err(e) => {
-            error!("error serializing recent measurements to JSON: {}", e);
-            return respond_500();
-        },
-    };
+fn check_r32_gt0(key: &str, value: Rational32) -> Result<(), ClientError> {
+    if value < Zero::zero() {
+        Err(ClientError::RationalValueZeroOrLess(String::from(key), value))
+    } else {
+        Ok(())
+    }
+}
 
-    // spit it out
-    let response_res = Response::builder()
-        .status(200)
-        .header("Content-Type", "application/json")
-        .body(Full::new(Bytes::from(recent_json)));
-    match response_res {
-        Ok(r) => Ok(r),
-        Err(e) => {
-            error!("failed to create response: {}", e);
-            return respond_500();
-        },
+fn check_timestamp_not_future(key: &str, value: DateTime<Local>) -> Result<(), ClientError> {
+    if value > Local::now() {
+        Err(ClientError::TimestampInFuture(String::from(key), value))
+    } else {
+        Ok(())
     }
 }
 
@@ -906,11 +1322,8 @@ fn get_form_i32_gt0(req_kv: &HashMap<String, String>, key: &str) -> Result<Optio
     }
     let i32_value: i32 = string_value.parse()
         .map_err(|e| ClientError::FailedToParseIntValue(String::from(key), string_value.clone(), e))?;
-    if i32_value < 0 {
-        Err(ClientError::IntValueZeroOrLess(String::from(key), i32_value))
-    } else {
-        Ok(Some(i32_value))
-    }
+    check_i32_gt0(key, i32_value)?;
+    Ok(Some(i32_value))
 }
 
 fn get_req_form_i32_gt0(req_kv: &HashMap<String, String>, key: &str) -> Result<i32, ClientError> {
@@ -958,11 +1371,8 @@ fn get_form_r32(req_kv: &HashMap<String, String>, key: &str) -> Result<Option<Ra
 fn get_form_r32_gt0(req_kv: &HashMap<String, String>, key: &str) -> Result<Option<Rational32>, ClientError> {
     match get_form_r32(req_kv, key)? {
         Some(v) => {
-            if v < Zero::zero() {
-                Err(ClientError::RationalValueZeroOrLess(String::from(key), v))
-            } else {
-                Ok(Some(v))
-            }
+            check_r32_gt0(key, v)?;
+            Ok(Some(v))
         },
         None => Ok(None),
     }
@@ -984,308 +1394,499 @@ fn get_req_form_r32_gt0(req_kv: &HashMap<String, String>, key: &str) -> Result<R
     }
 }
 
-fn get_measurement_from_form(req_kv: &HashMap<String, String>) -> Result<BloodPressureMeasurement, ClientError> {
-    let systolic_mmhg: i32 = get_req_form_i32_gt0(&req_kv, "systolic_mmhg")?;
-    let diastolic_mmhg: i32 = get_req_form_i32_gt0(&req_kv, "diastolic_mmhg")?;
-    let pulse_bpm: i32 = get_req_form_i32_gt0(&req_kv, "pulse_bpm")?;
-    let spo2_percent: Option<i32> = get_form_i32_gt0(&req_kv, "spo2_percent")?;
-
-    if let Some(sat) = spo2_percent {
-        if sat > 100 {
-            return Err(ClientError::IntValueTooHigh("spo2_percent".into(), sat, 100));
-        }
+fn get_form_timestamp_not_future(req_kv: &HashMap<String, String>, key: &str) -> Result<Option<DateTime<Local>>, ClientError> {
+    let string_value = match req_kv.get(key) {
+        Some(sv) => sv,
+        None => return Ok(None),
+    };
+    if string_value.is_empty() {
+        return Ok(None);
     }
-
-    let local_now = Local::now();
-    let measurement = BloodPressureMeasurement::new(
-        -1,
-        local_now,
-        systolic_mmhg,
-        diastolic_mmhg,
-        pulse_bpm,
-        spo2_percent,
-    );
-    Ok(measurement)
+    let timestamp = parse_timestamp(string_value)
+        .map_err(|e| ClientError::FailedToParseTimestampValue(String::from(key), string_value.clone(), e))?;
+    check_timestamp_not_future(key, timestamp)?;
+    Ok(Some(timestamp))
 }
 
-async fn get_mass_measurement_from_form(req_kv: &HashMap<String, String>) -> Result<BodyMassMeasurement, ClientError> {
-    let mass_kg: Rational32 = get_req_form_r32_gt0(&req_kv, "mass_kg")?;
-    let waist_circum_cm: Option<Rational32> = get_form_r32_gt0(&req_kv, "waist_circum_cm")?;
+fn get_form_range_boundary(req_kv: &HashMap<String, String>, key: &str, end_of_day: bool) -> Result<Option<DateTime<Local>>, ClientError> {
+    let string_value = match req_kv.get(key) {
+        Some(sv) => sv,
+        None => return Ok(None),
+    };
+    if string_value.is_empty() {
+        return Ok(None);
+    }
 
-    let height_cm: Option<i32> = {
-        let config_guard = CONFIG
-            .get().expect("initial config not set")
-            .read().await;
-        config_guard.height_cm
-    };
-    let height_m = height_cm
-        .map(|h| Rational32::new(h, 100));
-    let square_height_m2 = height_m
-        .map(|h| h * h);
-    let bmi: Option<Rational32> = square_height_m2.map(|sqh|
-        mass_kg / sqh
-    );
+    if let Ok(date) = NaiveDate::parse_from_str(string_value, "%Y-%m-%d") {
+        // a date as the end of the range includes the whole day
+        let day = if end_of_day {
+            match date.succ_opt() {
+                Some(d) => d,
+                None => return Err(ClientError::TimeRangeOutOfBounds(String::from(key))),
+            }
+        } else {
+            date
+        };
+        let timestamp = local_from_naive(day.and_time(NaiveTime::MIN))
+            .map_err(|e| ClientError::FailedToParseTimestampValue(String::from(key), string_value.clone(), e))?;
+        return Ok(Some(timestamp));
+    }
 
-    let local_now = Local::now();
-    let measurement = BodyMassMeasurement::new(
-        -1,
-        local_now,
-        mass_kg,
-        waist_circum_cm,
-        bmi,
-    );
-    Ok(measurement)
+    let timestamp = parse_timestamp(string_value)
+        .map_err(|e| ClientError::FailedToParseTimestampValue(String::from(key), string_value.clone(), e))?;
+    Ok(Some(timestamp))
 }
 
-async fn get_temperature_measurement_from_form(req_kv: &HashMap<String, String>) -> Result<BodyTemperatureMeasurement, ClientError> {
-    let location_id: i64 = get_req_form_i64(req_kv, "location")?;
+/// Obtains the time range to display from the `from`, `to` and `days` query parameters.
+///
+/// `days` is the length of the range ending at `to` (or now) and cannot be combined with `from`.
+/// If neither `from` nor `days` are given, the range spans `default_days` days.
+///
+/// The range is limited to the range the token may read. If the token's range is limited and none
+/// of the parameters are given, the token's whole range is returned.
+fn get_time_range(token: &AuthToken, req_kv: &HashMap<String, String>, default_days: i64) -> Result<TimeRange, ClientError> {
+    let from = get_form_range_boundary(req_kv, "from", false)?;
+    let to = get_form_range_boundary(req_kv, "to", true)?;
+    let days = get_form_i32_gt0(req_kv, "days")?;
+    if days == Some(0) {
+        return Err(ClientError::IntValueZeroOrLess("days".into(), 0));
+    }
+    if !token.range.is_unbounded() && from.is_none() && to.is_none() && days.is_none() {
+        return Ok(token.range);
+    }
 
-    let temp_celsius: Rational32 = get_req_form_r32(&req_kv, "temperature_celsius")?;
-    if temp_celsius < *ABSOLUTE_ZERO_CELSIUS {
-        // temperature below absolute zero?!
-        return Err(ClientError::RationalValueTooLow("temperature_celsius".into(), temp_celsius, *ABSOLUTE_ZERO_CELSIUS));
+    let start = match (from, days) {
+        (Some(_), Some(_)) => return Err(ClientError::ConflictingValues("days".into(), "from".into())),
+        (Some(f), None) => f,
+        (None, d) => {
+            let day_count = d.map(i64::from).unwrap_or(default_days);
+            to.unwrap_or_else(Local::now)
+                .checked_sub_signed(Duration::days(day_count))
+                .ok_or_else(|| ClientError::TimeRangeOutOfBounds("days".into()))?
+        },
+    };
+    if let Some(end) = to {
+        if end <= start {
+            return Err(ClientError::EmptyTimeRange(start, end));
+        }
     }
 
-    let local_now = Local::now();
-    let measurement = BodyTemperatureMeasurement::new(
-        -1,
-        local_now,
-        location_id,
-        temp_celsius,
-    );
-    Ok(measurement)
+    Ok(TimeRange::new(Some(start), to).intersect(&token.range))
 }
 
-async fn get_sugar_measurement_from_form(req_kv: &HashMap<String, String>) -> Result<BloodSugarMeasurement, ClientError> {
-    let unit_key = match req_kv.get("sugar_unit_key") {
-        Some(uk) => uk,
-        None => return Err(ClientError::MissingValue("sugar_unit_key".to_owned())),
-    };
-    let factor_to_mmol_per_l = if unit_key == "mmol-per-l" {
-        Rational32::new(1, 1)
-    } else if unit_key == "mg-per-dl" {
-        Rational32::new(1, SUGAR_MG_PER_DL_IN_MMOL_PER_L)
-    } else {
-        return Err(ClientError::ValueIsInvalidOption(
-            "sugar_unit_key".to_owned(),
-            unit_key.clone(),
-            vec!["mmol-per-l".to_owned(), "mg-per-dl".to_owned()],
-        ));
+/// Obtains the page of measurements requested via the `limit` and `cursor` query parameters.
+fn get_api_page(req_kv: &HashMap<String, String>) -> Result<Page, ClientError> {
+    let limit = get_form_i32_gt0(req_kv, "limit")?
+        .unwrap_or(API_DEFAULT_PAGE_SIZE);
+    if limit == 0 {
+        return Err(ClientError::IntValueZeroOrLess("limit".into(), 0));
+    }
+    if limit > API_MAX_PAGE_SIZE {
+        return Err(ClientError::IntValueTooHigh("limit".into(), limit, API_MAX_PAGE_SIZE));
+    }
+
+    let after = match req_kv.get("cursor") {
+        Some(cursor_str) if !cursor_str.is_empty() => {
+            let cursor = cursor_str.parse()
+                .map_err(|e| ClientError::FailedToParseCursorValue("cursor".into(), cursor_str.clone(), e))?;
+            Some(cursor)
+        },
+        _ => None,
     };
 
-    let sugar_value: Rational32 = get_req_form_r32_gt0(&req_kv, "sugar_value")?;
-    let sugar_mmol_per_l: Rational32 = sugar_value * factor_to_mmol_per_l;
+    Ok(Page::new(after, Some(limit.into())))
+}
 
-    let local_now = Local::now();
-    let measurement = BloodSugarMeasurement::new(
-        -1,
-        local_now,
-        sugar_mmol_per_l,
-    );
-    Ok(measurement)
-}
-
-async fn get_long_term_sugar_measurement_from_form(req_kv: &HashMap<String, String>) -> Result<LongTermBloodSugarMeasurement, ClientError> {
-    let unit_key = match req_kv.get("hba1c_unit_key") {
-        Some(uk) => uk,
-        None => return Err(ClientError::MissingValue("hba1c_unit_key".to_owned())),
-    };
-    let hba1c_value: Rational32 = get_req_form_r32_gt0(&req_kv, "hba1c_value")?;
-    let local_now = Local::now();
-    if unit_key == "mmol-per-mol" {
-        Ok(LongTermBloodSugarMeasurement::new(
-            -1,
-            local_now,
-            hba1c_value,
-        ))
-    } else if unit_key == "dcct-percent" {
-        Ok(LongTermBloodSugarMeasurement::new_dcct_percent(
-            -1,
-            local_now,
-            hba1c_value,
-        ))
-    } else {
-        Err(ClientError::ValueIsInvalidOption(
-            "hba1c_unit_key".to_owned(),
-            unit_key.clone(),
-            vec!["mmol-per-mol".to_owned(), "dcct-percent".to_owned()],
-        ))
+fn get_form_bool(req_kv: &HashMap<String, String>, key: &str) -> Result<bool, ClientError> {
+    match req_kv.get(key).map(|v| v.as_str()) {
+        None|Some("")|Some("0")|Some("false") => Ok(false),
+        Some("1")|Some("true") => Ok(true),
+        Some(other) => Err(ClientError::ValueIsInvalidOption(
+            key.to_owned(),
+            other.to_owned(),
+            vec!["true".to_owned(), "false".to_owned()],
+        )),
     }
 }
 
-async fn post_index(req: Request<Incoming>, token: &AuthToken) -> Result<Response<Full<Bytes>>, Infallible> {
-    if !token.write {
-        return respond_403_ro().await;
+fn get_form_date(req_kv: &HashMap<String, String>, key: &str) -> Result<Option<NaiveDate>, ClientError> {
+    let string_value = match req_kv.get(key) {
+        Some(sv) => sv,
+        None => return Ok(None),
+    };
+    if string_value.is_empty() {
+        return Ok(None);
     }
+    let date = NaiveDate::parse_from_str(string_value, "%Y-%m-%d")
+        .map_err(|e| ClientError::FailedToParseDateValue(String::from(key), string_value.clone(), e))?;
+    Ok(Some(date))
+}
 
-    let (req_parts, req_body) = req.into_parts();
-    let req_body_bytes = match req_body.collect().await {
-        Ok(rbc) => rbc.to_bytes().to_vec(),
-        Err(e) => {
-            error!("error reading request bytes: {}", e);
-            return respond_500();
-        },
-    };
-    let req_kv: HashMap<String, String> = form_urlencoded::parse(&req_body_bytes)
-        .map(|(a, b)| (a.to_string(), b.to_string()))
-        .collect();
+fn get_req_form_date(req_kv: &HashMap<String, String>, key: &str) -> Result<NaiveDate, ClientError> {
+    match get_form_date(req_kv, key) {
+        Ok(Some(v)) => Ok(v),
+        Ok(None) => Err(ClientError::MissingValue(String::from(key))),
+        Err(e) => Err(e),
+    }
+}
 
-    let new_measurement = match get_measurement_from_form(&req_kv) {
-        Ok(nm) => nm,
-        Err(e) => {
-            return respond_400(e).await;
-        },
-    };
+fn get_form_sex(req_kv: &HashMap<String, String>, key: &str) -> Result<Option<Sex>, ClientError> {
+    match req_kv.get(key).map(|v| v.as_str()) {
+        None|Some("") => Ok(None),
+        Some(other) => other.parse()
+            .map(Some)
+            .map_err(|_| ClientError::ValueIsInvalidOption(
+                key.to_owned(),
+                other.to_owned(),
+                vec![Sex::Female.to_string(), Sex::Male.to_string()],
+            )),
+    }
+}
 
-    match add_blood_pressure_measurement(&new_measurement).await {
-        Ok(rm) => rm,
-        Err(e) => {
-            error!("error adding measurement: {}", e);
-            return respond_500();
-        },
-    };
+/// Extends the page by one measurement to find out whether another page follows it.
+fn page_with_lookahead(page: &Page) -> Page {
+    Page::new(page.after, page.limit.map(|l| l + 1))
+}
 
-    redirect_to_self(req_parts).await
+/// Whether any alert rules apply to measurements of the scope.
+async fn has_alert_rules(scope: Scope) -> bool {
+    CONFIG
+        .get().expect("config is set")
+        .read().await
+        .alert_rules.iter()
+        .any(|r| r.metric.scope() == scope)
 }
 
-async fn post_mass(req: Request<Incoming>, token: &AuthToken) -> Result<Response<Full<Bytes>>, Infallible> {
-    if !token.write {
-        return respond_403_ro().await;
-    }
+/// The range of measurements that alert rules for a new measurement are evaluated against.
+fn alert_history_range(timestamp: DateTime<Local>) -> TimeRange {
+    TimeRange::new(
+        Some(timestamp - Duration::days(ALERT_HISTORY_DAYS)),
+        Some(timestamp + Duration::seconds(1)),
+    )
+}
 
-    let (req_parts, req_body) = req.into_parts();
-    let req_body_bytes = match req_body.collect().await {
-        Ok(rbc) => rbc.to_bytes().to_vec(),
+/// Evaluates the alert rules against a new measurement and its predecessors and raises the triggered
+/// alerts, which are returned along with the tasks running their actions.
+async fn check_alerts<M: Measurement>(user: &User, new_measurement: &M) -> (Vec<Alert>, AlertActions) {
+    if !has_alert_rules(M::SCOPE).await {
+        return (Vec::new(), AlertActions::none());
+    }
+    // the stored measurements contain the derived values (e.g. the BMI)
+    let range = alert_history_range(new_measurement.timestamp());
+    let mut measurements = match M::get_range(storage(), user.id, &range, &Page::all()).await {
+        Ok(ms) => ms,
         Err(e) => {
-            error!("error reading request bytes: {}", e);
-            return respond_500();
+            error!("error obtaining measurements to evaluate alert rules: {}", e);
+            return (Vec::new(), AlertActions::none());
         },
     };
+    measurements.retain(|m| (m.timestamp(), m.id()) <= (new_measurement.timestamp(), new_measurement.id()));
+
+    let config_guard = CONFIG
+        .get().expect("config is set")
+        .read().await;
+    let alerts = triggered_alerts(&user.name, &config_guard.alert_rules, &measurements);
+    let raised = alerts.iter()
+        .map(|(_rule, alert)| alert.clone())
+        .collect();
+    let actions = raise_alerts(alerts, &config_guard.alert_actions, config_guard.smtp.as_ref());
+    (raised, actions)
+}
+
+async fn collect_form<B: Body>(req_body: B) -> Result<HashMap<String, String>, B::Error> {
+    let req_body_bytes = req_body.collect().await?
+        .to_bytes();
     let req_kv: HashMap<String, String> = form_urlencoded::parse(&req_body_bytes)
         .map(|(a, b)| (a.to_string(), b.to_string()))
         .collect();
+    Ok(req_kv)
+}
 
-    let new_measurement = match get_mass_measurement_from_form(&req_kv).await {
-        Ok(nm) => nm,
-        Err(e) => {
-            return respond_400(e).await;
-        },
-    };
+async fn collect_bytes<B: Body>(req_body: B) -> Result<Bytes, B::Error> {
+    let req_body_bytes = req_body.collect().await?
+        .to_bytes();
+    Ok(req_body_bytes)
+}
 
-    match add_mass_measurement(&new_measurement).await {
-        Ok(rm) => rm,
+/// Parses a measurement from a JSON request body.
+///
+/// If the ID or the timestamp is missing from the JSON object, the given values are used instead.
+fn get_measurement_from_json<M: DeserializeOwned>(body: &[u8], default_id: i64, default_timestamp: DateTime<Local>) -> Result<M, ClientError> {
+    let mut value: serde_json::Value = serde_json::from_slice(body)
+        .map_err(ClientError::FailedToParseJson)?;
+    if let Some(object) = value.as_object_mut() {
+        if !object.contains_key("id") {
+            object.insert("id".to_owned(), default_id.into());
+        }
+        if !object.contains_key("timestamp") {
+            let timestamp_value = crate::ser_de::serde_datetime_local::serialize(&default_timestamp, serde_json::value::Serializer)
+                .map_err(ClientError::FailedToParseJson)?;
+            object.insert("timestamp".to_owned(), timestamp_value);
+        }
+    }
+    serde_json::from_value(value)
+        .map_err(ClientError::FailedToParseJson)
+}
+
+async fn get_profile(token: &AuthToken, user: &User) -> Result<Response<Full<Bytes>>, Infallible> {
+    let profile = match storage().get_profile(user.id).await {
+        Ok(p) => p,
         Err(e) => {
-            error!("error adding measurement: {}", e);
+            error!("error obtaining profile: {}", e);
             return respond_500();
         },
     };
 
-    redirect_to_self(req_parts).await
+    let template = ProfileTemplate {
+        token: token.clone(),
+        user: user.clone(),
+        profile,
+    };
+    respond_template(
+        &template,
+        200,
+        &HashMap::new(),
+    ).await
 }
 
-async fn post_temperature(req: Request<Incoming>, token: &AuthToken) -> Result<Response<Full<Bytes>>, Infallible> {
-    if !token.write {
+async fn post_profile<B>(req: Request<B>, token: &AuthToken, user: &User) -> Result<Response<Full<Bytes>>, Infallible>
+    where B: Body, B::Error: fmt::Display {
+    if !token.can_write(Scope::Profile) {
         return respond_403_ro().await;
     }
 
-    let (req_parts, req_body) = req.into_parts();
-    let req_body_bytes = match req_body.collect().await {
-        Ok(rbc) => rbc.to_bytes().to_vec(),
+    let req_kv = match collect_form(req.into_body()).await {
+        Ok(kv) => kv,
         Err(e) => {
             error!("error reading request bytes: {}", e);
             return respond_500();
         },
     };
-    let req_kv: HashMap<String, String> = form_urlencoded::parse(&req_body_bytes)
-        .map(|(a, b)| (a.to_string(), b.to_string()))
-        .collect();
 
-    let new_measurement = match get_temperature_measurement_from_form(&req_kv).await {
-        Ok(nm) => nm,
-        Err(e) => {
-            return respond_400(e).await;
-        },
+    let birth_date = match get_form_date(&req_kv, "birth_date") {
+        Ok(bd) => bd,
+        Err(e) => return respond_400(e).await,
     };
-
-    match add_temperature_measurement(&new_measurement).await {
-        Ok(rm) => rm,
-        Err(e) => {
-            error!("error adding measurement: {}", e);
-            return respond_500();
-        },
+    let sex = match get_form_sex(&req_kv, "sex") {
+        Ok(s) => s,
+        Err(e) => return respond_400(e).await,
+    };
+    let profile = Profile {
+        birth_date,
+        sex,
+        heights: Vec::new(),
     };
 
-    redirect_to_self(req_parts).await
+    if let Err(e) = storage().update_profile(user.id, &profile).await {
+        error!("error updating profile: {}", e);
+        return respond_500();
+    }
+
+    redirect_to("profile").await
 }
 
-async fn post_sugar(req: Request<Incoming>, token: &AuthToken) -> Result<Response<Full<Bytes>>, Infallible> {
-    if !token.write {
+async fn post_height<B>(req: Request<B>, token: &AuthToken, user: &User) -> Result<Response<Full<Bytes>>, Infallible>
+    where B: Body, B::Error: fmt::Display {
+    if !token.can_write(Scope::Profile) {
         return respond_403_ro().await;
     }
 
-    let (req_parts, req_body) = req.into_parts();
-    let req_body_bytes = match req_body.collect().await {
-        Ok(rbc) => rbc.to_bytes().to_vec(),
+    let req_kv = match collect_form(req.into_body()).await {
+        Ok(kv) => kv,
         Err(e) => {
             error!("error reading request bytes: {}", e);
             return respond_500();
         },
     };
-    let req_kv: HashMap<String, String> = form_urlencoded::parse(&req_body_bytes)
-        .map(|(a, b)| (a.to_string(), b.to_string()))
-        .collect();
 
-    let new_measurement = match get_sugar_measurement_from_form(&req_kv).await {
-        Ok(nm) => nm,
-        Err(e) => {
-            return respond_400(e).await;
-        },
+    let effective_date = match get_req_form_date(&req_kv, "effective_date") {
+        Ok(ed) => ed,
+        Err(e) => return respond_400(e).await,
+    };
+    let height_cm = match get_req_form_i32_gt0(&req_kv, "height_cm") {
+        Ok(h) => h,
+        Err(e) => return respond_400(e).await,
     };
 
-    match add_blood_sugar_measurement(&new_measurement).await {
-        Ok(rm) => rm,
+    if let Err(e) = storage().set_height(user.id, &BodyHeight::new(-1, effective_date, height_cm)).await {
+        error!("error setting height: {}", e);
+        return respond_500();
+    }
+
+    redirect_to("profile").await
+}
+
+async fn post_delete_height(token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    if !token.can_write(Scope::Profile) {
+        return respond_403_ro().await;
+    }
+
+    let height_id = match get_req_form_i64(query_kv, "id") {
+        Ok(hi) => hi,
+        Err(e) => return respond_400(e).await,
+    };
+    if let Err(e) = storage().remove_height(user.id, height_id).await {
+        error!("error removing height {}: {}", height_id, e);
+        return respond_500();
+    }
+
+    redirect_to("profile").await
+}
+
+async fn respond_shares(token: &AuthToken, user: &User, new_link: Option<String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let share_tokens = match storage().get_share_tokens(user.id).await {
+        Ok(st) => st,
         Err(e) => {
-            error!("error adding measurement: {}", e);
+            error!("error obtaining share tokens: {}", e);
             return respond_500();
         },
     };
 
-    redirect_to_self(req_parts).await
+    let template = SharesTemplate {
+        token: token.clone(),
+        share_tokens,
+        new_link,
+        now: Local::now(),
+        custom_types: custom_measurement_types().await,
+    };
+    respond_template(
+        &template,
+        200,
+        &HashMap::new(),
+    ).await
 }
 
-async fn post_long_term_sugar(req: Request<Incoming>, token: &AuthToken) -> Result<Response<Full<Bytes>>, Infallible> {
-    if !token.write {
+async fn get_shares(token: &AuthToken, user: &User) -> Result<Response<Full<Bytes>>, Infallible> {
+    if !token.can_write_any() {
         return respond_403_ro().await;
     }
 
-    let (req_parts, req_body) = req.into_parts();
-    let req_body_bytes = match req_body.collect().await {
-        Ok(rbc) => rbc.to_bytes().to_vec(),
+    respond_shares(token, user, None).await
+}
+
+async fn post_shares<B>(req: Request<B>, token: &AuthToken, user: &User) -> Result<Response<Full<Bytes>>, Infallible>
+    where B: Body, B::Error: fmt::Display {
+    if !token.can_write_any() {
+        return respond_403_ro().await;
+    }
+
+    let req_kv = match collect_form(req.into_body()).await {
+        Ok(kv) => kv,
         Err(e) => {
             error!("error reading request bytes: {}", e);
             return respond_500();
         },
     };
-    let req_kv: HashMap<String, String> = form_urlencoded::parse(&req_body_bytes)
-        .map(|(a, b)| (a.to_string(), b.to_string()))
+
+    let description = req_kv.get("description")
+        .map(|d| d.trim().to_owned())
+        .unwrap_or_default();
+    let days = match get_req_form_i32_gt0(&req_kv, "days") {
+        Ok(d) => d,
+        Err(e) => return respond_400(e).await,
+    };
+    if days > MAX_SHARE_DAYS {
+        return respond_400(ClientError::IntValueTooHigh("days".into(), days, MAX_SHARE_DAYS)).await;
+    }
+    let start = match get_form_range_boundary(&req_kv, "from", false) {
+        Ok(s) => s,
+        Err(e) => return respond_400(e).await,
+    };
+    let end = match get_form_range_boundary(&req_kv, "to", true) {
+        Ok(e) => e,
+        Err(e) => return respond_400(e).await,
+    };
+    if let (Some(s), Some(e)) = (start, end) {
+        if e <= s {
+            return respond_400(ClientError::EmptyTimeRange(s, e)).await;
+        }
+    }
+
+    let custom_scopes: Vec<Scope> = custom_measurement_types().await
+        .iter()
+        .map(|mt| mt.scope())
         .collect();
+    let mut scopes = Vec::new();
+    for scope in Scope::ALL.iter().chain(custom_scopes.iter()) {
+        match get_form_bool(&req_kv, &format!("scope-{}", scope.name())) {
+            Ok(true) => {},
+            Ok(false) => continue,
+            Err(e) => return respond_400(e).await,
+        }
+        // a share token cannot read more than the token that creates it
+        if !token.can_read(scope.clone()) {
+            return respond_403_scope().await;
+        }
+        scopes.push(scope.clone());
+    }
+    if scopes.is_empty() {
+        return respond_400(ClientError::MissingValue("scope".into())).await;
+    }
 
-    let new_measurement = match get_long_term_sugar_measurement_from_form(&req_kv).await {
-        Ok(nm) => nm,
+    let token_value = match generate_token_value() {
+        Ok(tv) => tv,
         Err(e) => {
-            return respond_400(e).await;
+            error!("failed to generate share token: {}", e);
+            return respond_500();
         },
     };
+    let now = Local::now();
+    let share_token = ShareToken {
+        id: -1,
+        token_sha256: sha256_hex(&token_value),
+        description,
+        scopes: scopes.into_iter().collect(),
+        range: TimeRange::new(start, end),
+        created: now,
+        expires: now + Duration::days(days.into()),
+        revoked: None,
+    };
+    if let Err(e) = storage().add_share_token(user.id, &share_token).await {
+        error!("error adding share token: {}", e);
+        return respond_500();
+    }
 
-    match add_long_term_blood_sugar_measurement(&new_measurement).await {
-        Ok(rm) => rm,
+    let base_url: Url = {
+        let config_guard = CONFIG
+            .get().expect("config is set")
+            .read().await;
+        match config_guard.base_url.parse() {
+            Ok(bu) => bu,
+            Err(e) => {
+                error!("failed to parse base URL: {}", e);
+                return respond_500();
+            },
+        }
+    };
+    let mut link = match base_url.join("login") {
+        Ok(l) => l,
         Err(e) => {
-            error!("error adding measurement: {}", e);
+            error!("failed to join {} and login: {}", base_url, e);
             return respond_500();
         },
     };
+    // browsers do not send the fragment, so the token stays out of server and proxy logs; the login
+    // page submits it
+    link.set_fragment(Some(&format!("token={}", token_value)));
 
-    redirect_to_self(req_parts).await
+    respond_shares(token, user, Some(link.to_string())).await
+}
+
+async fn post_revoke_share(token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    if !token.can_write_any() {
+        return respond_403_ro().await;
+    }
+
+    let share_token_id = match get_req_form_i64(query_kv, "id") {
+        Ok(sti) => sti,
+        Err(e) => return respond_400(e).await,
+    };
+    if let Err(e) = storage().revoke_share_token(user.id, share_token_id, Local::now()).await {
+        error!("error revoking share token {}: {}", share_token_id, e);
+        return respond_500();
+    }
+
+    redirect_to("shares").await
 }
 
 async fn respond_static_file(file_name: &str) -> Result<Response<Full<Bytes>>, Infallible> {
@@ -1338,122 +1939,787 @@ async fn respond_static_file(file_name: &str) -> Result<Response<Full<Bytes>>, I
     }
 }
 
-async fn handle_request(req: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+async fn get_export_decimal_places() -> usize {
+    CONFIG
+        .get().expect("config is set")
+        .read().await
+        .export_decimal_places
+}
+
+async fn get_export_bp(token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let range = match get_time_range(token, query_kv, DEFAULT_LOOKBACK_DAYS) {
+        Ok(r) => r,
+        Err(e) => return respond_400(e).await,
+    };
+    let measurements = match BloodPressureMeasurement::get_range(storage(), user.id, &range, &Page::all()).await {
+        Ok(ms) => ms,
+        Err(e) => {
+            error!("error obtaining measurements: {}", e);
+            return respond_500();
+        },
+    };
+
+    respond_csv("bp.csv", blood_pressure_to_csv(&measurements)).await
+}
+
+async fn get_export_mass(token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let range = match get_time_range(token, query_kv, DEFAULT_LOOKBACK_DAYS) {
+        Ok(r) => r,
+        Err(e) => return respond_400(e).await,
+    };
+    let measurements = match BodyMassMeasurement::get_range(storage(), user.id, &range, &Page::all()).await {
+        Ok(ms) => ms,
+        Err(e) => {
+            error!("error obtaining measurements: {}", e);
+            return respond_500();
+        },
+    };
+    let decimal_places = get_export_decimal_places().await;
+
+    respond_csv("mass.csv", mass_to_csv(&measurements, decimal_places)).await
+}
+
+async fn get_export_temperature(token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let range = match get_time_range(token, query_kv, DEFAULT_LOOKBACK_DAYS) {
+        Ok(r) => r,
+        Err(e) => return respond_400(e).await,
+    };
+    let measurements = match BodyTemperatureMeasurement::get_range(storage(), user.id, &range, &Page::all()).await {
+        Ok(ms) => ms,
+        Err(e) => {
+            error!("error obtaining measurements: {}", e);
+            return respond_500();
+        },
+    };
+    let temperature_locations = match storage().get_temperature_locations().await {
+        Ok(tl) => tl,
+        Err(e) => {
+            error!("error obtaining temperature locations: {}", e);
+            return respond_500();
+        },
+    };
+    let decimal_places = get_export_decimal_places().await;
+
+    respond_csv("temperature.csv", temperature_to_csv(&measurements, &temperature_locations, decimal_places)).await
+}
+
+async fn get_export_sugar(token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let range = match get_time_range(token, query_kv, DEFAULT_LOOKBACK_DAYS) {
+        Ok(r) => r,
+        Err(e) => return respond_400(e).await,
+    };
+    let measurements = match BloodSugarMeasurement::get_range(storage(), user.id, &range, &Page::all()).await {
+        Ok(ms) => ms,
+        Err(e) => {
+            error!("error obtaining measurements: {}", e);
+            return respond_500();
+        },
+    };
+    let decimal_places = get_export_decimal_places().await;
+
+    respond_csv("sugar.csv", sugar_to_csv(&measurements, decimal_places)).await
+}
+
+async fn get_export_long_term_sugar(token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let range = match get_time_range(token, query_kv, LONG_TERM_SUGAR_LOOKBACK_DAYS) {
+        Ok(r) => r,
+        Err(e) => return respond_400(e).await,
+    };
+    let measurements = match LongTermBloodSugarMeasurement::get_range(storage(), user.id, &range, &Page::all()).await {
+        Ok(ms) => ms,
+        Err(e) => {
+            error!("error obtaining measurements: {}", e);
+            return respond_500();
+        },
+    };
+    let decimal_places = get_export_decimal_places().await;
+
+    respond_csv("long-term-sugar.csv", long_term_sugar_to_csv(&measurements, decimal_places)).await
+}
+
+async fn get_export_fhir(token: &AuthToken, user: &User, query_kv: &HashMap<String, String>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let range = match get_time_range(token, query_kv, DEFAULT_LOOKBACK_DAYS) {
+        Ok(r) => r,
+        Err(e) => return respond_400(e).await,
+    };
+
+    let decimal_places = get_export_decimal_places().await;
+
+    // only the measurements that the token can read are exported
+    let mut bundle = FhirBundle::new(decimal_places);
+    for fhir_export in FHIR_EXPORTS.iter() {
+        if !token.can_read(fhir_export.scope()) {
+            continue;
+        }
+        if let Err(e) = fhir_export.add_to_bundle(&mut bundle, storage(), user.id, &range).await {
+            error!("error obtaining {:?} measurements: {}", fhir_export.scope(), e);
+            return respond_500();
+        }
+    }
+    let bundle_json = bundle.to_json(&Local::now()).to_string();
+
+    respond_attachment("fhir.json", "application/fhir+json", bundle_json).await
+}
+
+async fn post_import<B>(req: Request<B>, token: &AuthToken, user: &User, query_kv: &HashMap<String, String>, kind: ImportKind) -> Result<Response<Full<Bytes>>, Infallible>
+    where B: Body, B::Error: fmt::Display {
+    if !token.can_write(kind.scope()) {
+        return respond_json_403_ro().await;
+    }
+
+    let dry_run = match get_form_bool(query_kv, "dry_run") {
+        Ok(dr) => dr,
+        Err(e) => return respond_json_400(e).await,
+    };
+    let csv_data = match collect_bytes(req.into_body()).await {
+        Ok(cd) => cd,
+        Err(e) => {
+            error!("error reading request bytes: {}", e);
+            return respond_json_500();
+        },
+    };
+
+    match import_csv(user, kind, &csv_data, dry_run).await {
+        Ok((report, _alert_actions)) => respond_json(&report, 200).await,
+        Err(ImportError::ReadingHeaders(e)) => respond_json_400(ClientError::FailedToParseCsv(e)).await,
+        Err(e) => {
+            error!("error importing CSV: {}", e);
+            respond_json_500()
+        },
+    }
+}
+
+/// The name, URL and title of the pages whose data the token can read.
+fn readable_pages(token: &AuthToken) -> Vec<(&'static str, &'static str, &'static str)> {
+    SCOPE_PAGES.iter()
+        .filter(|(scope, _page, _href, _title)| match scope {
+            // the overview lists the custom types that can be read
+            Scope::AllCustom => token.can_read_any_custom(),
+            _ => token.can_read(scope.clone()),
+        })
+        .map(|(_scope, page, href, title)| (*page, *href, *title))
+        .collect()
+}
+
+/// The value of the `Set-Cookie` header that stores the session ID in the session cookie for the
+/// given number of seconds; an empty session ID with no time removes the session cookie.
+async fn session_cookie(session_id: &str, max_age_s: u64) -> Result<String, url::ParseError> {
+    let base_url: Url = CONFIG
+        .get().expect("config is set")
+        .read().await
+        .base_url
+        .parse()?;
+
+    let secure = if base_url.scheme() == "https" { "; Secure" } else { "" };
+    Ok(format!(
+        "{}={}; Path={}; Max-Age={}; HttpOnly; SameSite=Strict{}",
+        SESSION_COOKIE_NAME, session_id, base_url.path(), max_age_s, secure,
+    ))
+}
+
+/// How a request is authenticated.
+enum Credentials {
+    /// A token sent in the `Authorization` header using the `Bearer` scheme.
+    Bearer(String),
+    /// The ID of a session sent in the session cookie.
+    Session(String),
+}
+
+/// The credentials sent in the `Authorization` header or, without that header, in the session
+/// cookie.
+fn get_credentials<B>(req: &Request<B>) -> Option<Credentials> {
+    if let Some(authorization) = req.headers().get(AUTHORIZATION) {
+        // a malformed header does not fall back to the cookie
+        let (scheme, credentials) = authorization
+            .to_str().ok()?
+            .trim()
+            .split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return None;
+        }
+        return Some(Credentials::Bearer(credentials.trim().to_owned()));
+    }
+
+    get_session_id(req)
+        .map(Credentials::Session)
+}
+
+/// The session ID sent in the session cookie.
+fn get_session_id<B>(req: &Request<B>) -> Option<String> {
+    for cookie_header in req.headers().get_all(COOKIE) {
+        let cookie_str = match cookie_header.to_str() {
+            Ok(cs) => cs,
+            Err(_) => continue,
+        };
+        for cookie in cookie_str.split(';') {
+            if let Some((name, value)) = cookie.trim().split_once('=') {
+                if name == SESSION_COOKIE_NAME && !value.is_empty() {
+                    return Some(value.to_owned());
+                }
+            }
+        }
+    }
+    None
+}
+
+async fn find_token(token_value: &str) -> Option<AuthToken> {
+    CONFIG
+        .get().expect("config is set")
+        .read().await
+        .auth_tokens
+        .iter()
+        .find(|t| t.matches(token_value))
+        .cloned()
+}
+
+/// Finds the token with the given value among the tokens in the configuration and the active share
+/// tokens, returning it along with its user.
+async fn authenticate(token_value: &str) -> Result<Option<(AuthToken, User)>, DatabaseError> {
+    if let Some(token) = find_token(token_value).await {
+        let user = storage().get_or_add_user(&token.user).await?;
+        return Ok(Some((token, user)));
+    }
+
+    authenticate_share_token(token_value).await
+}
+
+/// Finds the active share token with the given value, returning it along with its user.
+async fn authenticate_share_token(token_value: &str) -> Result<Option<(AuthToken, User)>, DatabaseError> {
+    find_share_token(&sha256_hex(token_value)).await
+}
+
+/// Finds the active share token with the given hash, returning it along with its user.
+async fn find_share_token(share_token_sha256: &str) -> Result<Option<(AuthToken, User)>, DatabaseError> {
+    match storage().get_share_token_by_hash(share_token_sha256).await? {
+        Some((user, share_token)) if share_token.is_active_at(Local::now()) => {
+            let token = AuthToken::from_share_token(&user, &share_token);
+            Ok(Some((token, user)))
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Finds the active session with the given ID, returning the token it has been started with along
+/// with its user. If the token has been removed from the configuration or revoked in the meantime,
+/// the session is no longer valid.
+async fn authenticate_session(session_id: &str) -> Result<Option<(AuthToken, User)>, DatabaseError> {
+    let (user, session) = match storage().get_session_by_hash(&sha256_hex(session_id)).await? {
+        Some((u, s)) if s.is_active_at(Local::now()) => (u, s),
+        _ => return Ok(None),
+    };
+
+    let config_token = CONFIG
+        .get().expect("config is set")
+        .read().await
+        .auth_tokens
+        .iter()
+        .find(|t| t.user == user.name && t.session_key().as_deref() == Some(session.token_key.as_str()))
+        .cloned();
+    if let Some(token) = config_token {
+        return Ok(Some((token, user)));
+    }
+
+    match find_share_token(&session.token_key).await? {
+        Some((token, share_user)) if share_user.id == user.id => Ok(Some((token, share_user))),
+        _ => Ok(None),
+    }
+}
+
+/// Starts a session with the token, whose value has been presented by the browser, and redirects to
+/// the page, setting the session cookie.
+async fn log_in_and_redirect_to(token: &AuthToken, user: &User, token_value: &str, page: &str) -> Result<Response<Full<Bytes>>, Infallible> {
+    let session_id = match generate_token_value() {
+        Ok(si) => si,
+        Err(e) => {
+            error!("failed to generate session ID: {}", e);
+            return respond_500();
+        },
+    };
+    let session_days = CONFIG
+        .get().expect("config is set")
+        .read().await
+        .session_days;
+    let now = Local::now();
+    let expires = match now.checked_add_signed(Duration::days(session_days.into())) {
+        Some(e) => e,
+        None => {
+            error!("session_days {} is too large", session_days);
+            return respond_500();
+        },
+    };
+    let session = Session {
+        id: -1,
+        session_sha256: sha256_hex(&session_id),
+        // share tokens are identified by their hash, as in storage
+        token_key: token.session_key()
+            .unwrap_or_else(|| sha256_hex(token_value)),
+        created: now,
+        expires,
+    };
+
+    if let Err(e) = storage().remove_expired_sessions(now).await {
+        error!("error removing expired sessions: {}", e);
+        return respond_500();
+    }
+    if let Err(e) = storage().add_session(user.id, &session).await {
+        error!("error adding session: {}", e);
+        return respond_500();
+    }
+
+    let cookie = match session_cookie(&session_id, u64::from(session_days) * 24 * 60 * 60).await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("failed to parse base URL: {}", e);
+            return respond_500();
+        },
+    };
+    let mut headers = HashMap::new();
+    headers.insert("Set-Cookie".to_owned(), cookie);
+    redirect_to_with_headers(page, headers).await
+}
+
+async fn get_login() -> Result<Response<Full<Bytes>>, Infallible> {
+    let template = LoginTemplate {
+        failed: false,
+    };
+    respond_template(
+        &template,
+        200,
+        &HashMap::new(),
+    ).await
+}
+
+async fn post_login<B>(req: Request<B>) -> Result<Response<Full<Bytes>>, Infallible>
+    where B: Body, B::Error: fmt::Display {
+    let req_kv = match collect_form(req.into_body()).await {
+        Ok(kv) => kv,
+        Err(e) => {
+            error!("error reading request bytes: {}", e);
+            return respond_500();
+        },
+    };
+
+    let token_value = req_kv.get("token")
+        .map(|tv| tv.as_str())
+        .unwrap_or("");
+    // share links submit the form automatically; they only log in with share tokens, so that a link
+    // cannot log the visitor in to an account that they might then record measurements in
+    let share_only = match get_form_bool(&req_kv, "share") {
+        Ok(so) => so,
+        Err(e) => return respond_400(e).await,
+    };
+    let authentication = if share_only {
+        authenticate_share_token(token_value).await
+    } else {
+        authenticate(token_value).await
+    };
+    let (token, user) = match authentication {
+        Ok(Some(tu)) => tu,
+        Ok(None) => {
+            let template = LoginTemplate {
+                failed: true,
+            };
+            return respond_template(
+                &template,
+                403,
+                &HashMap::new(),
+            ).await;
+        },
+        Err(e) => {
+            error!("failed to authenticate: {}", e);
+            return respond_500();
+        },
+    };
+
+    // start at the first page the token can read
+    let first_page_href = readable_pages(&token)
+        .first()
+        .map(|(_page, href, _title)| *href)
+        .unwrap_or("./");
+    log_in_and_redirect_to(&token, &user, token_value, first_page_href).await
+}
+
+async fn post_logout<B>(req: Request<B>) -> Result<Response<Full<Bytes>>, Infallible> {
+    if let Some(session_id) = get_session_id(&req) {
+        if let Err(e) = storage().remove_session(&sha256_hex(&session_id)).await {
+            error!("error removing session: {}", e);
+            return respond_500();
+        }
+    }
+
+    let cookie = match session_cookie("", 0).await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("failed to parse base URL: {}", e);
+            return respond_500();
+        },
+    };
+    let mut headers = HashMap::new();
+    headers.insert("Set-Cookie".to_owned(), cookie);
+    redirect_to_with_headers("login", headers).await
+}
+
+/// The scope that a token must be able to read to access the endpoint at the given path. Endpoints
+/// without a scope (such as the FHIR export, which only contains the readable measurements) are
+/// accessible with every token.
+fn endpoint_scope(path: &str) -> Option<Scope> {
+    let measurement_scope = measurement_endpoint_scope::<BloodPressureMeasurement>(path)
+        .or_else(|| measurement_endpoint_scope::<BodyMassMeasurement>(path))
+        .or_else(|| measurement_endpoint_scope::<BodyTemperatureMeasurement>(path))
+        .or_else(|| measurement_endpoint_scope::<BloodSugarMeasurement>(path))
+        .or_else(|| measurement_endpoint_scope::<LongTermBloodSugarMeasurement>(path));
+    if measurement_scope.is_some() {
+        return measurement_scope;
+    }
+
+    match path {
+        "/export/bp.csv"|"/import/bp.csv"
+            => Some(Scope::Bp),
+        "/export/mass.csv"|"/import/mass.csv"
+            => Some(Scope::Mass),
+        "/export/temperature.csv"|"/import/temperature.csv"
+            => Some(Scope::Temperature),
+        "/export/sugar.csv"|"/import/sugar.csv"
+            => Some(Scope::Sugar),
+        "/export/long-term-sugar.csv"|"/import/long-term-sugar.csv"
+            => Some(Scope::LongTermSugar),
+        "/profile"|"/height"|"/delete-height"
+            => Some(Scope::Profile),
+        _ => custom_endpoint(path)
+            .map(|(_endpoint, type_name)| Scope::Custom(type_name.to_owned())),
+    }
+}
+
+fn measurement_endpoint_scope<M: Measurement>(path: &str) -> Option<Scope> {
+    measurement_endpoint::<M>(path)
+        .map(|_| M::SCOPE)
+}
+
+async fn handle_request<B>(req: Request<B>) -> Result<Response<Full<Bytes>>, Infallible>
+    where B: Body, B::Error: fmt::Display {
     if let Some(cap) = STATIC_PATH_RE.captures(req.uri().path()) {
         let static_file_name = cap.get(1).expect("filename captured");
         return respond_static_file(static_file_name.as_str()).await;
     }
 
-    // endpoints that do not require authentication before this line
+    if req.uri().path() == "/login" {
+        if req.method() == Method::GET {
+            return get_login().await;
+        } else if req.method() == Method::POST {
+            return post_login(req).await;
+        } else {
+            return respond_405(&[Method::GET, Method::POST]).await;
+        }
+    } else if req.uri().path() == "/logout" {
+        if req.method() == Method::POST {
+            return post_logout(req).await;
+        } else {
+            return respond_405(&[Method::POST]).await;
+        }
+    }
 
-    // check for token
-    let query_str = match req.uri().query() {
-        None => return respond_403().await,
-        Some(q) => q,
-    };
+    let query_str = req.uri().query().unwrap_or("");
     let query_kv: HashMap<String, String> = form_urlencoded::parse(query_str.as_bytes())
         .map(|(k, v)| (k.to_string(), v.to_string()))
         .collect();
-    let token_value = match query_kv.get("token") {
-        None => return respond_403().await,
-        Some(tv) => tv,
-    };
 
-    let token_opt = {
-        CONFIG
-            .get().expect("config is set")
-            .read().await
-            .auth_tokens
-            .iter()
-            .filter(|t| &t.token == token_value)
-            .map(|t| t.clone())
-            .nth(0)
+    // endpoints that do not require authentication before this line
+
+    // check for token
+    let authentication = match get_credentials(&req) {
+        None => return respond_403().await,
+        Some(Credentials::Bearer(token_value)) => authenticate(&token_value).await,
+        Some(Credentials::Session(session_id)) => authenticate_session(&session_id).await,
     };
-    let token = match token_opt {
-        Some(t) => t,
-        None => {
+    let (token, user) = match authentication {
+        Ok(Some(tu)) => tu,
+        Ok(None) => {
             // no such token found, at all
             return respond_403().await;
         },
+        Err(e) => {
+            error!("failed to authenticate: {}", e);
+            if req.uri().path().starts_with("/api/") || req.uri().path().starts_with("/import/") {
+                return respond_json_500();
+            } else {
+                return respond_500();
+            }
+        },
     };
 
     // authenticated-only endpoints beyond this line
 
-    if req.uri().path() == "/" {
+    // writing is checked by each handler, as some GET endpoints (edit forms) also require it
+    if let Some(scope) = endpoint_scope(req.uri().path()) {
+        if !token.can_read(scope) {
+            if req.uri().path().starts_with("/api/") || req.uri().path().starts_with("/import/") {
+                return respond_json_403_scope().await;
+            } else {
+                return respond_403_scope().await;
+            }
+        }
+    }
+
+    let path = req.uri().path().to_owned();
+    if let Some(endpoint) = measurement_endpoint::<BloodPressureMeasurement>(&path) {
+        return handle_measurement_request(&BuiltInType::<BloodPressureMeasurement>::new(), endpoint, req, &token, &user, &query_kv).await;
+    }
+    if let Some(endpoint) = measurement_endpoint::<BodyMassMeasurement>(&path) {
+        return handle_measurement_request(&BuiltInType::<BodyMassMeasurement>::new(), endpoint, req, &token, &user, &query_kv).await;
+    }
+    if let Some(endpoint) = measurement_endpoint::<BodyTemperatureMeasurement>(&path) {
+        return handle_measurement_request(&BuiltInType::<BodyTemperatureMeasurement>::new(), endpoint, req, &token, &user, &query_kv).await;
+    }
+    if let Some(endpoint) = measurement_endpoint::<BloodSugarMeasurement>(&path) {
+        return handle_measurement_request(&BuiltInType::<BloodSugarMeasurement>::new(), endpoint, req, &token, &user, &query_kv).await;
+    }
+    if let Some(endpoint) = measurement_endpoint::<LongTermBloodSugarMeasurement>(&path) {
+        return handle_measurement_request(&BuiltInType::<LongTermBloodSugarMeasurement>::new(), endpoint, req, &token, &user, &query_kv).await;
+    }
+    if let Some((endpoint, type_name)) = custom_endpoint(&path) {
+        return handle_custom_request::<B>(endpoint, type_name, req, &token, &user, &query_kv).await;
+    }
+
+    if req.uri().path() == "/custom" {
         if req.method() == Method::GET {
-            get_index(&token).await
-        } else if req.method() == Method::POST {
-            post_index(req, &token).await
+            get_custom_types(&token).await
         } else {
-            respond_405(&[Method::GET, Method::POST]).await
+            respond_405(&[Method::GET]).await
         }
-    } else if req.uri().path() == "/mass" {
+    } else if req.uri().path() == "/profile" {
         if req.method() == Method::GET {
-            get_mass(&token).await
+            get_profile(&token, &user).await
         } else if req.method() == Method::POST {
-            post_mass(req, &token).await
+            post_profile(req, &token, &user).await
         } else {
             respond_405(&[Method::GET, Method::POST]).await
         }
-    } else if req.uri().path() == "/temperature" {
-        if req.method() == Method::GET {
-            get_temperature(&token).await
-        } else if req.method() == Method::POST {
-            post_temperature(req, &token).await
+    } else if req.uri().path() == "/height" {
+        if req.method() == Method::POST {
+            post_height(req, &token, &user).await
         } else {
-            respond_405(&[Method::GET, Method::POST]).await
+            respond_405(&[Method::POST]).await
+        }
+    } else if req.uri().path() == "/delete-height" {
+        if req.method() == Method::POST {
+            post_delete_height(&token, &user, &query_kv).await
+        } else {
+            respond_405(&[Method::POST]).await
         }
-    } else if req.uri().path() == "/sugar" {
+    } else if req.uri().path() == "/shares" {
         if req.method() == Method::GET {
-            get_sugar(&token).await
+            get_shares(&token, &user).await
         } else if req.method() == Method::POST {
-            post_sugar(req, &token).await
+            post_shares(req, &token, &user).await
         } else {
             respond_405(&[Method::GET, Method::POST]).await
         }
-    } else if req.uri().path() == "/long-term-sugar" {
+    } else if req.uri().path() == "/revoke-share" {
+        if req.method() == Method::POST {
+            post_revoke_share(&token, &user, &query_kv).await
+        } else {
+            respond_405(&[Method::POST]).await
+        }
+    } else if req.uri().path() == "/export/bp.csv" {
         if req.method() == Method::GET {
-            get_long_term_sugar(&token).await
-        } else if req.method() == Method::POST {
-            post_long_term_sugar(req, &token).await
+            get_export_bp(&token, &user, &query_kv).await
         } else {
-            respond_405(&[Method::GET, Method::POST]).await
+            respond_405(&[Method::GET]).await
         }
-    } else if req.uri().path() == "/api/bp" {
+    } else if req.uri().path() == "/export/mass.csv" {
         if req.method() == Method::GET {
-            get_api_bp().await
+            get_export_mass(&token, &user, &query_kv).await
         } else {
             respond_405(&[Method::GET]).await
         }
-    } else if req.uri().path() == "/api/mass" {
+    } else if req.uri().path() == "/export/temperature.csv" {
         if req.method() == Method::GET {
-            get_api_mass().await
+            get_export_temperature(&token, &user, &query_kv).await
         } else {
             respond_405(&[Method::GET]).await
         }
-    } else if req.uri().path() == "/api/temperature" {
+    } else if req.uri().path() == "/export/sugar.csv" {
         if req.method() == Method::GET {
-            get_api_temperature().await
+            get_export_sugar(&token, &user, &query_kv).await
         } else {
             respond_405(&[Method::GET]).await
         }
-    } else if req.uri().path() == "/api/sugar" {
+    } else if req.uri().path() == "/export/long-term-sugar.csv" {
         if req.method() == Method::GET {
-            get_api_sugar().await
+            get_export_long_term_sugar(&token, &user, &query_kv).await
         } else {
             respond_405(&[Method::GET]).await
         }
-    } else if req.uri().path() == "/api/long-term-sugar" {
+    } else if req.uri().path() == "/export/fhir.json" {
         if req.method() == Method::GET {
-            get_api_long_term_sugar().await
+            get_export_fhir(&token, &user, &query_kv).await
         } else {
             respond_405(&[Method::GET]).await
         }
+    } else if req.uri().path() == "/import/bp.csv" {
+        if req.method() == Method::POST {
+            post_import(req, &token, &user, &query_kv, ImportKind::BloodPressure).await
+        } else {
+            respond_405(&[Method::POST]).await
+        }
+    } else if req.uri().path() == "/import/mass.csv" {
+        if req.method() == Method::POST {
+            post_import(req, &token, &user, &query_kv, ImportKind::Mass).await
+        } else {
+            respond_405(&[Method::POST]).await
+        }
+    } else if req.uri().path() == "/import/temperature.csv" {
+        if req.method() == Method::POST {
+            post_import(req, &token, &user, &query_kv, ImportKind::Temperature).await
+        } else {
+            respond_405(&[Method::POST]).await
+        }
+    } else if req.uri().path() == "/import/sugar.csv" {
+        if req.method() == Method::POST {
+            post_import(req, &token, &user, &query_kv, ImportKind::Sugar).await
+        } else {
+            respond_405(&[Method::POST]).await
+        }
+    } else if req.uri().path() == "/import/long-term-sugar.csv" {
+        if req.method() == Method::POST {
+            post_import(req, &token, &user, &query_kv, ImportKind::LongTermSugar).await
+        } else {
+            respond_405(&[Method::POST]).await
+        }
     } else {
         respond_404().await
     }
 }
 
+async fn run_import(args: &[OsString]) -> Result<(), ServerError> {
+    let usage = || ServerError::InvalidCommandLine(
+        "usage: beepee CONFIG import KIND FILE [--dry-run] [--user USER]".to_owned()
+    );
+
+    let kind_name = args.first()
+        .and_then(|k| k.to_str())
+        .ok_or_else(usage)?;
+    let kind = ImportKind::from_name(kind_name)
+        .ok_or_else(|| ServerError::InvalidCommandLine(format!(
+            "unknown measurement kind {:?}; valid kinds are {:?}", kind_name, ImportKind::names(),
+        )))?;
+    let path = args.get(1)
+        .map(PathBuf::from)
+        .ok_or_else(usage)?;
+    let mut dry_run = false;
+    let mut user_name = default_user();
+    let mut option_args = args[2..].iter();
+    while let Some(option_arg) = option_args.next() {
+        match option_arg.to_str() {
+            Some("--dry-run") => dry_run = true,
+            Some("--user") => {
+                user_name = option_args.next()
+                    .and_then(|u| u.to_str())
+                    .ok_or_else(usage)?
+                    .to_owned();
+            },
+            _ => return Err(usage()),
+        }
+    }
+
+    let csv_data = std::fs::read(&path)
+        .map_err(ServerError::ReadingImportFile)?;
+    let user = storage().get_or_add_user(&user_name).await
+        .map_err(ServerError::SettingUpDatabase)?;
+    let (report, alert_actions) = import_csv(&user, kind, &csv_data, dry_run).await
+        .map_err(ServerError::Importing)?;
+
+    for row_error in &report.errors {
+        println!("line {}: {}", row_error.line, row_error.message);
+    }
+    if report.dry_run {
+        println!("{} rows, {} valid; dry run, nothing inserted", report.row_count, report.valid_row_count);
+    } else {
+        println!("{} rows, {} valid, {} inserted", report.row_count, report.valid_row_count, report.inserted_count);
+    }
+    for alert in &report.alerts {
+        println!("alert: {}", alert.message);
+    }
+    // the runtime, and with it any unfinished action, ends when the command returns
+    alert_actions.finish().await;
+    Ok(())
+}
+
+fn run_generate_token(args: &[OsString]) -> Result<(), ServerError> {
+    let usage = || ServerError::InvalidCommandLine(
+        "usage: beepee CONFIG generate-token [--read-only] [--user USER]".to_owned()
+    );
+
+    let mut write = true;
+    let mut user_name = default_user();
+    let mut option_args = args.iter();
+    while let Some(option_arg) = option_args.next() {
+        match option_arg.to_str() {
+            Some("--read-only") => write = false,
+            Some("--user") => {
+                user_name = option_args.next()
+                    .and_then(|u| u.to_str())
+                    .ok_or_else(usage)?
+                    .to_owned();
+            },
+            _ => return Err(usage()),
+        }
+    }
+
+    let (token, token_hash) = generate_token()
+        .map_err(ServerError::GeneratingToken)?;
+    println!("token (only its hash is stored, so keep it now): {}", token);
+    println!("add to auth_tokens in the configuration:");
+    println!(
+        "    {{ token_hash = {}, write = {}, user = {} }}",
+        toml::Value::String(token_hash.to_string()), write, toml::Value::String(user_name),
+    );
+    Ok(())
+}
+
+async fn run_migrate(args: &[OsString]) -> Result<(), ServerError> {
+    if !args.is_empty() {
+        return Err(ServerError::InvalidCommandLine("usage: beepee CONFIG migrate".to_owned()));
+    }
+
+    let applied_versions = storage().run_migrations().await
+        .map_err(ServerError::Migrating)?;
+    if applied_versions.is_empty() {
+        println!("database schema is up to date");
+    }
+    for version in applied_versions {
+        println!("applied migration {}", version);
+    }
+    Ok(())
+}
+
+/// Records the height from the deprecated `height_cm` setting as the height of the default user
+/// unless that user already has a height.
+async fn import_legacy_height(height_cm: i32) -> Result<(), DatabaseError> {
+    let user = storage().get_or_add_user(&default_user()).await?;
+    let profile = storage().get_profile(user.id).await?;
+    if profile.heights.is_empty() {
+        storage().set_height(user.id, &BodyHeight::new(-1, Local::now().date_naive(), height_cm)).await?;
+        info!("recorded height_cm from the configuration as the height of user {:?}; the setting can be removed", user.name);
+    }
+    Ok(())
+}
+
+/// Reloads the configuration whenever SIGHUP is received.
+#[cfg(unix)]
+fn spawn_config_reloader() -> Result<(), std::io::Error> {
+    let mut hangups = signal(SignalKind::hangup())?;
+    tokio::task::spawn(async move {
+        while hangups.recv().await.is_some() {
+            info!("received SIGHUP; reloading configuration");
+            if let Err(e) = reload_config().await {
+                error!("failed to reload configuration, keeping the current one: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
 async fn run() -> Result<(), ServerError> {
     env_logger::init();
 
@@ -1465,8 +2731,53 @@ async fn run() -> Result<(), ServerError> {
     CONFIG_PATH
         .set(config_path).expect("failed to set config path");
 
+    // generating a token does not need the configuration
+    if let Some(command) = args.get(2) {
+        if command == "generate-token" {
+            return run_generate_token(&args[3..]);
+        }
+    }
+
     load_config().await?;
 
+    let (migrate_on_startup, legacy_height_cm) = {
+        let config_guard = CONFIG
+            .get().expect("no config lock")
+            .read().await;
+        init_storage(&config_guard)
+            .map_err(ServerError::SettingUpDatabase)?;
+        (config_guard.migrate_on_startup, config_guard.height_cm)
+    };
+
+    if let Some(command) = args.get(2) {
+        if command == "migrate" {
+            return run_migrate(&args[3..]).await;
+        }
+    }
+
+    if migrate_on_startup {
+        let applied_versions = storage().run_migrations().await
+            .map_err(ServerError::Migrating)?;
+        for version in applied_versions {
+            info!("applied database schema migration {}", version);
+        }
+    } else {
+        storage().check_schema_version().await
+            .map_err(ServerError::Migrating)?;
+    }
+    if let Some(height_cm) = legacy_height_cm {
+        import_legacy_height(height_cm).await
+            .map_err(ServerError::SettingUpDatabase)?;
+    }
+
+    if let Some(command) = args.get(2) {
+        if command == "import" {
+            return run_import(&args[3..]).await;
+        } else {
+            return Err(ServerError::InvalidCommandLine(format!("unknown command {:?}", command)));
+        }
+    }
+
     let addr: SocketAddr = {
         CONFIG
             .get().expect("no config lock")
@@ -1479,6 +2790,10 @@ async fn run() -> Result<(), ServerError> {
     let listener = TcpListener::bind(addr).await
         .expect("failed to bind to listen address");
 
+    #[cfg(unix)]
+    spawn_config_reloader()
+        .map_err(ServerError::ListeningForSignals)?;
+
     loop {
         let (stream, remote_addr) = listener.accept().await
             .expect("failed to accept connection");
@@ -1513,3 +2828,612 @@ fn main() -> ExitCode {
         },
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Once;
+    use tokio::sync::RwLock;
+    use crate::config::Config;
+
+    const TEST_CONFIG: &str = r#"
+        base_url = "http://beepee.example/"
+        storage = "memory"
+        http_listen = "127.0.0.1:0"
+        auth_tokens = [
+            { token = "rw", write = true },
+            { token = "ro", write = false },
+            { token = "other", write = true, user = "other" },
+            { token = "caregiver", read = ["bp"], write = ["temperature", "sugar"], user = "other" },
+            { token = "peak-flow", read = [], write = ["custom:peak-flow"], user = "other" },
+            { token_hash = "hmac-sha256$00112233445566778899aabbccddeeff$56829df140e042f581f82e7cab0bff774d3d3a56c8ec02cee31b29caac71251f", write = false },
+        ]
+        default_temperature_location_id = 1
+
+        [[alert_rules]]
+        name = "racing pulse"
+        metric = "pulse"
+        at_least = 250
+        actions = []
+
+        [[alert_rules]]
+        name = "crisis"
+        metric = "systolic"
+        at_least = 250
+        actions = ["record"]
+
+        [alert_actions]
+        record = { command = ["sh", "-c", "echo \"$BEEPEE_ALERT_USER\" >> \"${TMPDIR:-/tmp}/beepee-test-alerts\""] }
+
+        [hours]
+        morning_start = 5
+        morning_end = 13
+        midday_start = 11
+        midday_end = 20
+        evening_start = 17
+
+        [[custom_measurement_types]]
+        name = "peak-flow"
+        title = "peak expiratory flow"
+        fields = [
+            { key = "pef", unit = "l/min", min = 50, max = 900 },
+            { key = "variability", unit = "%", decimal_places = 1, optional = true },
+        ]
+
+        [[custom_measurement_types]]
+        name = "inr"
+        fields = [{ key = "inr", min = "0.5", max = 10, decimal_places = 1 }]
+    "#;
+
+    static INIT: Once = Once::new();
+
+    /// Sets up the configuration and an in-memory storage shared by all tests.
+    fn init() {
+        INIT.call_once(|| {
+            let config: Config = toml::from_str(TEST_CONFIG)
+                .expect("invalid test config");
+            init_storage(&config)
+                .expect("failed to set up storage");
+            if CONFIG.set(RwLock::new(config)).is_err() {
+                panic!("config already set");
+            }
+        });
+    }
+
+    async fn request(method: Method, uri: &str, token: Option<&str>, body: &str) -> Response<Full<Bytes>> {
+        let headers: Vec<(&str, String)> = token
+            .map(|t| ("Authorization", format!("Bearer {}", t)))
+            .into_iter()
+            .collect();
+        request_with_headers(method, uri, &headers, body).await
+    }
+
+    async fn request_with_headers(method: Method, uri: &str, headers: &[(&str, String)], body: &str) -> Response<Full<Bytes>> {
+        init();
+        let mut req_builder = Request::builder()
+            .method(method)
+            .uri(uri);
+        for (key, value) in headers {
+            req_builder = req_builder.header(*key, value);
+        }
+        let req = req_builder
+            .body(Full::new(Bytes::from(body.to_owned())))
+            .expect("failed to build request");
+        match handle_request(req).await {
+            Ok(response) => response,
+            Err(never) => match never {},
+        }
+    }
+
+    async fn body_string(response: Response<Full<Bytes>>) -> String {
+        let body_bytes = match response.into_body().collect().await {
+            Ok(c) => c.to_bytes(),
+            Err(never) => match never {},
+        };
+        String::from_utf8(body_bytes.to_vec())
+            .expect("response body is not UTF-8")
+    }
+
+    #[tokio::test]
+    async fn static_file_without_token() {
+        let response = request(Method::GET, "/static/style.css", None, "").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["Content-Type"], "text/css");
+    }
+
+    #[tokio::test]
+    async fn missing_or_unknown_token() {
+        assert_eq!(request(Method::GET, "/", None, "").await.status(), 403);
+        assert_eq!(request(Method::GET, "/?from=2024-01-01", None, "").await.status(), 403);
+        assert_eq!(request(Method::GET, "/", Some("nope"), "").await.status(), 403);
+        assert_eq!(request(Method::GET, "/api/bp", Some("nope"), "").await.status(), 403);
+    }
+
+    /// Logs in with the form and returns the session cookie to send with further requests.
+    async fn log_in(form: &str) -> (&'static str, String) {
+        let response = request(Method::POST, "/login", None, form).await;
+        assert_eq!(response.status(), 302);
+        let set_cookie = response.headers()["Set-Cookie"].to_str().unwrap();
+        let cookie = set_cookie.split(';').next().unwrap().to_owned();
+        ("Cookie", cookie)
+    }
+
+    #[tokio::test]
+    async fn login_starts_session() {
+        let response = request(Method::POST, "/login", None, "token=nope").await;
+        assert_eq!(response.status(), 403);
+
+        let response = request(Method::POST, "/login", None, "token=ro").await;
+        assert_eq!(response.status(), 302);
+        let set_cookie = response.headers()["Set-Cookie"].to_str().unwrap().to_owned();
+        assert!(set_cookie.starts_with("beepee_session="));
+        assert!(!set_cookie.starts_with("beepee_session=ro;"));
+        assert!(set_cookie.contains("HttpOnly"));
+
+        let session = log_in("token=ro").await;
+        let response = request_with_headers(Method::GET, "/", std::slice::from_ref(&session), "").await;
+        assert_eq!(response.status(), 200);
+
+        // the cookie does not accept the token itself
+        let token_cookie = ("Cookie", "beepee_session=ro".to_owned());
+        let response = request_with_headers(Method::GET, "/", &[token_cookie], "").await;
+        assert_eq!(response.status(), 403);
+
+        let response = request_with_headers(Method::POST, "/logout", std::slice::from_ref(&session), "").await;
+        assert!(response.headers()["Set-Cookie"].to_str().unwrap().contains("Max-Age=0"));
+        let response = request_with_headers(Method::GET, "/", &[session], "").await;
+        assert_eq!(response.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn import_raises_alerts_for_newest_row() {
+        init();
+        let user = storage().get_or_add_user("importer").await.unwrap();
+
+        let csv_data = b"timestamp,systolic_mmhg,diastolic_mmhg,pulse_bpm\n\
+            1990-01-01T08:00:00+01:00,120,80,260\n\
+            1990-01-02T08:00:00+01:00,120,80,60\n";
+        let (report, _alert_actions) = import_csv(&user, ImportKind::BloodPressure, csv_data, false).await.unwrap();
+        assert_eq!(report.inserted_count, 2);
+        assert_eq!(report.alerts, Vec::new());
+
+        let csv_data = b"timestamp,systolic_mmhg,diastolic_mmhg,pulse_bpm\n\
+            1990-02-02T08:00:00+01:00,120,80,270\n\
+            1990-02-01T08:00:00+01:00,120,80,60\n";
+        let (report, _alert_actions) = import_csv(&user, ImportKind::BloodPressure, csv_data, true).await.unwrap();
+        assert_eq!(report.alerts, Vec::new());
+        let (report, _alert_actions) = import_csv(&user, ImportKind::BloodPressure, csv_data, false).await.unwrap();
+        assert_eq!(report.alerts.len(), 1);
+        assert_eq!(report.alerts[0].rule, "racing pulse");
+        assert_eq!(report.alerts[0].value, "270");
+
+        // backfilling data older than the stored measurements raises no alerts
+        let csv_data = b"timestamp,systolic_mmhg,diastolic_mmhg,pulse_bpm\n\
+            1989-06-01T08:00:00+01:00,120,80,280\n";
+        let (report, _alert_actions) = import_csv(&user, ImportKind::BloodPressure, csv_data, false).await.unwrap();
+        assert_eq!(report.inserted_count, 1);
+        assert_eq!(report.alerts, Vec::new());
+    }
+
+    #[tokio::test]
+    async fn import_rejects_unknown_location() {
+        init();
+        let user = storage().get_or_add_user("location-importer").await.unwrap();
+
+        let csv_data = b"timestamp,location_id,temperature_celsius\n\
+            2001-01-01T08:00:00+01:00,1,36.7\n\
+            2001-01-02T08:00:00+01:00,42,36.8\n";
+        for dry_run in [true, false] {
+            let (report, _alert_actions) = import_csv(&user, ImportKind::Temperature, csv_data, dry_run).await.unwrap();
+            assert_eq!(report.valid_row_count, 1);
+            assert_eq!(report.inserted_count, if dry_run { 0 } else { 1 });
+            assert_eq!(report.errors.len(), 1);
+            assert_eq!(report.errors[0].line, 3);
+            assert_eq!(report.errors[0].error, "unknown-location");
+            assert_eq!(report.errors[0].key.as_deref(), Some("location_id"));
+        }
+    }
+
+    #[tokio::test]
+    async fn import_command_runs_alert_actions() {
+        init();
+        let alerts_path = std::env::temp_dir().join("beepee-test-alerts");
+        let _ = std::fs::remove_file(&alerts_path);
+        let csv_path = std::env::temp_dir().join(format!("beepee-test-import-{}.csv", std::process::id()));
+        std::fs::write(&csv_path, "timestamp,systolic_mmhg,diastolic_mmhg,pulse_bpm\n2001-01-01T08:00:00+01:00,260,80,60\n")
+            .unwrap();
+
+        let args: Vec<OsString> = vec!["bp".into(), csv_path.clone().into(), "--user".into(), "cli-importer".into()];
+        run_import(&args).await.unwrap();
+        std::fs::remove_file(&csv_path).unwrap();
+
+        // the command has finished by the time run_import returns
+        let recorded = std::fs::read_to_string(&alerts_path).unwrap();
+        assert!(recorded.lines().any(|l| l == "cli-importer"));
+    }
+
+    #[tokio::test]
+    async fn token_in_query_string_ignored() {
+        let response = request(Method::GET, "/mass?token=ro&days=7", None, "").await;
+        assert_eq!(response.status(), 403);
+        assert!(!response.headers().contains_key("Set-Cookie"));
+    }
+
+    #[tokio::test]
+    async fn hashed_token() {
+        assert_eq!(request(Method::GET, "/", Some("hashed"), "").await.status(), 200);
+        assert_eq!(request(Method::GET, "/", Some("hashed2"), "").await.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn read_only_token_cannot_write() {
+        let response = request(Method::POST, "/", Some("ro"), "systolic_mmhg=120&diastolic_mmhg=80&pulse_bpm=60").await;
+        assert_eq!(response.status(), 403);
+        let response = request(Method::DELETE, "/api/bp?id=1", Some("ro"), "").await;
+        assert_eq!(response.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn token_scopes() {
+        let response = request(Method::GET, "/", Some("caregiver"), "").await;
+        assert_eq!(response.status(), 200);
+        let body = body_string(response).await;
+        assert!(!body.contains("systolic_mmhg"));
+        assert!(body.contains("href=\"temperature\""));
+        assert!(!body.contains("href=\"mass\""));
+
+        let response = request(Method::GET, "/mass", Some("caregiver"), "").await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(response.headers()["Forbidden-Reason"], "token-scope");
+        let response = request(Method::GET, "/api/mass", Some("caregiver"), "").await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(response.headers()["Content-Type"], "application/json");
+
+        let response = request(Method::POST, "/api/bp", Some("caregiver"), r#"{"timestamp":"2024-05-06T07:08:09Z","systolic_mmhg":120,"diastolic_mmhg":80,"pulse_bpm":60}"#).await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(response.headers()["Forbidden-Reason"], "token-read-only");
+        let response = request(Method::POST, "/api/sugar", Some("caregiver"), r#"{"timestamp":"2024-05-06T07:08:09Z","sugar_mmol_per_l":"5"}"#).await;
+        assert_eq!(response.status(), 201);
+    }
+
+    #[tokio::test]
+    async fn blood_pressure_categories() {
+        let response = request(Method::POST, "/api/bp", Some("rw"), r#"{"timestamp":"2002-03-04T12:00:00Z","systolic_mmhg":165,"diastolic_mmhg":85,"pulse_bpm":60}"#).await;
+        assert_eq!(response.status(), 201);
+        let added: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(added["category"], "grade-2-hypertension");
+
+        let response = request(Method::GET, "/api/bp?from=2002-03-04&to=2002-03-04", Some("ro"), "").await;
+        let page: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(page["measurements"][0]["category"], "grade-2-hypertension");
+
+        let response = request(Method::GET, "/?from=2002-03-04&to=2002-03-04", Some("ro"), "").await;
+        let body = body_string(response).await;
+        assert!(body.contains("pressure bp-category grade-2-hypertension"));
+        assert!(body.contains("day-average pressure bp-category grade-2-hypertension"));
+    }
+
+    #[tokio::test]
+    async fn share_tokens() {
+        for date in &["2001-02-03", "2001-03-04"] {
+            let body = format!(r#"{{"timestamp":"{}T12:00:00Z","systolic_mmhg":121,"diastolic_mmhg":81,"pulse_bpm":61}}"#, date);
+            let response = request(Method::POST, "/api/bp", Some("rw"), &body).await;
+            assert_eq!(response.status(), 201);
+        }
+
+        let response = request(Method::POST, "/shares", Some("ro"), "days=7&scope-bp=true").await;
+        assert_eq!(response.status(), 403);
+        let response = request(Method::POST, "/shares", Some("caregiver"), "days=7&scope-mass=true").await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(response.headers()["Forbidden-Reason"], "token-scope");
+
+        let response = request(Method::POST, "/shares", Some("rw"), "description=doctor&days=7&from=2001-02-01&to=2001-02-28&scope-bp=true").await;
+        assert_eq!(response.status(), 200);
+        let body = body_string(response).await;
+        let link_start = body.find("http://beepee.example/login#token=")
+            .expect("share link not shown");
+        let share_token: String = body[link_start..].trim_start_matches("http://beepee.example/login#token=")
+            .chars()
+            .take_while(|c| c.is_ascii_hexdigit())
+            .collect();
+
+        // share links only log in with share tokens
+        let response = request(Method::POST, "/login", None, "token=rw&share=true").await;
+        assert_eq!(response.status(), 403);
+        let session = log_in(&format!("token={}&share=true", share_token)).await;
+        assert_eq!(request_with_headers(Method::GET, "/api/bp", std::slice::from_ref(&session), "").await.status(), 200);
+        let response = request(Method::GET, &format!("/?token={}", share_token), None, "").await;
+        assert_eq!(response.status(), 403);
+        assert!(!response.headers().contains_key("Set-Cookie"));
+
+        let response = request(Method::GET, "/api/bp", Some(&share_token), "").await;
+        assert_eq!(response.status(), 200);
+        let page: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(page["measurements"].as_array().map(|m| m.len()), Some(1));
+        assert_eq!(page["measurements"][0]["timestamp"].as_str().map(|t| &t[..7]), Some("2001-02"));
+        let response = request(Method::GET, "/api/bp?from=2001-01-01&to=2001-12-31", Some(&share_token), "").await;
+        let page: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(page["measurements"].as_array().map(|m| m.len()), Some(1));
+
+        assert_eq!(request(Method::GET, "/mass", Some(&share_token), "").await.status(), 403);
+        assert_eq!(request(Method::GET, "/shares", Some(&share_token), "").await.status(), 403);
+        let response = request(Method::POST, "/api/bp", Some(&share_token), r#"{"timestamp":"2001-02-05T12:00:00Z","systolic_mmhg":120,"diastolic_mmhg":80,"pulse_bpm":60}"#).await;
+        assert_eq!(response.headers()["Forbidden-Reason"], "token-read-only");
+
+        let response = request(Method::GET, "/shares", Some("rw"), "").await;
+        let body = body_string(response).await;
+        let id_start = body.find("revoke-share?id=")
+            .expect("revoke form not shown") + "revoke-share?id=".len();
+        let share_token_id: String = body[id_start..].chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        let response = request(Method::POST, &format!("/revoke-share?id={}", share_token_id), Some("rw"), "").await;
+        assert_eq!(response.status(), 302);
+        assert_eq!(request(Method::GET, "/api/bp", Some(&share_token), "").await.status(), 403);
+        assert_eq!(request_with_headers(Method::GET, "/api/bp", &[session], "").await.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn unknown_path_and_method() {
+        assert_eq!(request(Method::GET, "/nothing-here", Some("ro"), "").await.status(), 404);
+
+        let response = request(Method::PATCH, "/", Some("rw"), "").await;
+        assert_eq!(response.status(), 405);
+        assert_eq!(response.headers()["Allow"], "GET, POST");
+    }
+
+    #[tokio::test]
+    async fn form_validation_errors() {
+        let response = request(Method::POST, "/", Some("rw"), "systolic_mmhg=abc&diastolic_mmhg=80&pulse_bpm=60").await;
+        assert_eq!(response.status(), 400);
+        assert!(body_string(response).await.contains("systolic_mmhg"));
+
+        let response = request(Method::POST, "/", Some("rw"), "systolic_mmhg=120&pulse_bpm=60").await;
+        assert_eq!(response.status(), 400);
+        assert!(body_string(response).await.contains("diastolic_mmhg"));
+
+        let response = request(Method::POST, "/mass", Some("rw"), "mass_kg=-5").await;
+        assert_eq!(response.status(), 400);
+
+        let response = request(Method::GET, "/?days=0", Some("ro"), "").await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn time_range_out_of_bounds() {
+        let response = request(Method::GET, "/?days=2000000000", Some("ro"), "").await;
+        assert_eq!(response.status(), 400);
+        let response = request(Method::GET, "/api/mass?days=2000000000", Some("ro"), "").await;
+        assert_eq!(response.status(), 400);
+        let error: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(error["error"], "time-range-out-of-bounds");
+        assert_eq!(error["key"], "days");
+
+        let mut req_kv = HashMap::new();
+        req_kv.insert("to".to_owned(), NaiveDate::MAX.format("%Y-%m-%d").to_string());
+        match get_form_range_boundary(&req_kv, "to", true) {
+            Err(ClientError::TimeRangeOutOfBounds(key)) => assert_eq!(key, "to"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn valid_form_redirects() {
+        let response = request(Method::POST, "/", Some("rw"), "systolic_mmhg=187&diastolic_mmhg=97&pulse_bpm=77").await;
+        assert_eq!(response.status(), 302);
+        assert_eq!(response.headers()["Location"], "http://beepee.example/");
+
+        let response = request(Method::GET, "/", Some("ro"), "").await;
+        assert_eq!(response.status(), 200);
+        assert!(body_string(response).await.contains("187"));
+    }
+
+    #[tokio::test]
+    async fn api_round_trip() {
+        let response = request(Method::POST, "/api/sugar", Some("rw"), r#"{"timestamp":"2024-02-03T04:05:06Z","sugar_mmol_per_l":"27/5"}"#).await;
+        assert_eq!(response.status(), 201);
+        let added: BloodSugarMeasurement = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(added.sugar_mmol_per_l, Rational32::new(27, 5));
+
+        let response = request(Method::GET, "/api/sugar?from=2024-02-03&to=2024-02-03", Some("ro"), "").await;
+        assert_eq!(response.status(), 200);
+        let page: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(page["measurements"][0]["id"], added.id);
+
+        let response = request(Method::GET, "/api/sugar/statistics?from=2024-02-03&to=2024-02-03", Some("ro"), "").await;
+        assert_eq!(response.status(), 200);
+        let statistics: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(statistics["count"], 1);
+        assert_eq!(statistics["fields"]["sugar_mmol_per_l"]["median"], 5.4);
+        assert_eq!(statistics["fields"]["sugar_mmol_per_l"]["standard_deviation"], serde_json::Value::Null);
+
+        let response = request(Method::DELETE, &format!("/api/sugar?id={}", added.id), Some("rw"), "").await;
+        assert_eq!(response.status(), 204);
+        let response = request(Method::DELETE, &format!("/api/sugar?id={}", added.id), Some("rw"), "").await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn custom_measurement_round_trip() {
+        let response = request(Method::POST, "/api/custom/peak-flow", Some("rw"), r#"{"timestamp":"2024-04-05T06:07:08Z","values":{"pef":"451.4","variability":"12.34"}}"#).await;
+        assert_eq!(response.status(), 201);
+        let added: CustomMeasurement = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(added.values["pef"], Rational32::from_integer(451));
+        assert_eq!(added.values["variability"], Rational32::new(123, 10));
+
+        let response = request(Method::POST, "/api/custom/peak-flow", Some("rw"), r#"{"values":{"pef":"950"}}"#).await;
+        assert_eq!(response.status(), 400);
+        let error: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(error["error"], "value-too-high");
+        let response = request(Method::POST, "/api/custom/peak-flow", Some("rw"), r#"{"values":{"variability":"5"}}"#).await;
+        assert_eq!(response.status(), 400);
+        let response = request(Method::POST, "/api/custom/pain", Some("rw"), r#"{"values":{"score":"5"}}"#).await;
+        assert_eq!(response.status(), 404);
+
+        let response = request(Method::PUT, &format!("/api/custom/peak-flow?id={}", added.id), Some("rw"), r#"{"values":{"pef":"480"}}"#).await;
+        assert_eq!(response.status(), 200);
+
+        let response = request(Method::GET, "/api/custom/peak-flow?from=2024-04-05&to=2024-04-05", Some("ro"), "").await;
+        assert_eq!(response.status(), 200);
+        let page: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(page["measurements"][0]["id"], added.id);
+        assert_eq!(page["measurements"][0]["values"]["pef"], "480/1");
+        assert_eq!(page["measurements"][0]["values"]["variability"], serde_json::Value::Null);
+
+        let response = request(Method::GET, "/api/custom/peak-flow/statistics?from=2024-04-05&to=2024-04-05", Some("ro"), "").await;
+        assert_eq!(response.status(), 200);
+        let statistics: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(statistics["fields"]["pef"]["maximum"], 480.0);
+        assert_eq!(statistics["fields"]["variability"], serde_json::Value::Null);
+
+        let response = request(Method::GET, "/custom-peak-flow?from=2024-04-05&to=2024-04-05", Some("rw"), "").await;
+        assert_eq!(response.status(), 200);
+        let body = body_string(response).await;
+        assert!(body.contains("peak expiratory flow"));
+        assert!(body.contains(&format!("href=\"edit-custom-peak-flow?id={}\"", added.id)));
+        let response = request(Method::GET, "/custom", Some("ro"), "").await;
+        assert!(body_string(response).await.contains("href=\"custom-peak-flow\""));
+        let response = request(Method::GET, "/custom-pain", Some("rw"), "").await;
+        assert_eq!(response.status(), 404);
+        let response = request(Method::GET, "/custom-peak-flow", Some("caregiver"), "").await;
+        assert_eq!(response.status(), 403);
+
+        let response = request(Method::POST, &format!("/delete-custom-peak-flow?id={}", added.id), Some("rw"), "").await;
+        assert_eq!(response.status(), 302);
+        let response = request(Method::DELETE, &format!("/api/custom/peak-flow?id={}", added.id), Some("rw"), "").await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn custom_scopes_per_type() {
+        let response = request(Method::POST, "/api/custom/peak-flow", Some("peak-flow"), r#"{"values":{"pef":"400"}}"#).await;
+        assert_eq!(response.status(), 201);
+        let response = request(Method::POST, "/api/custom/inr", Some("peak-flow"), r#"{"values":{"inr":"2.5"}}"#).await;
+        assert_eq!(response.status(), 403);
+        let response = request(Method::GET, "/custom-inr", Some("peak-flow"), "").await;
+        assert_eq!(response.status(), 403);
+        let response = request(Method::GET, "/custom-peak-flow", Some("caregiver"), "").await;
+        assert_eq!(response.status(), 403);
+
+        let response = request(Method::GET, "/custom", Some("peak-flow"), "").await;
+        assert_eq!(response.status(), 200);
+        let body = body_string(response).await;
+        assert!(body.contains("href=\"custom-peak-flow\""));
+        assert!(!body.contains("href=\"custom-inr\""));
+        let response = request(Method::GET, "/custom", Some("caregiver"), "").await;
+        assert_eq!(response.status(), 403);
+
+        let response = request(Method::POST, "/shares", Some("peak-flow"), "days=7&scope-custom:inr=true").await;
+        assert_eq!(response.status(), 403);
+        let response = request(Method::POST, "/shares", Some("peak-flow"), "days=7&scope-custom%3Apeak-flow=true").await;
+        assert_eq!(response.status(), 200);
+        let body = body_string(response).await;
+        assert!(body.contains("name=\"scope-custom:peak-flow\""));
+        assert!(body.contains("<td class=\"scopes\">custom:peak-flow</td>"));
+    }
+
+    #[tokio::test]
+    async fn bmi_from_height_history() {
+        let response = request(Method::POST, "/height", Some("other"), "effective_date=2020-01-01&height_cm=200").await;
+        assert_eq!(response.status(), 302);
+        let response = request(Method::POST, "/height", Some("other"), "effective_date=2022-01-01&height_cm=100").await;
+        assert_eq!(response.status(), 302);
+
+        for (date, expected_bmi) in &[("2019-06-01", 20), ("2021-06-01", 20), ("2023-06-01", 80)] {
+            let body = format!(r#"{{"timestamp":"{}T12:00:00Z","mass_kg":"80"}}"#, date);
+            let response = request(Method::POST, "/api/mass", Some("other"), &body).await;
+            assert_eq!(response.status(), 201);
+            let added: BodyMassMeasurement = serde_json::from_str(&body_string(response).await)
+                .expect("invalid JSON response");
+            assert_eq!(added.bmi, Some(Rational32::from_integer(*expected_bmi)), "BMI on {}", date);
+        }
+    }
+
+    #[tokio::test]
+    async fn users_isolated() {
+        let response = request(Method::POST, "/api/temperature", Some("rw"), r#"{"timestamp":"2023-06-07T08:09:10Z","location_id":1,"temperature_celsius":"367/10"}"#).await;
+        assert_eq!(response.status(), 201);
+        let added: BodyTemperatureMeasurement = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+
+        let response = request(Method::GET, "/api/temperature?from=2023-06-07&to=2023-06-07", Some("other"), "").await;
+        assert_eq!(response.status(), 200);
+        let page: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(page["measurements"].as_array().map(|m| m.len()), Some(0));
+
+        let response = request(Method::DELETE, &format!("/api/temperature?id={}", added.id), Some("other"), "").await;
+        assert_eq!(response.status(), 404);
+        let response = request(Method::GET, "/api/temperature?from=2023-06-07&to=2023-06-07", Some("ro"), "").await;
+        let page: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(page["measurements"][0]["id"], added.id);
+    }
+
+    #[tokio::test]
+    async fn fhir_export_limited_to_scopes() {
+        let response = request(Method::POST, "/api/bp", Some("other"), r#"{"timestamp":"2024-07-01T08:00:00Z","systolic_mmhg":120,"diastolic_mmhg":80,"pulse_bpm":60}"#).await;
+        assert_eq!(response.status(), 201);
+        let response = request(Method::POST, "/api/mass", Some("other"), r#"{"timestamp":"2024-07-01T08:00:00Z","mass_kg":"80"}"#).await;
+        assert_eq!(response.status(), 201);
+
+        let resource_ids = |body: String| -> Vec<String> {
+            let bundle: serde_json::Value = serde_json::from_str(&body)
+                .expect("invalid JSON response");
+            bundle["entry"].as_array().unwrap().iter()
+                .map(|e| e["resource"]["id"].as_str().unwrap().to_owned())
+                .collect()
+        };
+        let response = request(Method::GET, "/export/fhir.json?from=2024-07-01&to=2024-07-01", Some("other"), "").await;
+        assert_eq!(response.status(), 200);
+        let ids = resource_ids(body_string(response).await);
+        assert!(ids.iter().any(|id| id.starts_with("bp-")));
+        assert!(ids.iter().any(|id| id.starts_with("mass-")));
+
+        let response = request(Method::GET, "/export/fhir.json?from=2024-07-01&to=2024-07-01", Some("caregiver"), "").await;
+        assert_eq!(response.status(), 200);
+        let ids = resource_ids(body_string(response).await);
+        assert!(ids.iter().any(|id| id.starts_with("bp-")));
+        assert!(!ids.iter().any(|id| id.starts_with("mass-")));
+    }
+
+    #[tokio::test]
+    async fn unknown_temperature_location_rejected() {
+        let response = request(Method::POST, "/api/temperature", Some("rw"), r#"{"timestamp":"2023-06-08T08:09:10Z","location_id":42,"temperature_celsius":"367/10"}"#).await;
+        assert_eq!(response.status(), 400);
+        let error: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(error["error"], "unknown-location");
+        assert_eq!(error["key"], "location_id");
+
+        let response = request(Method::POST, "/temperature", Some("rw"), "location=42&temperature_celsius=36.7").await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn internal_error_responses() {
+        let response = respond_500().unwrap();
+        assert_eq!(response.status(), 500);
+        assert_eq!(response.headers()["Content-Type"], "text/html; charset=utf-8");
+
+        let response = respond_json_500().unwrap();
+        assert_eq!(response.status(), 500);
+        assert_eq!(response.headers()["Content-Type"], "application/json");
+        let error: serde_json::Value = serde_json::from_str(&body_string(response).await)
+            .expect("invalid JSON response");
+        assert_eq!(error["error"], "internal-error");
+    }
+}
@@ -0,0 +1,1108 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
+use std::marker::PhantomData;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Local, Timelike};
+use http_body_util::Full;
+use hyper::Response;
+use hyper::body::Bytes;
+use log::error;
+use num_rational::Rational32;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    ABSOLUTE_ZERO_CELSIUS, ClientError, DEFAULT_LOOKBACK_DAYS, EditTemplate, ListTemplate,
+    LONG_TERM_SUGAR_LOOKBACK_DAYS, LongTermSugarEditTemplate, LongTermSugarListTemplate,
+    MassEditTemplate, MassListTemplate, SugarEditTemplate, SugarListTemplate, TemperatureEditTemplate,
+    TemperatureListTemplate, check_i32_gt0, check_r32_gt0, check_timestamp_not_future, get_form_i32_gt0,
+    get_form_r32_gt0, get_form_timestamp_not_future, get_req_form_i32_gt0, get_req_form_i64,
+    get_req_form_r32, get_req_form_r32_gt0, respond_500, respond_template,
+};
+use crate::classification::ClassifiedBloodPressureMeasurement;
+use crate::config::{AuthToken, CONFIG, Config, Scope};
+use crate::database::DatabaseError;
+use crate::model::{
+    BloodPressureMeasurement, BloodSugarMeasurement, BodyMassMeasurement, BodyTemperatureMeasurement,
+    DailyBloodPressureMeasurements, LongTermBloodSugarMeasurement, Page, SUGAR_MG_PER_DL_IN_MMOL_PER_L,
+    TimeRange, User,
+};
+use crate::statistics::MeasurementStatistics;
+use crate::storage::{ColumnValue, MeasurementColumn, MeasurementRow, MeasurementTable, Storage, storage};
+
+
+/// A numeric value of a measurement type. The strings are borrowed for the built-in types and owned
+/// for the custom types from the configuration.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct MeasurementField {
+    /// The key of the field in the JSON API.
+    pub key: Cow<'static, str>,
+    /// The CSS class of the field's column in the statistics table.
+    pub class: Cow<'static, str>,
+    pub unit: Cow<'static, str>,
+    /// The number of decimal places with which values are displayed.
+    pub decimal_places: usize,
+}
+impl MeasurementField {
+    pub const fn new(key: &'static str, class: &'static str, unit: &'static str, decimal_places: usize) -> Self {
+        Self {
+            key: Cow::Borrowed(key),
+            class: Cow::Borrowed(class),
+            unit: Cow::Borrowed(unit),
+            decimal_places,
+        }
+    }
+}
+
+
+/// A stored measurement of any type.
+pub(crate) trait Record: Clone + Send + Sync + Serialize + DeserializeOwned + 'static {
+    fn id(&self) -> i64;
+    fn set_id(&mut self, id: i64);
+    fn timestamp(&self) -> DateTime<Local>;
+}
+
+
+/// A built-in type of measurement with its own list page, edit page and API endpoints.
+///
+/// The pages of a type are at `/<PAGE>`, `/edit-<KEY>` and `/delete-<KEY>`; the API endpoints at
+/// `/api/<KEY>` and `/api/<KEY>/statistics`.
+#[async_trait]
+pub(crate) trait Measurement: Record {
+    /// The scope that tokens need to read or write measurements of this type.
+    const SCOPE: Scope;
+    const KEY: &'static str;
+    /// The list page relative to the base URL, which is empty for the main page.
+    const PAGE: &'static str;
+    /// How far back the list page and the API look if no range is given.
+    const LOOKBACK_DAYS: i64 = DEFAULT_LOOKBACK_DAYS;
+    /// The numeric values shown in the statistics and checked by alert rules.
+    const FIELDS: &'static [MeasurementField];
+    /// The table that stores the measurements of this type.
+    const TABLE: MeasurementTable;
+
+    /// The representation of a measurement in the JSON API.
+    type Json: Serialize + Send;
+
+    /// The values of the fields in the order of `FIELDS`, with `None` for the values missing from
+    /// this measurement.
+    fn values(&self) -> Vec<Option<Rational32>>;
+
+    /// The value of the field with the given key.
+    fn value(&self, key: &str) -> Option<Rational32> {
+        Self::FIELDS.iter()
+            .position(|f| f.key == key)
+            .and_then(|i| self.values()[i])
+    }
+
+    /// Checks that the values of the measurement are plausible.
+    fn validate(&self) -> Result<(), ClientError>;
+
+    /// Checks that the values of the measurements referring to other stored data, such as the
+    /// location of a temperature measurement, refer to data that exists. Returns the result for
+    /// each measurement in order.
+    async fn check_references(_storage: &dyn Storage, measurements: &[Self]) -> Result<Vec<Result<(), ClientError>>, DatabaseError> {
+        Ok(measurements.iter().map(|_| Ok(())).collect())
+    }
+
+    /// Reads and validates a measurement entered into the form on the list or edit page.
+    fn from_form(req_kv: &HashMap<String, String>, default_timestamp: DateTime<Local>) -> Result<Self, ClientError>;
+
+    /// Fills in the values of the measurements that are derived from the user's other data rather
+    /// than entered or stored.
+    async fn complete(_storage: &dyn Storage, _user_id: i64, _measurements: &mut [Self]) -> Result<(), DatabaseError> {
+        Ok(())
+    }
+
+    fn to_json(self, config: &Config) -> Self::Json;
+
+    /// The stored values of the measurement, in the order of the columns of `TABLE`.
+    fn to_row(&self) -> MeasurementRow;
+    /// Reads a measurement from a row of `TABLE`, without the derived values.
+    fn from_row(row: &MeasurementRow) -> Result<Self, DatabaseError>;
+
+    async fn add(storage: &dyn Storage, user_id: i64, measurement: &Self) -> Result<i64, DatabaseError> {
+        let measurement_ids = Self::add_all(storage, user_id, std::slice::from_ref(measurement)).await?;
+        Ok(measurement_ids[0])
+    }
+
+    /// Adds the measurements in a single transaction.
+    async fn add_all(storage: &dyn Storage, user_id: i64, measurements: &[Self]) -> Result<Vec<i64>, DatabaseError> {
+        let rows: Vec<MeasurementRow> = measurements.iter()
+            .map(Self::to_row)
+            .collect();
+        storage.add_measurements(user_id, &Self::TABLE, &rows).await
+    }
+
+    async fn update(storage: &dyn Storage, user_id: i64, measurement: &Self) -> Result<(), DatabaseError> {
+        storage.update_measurement(user_id, &Self::TABLE, &measurement.to_row()).await
+    }
+
+    async fn remove(storage: &dyn Storage, user_id: i64, measurement_id: i64) -> Result<(), DatabaseError> {
+        storage.remove_measurement(user_id, &Self::TABLE, measurement_id).await
+    }
+
+    async fn get(storage: &dyn Storage, user_id: i64, measurement_id: i64) -> Result<Option<Self>, DatabaseError> {
+        let row = match storage.get_measurement(user_id, &Self::TABLE, measurement_id).await? {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+        let mut measurement = Self::from_row(&row)?;
+        Self::complete(storage, user_id, std::slice::from_mut(&mut measurement)).await?;
+        Ok(Some(measurement))
+    }
+
+    /// The measurements within the range, ordered by timestamp and ID.
+    async fn get_range(storage: &dyn Storage, user_id: i64, range: &TimeRange, page: &Page) -> Result<Vec<Self>, DatabaseError> {
+        let rows = storage.get_measurements(user_id, &Self::TABLE, range, page).await?;
+        let mut measurements = rows.iter()
+            .map(Self::from_row)
+            .collect::<Result<Vec<Self>, DatabaseError>>()?;
+        Self::complete(storage, user_id, &mut measurements).await?;
+        Ok(measurements)
+    }
+
+    /// Renders the list page for the measurements, which are ordered by timestamp.
+    async fn respond_list(
+        token: &AuthToken,
+        range: TimeRange,
+        measurements: Vec<Self>,
+        statistics: Option<MeasurementStatistics>,
+    ) -> Result<Response<Full<Bytes>>, Infallible>;
+
+    /// Renders the page for editing the measurement.
+    async fn respond_edit(measurement: Self) -> Result<Response<Full<Bytes>>, Infallible>;
+
+    /// The path of the list page.
+    fn list_path() -> String {
+        format!("/{}", Self::PAGE)
+    }
+
+    /// The list page relative to the base URL, for redirects.
+    fn list_page_noslash() -> &'static str {
+        if Self::PAGE.is_empty() {
+            "./"
+        } else {
+            Self::PAGE
+        }
+    }
+}
+
+
+/// A page or API endpoint of a measurement type.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum MeasurementEndpoint {
+    List,
+    Edit,
+    Delete,
+    Api,
+    ApiStatistics,
+}
+
+/// The endpoint of the measurement type at the given path, if any.
+pub(crate) fn measurement_endpoint<M: Measurement>(path: &str) -> Option<MeasurementEndpoint> {
+    if path == M::list_path() {
+        return Some(MeasurementEndpoint::List);
+    }
+    if let Some(key) = path.strip_prefix("/edit-") {
+        if key == M::KEY {
+            return Some(MeasurementEndpoint::Edit);
+        }
+    }
+    if let Some(key) = path.strip_prefix("/delete-") {
+        if key == M::KEY {
+            return Some(MeasurementEndpoint::Delete);
+        }
+    }
+    let api_path = path.strip_prefix("/api/")?;
+    if api_path == M::KEY {
+        Some(MeasurementEndpoint::Api)
+    } else if api_path.strip_suffix("/statistics") == Some(M::KEY) {
+        Some(MeasurementEndpoint::ApiStatistics)
+    } else {
+        None
+    }
+}
+
+
+/// Describes a type of measurement to the handlers of its pages and API endpoints: a built-in type
+/// through `BuiltInType` or a custom type through its configuration.
+#[async_trait]
+pub(crate) trait MeasurementType: Send + Sync {
+    type Measurement: Record;
+    /// The representation of a measurement in the JSON API.
+    type Json: Serialize + Send;
+
+    /// The scope that tokens need to read or write measurements of this type.
+    fn scope(&self) -> Scope;
+    /// The API endpoint relative to the base URL.
+    fn api_page_noslash(&self) -> String;
+    /// The list page relative to the base URL, for redirects.
+    fn list_page_noslash(&self) -> String;
+    /// How far back the list page and the API look if no range is given.
+    fn lookback_days(&self) -> i64;
+
+    /// Checks that the values of the measurement are plausible, bringing them into shape if needed.
+    fn validate(&self, measurement: &mut Self::Measurement) -> Result<(), ClientError>;
+    /// Reads and validates a measurement entered into the form on the list or edit page.
+    fn read_form(&self, req_kv: &HashMap<String, String>, default_timestamp: DateTime<Local>) -> Result<Self::Measurement, ClientError>;
+    fn to_json(&self, measurement: Self::Measurement, config: &Config) -> Self::Json;
+    fn statistics(&self, measurements: &[Self::Measurement]) -> MeasurementStatistics;
+
+    /// Checks that the measurements only refer to stored data that exists, returning the result for
+    /// each measurement in order.
+    async fn check_references(&self, _storage: &dyn Storage, measurements: &[Self::Measurement]) -> Result<Vec<Result<(), ClientError>>, DatabaseError> {
+        Ok(measurements.iter().map(|_| Ok(())).collect())
+    }
+
+    /// Fills in the values of the measurements that are derived from the user's other data.
+    async fn complete(&self, storage: &dyn Storage, user_id: i64, measurements: &mut [Self::Measurement]) -> Result<(), DatabaseError>;
+    async fn add(&self, storage: &dyn Storage, user_id: i64, measurement: &Self::Measurement) -> Result<i64, DatabaseError>;
+    async fn update(&self, storage: &dyn Storage, user_id: i64, measurement: &Self::Measurement) -> Result<(), DatabaseError>;
+    async fn remove(&self, storage: &dyn Storage, user_id: i64, measurement_id: i64) -> Result<(), DatabaseError>;
+    async fn get(&self, storage: &dyn Storage, user_id: i64, measurement_id: i64) -> Result<Option<Self::Measurement>, DatabaseError>;
+    /// The measurements within the range, ordered by timestamp and ID.
+    async fn get_range(&self, storage: &dyn Storage, user_id: i64, range: &TimeRange, page: &Page) -> Result<Vec<Self::Measurement>, DatabaseError>;
+
+    /// Evaluates the alert rules against a measurement that has just been added.
+    async fn check_alerts(&self, user: &User, new_measurement: &Self::Measurement);
+
+    /// Renders the list page for the measurements, which are ordered by timestamp.
+    async fn respond_list(
+        &self,
+        token: &AuthToken,
+        range: TimeRange,
+        measurements: Vec<Self::Measurement>,
+        statistics: Option<MeasurementStatistics>,
+    ) -> Result<Response<Full<Bytes>>, Infallible>;
+
+    /// Renders the page for editing the measurement.
+    async fn respond_edit(&self, measurement: Self::Measurement) -> Result<Response<Full<Bytes>>, Infallible>;
+}
+
+/// Describes the built-in measurement type `M`.
+pub(crate) struct BuiltInType<M>(PhantomData<M>);
+impl<M: Measurement> BuiltInType<M> {
+    pub const fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+#[async_trait]
+impl<M: Measurement> MeasurementType for BuiltInType<M> {
+    type Measurement = M;
+    type Json = M::Json;
+
+    fn scope(&self) -> Scope { M::SCOPE }
+    fn api_page_noslash(&self) -> String { format!("api/{}", M::KEY) }
+    fn list_page_noslash(&self) -> String { M::list_page_noslash().to_owned() }
+    fn lookback_days(&self) -> i64 { M::LOOKBACK_DAYS }
+
+    fn validate(&self, measurement: &mut M) -> Result<(), ClientError> {
+        measurement.validate()
+    }
+
+    fn read_form(&self, req_kv: &HashMap<String, String>, default_timestamp: DateTime<Local>) -> Result<M, ClientError> {
+        M::from_form(req_kv, default_timestamp)
+    }
+
+    fn to_json(&self, measurement: M, config: &Config) -> M::Json {
+        measurement.to_json(config)
+    }
+
+    fn statistics(&self, measurements: &[M]) -> MeasurementStatistics {
+        MeasurementStatistics::calculate(measurements)
+    }
+
+    async fn check_references(&self, storage: &dyn Storage, measurements: &[M]) -> Result<Vec<Result<(), ClientError>>, DatabaseError> {
+        M::check_references(storage, measurements).await
+    }
+
+    async fn complete(&self, storage: &dyn Storage, user_id: i64, measurements: &mut [M]) -> Result<(), DatabaseError> {
+        M::complete(storage, user_id, measurements).await
+    }
+
+    async fn add(&self, storage: &dyn Storage, user_id: i64, measurement: &M) -> Result<i64, DatabaseError> {
+        M::add(storage, user_id, measurement).await
+    }
+
+    async fn update(&self, storage: &dyn Storage, user_id: i64, measurement: &M) -> Result<(), DatabaseError> {
+        M::update(storage, user_id, measurement).await
+    }
+
+    async fn remove(&self, storage: &dyn Storage, user_id: i64, measurement_id: i64) -> Result<(), DatabaseError> {
+        M::remove(storage, user_id, measurement_id).await
+    }
+
+    async fn get(&self, storage: &dyn Storage, user_id: i64, measurement_id: i64) -> Result<Option<M>, DatabaseError> {
+        M::get(storage, user_id, measurement_id).await
+    }
+
+    async fn get_range(&self, storage: &dyn Storage, user_id: i64, range: &TimeRange, page: &Page) -> Result<Vec<M>, DatabaseError> {
+        M::get_range(storage, user_id, range, page).await
+    }
+
+    async fn check_alerts(&self, user: &User, new_measurement: &M) {
+        // the server keeps running the actions in the background
+        let _ = crate::check_alerts(user, new_measurement).await;
+    }
+
+    async fn respond_list(
+        &self,
+        token: &AuthToken,
+        range: TimeRange,
+        measurements: Vec<M>,
+        statistics: Option<MeasurementStatistics>,
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        M::respond_list(token, range, measurements, statistics).await
+    }
+
+    async fn respond_edit(&self, measurement: M) -> Result<Response<Full<Bytes>>, Infallible> {
+        M::respond_edit(measurement).await
+    }
+}
+
+
+impl Record for BloodPressureMeasurement {
+    fn id(&self) -> i64 { self.id }
+    fn set_id(&mut self, id: i64) { self.id = id; }
+    fn timestamp(&self) -> DateTime<Local> { self.timestamp }
+}
+
+#[async_trait]
+impl Measurement for BloodPressureMeasurement {
+    const SCOPE: Scope = Scope::Bp;
+    const KEY: &'static str = "bp";
+    const PAGE: &'static str = "";
+    const FIELDS: &'static [MeasurementField] = &[
+        MeasurementField::new("systolic_mmhg", "systolic", "mmHg", 0),
+        MeasurementField::new("diastolic_mmhg", "diastolic", "mmHg", 0),
+        MeasurementField::new("pulse_bpm", "pulse", "/min", 0),
+        MeasurementField::new("spo2_percent", "spo2", "%", 0),
+    ];
+    const TABLE: MeasurementTable = MeasurementTable {
+        name: "measurements",
+        columns: &[
+            MeasurementColumn::integer("systolic_mmhg"),
+            MeasurementColumn::integer("diastolic_mmhg"),
+            MeasurementColumn::integer("pulse_bpm"),
+            MeasurementColumn::integer("spo2_percent"),
+        ],
+    };
+
+    type Json = ClassifiedBloodPressureMeasurement;
+
+    fn values(&self) -> Vec<Option<Rational32>> {
+        vec![
+            Some(Rational32::from_integer(self.systolic_mmhg)),
+            Some(Rational32::from_integer(self.diastolic_mmhg)),
+            Some(Rational32::from_integer(self.pulse_bpm)),
+            self.spo2_percent.map(Rational32::from_integer),
+        ]
+    }
+
+    fn validate(&self) -> Result<(), ClientError> {
+        check_timestamp_not_future("timestamp", self.timestamp)?;
+        check_i32_gt0("systolic_mmhg", self.systolic_mmhg)?;
+        check_i32_gt0("diastolic_mmhg", self.diastolic_mmhg)?;
+        check_i32_gt0("pulse_bpm", self.pulse_bpm)?;
+        if let Some(sat) = self.spo2_percent {
+            check_i32_gt0("spo2_percent", sat)?;
+            if sat > 100 {
+                return Err(ClientError::IntValueTooHigh("spo2_percent".into(), sat, 100));
+            }
+        }
+        Ok(())
+    }
+
+    fn from_form(req_kv: &HashMap<String, String>, default_timestamp: DateTime<Local>) -> Result<Self, ClientError> {
+        let systolic_mmhg: i32 = get_req_form_i32_gt0(req_kv, "systolic_mmhg")?;
+        let diastolic_mmhg: i32 = get_req_form_i32_gt0(req_kv, "diastolic_mmhg")?;
+        let pulse_bpm: i32 = get_req_form_i32_gt0(req_kv, "pulse_bpm")?;
+        let spo2_percent: Option<i32> = get_form_i32_gt0(req_kv, "spo2_percent")?;
+
+        let timestamp = get_form_timestamp_not_future(req_kv, "timestamp")?
+            .unwrap_or(default_timestamp);
+        let measurement = Self::new(
+            -1,
+            timestamp,
+            systolic_mmhg,
+            diastolic_mmhg,
+            pulse_bpm,
+            spo2_percent,
+        );
+        measurement.validate()?;
+        Ok(measurement)
+    }
+
+    fn to_json(self, config: &Config) -> Self::Json {
+        ClassifiedBloodPressureMeasurement::new(config.bp_guideline, self)
+    }
+
+    fn to_row(&self) -> MeasurementRow {
+        MeasurementRow {
+            id: self.id,
+            timestamp: self.timestamp,
+            values: vec![
+                Some(ColumnValue::Integer(self.systolic_mmhg.into())),
+                Some(ColumnValue::Integer(self.diastolic_mmhg.into())),
+                Some(ColumnValue::Integer(self.pulse_bpm.into())),
+                self.spo2_percent.map(|s| ColumnValue::Integer(s.into())),
+            ],
+        }
+    }
+
+    fn from_row(row: &MeasurementRow) -> Result<Self, DatabaseError> {
+        Ok(Self::new(
+            row.id,
+            row.timestamp,
+            row.i32(0)?,
+            row.i32(1)?,
+            row.i32(2)?,
+            row.opt_i32(3)?,
+        ))
+    }
+
+    async fn respond_list(
+        token: &AuthToken,
+        range: TimeRange,
+        measurements: Vec<Self>,
+        statistics: Option<MeasurementStatistics>,
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        // group measurements by day
+        let (hours, guideline) = {
+            let config_guard = CONFIG
+                .get().unwrap()
+                .read().await;
+            (config_guard.hours, config_guard.bp_guideline)
+        };
+        let mut day_to_measurements: BTreeMap<String, DailyBloodPressureMeasurements> = BTreeMap::new();
+        for measurement in &measurements {
+            let mut day = measurement.timestamp.date_naive();
+            if measurement.timestamp.hour() < hours.morning_start {
+                // count this as (the evening of) the previous day
+                day = day.pred_opt().expect("no previous day?!");
+            }
+
+            let date_string = day.format("%Y-%m-%d").to_string();
+
+            let entry = day_to_measurements
+                .entry(date_string.clone())
+                .or_insert_with(|| DailyBloodPressureMeasurements::new_empty(date_string));
+
+            let this_hour = measurement.timestamp.hour();
+
+            if this_hour < hours.morning_start && entry.evening.is_none() {
+                // night (previous day)
+                entry.evening = Some(*measurement);
+            } else if this_hour >= hours.morning_start && this_hour < hours.morning_end && entry.morning.is_none() {
+                // morning
+                entry.morning = Some(*measurement);
+            } else if this_hour >= hours.midday_start && this_hour < hours.midday_end && entry.midday.is_none() {
+                // midday
+                entry.midday = Some(*measurement);
+            } else if this_hour >= hours.evening_start && entry.evening.is_none() {
+                // night
+                entry.evening = Some(*measurement);
+            } else {
+                entry.other.push(*measurement);
+            }
+        }
+
+        let days_and_measurements: Vec<DailyBloodPressureMeasurements> = day_to_measurements
+            .into_values()
+            .rev()
+            .collect();
+
+        let category_counts = guideline.count_categories(&measurements);
+
+        let template = ListTemplate {
+            token: token.clone(),
+            range,
+            measurements,
+            days_and_measurements,
+            statistics,
+            guideline,
+            category_counts,
+        };
+        respond_template(
+            &template,
+            200,
+            &HashMap::new(),
+        ).await
+    }
+
+    async fn respond_edit(measurement: Self) -> Result<Response<Full<Bytes>>, Infallible> {
+        let template = EditTemplate {
+            measurement,
+        };
+        respond_template(
+            &template,
+            200,
+            &HashMap::new(),
+        ).await
+    }
+}
+
+
+impl Record for BodyMassMeasurement {
+    fn id(&self) -> i64 { self.id }
+    fn set_id(&mut self, id: i64) { self.id = id; }
+    fn timestamp(&self) -> DateTime<Local> { self.timestamp }
+}
+
+#[async_trait]
+impl Measurement for BodyMassMeasurement {
+    const SCOPE: Scope = Scope::Mass;
+    const KEY: &'static str = "mass";
+    const PAGE: &'static str = "mass";
+    const FIELDS: &'static [MeasurementField] = &[
+        MeasurementField::new("mass_kg", "mass", "kg", 2),
+        MeasurementField::new("waist_circum_cm", "waist-circum", "cm", 2),
+        MeasurementField::new("bmi", "bmi", "kg/m²", 2),
+    ];
+    const TABLE: MeasurementTable = MeasurementTable {
+        name: "mass_measurements",
+        columns: &[
+            MeasurementColumn::decimal("mass_kg"),
+            MeasurementColumn::decimal("waist_circum_cm"),
+        ],
+    };
+
+    type Json = Self;
+
+    fn values(&self) -> Vec<Option<Rational32>> {
+        vec![
+            Some(self.mass_kg),
+            self.waist_circum_cm,
+            self.bmi,
+        ]
+    }
+
+    fn validate(&self) -> Result<(), ClientError> {
+        check_timestamp_not_future("timestamp", self.timestamp)?;
+        check_r32_gt0("mass_kg", self.mass_kg)?;
+        if let Some(circum) = self.waist_circum_cm {
+            check_r32_gt0("waist_circum_cm", circum)?;
+        }
+        Ok(())
+    }
+
+    fn from_form(req_kv: &HashMap<String, String>, default_timestamp: DateTime<Local>) -> Result<Self, ClientError> {
+        let mass_kg: Rational32 = get_req_form_r32_gt0(req_kv, "mass_kg")?;
+        let waist_circum_cm: Option<Rational32> = get_form_r32_gt0(req_kv, "waist_circum_cm")?;
+
+        let timestamp = get_form_timestamp_not_future(req_kv, "timestamp")?
+            .unwrap_or(default_timestamp);
+        // the BMI is calculated from the profile when the measurement is read
+        let measurement = Self::new(
+            -1,
+            timestamp,
+            mass_kg,
+            waist_circum_cm,
+            None,
+        );
+        measurement.validate()?;
+        Ok(measurement)
+    }
+
+    async fn complete(storage: &dyn Storage, user_id: i64, measurements: &mut [Self]) -> Result<(), DatabaseError> {
+        if measurements.is_empty() {
+            return Ok(());
+        }
+        let profile = storage.get_profile(user_id).await?;
+        for measurement in measurements {
+            measurement.bmi = profile.bmi_at(measurement.timestamp, measurement.mass_kg);
+        }
+        Ok(())
+    }
+
+    fn to_json(self, _config: &Config) -> Self::Json {
+        self
+    }
+
+    fn to_row(&self) -> MeasurementRow {
+        MeasurementRow {
+            id: self.id,
+            timestamp: self.timestamp,
+            values: vec![
+                Some(ColumnValue::Decimal(self.mass_kg)),
+                self.waist_circum_cm.map(ColumnValue::Decimal),
+            ],
+        }
+    }
+
+    fn from_row(row: &MeasurementRow) -> Result<Self, DatabaseError> {
+        Ok(Self::new(
+            row.id,
+            row.timestamp,
+            row.decimal(0)?,
+            row.opt_decimal(1)?,
+            None,
+        ))
+    }
+
+    async fn respond_list(
+        token: &AuthToken,
+        range: TimeRange,
+        mut measurements: Vec<Self>,
+        statistics: Option<MeasurementStatistics>,
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        measurements.reverse();
+        let template = MassListTemplate {
+            token: token.clone(),
+            range,
+            measurements,
+            statistics,
+        };
+        respond_template(
+            &template,
+            200,
+            &HashMap::new(),
+        ).await
+    }
+
+    async fn respond_edit(measurement: Self) -> Result<Response<Full<Bytes>>, Infallible> {
+        let template = MassEditTemplate {
+            measurement,
+        };
+        respond_template(
+            &template,
+            200,
+            &HashMap::new(),
+        ).await
+    }
+}
+
+
+impl Record for BodyTemperatureMeasurement {
+    fn id(&self) -> i64 { self.id }
+    fn set_id(&mut self, id: i64) { self.id = id; }
+    fn timestamp(&self) -> DateTime<Local> { self.timestamp }
+}
+
+#[async_trait]
+impl Measurement for BodyTemperatureMeasurement {
+    const SCOPE: Scope = Scope::Temperature;
+    const KEY: &'static str = "temperature";
+    const PAGE: &'static str = "temperature";
+    const FIELDS: &'static [MeasurementField] = &[
+        MeasurementField::new("temperature_celsius", "temperature", "°C", 2),
+    ];
+    const TABLE: MeasurementTable = MeasurementTable {
+        name: "body_temperature_measurements",
+        columns: &[
+            MeasurementColumn::integer("location_id"),
+            MeasurementColumn::decimal("temperature_celsius"),
+        ],
+    };
+
+    type Json = Self;
+
+    fn values(&self) -> Vec<Option<Rational32>> {
+        vec![
+            Some(self.temperature_celsius),
+        ]
+    }
+
+    fn validate(&self) -> Result<(), ClientError> {
+        check_timestamp_not_future("timestamp", self.timestamp)?;
+        if self.temperature_celsius < *ABSOLUTE_ZERO_CELSIUS {
+            // temperature below absolute zero?!
+            return Err(ClientError::RationalValueTooLow("temperature_celsius".into(), self.temperature_celsius, *ABSOLUTE_ZERO_CELSIUS));
+        }
+        Ok(())
+    }
+
+    async fn check_references(storage: &dyn Storage, measurements: &[Self]) -> Result<Vec<Result<(), ClientError>>, DatabaseError> {
+        let location_ids: HashSet<i64> = storage.get_temperature_locations().await?
+            .iter()
+            .map(|l| l.id)
+            .collect();
+        let results = measurements.iter()
+            .map(|m| if location_ids.contains(&m.location_id) {
+                Ok(())
+            } else {
+                Err(ClientError::UnknownLocation(m.location_id))
+            })
+            .collect();
+        Ok(results)
+    }
+
+    fn from_form(req_kv: &HashMap<String, String>, default_timestamp: DateTime<Local>) -> Result<Self, ClientError> {
+        let location_id: i64 = get_req_form_i64(req_kv, "location")?;
+
+        let temp_celsius: Rational32 = get_req_form_r32(req_kv, "temperature_celsius")?;
+
+        let timestamp = get_form_timestamp_not_future(req_kv, "timestamp")?
+            .unwrap_or(default_timestamp);
+        let measurement = Self::new(
+            -1,
+            timestamp,
+            location_id,
+            temp_celsius,
+        );
+        measurement.validate()?;
+        Ok(measurement)
+    }
+
+    fn to_json(self, _config: &Config) -> Self::Json {
+        self
+    }
+
+    fn to_row(&self) -> MeasurementRow {
+        MeasurementRow {
+            id: self.id,
+            timestamp: self.timestamp,
+            values: vec![
+                Some(ColumnValue::Integer(self.location_id)),
+                Some(ColumnValue::Decimal(self.temperature_celsius)),
+            ],
+        }
+    }
+
+    fn from_row(row: &MeasurementRow) -> Result<Self, DatabaseError> {
+        Ok(Self::new(
+            row.id,
+            row.timestamp,
+            row.integer(0)?,
+            row.decimal(1)?,
+        ))
+    }
+
+    async fn respond_list(
+        token: &AuthToken,
+        range: TimeRange,
+        mut measurements: Vec<Self>,
+        statistics: Option<MeasurementStatistics>,
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        measurements.reverse();
+        let temperature_locations = match storage().get_temperature_locations().await {
+            Ok(l) => l,
+            Err(e) => {
+                error!("error obtaining temperature locations: {}", e);
+                return respond_500();
+            },
+        };
+        let default_temperature_location_id = CONFIG
+            .get().unwrap()
+            .read().await
+            .default_temperature_location_id;
+
+        let template = TemperatureListTemplate {
+            token: token.clone(),
+            range,
+            measurements,
+            temperature_locations,
+            default_temperature_location_id,
+            statistics,
+        };
+        respond_template(
+            &template,
+            200,
+            &HashMap::new(),
+        ).await
+    }
+
+    async fn respond_edit(measurement: Self) -> Result<Response<Full<Bytes>>, Infallible> {
+        let temperature_locations = match storage().get_temperature_locations().await {
+            Ok(l) => l,
+            Err(e) => {
+                error!("error obtaining temperature locations: {}", e);
+                return respond_500();
+            },
+        };
+
+        let template = TemperatureEditTemplate {
+            measurement,
+            temperature_locations,
+        };
+        respond_template(
+            &template,
+            200,
+            &HashMap::new(),
+        ).await
+    }
+}
+
+
+impl Record for BloodSugarMeasurement {
+    fn id(&self) -> i64 { self.id }
+    fn set_id(&mut self, id: i64) { self.id = id; }
+    fn timestamp(&self) -> DateTime<Local> { self.timestamp }
+}
+
+#[async_trait]
+impl Measurement for BloodSugarMeasurement {
+    const SCOPE: Scope = Scope::Sugar;
+    const KEY: &'static str = "sugar";
+    const PAGE: &'static str = "sugar";
+    const FIELDS: &'static [MeasurementField] = &[
+        MeasurementField::new("sugar_mmol_per_l", "sugar mmol-per-l", "mmol/l", 1),
+        MeasurementField::new("sugar_mg_per_dl", "sugar mg-per-dl", "mg/dl", 0),
+    ];
+    const TABLE: MeasurementTable = MeasurementTable {
+        name: "blood_sugar_measurements",
+        columns: &[
+            MeasurementColumn::decimal("sugar_mmol_per_l"),
+        ],
+    };
+
+    type Json = Self;
+
+    fn values(&self) -> Vec<Option<Rational32>> {
+        vec![
+            Some(self.sugar_mmol_per_l),
+            Some(self.sugar_mg_per_dl()),
+        ]
+    }
+
+    fn validate(&self) -> Result<(), ClientError> {
+        check_timestamp_not_future("timestamp", self.timestamp)?;
+        check_r32_gt0("sugar_mmol_per_l", self.sugar_mmol_per_l)?;
+        Ok(())
+    }
+
+    fn from_form(req_kv: &HashMap<String, String>, default_timestamp: DateTime<Local>) -> Result<Self, ClientError> {
+        let unit_key = match req_kv.get("sugar_unit_key") {
+            Some(uk) => uk,
+            None => return Err(ClientError::MissingValue("sugar_unit_key".to_owned())),
+        };
+        let factor_to_mmol_per_l = if unit_key == "mmol-per-l" {
+            Rational32::new(1, 1)
+        } else if unit_key == "mg-per-dl" {
+            Rational32::new(1, SUGAR_MG_PER_DL_IN_MMOL_PER_L)
+        } else {
+            return Err(ClientError::ValueIsInvalidOption(
+                "sugar_unit_key".to_owned(),
+                unit_key.clone(),
+                vec!["mmol-per-l".to_owned(), "mg-per-dl".to_owned()],
+            ));
+        };
+
+        let sugar_value: Rational32 = get_req_form_r32_gt0(req_kv, "sugar_value")?;
+        let sugar_mmol_per_l: Rational32 = sugar_value * factor_to_mmol_per_l;
+
+        let timestamp = get_form_timestamp_not_future(req_kv, "timestamp")?
+            .unwrap_or(default_timestamp);
+        let measurement = Self::new(
+            -1,
+            timestamp,
+            sugar_mmol_per_l,
+        );
+        measurement.validate()?;
+        Ok(measurement)
+    }
+
+    fn to_json(self, _config: &Config) -> Self::Json {
+        self
+    }
+
+    fn to_row(&self) -> MeasurementRow {
+        MeasurementRow {
+            id: self.id,
+            timestamp: self.timestamp,
+            values: vec![
+                Some(ColumnValue::Decimal(self.sugar_mmol_per_l)),
+            ],
+        }
+    }
+
+    fn from_row(row: &MeasurementRow) -> Result<Self, DatabaseError> {
+        Ok(Self::new(
+            row.id,
+            row.timestamp,
+            row.decimal(0)?,
+        ))
+    }
+
+    async fn respond_list(
+        token: &AuthToken,
+        range: TimeRange,
+        mut measurements: Vec<Self>,
+        statistics: Option<MeasurementStatistics>,
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        measurements.reverse();
+        let template = SugarListTemplate {
+            token: token.clone(),
+            range,
+            measurements,
+            statistics,
+        };
+        respond_template(
+            &template,
+            200,
+            &HashMap::new(),
+        ).await
+    }
+
+    async fn respond_edit(measurement: Self) -> Result<Response<Full<Bytes>>, Infallible> {
+        let template = SugarEditTemplate {
+            measurement,
+        };
+        respond_template(
+            &template,
+            200,
+            &HashMap::new(),
+        ).await
+    }
+}
+
+
+impl Record for LongTermBloodSugarMeasurement {
+    fn id(&self) -> i64 { self.id }
+    fn set_id(&mut self, id: i64) { self.id = id; }
+    fn timestamp(&self) -> DateTime<Local> { self.timestamp }
+}
+
+#[async_trait]
+impl Measurement for LongTermBloodSugarMeasurement {
+    const SCOPE: Scope = Scope::LongTermSugar;
+    const KEY: &'static str = "long-term-sugar";
+    const PAGE: &'static str = "long-term-sugar";
+    const LOOKBACK_DAYS: i64 = LONG_TERM_SUGAR_LOOKBACK_DAYS;
+    const FIELDS: &'static [MeasurementField] = &[
+        MeasurementField::new("hba1c_mmol_per_mol", "hba1c mmol-per-mol", "mmol/mol", 0),
+        MeasurementField::new("hba1c_dcct_percent", "hba1c dcct-percent", "%", 1),
+    ];
+    const TABLE: MeasurementTable = MeasurementTable {
+        name: "long_term_blood_sugar_measurements",
+        columns: &[
+            MeasurementColumn::decimal("hba1c_mmol_per_mol"),
+        ],
+    };
+
+    type Json = Self;
+
+    fn values(&self) -> Vec<Option<Rational32>> {
+        vec![
+            Some(self.hba1c_mmol_per_mol),
+            Some(self.hba1c_dcct_percent()),
+        ]
+    }
+
+    fn validate(&self) -> Result<(), ClientError> {
+        check_timestamp_not_future("timestamp", self.timestamp)?;
+        check_r32_gt0("hba1c_mmol_per_mol", self.hba1c_mmol_per_mol)?;
+        Ok(())
+    }
+
+    fn from_form(req_kv: &HashMap<String, String>, default_timestamp: DateTime<Local>) -> Result<Self, ClientError> {
+        let unit_key = match req_kv.get("hba1c_unit_key") {
+            Some(uk) => uk,
+            None => return Err(ClientError::MissingValue("hba1c_unit_key".to_owned())),
+        };
+        let hba1c_value: Rational32 = get_req_form_r32_gt0(req_kv, "hba1c_value")?;
+        let timestamp = get_form_timestamp_not_future(req_kv, "timestamp")?
+            .unwrap_or(default_timestamp);
+        let measurement = if unit_key == "mmol-per-mol" {
+            Self::new(
+                -1,
+                timestamp,
+                hba1c_value,
+            )
+        } else if unit_key == "dcct-percent" {
+            Self::new_dcct_percent(
+                -1,
+                timestamp,
+                hba1c_value,
+            )
+        } else {
+            return Err(ClientError::ValueIsInvalidOption(
+                "hba1c_unit_key".to_owned(),
+                unit_key.clone(),
+                vec!["mmol-per-mol".to_owned(), "dcct-percent".to_owned()],
+            ));
+        };
+        measurement.validate()?;
+        Ok(measurement)
+    }
+
+    fn to_json(self, _config: &Config) -> Self::Json {
+        self
+    }
+
+    fn to_row(&self) -> MeasurementRow {
+        MeasurementRow {
+            id: self.id,
+            timestamp: self.timestamp,
+            values: vec![
+                Some(ColumnValue::Decimal(self.hba1c_mmol_per_mol)),
+            ],
+        }
+    }
+
+    fn from_row(row: &MeasurementRow) -> Result<Self, DatabaseError> {
+        Ok(Self::new(
+            row.id,
+            row.timestamp,
+            row.decimal(0)?,
+        ))
+    }
+
+    async fn respond_list(
+        token: &AuthToken,
+        range: TimeRange,
+        mut measurements: Vec<Self>,
+        statistics: Option<MeasurementStatistics>,
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        measurements.reverse();
+        let template = LongTermSugarListTemplate {
+            token: token.clone(),
+            range,
+            measurements,
+            statistics,
+        };
+        respond_template(
+            &template,
+            200,
+            &HashMap::new(),
+        ).await
+    }
+
+    async fn respond_edit(measurement: Self) -> Result<Response<Full<Bytes>>, Infallible> {
+        let template = LongTermSugarEditTemplate {
+            measurement,
+        };
+        respond_template(
+            &template,
+            200,
+            &HashMap::new(),
+        ).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    #[test]
+    fn endpoints_by_path() {
+        assert_eq!(measurement_endpoint::<BloodPressureMeasurement>("/"), Some(MeasurementEndpoint::List));
+        assert_eq!(measurement_endpoint::<BloodPressureMeasurement>("/edit-bp"), Some(MeasurementEndpoint::Edit));
+        assert_eq!(measurement_endpoint::<BloodPressureMeasurement>("/mass"), None);
+        assert_eq!(measurement_endpoint::<BodyMassMeasurement>("/mass"), Some(MeasurementEndpoint::List));
+        assert_eq!(measurement_endpoint::<BodyMassMeasurement>("/delete-mass"), Some(MeasurementEndpoint::Delete));
+        assert_eq!(measurement_endpoint::<LongTermBloodSugarMeasurement>("/api/long-term-sugar"), Some(MeasurementEndpoint::Api));
+        assert_eq!(measurement_endpoint::<LongTermBloodSugarMeasurement>("/api/long-term-sugar/statistics"), Some(MeasurementEndpoint::ApiStatistics));
+        assert_eq!(measurement_endpoint::<BloodSugarMeasurement>("/api/long-term-sugar"), None);
+        assert_eq!(measurement_endpoint::<BloodSugarMeasurement>("/api/sugar/other"), None);
+    }
+
+    #[test]
+    fn values_by_key() {
+        let timestamp = Local.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap();
+        let measurement = BloodPressureMeasurement::new(-1, timestamp, 120, 80, 60, None);
+        assert_eq!(measurement.value("diastolic_mmhg"), Some(Rational32::from_integer(80)));
+        assert_eq!(measurement.value("spo2_percent"), None);
+        assert_eq!(measurement.value("mass_kg"), None);
+
+        let cold = BodyTemperatureMeasurement::new(-1, timestamp, 1, Rational32::from_integer(-300));
+        assert!(cold.validate().is_err());
+    }
+}
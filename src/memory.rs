@@ -0,0 +1,371 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+
+use crate::database::DatabaseError;
+use crate::migrations::MigrationError;
+use crate::model::{
+    BodyHeight, BodyTemperatureLocation, CustomMeasurement, Page, Profile, Session, ShareToken,
+    TimeRange, User,
+};
+use crate::storage::{MeasurementRow, MeasurementTable, Storage};
+
+
+/// The temperature locations that a new in-memory storage starts out with, as in the SQLite schema.
+const INITIAL_TEMPERATURE_LOCATIONS: [&str; 5] = ["rectum", "mouth", "armpit", "ear", "forehead"];
+
+
+/// The measurements of one table, keyed by ID, with the ID of the user each one belongs to.
+struct Table<T> {
+    last_id: i64,
+    rows: BTreeMap<i64, (i64, T)>,
+}
+impl<T: Clone> Table<T> {
+    fn new() -> Self {
+        Self {
+            last_id: 0,
+            rows: BTreeMap::new(),
+        }
+    }
+
+    /// Inserts a new row, returning its ID. The function receives the ID to store in the row.
+    fn insert<F: FnOnce(i64) -> T>(&mut self, user_id: i64, make_row: F) -> i64 {
+        self.last_id += 1;
+        let id = self.last_id;
+        self.rows.insert(id, (user_id, make_row(id)));
+        id
+    }
+
+    /// Replaces an existing row; like SQL UPDATE, nothing happens if the user has no row with this ID.
+    fn update(&mut self, user_id: i64, id: i64, row: T) {
+        if let Some((row_user_id, existing)) = self.rows.get_mut(&id) {
+            if *row_user_id == user_id {
+                *existing = row;
+            }
+        }
+    }
+
+    fn remove(&mut self, user_id: i64, id: i64) {
+        if self.get(user_id, id).is_some() {
+            self.rows.remove(&id);
+        }
+    }
+
+    fn get(&self, user_id: i64, id: i64) -> Option<T> {
+        self.rows.get(&id)
+            .filter(|(row_user_id, _row)| *row_user_id == user_id)
+            .map(|(_row_user_id, row)| row.clone())
+    }
+
+    /// The user's rows in the given range and page, ordered by timestamp and ID.
+    fn list<F: Fn(&T) -> DateTime<Local>>(&self, user_id: i64, range: &TimeRange, page: &Page, get_timestamp: F) -> Vec<T> {
+        let mut keyed_rows: Vec<(DateTime<Local>, i64, &T)> = self.rows.iter()
+            .filter(|(_id, (row_user_id, _row))| *row_user_id == user_id)
+            .map(|(id, (_row_user_id, row))| (get_timestamp(row), *id, row))
+            .filter(|(timestamp, _id, _row)| range.start.map(|s| *timestamp >= s).unwrap_or(true))
+            .filter(|(timestamp, _id, _row)| range.end.map(|e| *timestamp < e).unwrap_or(true))
+            .filter(|(timestamp, id, _row)| page.after.map(|a| (*timestamp, *id) > (a.timestamp, a.id)).unwrap_or(true))
+            .collect();
+        keyed_rows.sort_by_key(|(timestamp, id, _row)| (*timestamp, *id));
+
+        let limit = page.limit
+            .map(|l| l.max(0) as usize)
+            .unwrap_or(keyed_rows.len());
+        keyed_rows.into_iter()
+            .take(limit)
+            .map(|(_timestamp, _id, row)| row.clone())
+            .collect()
+    }
+}
+
+
+struct Tables {
+    users: Vec<User>,
+    /// Keyed by user ID; users without an entry have an empty profile.
+    profiles: BTreeMap<i64, Profile>,
+    last_height_id: i64,
+    /// Keyed by share token ID; the values also contain the ID of the user who created the token.
+    share_tokens: BTreeMap<i64, (i64, ShareToken)>,
+    /// Keyed by session ID; the values also contain the ID of the user who started the session.
+    sessions: BTreeMap<i64, (i64, Session)>,
+    /// Keyed by the name of the measurement table.
+    measurements: BTreeMap<&'static str, Table<MeasurementRow>>,
+    temperature_locations: BTreeMap<i64, BodyTemperatureLocation>,
+    /// Keyed by the name of the custom measurement type.
+    custom: BTreeMap<String, Table<CustomMeasurement>>,
+}
+
+
+/// Storage that only keeps measurements in memory, meant for testing. Unlike the database backends,
+/// decimal values are not rounded and constraints are not enforced.
+pub(crate) struct MemoryStorage {
+    tables: Mutex<Tables>,
+}
+impl MemoryStorage {
+    pub fn new() -> Self {
+        let mut temperature_locations = BTreeMap::new();
+        for (index, name) in INITIAL_TEMPERATURE_LOCATIONS.iter().enumerate() {
+            let id = (index as i64) + 1;
+            temperature_locations.insert(id, BodyTemperatureLocation::new(id, (*name).to_owned()));
+        }
+
+        Self {
+            tables: Mutex::new(Tables {
+                users: Vec::new(),
+                profiles: BTreeMap::new(),
+                last_height_id: 0,
+                share_tokens: BTreeMap::new(),
+                sessions: BTreeMap::new(),
+                measurements: BTreeMap::new(),
+                temperature_locations,
+                custom: BTreeMap::new(),
+            }),
+        }
+    }
+
+    fn with_tables<T, F: FnOnce(&mut Tables) -> T>(&self, f: F) -> T {
+        let mut tables_guard = self.tables
+            .lock().expect("memory storage mutex poisoned");
+        f(&mut tables_guard)
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn run_migrations(&self) -> Result<Vec<i32>, MigrationError> {
+        Ok(Vec::new())
+    }
+
+    async fn check_schema_version(&self) -> Result<(), MigrationError> {
+        Ok(())
+    }
+
+    async fn get_or_add_user(&self, name: &str) -> Result<User, DatabaseError> {
+        Ok(self.with_tables(|t| {
+            if let Some(user) = t.users.iter().find(|u| u.name == name) {
+                return user.clone();
+            }
+            let user = User::new((t.users.len() as i64) + 1, name.to_owned());
+            t.users.push(user.clone());
+            user
+        }))
+    }
+
+    async fn get_profile(&self, user_id: i64) -> Result<Profile, DatabaseError> {
+        Ok(self.with_tables(|t| t.profiles.get(&user_id).cloned().unwrap_or_default()))
+    }
+
+    async fn update_profile(&self, user_id: i64, profile: &Profile) -> Result<(), DatabaseError> {
+        self.with_tables(|t| {
+            let stored_profile = t.profiles.entry(user_id).or_default();
+            stored_profile.birth_date = profile.birth_date;
+            stored_profile.sex = profile.sex;
+        });
+        Ok(())
+    }
+
+    async fn set_height(&self, user_id: i64, height: &BodyHeight) -> Result<i64, DatabaseError> {
+        Ok(self.with_tables(|t| {
+            let heights = &mut t.profiles.entry(user_id).or_default().heights;
+            if let Some(existing) = heights.iter_mut().find(|h| h.effective_date == height.effective_date) {
+                existing.height_cm = height.height_cm;
+                return existing.id;
+            }
+            t.last_height_id += 1;
+            heights.push(BodyHeight { id: t.last_height_id, ..*height });
+            heights.sort_by_key(|h| h.effective_date);
+            t.last_height_id
+        }))
+    }
+
+    async fn remove_height(&self, user_id: i64, height_id: i64) -> Result<(), DatabaseError> {
+        self.with_tables(|t| {
+            if let Some(profile) = t.profiles.get_mut(&user_id) {
+                profile.heights.retain(|h| h.id != height_id);
+            }
+        });
+        Ok(())
+    }
+
+    async fn add_share_token(&self, user_id: i64, share_token: &ShareToken) -> Result<i64, DatabaseError> {
+        Ok(self.with_tables(|t| {
+            let id = t.share_tokens.keys().last()
+                .map_or(1, |last_id| last_id + 1);
+            t.share_tokens.insert(id, (user_id, ShareToken { id, ..share_token.clone() }));
+            id
+        }))
+    }
+
+    async fn get_share_tokens(&self, user_id: i64) -> Result<Vec<ShareToken>, DatabaseError> {
+        Ok(self.with_tables(|t| {
+            let mut share_tokens: Vec<ShareToken> = t.share_tokens.values()
+                .filter(|(uid, _)| *uid == user_id)
+                .map(|(_, st)| st.clone())
+                .collect();
+            share_tokens.sort_by(|a, b| b.created.cmp(&a.created).then(b.id.cmp(&a.id)));
+            share_tokens
+        }))
+    }
+
+    async fn get_share_token_by_hash(&self, token_sha256: &str) -> Result<Option<(User, ShareToken)>, DatabaseError> {
+        Ok(self.with_tables(|t| {
+            let (user_id, share_token) = t.share_tokens.values()
+                .find(|(_, st)| st.token_sha256 == token_sha256)?;
+            let user = t.users.iter()
+                .find(|u| u.id == *user_id)?;
+            Some((user.clone(), share_token.clone()))
+        }))
+    }
+
+    async fn revoke_share_token(&self, user_id: i64, share_token_id: i64, revoked: DateTime<Local>) -> Result<(), DatabaseError> {
+        self.with_tables(|t| {
+            if let Some((uid, share_token)) = t.share_tokens.get_mut(&share_token_id) {
+                if *uid == user_id && share_token.revoked.is_none() {
+                    share_token.revoked = Some(revoked);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn add_session(&self, user_id: i64, session: &Session) -> Result<i64, DatabaseError> {
+        Ok(self.with_tables(|t| {
+            let id = t.sessions.keys().last()
+                .map_or(1, |last_id| last_id + 1);
+            t.sessions.insert(id, (user_id, Session { id, ..session.clone() }));
+            id
+        }))
+    }
+
+    async fn get_session_by_hash(&self, session_sha256: &str) -> Result<Option<(User, Session)>, DatabaseError> {
+        Ok(self.with_tables(|t| {
+            let (user_id, session) = t.sessions.values()
+                .find(|(_, s)| s.session_sha256 == session_sha256)?;
+            let user = t.users.iter()
+                .find(|u| u.id == *user_id)?;
+            Some((user.clone(), session.clone()))
+        }))
+    }
+
+    async fn remove_session(&self, session_sha256: &str) -> Result<(), DatabaseError> {
+        self.with_tables(|t| t.sessions.retain(|_, (_, s)| s.session_sha256 != session_sha256));
+        Ok(())
+    }
+
+    async fn remove_expired_sessions(&self, now: DateTime<Local>) -> Result<(), DatabaseError> {
+        self.with_tables(|t| t.sessions.retain(|_, (_, s)| s.is_active_at(now)));
+        Ok(())
+    }
+
+    async fn add_measurements(&self, user_id: i64, table: &MeasurementTable, rows: &[MeasurementRow]) -> Result<Vec<i64>, DatabaseError> {
+        Ok(self.with_tables(|t| {
+            let measurements = t.measurements
+                .entry(table.name)
+                .or_insert_with(Table::new);
+            rows.iter()
+                .map(|r| measurements.insert(user_id, |id| MeasurementRow { id, ..r.clone() }))
+                .collect()
+        }))
+    }
+
+    async fn remove_measurement(&self, user_id: i64, table: &MeasurementTable, measurement_id: i64) -> Result<(), DatabaseError> {
+        self.with_tables(|t| {
+            if let Some(measurements) = t.measurements.get_mut(table.name) {
+                measurements.remove(user_id, measurement_id);
+            }
+        });
+        Ok(())
+    }
+
+    async fn update_measurement(&self, user_id: i64, table: &MeasurementTable, row: &MeasurementRow) -> Result<(), DatabaseError> {
+        self.with_tables(|t| {
+            if let Some(measurements) = t.measurements.get_mut(table.name) {
+                measurements.update(user_id, row.id, row.clone());
+            }
+        });
+        Ok(())
+    }
+
+    async fn get_measurement(&self, user_id: i64, table: &MeasurementTable, measurement_id: i64) -> Result<Option<MeasurementRow>, DatabaseError> {
+        Ok(self.with_tables(|t| t.measurements
+            .get(table.name)
+            .and_then(|measurements| measurements.get(user_id, measurement_id))))
+    }
+
+    async fn get_measurements(&self, user_id: i64, table: &MeasurementTable, range: &TimeRange, page: &Page) -> Result<Vec<MeasurementRow>, DatabaseError> {
+        Ok(self.with_tables(|t| t.measurements
+            .get(table.name)
+            .map(|measurements| measurements.list(user_id, range, page, |r| r.timestamp))
+            .unwrap_or_default()))
+    }
+
+    async fn add_temperature_location(&self, loc: &BodyTemperatureLocation) -> Result<i64, DatabaseError> {
+        Ok(self.with_tables(|t| {
+            let id = t.temperature_locations.keys()
+                .next_back()
+                .map_or(1, |last_id| last_id + 1);
+            t.temperature_locations.insert(id, BodyTemperatureLocation::new(id, loc.name.clone()));
+            id
+        }))
+    }
+
+    async fn remove_temperature_location(&self, loc_id: i64) -> Result<(), DatabaseError> {
+        self.with_tables(|t| t.temperature_locations.remove(&loc_id));
+        Ok(())
+    }
+
+    async fn update_temperature_location(&self, loc: &BodyTemperatureLocation) -> Result<(), DatabaseError> {
+        self.with_tables(|t| {
+            if let Some(existing) = t.temperature_locations.get_mut(&loc.id) {
+                *existing = loc.clone();
+            }
+        });
+        Ok(())
+    }
+
+    async fn get_temperature_locations(&self) -> Result<Vec<BodyTemperatureLocation>, DatabaseError> {
+        let mut locations: Vec<BodyTemperatureLocation> = self.with_tables(|t| t.temperature_locations.values().cloned().collect());
+        locations.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(locations)
+    }
+
+    async fn add_custom_measurement(&self, user_id: i64, type_name: &str, measurement: &CustomMeasurement) -> Result<i64, DatabaseError> {
+        Ok(self.with_tables(|t| t.custom
+            .entry(type_name.to_owned())
+            .or_insert_with(Table::new)
+            .insert(user_id, |id| CustomMeasurement { id, ..measurement.clone() })))
+    }
+
+    async fn remove_custom_measurement(&self, user_id: i64, type_name: &str, measurement_id: i64) -> Result<(), DatabaseError> {
+        self.with_tables(|t| {
+            if let Some(table) = t.custom.get_mut(type_name) {
+                table.remove(user_id, measurement_id);
+            }
+        });
+        Ok(())
+    }
+
+    async fn update_custom_measurement(&self, user_id: i64, type_name: &str, measurement: &CustomMeasurement) -> Result<(), DatabaseError> {
+        self.with_tables(|t| {
+            if let Some(table) = t.custom.get_mut(type_name) {
+                table.update(user_id, measurement.id, measurement.clone());
+            }
+        });
+        Ok(())
+    }
+
+    async fn get_custom_measurement(&self, user_id: i64, type_name: &str, measurement_id: i64) -> Result<Option<CustomMeasurement>, DatabaseError> {
+        Ok(self.with_tables(|t| t.custom
+            .get(type_name)
+            .and_then(|table| table.get(user_id, measurement_id))))
+    }
+
+    async fn get_custom_measurements(&self, user_id: i64, type_name: &str, range: &TimeRange, page: &Page) -> Result<Vec<CustomMeasurement>, DatabaseError> {
+        Ok(self.with_tables(|t| t.custom
+            .get(type_name)
+            .map(|table| table.list(user_id, range, page, |m| m.timestamp))
+            .unwrap_or_default()))
+    }
+}
@@ -0,0 +1,314 @@
+use std::error::Error;
+use std::fmt;
+
+use deadpool_postgres::Object;
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior};
+
+use crate::database::DatabaseError;
+
+
+/// An embedded schema migration.
+struct Migration {
+    version: i32,
+    name: &'static str,
+    sql: &'static str,
+}
+
+/// All PostgreSQL schema migrations, ordered by version. Versions must never be reused or
+/// reordered once released; add new migrations at the end.
+const POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../db/migrations/postgres/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "spo2",
+        sql: include_str!("../db/migrations/postgres/0002_spo2.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "waist_circumference",
+        sql: include_str!("../db/migrations/postgres/0003_waist_circumference.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "users",
+        sql: include_str!("../db/migrations/postgres/0004_users.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "profiles",
+        sql: include_str!("../db/migrations/postgres/0005_profiles.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "share_tokens",
+        sql: include_str!("../db/migrations/postgres/0006_share_tokens.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "custom_measurements",
+        sql: include_str!("../db/migrations/postgres/0007_custom_measurements.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "sessions",
+        sql: include_str!("../db/migrations/postgres/0008_sessions.sql"),
+    },
+];
+
+/// All SQLite schema migrations, ordered by version. These are versioned independently of the
+/// PostgreSQL migrations.
+const SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../db/migrations/sqlite/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "users",
+        sql: include_str!("../db/migrations/sqlite/0002_users.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "profiles",
+        sql: include_str!("../db/migrations/sqlite/0003_profiles.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "share_tokens",
+        sql: include_str!("../db/migrations/sqlite/0004_share_tokens.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "custom_measurements",
+        sql: include_str!("../db/migrations/sqlite/0005_custom_measurements.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "sessions",
+        sql: include_str!("../db/migrations/sqlite/0006_sessions.sql"),
+    },
+];
+
+/// Arbitrary key of the advisory lock that keeps concurrently starting instances from migrating at
+/// the same time.
+const MIGRATION_LOCK_KEY: i64 = 0x6265_6570_6565;
+
+
+#[derive(Debug)]
+pub(crate) enum MigrationError {
+    Database(DatabaseError),
+    DatabaseNewer(i32, i32),
+    PendingMigrations(i32, i32),
+}
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Database(e)
+                => write!(f, "{}", e),
+            MigrationError::DatabaseNewer(database_version, binary_version)
+                => write!(f, "database schema version {} is newer than the latest version {} known to this program; please upgrade", database_version, binary_version),
+            MigrationError::PendingMigrations(database_version, binary_version)
+                => write!(f, "database schema version {} is older than version {}; run the \"migrate\" command", database_version, binary_version),
+        }
+    }
+}
+impl Error for MigrationError {
+}
+impl From<DatabaseError> for MigrationError {
+    fn from(e: DatabaseError) -> Self {
+        MigrationError::Database(e)
+    }
+}
+impl From<tokio_postgres::Error> for MigrationError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        MigrationError::Database(DatabaseError::Postgres(e))
+    }
+}
+impl From<rusqlite::Error> for MigrationError {
+    fn from(e: rusqlite::Error) -> Self {
+        MigrationError::Database(DatabaseError::Sqlite(e))
+    }
+}
+
+
+fn latest_version(migrations: &[Migration]) -> i32 {
+    migrations.last()
+        .map(|m| m.version)
+        .unwrap_or(0)
+}
+
+fn check_not_newer(database_version: i32, migrations: &[Migration]) -> Result<(), MigrationError> {
+    if database_version > latest_version(migrations) {
+        Err(MigrationError::DatabaseNewer(database_version, latest_version(migrations)))
+    } else {
+        Ok(())
+    }
+}
+
+fn check_current(database_version: i32, migrations: &[Migration]) -> Result<(), MigrationError> {
+    check_not_newer(database_version, migrations)?;
+    if database_version < latest_version(migrations) {
+        Err(MigrationError::PendingMigrations(database_version, latest_version(migrations)))
+    } else {
+        Ok(())
+    }
+}
+
+
+/// Applies all migrations that have not yet been applied to the PostgreSQL database, returning the
+/// versions that have been applied. All migrations are applied in a single transaction.
+pub(crate) async fn run_postgres_migrations(client: &mut Object) -> Result<Vec<i32>, MigrationError> {
+    let transaction = client.transaction()
+        .await?;
+
+    transaction
+        .execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_KEY])
+        .await?;
+    // CREATE SCHEMA IF NOT EXISTS requires the CREATE privilege on the database even if the schema exists
+    transaction
+        .batch_execute("
+            DO $$
+            BEGIN
+                IF NOT EXISTS (SELECT 1 FROM pg_namespace WHERE nspname = 'beepee') THEN
+                    CREATE SCHEMA beepee;
+                END IF;
+            END
+            $$;
+            CREATE TABLE IF NOT EXISTS beepee.schema_migrations
+            ( version integer NOT NULL
+            , name character varying(256) NOT NULL
+            , applied_at timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP
+            , CONSTRAINT schema_migrations_pkey PRIMARY KEY (version)
+            );
+        ")
+        .await?;
+
+    let row = transaction
+        .query_one("SELECT CAST(COALESCE(MAX(version), 0) AS integer) FROM beepee.schema_migrations", &[])
+        .await?;
+    let database_version: i32 = row.get(0);
+    check_not_newer(database_version, POSTGRES_MIGRATIONS)?;
+
+    let mut applied_versions = Vec::new();
+    for migration in POSTGRES_MIGRATIONS.iter().filter(|m| m.version > database_version) {
+        transaction
+            .batch_execute(migration.sql)
+            .await?;
+        transaction
+            .execute(
+                "INSERT INTO beepee.schema_migrations (version, name) VALUES ($1, $2)",
+                &[&migration.version, &migration.name],
+            )
+            .await?;
+        applied_versions.push(migration.version);
+    }
+
+    transaction.commit()
+        .await?;
+    Ok(applied_versions)
+}
+
+/// Ensures that the PostgreSQL database schema is at exactly the version expected by this program
+/// without changing the database.
+pub(crate) async fn check_postgres_schema_version(client: &Object) -> Result<(), MigrationError> {
+    let row = client
+        .query_one("SELECT to_regclass('beepee.schema_migrations') IS NOT NULL", &[])
+        .await?;
+    let has_migrations_table: bool = row.get(0);
+    let database_version: i32 = if has_migrations_table {
+        let row = client
+            .query_one("SELECT CAST(COALESCE(MAX(version), 0) AS integer) FROM beepee.schema_migrations", &[])
+            .await?;
+        row.get(0)
+    } else {
+        0
+    };
+
+    check_current(database_version, POSTGRES_MIGRATIONS)
+}
+
+
+/// Applies all migrations that have not yet been applied to the SQLite database, returning the
+/// versions that have been applied. All migrations are applied in a single transaction.
+pub(crate) fn run_sqlite_migrations(connection: &mut Connection) -> Result<Vec<i32>, MigrationError> {
+    // an immediate transaction takes the write lock right away
+    let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    transaction.execute_batch("
+        CREATE TABLE IF NOT EXISTS schema_migrations
+        ( version INTEGER NOT NULL PRIMARY KEY
+        , name TEXT NOT NULL
+        , applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+    ")?;
+
+    let database_version: i32 = transaction
+        .query_row("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", [], |row| row.get(0))?;
+    check_not_newer(database_version, SQLITE_MIGRATIONS)?;
+
+    let mut applied_versions = Vec::new();
+    for migration in SQLITE_MIGRATIONS.iter().filter(|m| m.version > database_version) {
+        transaction.execute_batch(migration.sql)?;
+        transaction.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES (?1, ?2)",
+            (migration.version, migration.name),
+        )?;
+        applied_versions.push(migration.version);
+    }
+
+    transaction.commit()?;
+    Ok(applied_versions)
+}
+
+/// Ensures that the SQLite database schema is at exactly the version expected by this program
+/// without changing the database.
+pub(crate) fn check_sqlite_schema_version(connection: &Connection) -> Result<(), MigrationError> {
+    let has_migrations_table = connection
+        .query_row("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'", [], |_row| Ok(()))
+        .optional()?
+        .is_some();
+    let database_version: i32 = if has_migrations_table {
+        connection
+            .query_row("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", [], |row| row.get(0))?
+    } else {
+        0
+    };
+
+    check_current(database_version, SQLITE_MIGRATIONS)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_consecutive() {
+        for migrations in &[POSTGRES_MIGRATIONS, SQLITE_MIGRATIONS] {
+            for (i, migration) in migrations.iter().enumerate() {
+                assert_eq!(migration.version, (i as i32) + 1, "migration {:?} out of order", migration.name);
+            }
+        }
+    }
+
+    #[test]
+    fn sqlite_migrations_applied() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        assert!(check_sqlite_schema_version(&connection).is_err());
+        let applied_versions = run_sqlite_migrations(&mut connection).unwrap();
+        assert_eq!(applied_versions, vec![1, 2, 3, 4, 5, 6]);
+        check_sqlite_schema_version(&connection).unwrap();
+        assert_eq!(run_sqlite_migrations(&mut connection).unwrap(), Vec::<i32>::new());
+
+        connection.execute("INSERT INTO schema_migrations (version, name) VALUES (99, 'future')", []).unwrap();
+        match run_sqlite_migrations(&mut connection) {
+            Err(MigrationError::DatabaseNewer(99, 6)) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -1,11 +1,15 @@
+use std::collections::BTreeMap;
 use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Duration, Local, NaiveDate, SecondsFormat};
 use num_rational::Rational32;
-use num_traits::Zero;
 use serde::{Deserialize, Serialize};
 
-use crate::numerism::{optional_max, optional_min, quasi_n_tile_index};
+use crate::config::Scopes;
 
 
 pub(crate) const SUGAR_MG_PER_DL_IN_MMOL_PER_L: i32 = 18;
@@ -15,6 +19,298 @@ pub(crate) const HBA1C_MULTIPLICATIVE_NUMER: i32 = 10_929;
 pub(crate) const HBA1C_MULTIPLICATIVE_DENOM: i32 = 1_000;
 
 
+/// A range of time. The start is inclusive, the end is exclusive; a missing boundary means the
+/// range is unbounded in that direction.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct TimeRange {
+    pub start: Option<DateTime<Local>>,
+    pub end: Option<DateTime<Local>>,
+}
+impl TimeRange {
+    pub fn new(
+        start: Option<DateTime<Local>>,
+        end: Option<DateTime<Local>>,
+    ) -> Self {
+        Self {
+            start,
+            end,
+        }
+    }
+
+    pub fn start_date_string(&self) -> String {
+        self.start
+            .map(|s| s.format("%Y-%m-%d").to_string())
+            .unwrap_or_default()
+    }
+
+    /// The date of the last day touched by the range.
+    pub fn last_date_string(&self) -> String {
+        self.end
+            .map(|e| (e - Duration::nanoseconds(1)).format("%Y-%m-%d").to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.start.is_none() && self.end.is_none()
+    }
+
+    /// The part of this range that is also within the other range.
+    pub fn intersect(&self, other: &TimeRange) -> TimeRange {
+        let start = match (self.start, other.start) {
+            (Some(s), Some(o)) => Some(s.max(o)),
+            (s, o) => s.or(o),
+        };
+        let end = match (self.end, other.end) {
+            (Some(e), Some(o)) => Some(e.min(o)),
+            (e, o) => e.or(o),
+        };
+        TimeRange::new(start, end)
+    }
+}
+
+/// The position of a measurement in a list ordered by timestamp and ID.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct PageCursor {
+    pub timestamp: DateTime<Local>,
+    pub id: i64,
+}
+impl PageCursor {
+    pub fn new(
+        timestamp: DateTime<Local>,
+        id: i64,
+    ) -> Self {
+        Self {
+            timestamp,
+            id,
+        }
+    }
+}
+impl fmt::Display for PageCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true), self.id)
+    }
+}
+impl FromStr for PageCursor {
+    type Err = ParsePageCursorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (timestamp_str, id_str) = s.rsplit_once(',')
+            .ok_or(ParsePageCursorError::MissingSeparator)?;
+        let timestamp = DateTime::parse_from_rfc3339(timestamp_str)
+            .map_err(ParsePageCursorError::InvalidTimestamp)?
+            .with_timezone(&Local);
+        let id = id_str.parse()
+            .map_err(ParsePageCursorError::InvalidId)?;
+        Ok(Self::new(timestamp, id))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum ParsePageCursorError {
+    MissingSeparator,
+    InvalidTimestamp(chrono::ParseError),
+    InvalidId(ParseIntError),
+}
+impl fmt::Display for ParsePageCursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParsePageCursorError::MissingSeparator
+                => write!(f, "missing separator between timestamp and ID"),
+            ParsePageCursorError::InvalidTimestamp(e)
+                => write!(f, "invalid timestamp: {}", e),
+            ParsePageCursorError::InvalidId(e)
+                => write!(f, "invalid ID: {}", e),
+        }
+    }
+}
+impl Error for ParsePageCursorError {
+}
+
+
+/// The portion of a list of measurements to obtain: at most `limit` measurements (if set) following
+/// the measurement at `after` (if set).
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Page {
+    pub after: Option<PageCursor>,
+    pub limit: Option<i64>,
+}
+impl Page {
+    pub fn new(
+        after: Option<PageCursor>,
+        limit: Option<i64>,
+    ) -> Self {
+        Self {
+            after,
+            limit,
+        }
+    }
+
+    /// The whole list.
+    pub fn all() -> Self {
+        Self::new(None, None)
+    }
+
+    pub fn after_timestamp(&self) -> Option<DateTime<Local>> {
+        self.after.map(|a| a.timestamp)
+    }
+
+    pub fn after_id(&self) -> Option<i64> {
+        self.after.map(|a| a.id)
+    }
+}
+
+
+/// A person whose measurements are tracked. Each authentication token belongs to a user.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct User {
+    pub id: i64,
+    pub name: String,
+}
+impl User {
+    pub fn new(
+        id: i64,
+        name: String,
+    ) -> Self {
+        Self {
+            id,
+            name,
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Sex {
+    Female,
+    Male,
+}
+impl Sex {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Sex::Female => "female",
+            Sex::Male => "male",
+        }
+    }
+}
+impl fmt::Display for Sex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+impl FromStr for Sex {
+    type Err = ParseSexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "female" => Ok(Sex::Female),
+            "male" => Ok(Sex::Male),
+            other => Err(ParseSexError(other.to_owned())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct ParseSexError(String);
+impl fmt::Display for ParseSexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown sex {:?}", self.0)
+    }
+}
+impl Error for ParseSexError {
+}
+
+/// A body height that applies from its effective date until the effective date of the next height.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct BodyHeight {
+    pub id: i64,
+    pub effective_date: NaiveDate,
+    pub height_cm: i32,
+}
+impl BodyHeight {
+    pub fn new(
+        id: i64,
+        effective_date: NaiveDate,
+        height_cm: i32,
+    ) -> Self {
+        Self {
+            id,
+            effective_date,
+            height_cm,
+        }
+    }
+}
+
+/// The personal data of a user that measurements are evaluated against.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) struct Profile {
+    pub birth_date: Option<NaiveDate>,
+    pub sex: Option<Sex>,
+    /// Ordered by effective date.
+    pub heights: Vec<BodyHeight>,
+}
+impl Profile {
+    /// The height at the given time. Times before the first recorded height use the first height.
+    pub fn height_cm_at(&self, timestamp: DateTime<Local>) -> Option<i32> {
+        let date = timestamp.date_naive();
+        self.heights.iter()
+            .rev()
+            .find(|h| h.effective_date <= date)
+            .or_else(|| self.heights.first())
+            .map(|h| h.height_cm)
+    }
+
+    pub fn bmi_at(&self, timestamp: DateTime<Local>, mass_kg: Rational32) -> Option<Rational32> {
+        let height_m = self.height_cm_at(timestamp)
+            .map(|h| Rational32::new(h, 100));
+        height_m
+            .map(|h| mass_kg / (h * h))
+    }
+}
+
+
+/// A read-only token, created by a user to share some of their measurements (e.g. with a doctor),
+/// which is valid until it expires or is revoked.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct ShareToken {
+    pub id: i64,
+    /// The SHA-256 hash of the token in hexadecimal; the token itself is only shown when the share
+    /// token is created.
+    pub token_sha256: String,
+    pub description: String,
+    pub scopes: Scopes,
+    pub range: TimeRange,
+    pub created: DateTime<Local>,
+    pub expires: DateTime<Local>,
+    pub revoked: Option<DateTime<Local>>,
+}
+impl ShareToken {
+    pub fn is_active_at(&self, timestamp: DateTime<Local>) -> bool {
+        self.revoked.is_none() && timestamp < self.expires
+    }
+}
+
+
+/// A browser session, started by logging in with a token and ended by logging out or expiring.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct Session {
+    pub id: i64,
+    /// The SHA-256 hash of the session ID in hexadecimal; the ID itself is only stored in the
+    /// session cookie.
+    pub session_sha256: String,
+    /// Identifies the token that has been used to log in without revealing it (see
+    /// `AuthToken::session_key`), so that the session ends when the token is removed or revoked.
+    pub token_key: String,
+    pub created: DateTime<Local>,
+    pub expires: DateTime<Local>,
+}
+impl Session {
+    pub fn is_active_at(&self, timestamp: DateTime<Local>) -> bool {
+        timestamp < self.expires
+    }
+}
+
+
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub(crate) struct BloodPressureMeasurement {
     pub id: i64,
@@ -43,28 +339,6 @@ impl BloodPressureMeasurement {
         }
     }
 
-    pub fn values_max(&self, other: &Self) -> Self {
-        Self::new(
-            -1,
-            self.timestamp.max(other.timestamp),
-            self.systolic_mmhg.max(other.systolic_mmhg),
-            self.diastolic_mmhg.max(other.diastolic_mmhg),
-            self.pulse_bpm.max(other.pulse_bpm),
-            optional_max(self.spo2_percent, other.spo2_percent),
-        )
-    }
-
-    pub fn values_min(&self, other: &Self) -> Self {
-        Self::new(
-            -1,
-            self.timestamp.min(other.timestamp),
-            self.systolic_mmhg.min(other.systolic_mmhg),
-            self.diastolic_mmhg.min(other.diastolic_mmhg),
-            self.pulse_bpm.min(other.pulse_bpm),
-            optional_min(self.spo2_percent, other.spo2_percent),
-        )
-    }
-
     pub fn average(measurements: &[Self]) -> Self {
         assert_ne!(measurements.len(), 0);
         let len_i32: i32 = measurements.len().try_into().unwrap();
@@ -91,34 +365,6 @@ impl BloodPressureMeasurement {
             spo2_percent,
         )
     }
-
-    pub fn quasi_n_tile(measurements: &[Self], n_num: usize, n_den: usize) -> Self {
-        assert_ne!(measurements.len(), 0);
-
-        let index = quasi_n_tile_index(measurements.len(), n_num, n_den);
-
-        let mut systolics: Vec<i32> = measurements.iter().map(|m| m.systolic_mmhg).collect();
-        systolics.sort_unstable();
-
-        let mut diastolics: Vec<i32> = measurements.iter().map(|m| m.diastolic_mmhg).collect();
-        diastolics.sort_unstable();
-
-        let mut pulses: Vec<i32> = measurements.iter().map(|m| m.pulse_bpm).collect();
-        pulses.sort_unstable();
-
-        let mut spo2s: Vec<i32> = measurements.iter().filter_map(|m| m.spo2_percent).collect();
-        let spo2_index = quasi_n_tile_index(spo2s.len(), n_num, n_den);
-        spo2s.sort_unstable();
-
-        Self::new(
-            -1,
-            measurements[0].timestamp,
-            systolics[index],
-            diastolics[index],
-            pulses[index],
-            if spo2s.len() > 0 { Some(spo2s[spo2_index]) } else { None },
-        )
-    }
 }
 
 
@@ -150,6 +396,25 @@ impl DailyBloodPressureMeasurements {
     pub fn new_empty(date_string: String) -> Self {
         Self::new(date_string, None, None, None, Vec::new())
     }
+
+    pub fn all_measurements(&self) -> Vec<BloodPressureMeasurement> {
+        self.morning.iter()
+            .chain(self.midday.iter())
+            .chain(self.evening.iter())
+            .chain(self.other.iter())
+            .copied()
+            .collect()
+    }
+
+    /// The average of all measurements of the day, or `None` if there are none.
+    pub fn average(&self) -> Option<BloodPressureMeasurement> {
+        let measurements = self.all_measurements();
+        if measurements.is_empty() {
+            None
+        } else {
+            Some(BloodPressureMeasurement::average(&measurements))
+        }
+    }
 }
 
 
@@ -158,8 +423,8 @@ pub(crate) struct BodyMassMeasurement {
     pub id: i64,
     #[serde(with = "crate::ser_de::serde_datetime_local")] pub timestamp: DateTime<Local>,
     #[serde(with = "crate::ser_de::serde_rat32")] pub mass_kg: Rational32,
-    #[serde(with = "crate::ser_de::serde_rat32_opt")] pub waist_circum_cm: Option<Rational32>,
-    #[serde(with = "crate::ser_de::serde_rat32_opt")] pub bmi: Option<Rational32>,
+    #[serde(default, with = "crate::ser_de::serde_rat32_opt")] pub waist_circum_cm: Option<Rational32>,
+    #[serde(default, with = "crate::ser_de::serde_rat32_opt")] pub bmi: Option<Rational32>,
 }
 impl BodyMassMeasurement {
     pub fn new(
@@ -177,75 +442,6 @@ impl BodyMassMeasurement {
             bmi,
         }
     }
-
-    pub fn values_max(&self, other: &Self) -> Self {
-        Self::new(
-            -1,
-            self.timestamp.max(other.timestamp),
-            self.mass_kg.max(other.mass_kg),
-            optional_max(self.waist_circum_cm, other.waist_circum_cm),
-            optional_max(self.bmi, other.bmi),
-        )
-    }
-
-    pub fn values_min(&self, other: &Self) -> Self {
-        Self::new(
-            -1,
-            self.timestamp.min(other.timestamp),
-            self.mass_kg.min(other.mass_kg),
-            optional_min(self.waist_circum_cm, other.waist_circum_cm),
-            optional_min(self.bmi, other.bmi),
-        )
-    }
-
-    pub fn average(measurements: &[Self]) -> Self {
-        assert_ne!(measurements.len(), 0);
-        let len_i32: i32 = measurements.len().try_into().unwrap();
-        let len_r32: Rational32 = len_i32.into();
-
-        let circum_len_i32: i32 = measurements.iter().filter(|m| m.waist_circum_cm.is_some()).count().try_into().unwrap();
-        let circum_len_r32: Rational32 = circum_len_i32.into();
-
-        let bmi_len_i32: i32 = measurements.iter().filter(|m| m.bmi.is_some()).count().try_into().unwrap();
-        let bmi_len_r32: Rational32 = bmi_len_i32.into();
-
-        let mass_sum: Rational32 = measurements.iter().map(|m| m.mass_kg).sum();
-        let bmi_sum: Rational32 = measurements.iter().filter_map(|m| m.bmi).sum();
-        let circum_sum: Rational32 = measurements.iter().filter_map(|m| m.waist_circum_cm).sum();
-
-        Self::new(
-            -1,
-            measurements[0].timestamp,
-            mass_sum / len_r32,
-            if circum_len_r32 != Rational32::zero() { Some(circum_sum / circum_len_r32) } else { None },
-            if bmi_len_r32 != Rational32::zero() { Some(bmi_sum / bmi_len_r32) } else { None },
-        )
-    }
-
-    pub fn quasi_n_tile(measurements: &[Self], n_num: usize, n_den: usize) -> Self {
-        assert_ne!(measurements.len(), 0);
-
-        let mut masses: Vec<Rational32> = measurements.iter().map(|m| m.mass_kg).collect();
-        masses.sort_unstable();
-
-        let mut bmis: Vec<Rational32> = measurements.iter().filter_map(|m| m.bmi).collect();
-        bmis.sort_unstable();
-
-        let mut circums: Vec<Rational32> = measurements.iter().filter_map(|m| m.waist_circum_cm).collect();
-        circums.sort_unstable();
-
-        let index = quasi_n_tile_index(measurements.len(), n_num, n_den);
-        let bmi_index = quasi_n_tile_index(bmis.len(), n_num, n_den);
-        let circum_index = quasi_n_tile_index(circums.len(), n_num, n_den);
-
-        Self::new(
-            -1,
-            measurements[0].timestamp,
-            masses[index],
-            circums.get(circum_index).map(|c| c.clone()),
-            bmis.get(bmi_index).map(|b| b.clone()),
-        )
-    }
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
@@ -286,55 +482,6 @@ impl BodyTemperatureMeasurement {
             temperature_celsius,
         }
     }
-
-    pub fn values_max(&self, other: &Self) -> Self {
-        Self::new(
-            -1,
-            self.timestamp.max(other.timestamp),
-            self.location_id.max(other.location_id),
-            self.temperature_celsius.max(other.temperature_celsius),
-        )
-    }
-
-    pub fn values_min(&self, other: &Self) -> Self {
-        Self::new(
-            -1,
-            self.timestamp.min(other.timestamp),
-            self.location_id.min(other.location_id),
-            self.temperature_celsius.max(other.temperature_celsius),
-        )
-    }
-
-    pub fn average(measurements: &[Self]) -> Self {
-        assert_ne!(measurements.len(), 0);
-        let len_i32: i32 = measurements.len().try_into().unwrap();
-        let len_r32: Rational32 = len_i32.into();
-
-        let temperature_celsius_sum: Rational32 = measurements.iter().map(|m| m.temperature_celsius).sum();
-
-        Self::new(
-            -1,
-            measurements[0].timestamp,
-            measurements[0].location_id,
-            temperature_celsius_sum / len_r32,
-        )
-    }
-
-    pub fn quasi_n_tile(measurements: &[Self], n_num: usize, n_den: usize) -> Self {
-        assert_ne!(measurements.len(), 0);
-
-        let mut temperatures: Vec<Rational32> = measurements.iter().map(|m| m.temperature_celsius).collect();
-        temperatures.sort_unstable();
-
-        let index = quasi_n_tile_index(measurements.len(), n_num, n_den);
-
-        Self::new(
-            -1,
-            measurements[0].timestamp,
-            measurements[0].location_id,
-            temperatures[index],
-        )
-    }
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
@@ -372,51 +519,6 @@ impl BloodSugarMeasurement {
     pub fn sugar_mg_per_dl(&self) -> Rational32 {
         self.sugar_mmol_per_l * SUGAR_MG_PER_DL_IN_MMOL_PER_L
     }
-
-    pub fn values_max(&self, other: &Self) -> Self {
-        Self::new(
-            -1,
-            self.timestamp.max(other.timestamp),
-            self.sugar_mmol_per_l.max(other.sugar_mmol_per_l),
-        )
-    }
-
-    pub fn values_min(&self, other: &Self) -> Self {
-        Self::new(
-            -1,
-            self.timestamp.min(other.timestamp),
-            self.sugar_mmol_per_l.min(other.sugar_mmol_per_l),
-        )
-    }
-
-    pub fn average(measurements: &[Self]) -> Self {
-        assert_ne!(measurements.len(), 0);
-        let len_i32: i32 = measurements.len().try_into().unwrap();
-        let len_r32: Rational32 = len_i32.into();
-
-        let sugar_mmol_per_l_sum: Rational32 = measurements.iter().map(|m| m.sugar_mmol_per_l).sum();
-
-        Self::new(
-            -1,
-            measurements[0].timestamp,
-            sugar_mmol_per_l_sum / len_r32,
-        )
-    }
-
-    pub fn quasi_n_tile(measurements: &[Self], n_num: usize, n_den: usize) -> Self {
-        assert_ne!(measurements.len(), 0);
-
-        let mut sugars_mmol_per_l: Vec<Rational32> = measurements.iter().map(|m| m.sugar_mmol_per_l).collect();
-        sugars_mmol_per_l.sort_unstable();
-
-        let index = quasi_n_tile_index(measurements.len(), n_num, n_den);
-
-        Self::new(
-            -1,
-            measurements[0].timestamp,
-            sugars_mmol_per_l[index],
-        )
-    }
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
@@ -460,59 +562,47 @@ impl LongTermBloodSugarMeasurement {
 
         (self.hba1c_mmol_per_mol / multiplicative_factor) + additive_factor
     }
+}
 
-    pub fn values_max(&self, other: &Self) -> Self {
-        Self::new(
-            -1,
-            self.timestamp.max(other.timestamp),
-            self.hba1c_mmol_per_mol.max(other.hba1c_mmol_per_mol),
-        )
-    }
-
-    pub fn values_min(&self, other: &Self) -> Self {
-        Self::new(
-            -1,
-            self.timestamp.min(other.timestamp),
-            self.hba1c_mmol_per_mol.min(other.hba1c_mmol_per_mol),
-        )
-    }
-
-    pub fn average(measurements: &[Self]) -> Self {
-        assert_ne!(measurements.len(), 0);
-        let len_i32: i32 = measurements.len().try_into().unwrap();
-        let len_r32: Rational32 = len_i32.into();
-
-        let sugar_mmol_per_l_sum: Rational32 = measurements.iter().map(|m| m.hba1c_mmol_per_mol).sum();
+/// A custom measurement's ID and timestamp joined with the key and value of one of its values.
+pub(crate) type CustomValueRow = (i64, DateTime<Local>, Option<(String, Rational32)>);
 
-        Self::new(
-            -1,
-            measurements[0].timestamp,
-            sugar_mmol_per_l_sum / len_r32,
-        )
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct CustomMeasurement {
+    pub id: i64,
+    #[serde(with = "crate::ser_de::serde_datetime_local")] pub timestamp: DateTime<Local>,
+    /// The values by the keys of the fields of the measurement type.
+    #[serde(with = "crate::ser_de::serde_rat32_map")] pub values: BTreeMap<String, Rational32>,
+}
+impl CustomMeasurement {
+    pub fn new(
+        id: i64,
+        timestamp: DateTime<Local>,
+        values: BTreeMap<String, Rational32>,
+    ) -> Self {
+        Self {
+            id,
+            timestamp,
+            values,
+        }
     }
 
-    pub fn quasi_n_tile(measurements: &[Self], n_num: usize, n_den: usize) -> Self {
-        assert_ne!(measurements.len(), 0);
-
-        let mut sugars_mmol_per_l: Vec<Rational32> = measurements.iter().map(|m| m.hba1c_mmol_per_mol).collect();
-        sugars_mmol_per_l.sort_unstable();
-
-        let index = quasi_n_tile_index(measurements.len(), n_num, n_den);
-
-        Self::new(
-            -1,
-            measurements[0].timestamp,
-            sugars_mmol_per_l[index],
-        )
+    /// Assembles measurements from rows joining each measurement with its values, which must be
+    /// ordered by measurement. Measurements without values have a single row without a value.
+    pub fn from_value_rows<I: IntoIterator<Item = CustomValueRow>>(rows: I) -> Vec<Self> {
+        let mut measurements: Vec<Self> = Vec::new();
+        for (id, timestamp, value) in rows {
+            let is_next = measurements.last()
+                .map(|m| m.id != id)
+                .unwrap_or(true);
+            if is_next {
+                measurements.push(Self::new(id, timestamp, BTreeMap::new()));
+            }
+            if let Some((key, value)) = value {
+                measurements.last_mut().expect("measurement added")
+                    .values.insert(key, value);
+            }
+        }
+        measurements
     }
 }
-
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
-pub(crate) struct MeasurementStatistics<T> {
-    pub maximum: T,
-    pub quasi_q3: T,
-    pub average: T,
-    pub quasi_q2: T,
-    pub quasi_q1: T,
-    pub minimum: T,
-}
@@ -73,33 +73,27 @@ pub(crate) fn r32_from_decimal(decimal_str: &str) -> Result<Rational32, ParseRat
     }
 }
 
-#[inline]
-pub(crate) fn quasi_n_tile_index(element_count: usize, n_num: usize, n_den: usize) -> usize {
-    if element_count == 0 {
-        0
+/// Formats a rational number as a decimal number with the given number of decimal places, rounding
+/// halves away from zero.
+pub(crate) fn r32_to_decimal(value: Rational32, decimal_places: usize) -> String {
+    // i128 can hold 10**38; the numerator needs another 10**10
+    let decimal_places = decimal_places.min(28);
+    let scale = 10i128.pow(decimal_places as u32);
+
+    let numer = i128::from(*value.numer());
+    let denom = i128::from(*value.denom());
+    let negative = (numer < 0) != (denom < 0);
+    let (numer, denom) = (numer.abs(), denom.abs());
+
+    let scaled = (2 * numer * scale + denom) / (2 * denom);
+    let sign = if negative && scaled != 0 { "-" } else { "" };
+    if decimal_places == 0 {
+        format!("{}{}", sign, scaled)
     } else {
-        (element_count - 1) * n_num / n_den
+        format!("{}{}.{:0width$}", sign, scaled / scale, scaled % scale, width = decimal_places)
     }
 }
 
-#[inline]
-fn optional_pick<T, A: FnMut(T, T) -> Option<T>>(left: Option<T>, right: Option<T>, mut arbitration: A) -> Option<T> {
-    match (left, right) {
-        (None, None) => None,
-        (Some(l), None) => Some(l),
-        (None, Some(r)) => Some(r),
-        (Some(l), Some(r)) => arbitration(l, r),
-    }
-}
-#[inline]
-pub(crate) fn optional_max<T: Ord>(left: Option<T>, right: Option<T>) -> Option<T> {
-    optional_pick(left, right, |l, r| Some(l.max(r)))
-}
-#[inline]
-pub(crate) fn optional_min<T: Ord>(left: Option<T>, right: Option<T>) -> Option<T> {
-    optional_pick(left, right, |l, r| Some(l.min(r)))
-}
-
 
 #[cfg(test)]
 mod tests {
@@ -136,4 +130,20 @@ mod tests {
         test(0, 1, "0.0");
         test(-21, 5, "-4.2");
     }
+
+    #[test]
+    fn r32_to_decimal_places() {
+        assert_eq!(r32_to_decimal(Rational32::new(6, 5), 2), "1.20");
+        assert_eq!(r32_to_decimal(Rational32::new(1, 3), 3), "0.333");
+        assert_eq!(r32_to_decimal(Rational32::new(2, 3), 0), "1");
+        assert_eq!(r32_to_decimal(Rational32::new(-42, 1), 1), "-42.0");
+    }
+
+    #[test]
+    fn r32_to_decimal_rounding() {
+        assert_eq!(r32_to_decimal(Rational32::new(1, 8), 2), "0.13");
+        assert_eq!(r32_to_decimal(Rational32::new(-1, 8), 2), "-0.13");
+        assert_eq!(r32_to_decimal(Rational32::new(-1, 1000), 2), "0.00");
+        assert_eq!(r32_to_decimal(Rational32::new(i32::MAX, 7), 1), "306783378.1");
+    }
 }
@@ -1,5 +1,72 @@
+use std::error::Error;
+use std::fmt;
+
+use chrono::{DateTime, Local, LocalResult, NaiveDateTime, TimeZone};
+
+
+const NAIVE_TIMESTAMP_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+];
+
+
+#[derive(Debug)]
+pub(crate) enum ParseTimestampError {
+    InvalidFormat(chrono::ParseError),
+    AmbiguousLocalTime(NaiveDateTime),
+    NonexistentLocalTime(NaiveDateTime),
+}
+impl fmt::Display for ParseTimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidFormat(e)
+                => write!(f, "invalid format: {}", e),
+            Self::AmbiguousLocalTime(naive)
+                => write!(f, "local time {} is ambiguous; please specify a UTC offset", naive),
+            Self::NonexistentLocalTime(naive)
+                => write!(f, "local time {} does not exist", naive),
+        }
+    }
+}
+impl Error for ParseTimestampError {
+}
+
+
+/// Parses a timestamp from a string.
+///
+/// Timestamps with time zone information (RFC 3339, including the UTC format emitted by
+/// `serde_datetime_local`) are converted to local time. Timestamps without time zone information
+/// (such as the values of HTML `datetime-local` inputs) are interpreted as local time.
+pub(crate) fn parse_timestamp(string: &str) -> Result<DateTime<Local>, ParseTimestampError> {
+    if let Ok(with_offset) = DateTime::parse_from_rfc3339(string) {
+        return Ok(with_offset.with_timezone(&Local));
+    }
+
+    let mut first_error = None;
+    for format in &NAIVE_TIMESTAMP_FORMATS {
+        match NaiveDateTime::parse_from_str(string, format) {
+            Ok(naive) => return local_from_naive(naive),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            },
+        }
+    }
+    Err(ParseTimestampError::InvalidFormat(first_error.expect("at least one format attempted")))
+}
+
+pub(crate) fn local_from_naive(naive: NaiveDateTime) -> Result<DateTime<Local>, ParseTimestampError> {
+    match Local.from_local_datetime(&naive) {
+        LocalResult::Single(local) => Ok(local),
+        LocalResult::Ambiguous(_, _) => Err(ParseTimestampError::AmbiguousLocalTime(naive)),
+        LocalResult::None => Err(ParseTimestampError::NonexistentLocalTime(naive)),
+    }
+}
+
+
 pub(crate) mod serde_datetime_local {
-    use chrono::{DateTime, Local, NaiveDateTime, Utc};
+    use chrono::{DateTime, Local, Utc};
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
     use serde::de::Error as _;
 
@@ -13,10 +80,8 @@ pub(crate) mod serde_datetime_local {
 
     pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Local>, D::Error> {
         let string = String::deserialize(deserializer)?;
-        let naive_utc = NaiveDateTime::parse_from_str(&string, TIME_FORMAT)
-            .map_err(|e| D::Error::custom(e))?;
-        let utc = naive_utc.and_utc();
-        Ok(utc.with_timezone(&Local))
+        super::parse_timestamp(&string)
+            .map_err(D::Error::custom)
     }
 }
 
@@ -32,10 +97,9 @@ fn string_to_rat32(s: &str) -> Result<num_rational::Rational32, String> {
             .map_err(|e| format!("failed to parse denominator {:?}: {}", denom_str, e))?;
         (num, denom)
     } else {
-        // assume numerator only
-        let num = s.parse()
-            .map_err(|e| format!("failed to parse lone numerator {:?}: {}", s, e))?;
-        (num, 1)
+        // assume numerator only, possibly in decimal notation
+        return crate::numerism::r32_from_decimal(s)
+            .map_err(|e| format!("failed to parse lone numerator {:?}: {}", s, e));
     };
     if denom == 0 {
         return Err("denominator must not be zero".to_owned());
@@ -84,3 +148,100 @@ pub(crate) mod serde_rat32_opt {
     }
 }
 
+/// Like `serde_rat32_opt`, but also accepts integers and floating-point numbers, which are
+/// converted using their shortest decimal representation.
+pub(crate) mod serde_rat32_number_opt {
+    use num_rational::Rational32;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error as _;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberValue {
+        Integer(i64),
+        Float(f64),
+        String(String),
+    }
+
+    pub fn serialize<S: Serializer>(value: &Option<Rational32>, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serde_rat32_opt::serialize(value, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Rational32>, D::Error> {
+        let value: Option<NumberValue> = Option::deserialize(deserializer)?;
+        let string = match value {
+            Some(NumberValue::Integer(i)) => i.to_string(),
+            Some(NumberValue::Float(f)) => f.to_string(),
+            Some(NumberValue::String(s)) => s,
+            None => return Ok(None),
+        };
+        let rat = super::string_to_rat32(&string)
+            .map_err(D::Error::custom)?;
+        Ok(Some(rat))
+    }
+}
+
+
+pub(crate) mod serde_rat32_map {
+    use std::collections::BTreeMap;
+
+    use num_rational::Rational32;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde::de::Error as _;
+
+    pub fn serialize<S: Serializer>(value: &BTreeMap<String, Rational32>, serializer: S) -> Result<S::Ok, S::Error> {
+        let strings: BTreeMap<&String, String> = value.iter()
+            .map(|(k, v)| (k, super::rat32_to_string(v)))
+            .collect();
+        strings.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, Rational32>, D::Error> {
+        let strings: BTreeMap<String, String> = BTreeMap::deserialize(deserializer)?;
+        strings.into_iter()
+            .map(|(k, s)| {
+                let rat = super::string_to_rat32(&s)
+                    .map_err(|e| D::Error::custom(format!("value for key {:?}: {}", k, e)))?;
+                Ok((k, rat))
+            })
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> DateTime<Local> {
+        let naive = NaiveDate::from_ymd_opt(year, month, day).unwrap()
+            .and_hms_opt(hour, minute, second).unwrap();
+        Local.from_local_datetime(&naive).unwrap()
+    }
+
+    #[test]
+    fn timestamp_with_offset() {
+        let utc = parse_timestamp("2021-10-18T09:30:00Z").unwrap();
+        let offset = parse_timestamp("2021-10-18T11:30:00+02:00").unwrap();
+        let fractional = parse_timestamp("2021-10-18T09:30:00.000Z").unwrap();
+        assert_eq!(utc, offset);
+        assert_eq!(utc, fractional);
+        assert_eq!(utc.timestamp(), 1634549400);
+    }
+
+    #[test]
+    fn timestamp_local() {
+        assert_eq!(local(2021, 10, 18, 9, 30, 0), parse_timestamp("2021-10-18T09:30").unwrap());
+        assert_eq!(local(2021, 10, 18, 9, 30, 15), parse_timestamp("2021-10-18T09:30:15").unwrap());
+        assert_eq!(local(2021, 10, 18, 9, 30, 15), parse_timestamp("2021-10-18 09:30:15").unwrap());
+    }
+
+    #[test]
+    fn timestamp_invalid() {
+        assert!(parse_timestamp("").is_err());
+        assert!(parse_timestamp("2021-10-18").is_err());
+        assert!(parse_timestamp("2021-13-18T09:30").is_err());
+        assert!(parse_timestamp("yesterday").is_err());
+    }
+}
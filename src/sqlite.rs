@@ -0,0 +1,811 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use num_rational::Rational32;
+use rusqlite::{Connection, OptionalExtension, Row, params_from_iter};
+use rusqlite::types::{Type, Value};
+
+use crate::config::{MAX_CUSTOM_DECIMAL_PLACES, Scopes};
+use crate::database::DatabaseError;
+use crate::migrations::{MigrationError, check_sqlite_schema_version, run_sqlite_migrations};
+use crate::model::{
+    BodyHeight, BodyTemperatureLocation, CustomMeasurement, CustomValueRow, Page, Profile, Session,
+    Sex, ShareToken, TimeRange, User,
+};
+use crate::numerism::{r32_from_decimal, r32_to_decimal};
+use crate::storage::{ColumnType, ColumnValue, MeasurementRow, MeasurementTable, Storage};
+
+
+/// The number of decimal places stored for decimal values, as with the PostgreSQL schema.
+const DECIMAL_PLACES: usize = 2;
+
+
+fn timestamp_to_sql(timestamp: &DateTime<Local>) -> String {
+    // fixed-width UTC timestamps sort in chronological order
+    timestamp.with_timezone(&Utc)
+        .format("%Y-%m-%dT%H:%M:%S%.6fZ")
+        .to_string()
+}
+
+fn timestamp_from_sql(row: &Row, index: usize) -> rusqlite::Result<DateTime<Local>> {
+    let timestamp_string: String = row.get(index)?;
+    DateTime::parse_from_rfc3339(&timestamp_string)
+        .map(|t| t.with_timezone(&Local))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))
+}
+
+fn opt_timestamp_from_sql(row: &Row, index: usize) -> rusqlite::Result<Option<DateTime<Local>>> {
+    let timestamp_string: Option<String> = row.get(index)?;
+    match timestamp_string {
+        Some(_) => timestamp_from_sql(row, index)
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+fn date_to_sql(date: &NaiveDate) -> String {
+    date.format("%Y-%m-%d")
+        .to_string()
+}
+
+fn date_from_sql(row: &Row, index: usize) -> rusqlite::Result<NaiveDate> {
+    let date_string: String = row.get(index)?;
+    NaiveDate::parse_from_str(&date_string, "%Y-%m-%d")
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))
+}
+
+fn opt_date_from_sql(row: &Row, index: usize) -> rusqlite::Result<Option<NaiveDate>> {
+    let date_string: Option<String> = row.get(index)?;
+    match date_string {
+        Some(ds) => NaiveDate::parse_from_str(&ds, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e))),
+        None => Ok(None),
+    }
+}
+
+fn opt_sex_from_sql(row: &Row, index: usize) -> rusqlite::Result<Option<Sex>> {
+    let sex_string: Option<String> = row.get(index)?;
+    match sex_string {
+        Some(ss) => ss.parse()
+            .map(Some)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e))),
+        None => Ok(None),
+    }
+}
+
+fn decimal_to_sql(value: Rational32) -> String {
+    r32_to_decimal(value, DECIMAL_PLACES)
+}
+
+fn opt_decimal_from_sql(row: &Row, index: usize) -> rusqlite::Result<Option<Rational32>> {
+    let decimal_string: Option<String> = row.get(index)?;
+    match decimal_string {
+        Some(ds) => r32_from_decimal(&ds)
+            .map(Some)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e))),
+        None => Ok(None),
+    }
+}
+
+/// The parameters `?1` to `?6` of the queries listing a user's measurements in a range.
+type RangePageParams = (Option<String>, Option<String>, Option<String>, Option<i64>, Option<i64>, i64);
+
+fn range_page_params(user_id: i64, range: &TimeRange, page: &Page) -> RangePageParams {
+    (
+        range.start.as_ref().map(timestamp_to_sql),
+        range.end.as_ref().map(timestamp_to_sql),
+        page.after_timestamp().as_ref().map(timestamp_to_sql),
+        page.after_id(),
+        page.limit,
+        user_id,
+    )
+}
+
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    Ok(User::new(
+        row.get(0)?,
+        row.get(1)?,
+    ))
+}
+
+fn height_from_row(row: &Row) -> rusqlite::Result<BodyHeight> {
+    Ok(BodyHeight::new(
+        row.get(0)?,
+        date_from_sql(row, 1)?,
+        row.get(2)?,
+    ))
+}
+
+/// Reads a share token from the columns starting at the given index.
+fn share_token_from_row(row: &Row, first_index: usize) -> rusqlite::Result<ShareToken> {
+    let scopes_string: String = row.get(first_index + 3)?;
+    let scopes = Scopes::from_names_string(&scopes_string)
+        .ok_or_else(|| rusqlite::Error::FromSqlConversionFailure(
+            first_index + 3, Type::Text, format!("unknown scope in {:?}", scopes_string).into(),
+        ))?;
+    Ok(ShareToken {
+        id: row.get(first_index)?,
+        token_sha256: row.get(first_index + 1)?,
+        description: row.get(first_index + 2)?,
+        scopes,
+        range: TimeRange::new(opt_timestamp_from_sql(row, first_index + 4)?, opt_timestamp_from_sql(row, first_index + 5)?),
+        created: timestamp_from_sql(row, first_index + 6)?,
+        expires: timestamp_from_sql(row, first_index + 7)?,
+        revoked: opt_timestamp_from_sql(row, first_index + 8)?,
+    })
+}
+
+/// Reads a session from the columns starting at the given index.
+fn session_from_row(row: &Row, first_index: usize) -> rusqlite::Result<Session> {
+    Ok(Session {
+        id: row.get(first_index)?,
+        session_sha256: row.get(first_index + 1)?,
+        token_key: row.get(first_index + 2)?,
+        created: timestamp_from_sql(row, first_index + 3)?,
+        expires: timestamp_from_sql(row, first_index + 4)?,
+    })
+}
+
+fn temperature_location_from_row(row: &Row) -> rusqlite::Result<BodyTemperatureLocation> {
+    Ok(BodyTemperatureLocation::new(
+        row.get(0)?,
+        row.get(1)?,
+    ))
+}
+
+/// Reads a measurement joined with one of its values, which is missing if the measurement has none.
+fn custom_value_from_row(row: &Row) -> rusqlite::Result<CustomValueRow> {
+    let field_key: Option<String> = row.get(2)?;
+    let value = opt_decimal_from_sql(row, 3)?;
+    Ok((
+        row.get(0)?,
+        timestamp_from_sql(row, 1)?,
+        field_key.zip(value),
+    ))
+}
+
+
+/// The parameters for the values of a measurement row.
+fn measurement_value_params(row: &MeasurementRow) -> Vec<Value> {
+    row.values.iter()
+        .map(|value| match value {
+            Some(ColumnValue::Integer(i)) => Value::Integer(*i),
+            Some(ColumnValue::Decimal(d)) => Value::Text(decimal_to_sql(*d)),
+            None => Value::Null,
+        })
+        .collect()
+}
+
+/// Reads a measurement row selected with the ID, the timestamp and the value columns in this order.
+fn measurement_row_from_row(table: &MeasurementTable, row: &Row) -> rusqlite::Result<MeasurementRow> {
+    let mut values = Vec::with_capacity(table.columns.len());
+    for (index, column) in table.columns.iter().enumerate() {
+        let value = match column.column_type {
+            ColumnType::Integer => {
+                let integer: Option<i64> = row.get(index + 2)?;
+                integer.map(ColumnValue::Integer)
+            },
+            ColumnType::Decimal => opt_decimal_from_sql(row, index + 2)?
+                .map(ColumnValue::Decimal),
+        };
+        values.push(value);
+    }
+    Ok(MeasurementRow {
+        id: row.get(0)?,
+        timestamp: timestamp_from_sql(row, 1)?,
+        values,
+    })
+}
+
+/// The ID, the timestamp and the value columns of the measurement table, separated by commas.
+fn measurement_select_columns(table: &MeasurementTable) -> String {
+    let mut columns = vec!["id", "\"timestamp\""];
+    columns.extend(table.columns.iter().map(|c| c.name));
+    columns.join(", ")
+}
+
+fn insert_measurement(connection: &Connection, user_id: i64, table: &MeasurementTable, measurement: &MeasurementRow) -> Result<i64, DatabaseError> {
+    let column_names: Vec<&str> = table.columns.iter()
+        .map(|c| c.name)
+        .collect();
+    let value_placeholders: Vec<String> = (0..table.columns.len())
+        .map(|i| format!("?{}", i + 2))
+        .collect();
+    let query = format!(
+        "INSERT INTO {} (\"timestamp\", {}, user_id) VALUES (?1, {}, ?{})",
+        table.name, column_names.join(", "), value_placeholders.join(", "), table.columns.len() + 2,
+    );
+    let mut params = vec![Value::Text(timestamp_to_sql(&measurement.timestamp))];
+    params.extend(measurement_value_params(measurement));
+    params.push(Value::Integer(user_id));
+
+    connection
+        .prepare_cached(&query)?
+        .execute(params_from_iter(params))?;
+    Ok(connection.last_insert_rowid())
+}
+
+fn insert_custom_values(connection: &Connection, measurement_id: i64, values: &BTreeMap<String, Rational32>) -> Result<(), DatabaseError> {
+    let mut statement = connection
+        .prepare_cached("INSERT INTO custom_measurement_values (measurement_id, field_key, \"value\") VALUES (?1, ?2, ?3)")?;
+    for (field_key, value) in values {
+        statement.execute((measurement_id, field_key, r32_to_decimal(*value, MAX_CUSTOM_DECIMAL_PLACES)))?;
+    }
+    Ok(())
+}
+
+
+/// Storage in an SQLite database. SQLite does not support concurrent writers, so all accesses share
+/// a single connection; they are performed on the blocking thread pool.
+pub(crate) struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+}
+impl SqliteStorage {
+    /// Opens the SQLite database at the given path, creating it if it does not exist yet. The path
+    /// `:memory:` opens a new in-memory database.
+    pub fn open(path: &Path) -> Result<Self, DatabaseError> {
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        connection.busy_timeout(Duration::from_secs(5))?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    async fn with_connection<T, E, F>(&self, f: F) -> Result<T, E>
+        where
+            T: Send + 'static,
+            E: From<DatabaseError> + Send + 'static,
+            F: FnOnce(&mut Connection) -> Result<T, E> + Send + 'static {
+        let connection = Arc::clone(&self.connection);
+        tokio::task::spawn_blocking(move || {
+            let mut connection_guard = connection
+                .lock().expect("SQLite connection mutex poisoned");
+            f(&mut connection_guard)
+        })
+            .await
+            .map_err(|e| E::from(DatabaseError::from(e)))?
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn run_migrations(&self) -> Result<Vec<i32>, MigrationError> {
+        self.with_connection(run_sqlite_migrations)
+            .await
+    }
+
+    async fn check_schema_version(&self) -> Result<(), MigrationError> {
+        self.with_connection(|connection| check_sqlite_schema_version(connection))
+            .await
+    }
+
+    async fn get_or_add_user(&self, name: &str) -> Result<User, DatabaseError> {
+        let name = name.to_owned();
+        self.with_connection(move |connection| {
+            let existing_user = connection
+                .prepare_cached("SELECT id, \"name\" FROM users WHERE \"name\" = ?1")?
+                .query_row((&name,), user_from_row)
+                .optional()?;
+            if let Some(user) = existing_user {
+                return Ok(user);
+            }
+
+            // another instance might be adding the same user concurrently
+            connection
+                .prepare_cached("INSERT OR IGNORE INTO users (\"name\") VALUES (?1)")?
+                .execute((&name,))?;
+            let user = connection
+                .prepare_cached("SELECT id, \"name\" FROM users WHERE \"name\" = ?1")?
+                .query_row((&name,), user_from_row)?;
+            Ok(user)
+        })
+            .await
+    }
+
+    async fn get_profile(&self, user_id: i64) -> Result<Profile, DatabaseError> {
+        self.with_connection(move |connection| {
+            let (birth_date, sex) = connection
+                .prepare_cached("SELECT birth_date, sex FROM users WHERE id = ?1")?
+                .query_row((user_id,), |row| Ok((opt_date_from_sql(row, 0)?, opt_sex_from_sql(row, 1)?)))?;
+            let heights = connection
+                .prepare_cached("SELECT id, effective_date, height_cm FROM body_heights WHERE user_id = ?1 ORDER BY effective_date")?
+                .query_map((user_id,), height_from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Profile {
+                birth_date,
+                sex,
+                heights,
+            })
+        })
+            .await
+    }
+
+    async fn update_profile(&self, user_id: i64, profile: &Profile) -> Result<(), DatabaseError> {
+        let birth_date = profile.birth_date.as_ref().map(date_to_sql);
+        let sex = profile.sex.map(|s| s.as_str());
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached("UPDATE users SET birth_date=?1, sex=?2 WHERE id=?3")?
+                .execute((birth_date, sex, user_id))?;
+            Ok(())
+        })
+            .await
+    }
+
+    async fn set_height(&self, user_id: i64, height: &BodyHeight) -> Result<i64, DatabaseError> {
+        let height = *height;
+        self.with_connection(move |connection| {
+            let height_id = connection
+                .prepare_cached("INSERT INTO body_heights (user_id, effective_date, height_cm) VALUES (?1, ?2, ?3) ON CONFLICT (user_id, effective_date) DO UPDATE SET height_cm = excluded.height_cm RETURNING id")?
+                .query_row((user_id, date_to_sql(&height.effective_date), height.height_cm), |row| row.get(0))?;
+            Ok(height_id)
+        })
+            .await
+    }
+
+    async fn remove_height(&self, user_id: i64, height_id: i64) -> Result<(), DatabaseError> {
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached("DELETE FROM body_heights WHERE id = ?1 AND user_id = ?2")?
+                .execute((height_id, user_id))?;
+            Ok(())
+        })
+            .await
+    }
+
+    async fn add_share_token(&self, user_id: i64, share_token: &ShareToken) -> Result<i64, DatabaseError> {
+        let params = (
+            share_token.token_sha256.clone(),
+            share_token.description.clone(),
+            share_token.scopes.to_names_string(),
+            share_token.range.start.as_ref().map(timestamp_to_sql),
+            share_token.range.end.as_ref().map(timestamp_to_sql),
+            timestamp_to_sql(&share_token.created),
+            timestamp_to_sql(&share_token.expires),
+            share_token.revoked.as_ref().map(timestamp_to_sql),
+            user_id,
+        );
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached("INSERT INTO share_tokens (token_sha256, description, scopes, range_start, range_end, created_at, expires_at, revoked_at, user_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)")?
+                .execute(params)?;
+            Ok(connection.last_insert_rowid())
+        })
+            .await
+    }
+
+    async fn get_share_tokens(&self, user_id: i64) -> Result<Vec<ShareToken>, DatabaseError> {
+        self.with_connection(move |connection| {
+            let share_tokens = connection
+                .prepare_cached("SELECT id, token_sha256, description, scopes, range_start, range_end, created_at, expires_at, revoked_at FROM share_tokens WHERE user_id = ?1 ORDER BY created_at DESC, id DESC")?
+                .query_map((user_id,), |row| share_token_from_row(row, 0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(share_tokens)
+        })
+            .await
+    }
+
+    async fn get_share_token_by_hash(&self, token_sha256: &str) -> Result<Option<(User, ShareToken)>, DatabaseError> {
+        let token_sha256 = token_sha256.to_owned();
+        self.with_connection(move |connection| {
+            let user_and_share_token = connection
+                .prepare_cached("SELECT u.id, u.\"name\", st.id, st.token_sha256, st.description, st.scopes, st.range_start, st.range_end, st.created_at, st.expires_at, st.revoked_at FROM share_tokens st INNER JOIN users u ON u.id = st.user_id WHERE st.token_sha256 = ?1")?
+                .query_row((&token_sha256,), |row| Ok((user_from_row(row)?, share_token_from_row(row, 2)?)))
+                .optional()?;
+            Ok(user_and_share_token)
+        })
+            .await
+    }
+
+    async fn revoke_share_token(&self, user_id: i64, share_token_id: i64, revoked: DateTime<Local>) -> Result<(), DatabaseError> {
+        let revoked = timestamp_to_sql(&revoked);
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached("UPDATE share_tokens SET revoked_at=?1 WHERE id=?2 AND user_id=?3 AND revoked_at IS NULL")?
+                .execute((revoked, share_token_id, user_id))?;
+            Ok(())
+        })
+            .await
+    }
+
+    async fn add_session(&self, user_id: i64, session: &Session) -> Result<i64, DatabaseError> {
+        let params = (
+            session.session_sha256.clone(),
+            session.token_key.clone(),
+            timestamp_to_sql(&session.created),
+            timestamp_to_sql(&session.expires),
+            user_id,
+        );
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached("INSERT INTO sessions (session_sha256, token_key, created_at, expires_at, user_id) VALUES (?1, ?2, ?3, ?4, ?5)")?
+                .execute(params)?;
+            Ok(connection.last_insert_rowid())
+        })
+            .await
+    }
+
+    async fn get_session_by_hash(&self, session_sha256: &str) -> Result<Option<(User, Session)>, DatabaseError> {
+        let session_sha256 = session_sha256.to_owned();
+        self.with_connection(move |connection| {
+            let user_and_session = connection
+                .prepare_cached("SELECT u.id, u.\"name\", s.id, s.session_sha256, s.token_key, s.created_at, s.expires_at FROM sessions s INNER JOIN users u ON u.id = s.user_id WHERE s.session_sha256 = ?1")?
+                .query_row((&session_sha256,), |row| Ok((user_from_row(row)?, session_from_row(row, 2)?)))
+                .optional()?;
+            Ok(user_and_session)
+        })
+            .await
+    }
+
+    async fn remove_session(&self, session_sha256: &str) -> Result<(), DatabaseError> {
+        let session_sha256 = session_sha256.to_owned();
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached("DELETE FROM sessions WHERE session_sha256 = ?1")?
+                .execute((&session_sha256,))?;
+            Ok(())
+        })
+            .await
+    }
+
+    async fn remove_expired_sessions(&self, now: DateTime<Local>) -> Result<(), DatabaseError> {
+        let now = timestamp_to_sql(&now);
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached("DELETE FROM sessions WHERE expires_at <= ?1")?
+                .execute((&now,))?;
+            Ok(())
+        })
+            .await
+    }
+
+    async fn add_measurements(&self, user_id: i64, table: &MeasurementTable, rows: &[MeasurementRow]) -> Result<Vec<i64>, DatabaseError> {
+        let table = *table;
+        let rows = rows.to_vec();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let mut measurement_ids = Vec::with_capacity(rows.len());
+            for row in &rows {
+                measurement_ids.push(insert_measurement(&transaction, user_id, &table, row)?);
+            }
+            transaction.commit()?;
+            Ok(measurement_ids)
+        })
+            .await
+    }
+
+    async fn remove_measurement(&self, user_id: i64, table: &MeasurementTable, measurement_id: i64) -> Result<(), DatabaseError> {
+        let query = format!("DELETE FROM {} WHERE id = ?1 AND user_id = ?2", table.name);
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached(&query)?
+                .execute((measurement_id, user_id))?;
+            Ok(())
+        })
+            .await
+    }
+
+    async fn update_measurement(&self, user_id: i64, table: &MeasurementTable, row: &MeasurementRow) -> Result<(), DatabaseError> {
+        let assignments: Vec<String> = table.columns.iter()
+            .enumerate()
+            .map(|(i, c)| format!("{}=?{}", c.name, i + 2))
+            .collect();
+        let query = format!(
+            "UPDATE {} SET \"timestamp\"=?1, {} WHERE id=?{} AND user_id=?{}",
+            table.name, assignments.join(", "), table.columns.len() + 2, table.columns.len() + 3,
+        );
+        let mut params = vec![Value::Text(timestamp_to_sql(&row.timestamp))];
+        params.extend(measurement_value_params(row));
+        params.push(Value::Integer(row.id));
+        params.push(Value::Integer(user_id));
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached(&query)?
+                .execute(params_from_iter(params))?;
+            Ok(())
+        })
+            .await
+    }
+
+    async fn get_measurement(&self, user_id: i64, table: &MeasurementTable, measurement_id: i64) -> Result<Option<MeasurementRow>, DatabaseError> {
+        let table = *table;
+        let query = format!(
+            "SELECT {} FROM {} WHERE id = ?1 AND user_id = ?2",
+            measurement_select_columns(&table), table.name,
+        );
+        self.with_connection(move |connection| {
+            let measurement = connection
+                .prepare_cached(&query)?
+                .query_row((measurement_id, user_id), |row| measurement_row_from_row(&table, row))
+                .optional()?;
+            Ok(measurement)
+        })
+            .await
+    }
+
+    async fn get_measurements(&self, user_id: i64, table: &MeasurementTable, range: &TimeRange, page: &Page) -> Result<Vec<MeasurementRow>, DatabaseError> {
+        let table = *table;
+        let query = format!(
+            "SELECT {} FROM {} WHERE (?1 IS NULL OR \"timestamp\" >= ?1) AND (?2 IS NULL OR \"timestamp\" < ?2) AND (?3 IS NULL OR (\"timestamp\", id) > (?3, ?4)) AND user_id = ?6 ORDER BY \"timestamp\", id LIMIT COALESCE(?5, -1)",
+            measurement_select_columns(&table), table.name,
+        );
+        let params = range_page_params(user_id, range, page);
+        self.with_connection(move |connection| {
+            let measurements = connection
+                .prepare_cached(&query)?
+                .query_map(params, |row| measurement_row_from_row(&table, row))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(measurements)
+        })
+            .await
+    }
+
+    async fn add_temperature_location(&self, loc: &BodyTemperatureLocation) -> Result<i64, DatabaseError> {
+        let loc = loc.clone();
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached("INSERT INTO body_temperature_locations (\"name\") VALUES (?1)")?
+                .execute((&loc.name,))?;
+            Ok(connection.last_insert_rowid())
+        })
+            .await
+    }
+
+    async fn remove_temperature_location(&self, loc_id: i64) -> Result<(), DatabaseError> {
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached("DELETE FROM body_temperature_locations WHERE id = ?1")?
+                .execute((loc_id,))?;
+            Ok(())
+        })
+            .await
+    }
+
+    async fn update_temperature_location(&self, loc: &BodyTemperatureLocation) -> Result<(), DatabaseError> {
+        let loc = loc.clone();
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached("UPDATE body_temperature_locations SET \"name\"=?1 WHERE id=?2")?
+                .execute((&loc.name, loc.id))?;
+            Ok(())
+        })
+            .await
+    }
+
+    async fn get_temperature_locations(&self) -> Result<Vec<BodyTemperatureLocation>, DatabaseError> {
+        self.with_connection(|connection| {
+            let locations = connection
+                .prepare_cached("SELECT id, \"name\" FROM body_temperature_locations ORDER BY \"name\"")?
+                .query_map((), temperature_location_from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(locations)
+        })
+            .await
+    }
+
+    async fn add_custom_measurement(&self, user_id: i64, type_name: &str, measurement: &CustomMeasurement) -> Result<i64, DatabaseError> {
+        let type_name = type_name.to_owned();
+        let measurement = measurement.clone();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            transaction
+                .prepare_cached("INSERT INTO custom_measurements (user_id, type_name, \"timestamp\") VALUES (?1, ?2, ?3)")?
+                .execute((user_id, &type_name, timestamp_to_sql(&measurement.timestamp)))?;
+            let measurement_id = transaction.last_insert_rowid();
+            insert_custom_values(&transaction, measurement_id, &measurement.values)?;
+            transaction.commit()?;
+            Ok(measurement_id)
+        })
+            .await
+    }
+
+    async fn remove_custom_measurement(&self, user_id: i64, type_name: &str, measurement_id: i64) -> Result<(), DatabaseError> {
+        let type_name = type_name.to_owned();
+        self.with_connection(move |connection| {
+            // the values are deleted along with the measurement
+            connection
+                .prepare_cached("DELETE FROM custom_measurements WHERE id = ?1 AND user_id = ?2 AND type_name = ?3")?
+                .execute((measurement_id, user_id, &type_name))?;
+            Ok(())
+        })
+            .await
+    }
+
+    async fn update_custom_measurement(&self, user_id: i64, type_name: &str, measurement: &CustomMeasurement) -> Result<(), DatabaseError> {
+        let type_name = type_name.to_owned();
+        let measurement = measurement.clone();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let updated_count = transaction
+                .prepare_cached("UPDATE custom_measurements SET \"timestamp\"=?1 WHERE id=?2 AND user_id=?3 AND type_name=?4")?
+                .execute((timestamp_to_sql(&measurement.timestamp), measurement.id, user_id, &type_name))?;
+            if updated_count > 0 {
+                transaction
+                    .prepare_cached("DELETE FROM custom_measurement_values WHERE measurement_id = ?1")?
+                    .execute((measurement.id,))?;
+                insert_custom_values(&transaction, measurement.id, &measurement.values)?;
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+            .await
+    }
+
+    async fn get_custom_measurement(&self, user_id: i64, type_name: &str, measurement_id: i64) -> Result<Option<CustomMeasurement>, DatabaseError> {
+        let type_name = type_name.to_owned();
+        self.with_connection(move |connection| {
+            let rows = connection
+                .prepare_cached("SELECT m.id, m.\"timestamp\", v.field_key, v.\"value\" FROM custom_measurements m LEFT JOIN custom_measurement_values v ON v.measurement_id = m.id WHERE m.id = ?1 AND m.user_id = ?2 AND m.type_name = ?3")?
+                .query_map((measurement_id, user_id, &type_name), custom_value_from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(CustomMeasurement::from_value_rows(rows).pop())
+        })
+            .await
+    }
+
+    async fn get_custom_measurements(&self, user_id: i64, type_name: &str, range: &TimeRange, page: &Page) -> Result<Vec<CustomMeasurement>, DatabaseError> {
+        let (start, end, after_timestamp, after_id, limit, user_id) = range_page_params(user_id, range, page);
+        let type_name = type_name.to_owned();
+        self.with_connection(move |connection| {
+            let rows = connection
+                .prepare_cached("SELECT m.id, m.\"timestamp\", v.field_key, v.\"value\" FROM (SELECT id, \"timestamp\" FROM custom_measurements WHERE (?1 IS NULL OR \"timestamp\" >= ?1) AND (?2 IS NULL OR \"timestamp\" < ?2) AND (?3 IS NULL OR (\"timestamp\", id) > (?3, ?4)) AND user_id = ?6 AND type_name = ?7 ORDER BY \"timestamp\", id LIMIT COALESCE(?5, -1)) m LEFT JOIN custom_measurement_values v ON v.measurement_id = m.id ORDER BY m.\"timestamp\", m.id")?
+                .query_map((start, end, after_timestamp, after_id, limit, user_id, &type_name), custom_value_from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(CustomMeasurement::from_value_rows(rows))
+        })
+            .await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::measurement::Measurement;
+    use crate::model::{
+        BloodPressureMeasurement, BloodSugarMeasurement, BodyMassMeasurement, BodyTemperatureMeasurement,
+        PageCursor,
+    };
+
+    async fn open_in_memory() -> SqliteStorage {
+        let storage = SqliteStorage::open(Path::new(":memory:"))
+            .unwrap();
+        storage.run_migrations().await
+            .unwrap();
+        storage
+    }
+
+    #[tokio::test]
+    async fn blood_pressure_round_trip() {
+        let storage = open_in_memory().await;
+        let timestamp = Local.with_ymd_and_hms(2024, 3, 1, 8, 30, 0).unwrap();
+        let measurement_id = BloodPressureMeasurement::add(&storage, 1, &BloodPressureMeasurement::new(0, timestamp, 120, 80, 60, Some(98)))
+            .await.unwrap();
+
+        let mut measurement = BloodPressureMeasurement::get(&storage, 1, measurement_id)
+            .await.unwrap().unwrap();
+        assert_eq!(measurement, BloodPressureMeasurement::new(measurement_id, timestamp, 120, 80, 60, Some(98)));
+        let other_user = storage.get_or_add_user("other")
+            .await.unwrap();
+        assert_eq!(BloodPressureMeasurement::get(&storage, other_user.id, measurement_id).await.unwrap(), None);
+
+        measurement.spo2_percent = None;
+        BloodPressureMeasurement::update(&storage, 1, &measurement)
+            .await.unwrap();
+        assert_eq!(BloodPressureMeasurement::get(&storage, 1, measurement_id).await.unwrap(), Some(measurement));
+
+        BloodPressureMeasurement::remove(&storage, 1, measurement_id)
+            .await.unwrap();
+        assert_eq!(BloodPressureMeasurement::get(&storage, 1, measurement_id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn decimals_and_nulls_round_trip() {
+        let storage = open_in_memory().await;
+        let timestamp = Local.with_ymd_and_hms(2024, 3, 1, 8, 30, 0).unwrap();
+        let mass = BodyMassMeasurement::new(0, timestamp, Rational32::new(1613, 20), None, None);
+        let mass_id = BodyMassMeasurement::add(&storage, 1, &mass)
+            .await.unwrap();
+        assert_eq!(BodyMassMeasurement::get(&storage, 1, mass_id).await.unwrap(), Some(BodyMassMeasurement { id: mass_id, ..mass }));
+
+        let temperature = BodyTemperatureMeasurement::new(0, timestamp, 3, Rational32::new(3685, 100));
+        let temperature_id = BodyTemperatureMeasurement::add(&storage, 1, &temperature)
+            .await.unwrap();
+        assert_eq!(BodyTemperatureMeasurement::get(&storage, 1, temperature_id).await.unwrap(), Some(BodyTemperatureMeasurement { id: temperature_id, ..temperature }));
+    }
+
+    #[tokio::test]
+    async fn share_token_round_trip() {
+        let storage = open_in_memory().await;
+        let user = storage.get_or_add_user("sharer")
+            .await.unwrap();
+        let created = Local.with_ymd_and_hms(2024, 3, 1, 8, 30, 0).unwrap();
+        let mut share_token = ShareToken {
+            id: 0,
+            token_sha256: "ab".repeat(32),
+            description: "doctor".to_owned(),
+            scopes: Scopes::from_names_string("bp,long-term-sugar").unwrap(),
+            range: TimeRange::new(Some(created - chrono::Duration::days(30)), None),
+            created,
+            expires: created + chrono::Duration::days(7),
+            revoked: None,
+        };
+        share_token.id = storage.add_share_token(user.id, &share_token)
+            .await.unwrap();
+
+        assert_eq!(storage.get_share_tokens(user.id).await.unwrap(), vec![share_token.clone()]);
+        assert_eq!(storage.get_share_tokens(1).await.unwrap(), Vec::new());
+        assert_eq!(storage.get_share_token_by_hash(&share_token.token_sha256).await.unwrap(), Some((user.clone(), share_token.clone())));
+        assert_eq!(storage.get_share_token_by_hash("cd").await.unwrap(), None);
+
+        storage.revoke_share_token(1, share_token.id, created)
+            .await.unwrap();
+        assert_eq!(storage.get_share_tokens(user.id).await.unwrap()[0].revoked, None);
+        storage.revoke_share_token(user.id, share_token.id, created)
+            .await.unwrap();
+        assert_eq!(storage.get_share_tokens(user.id).await.unwrap()[0].revoked, Some(created));
+    }
+
+    #[tokio::test]
+    async fn range_and_paging() {
+        let storage = open_in_memory().await;
+        let timestamps: Vec<DateTime<Local>> = (1..=4)
+            .map(|day| Local.with_ymd_and_hms(2024, 3, day, 8, 0, 0).unwrap())
+            .collect();
+        let measurements: Vec<BloodSugarMeasurement> = timestamps.iter()
+            .map(|t| BloodSugarMeasurement::new(0, *t, Rational32::new(11, 2)))
+            .collect();
+        let ids = BloodSugarMeasurement::add_all(&storage, 1, &measurements)
+            .await.unwrap();
+        assert_eq!(ids.len(), 4);
+
+        let range = TimeRange::new(Some(timestamps[1]), Some(timestamps[3]));
+        let in_range = BloodSugarMeasurement::get_range(&storage, 1, &range, &Page::all())
+            .await.unwrap();
+        assert_eq!(in_range.iter().map(|m| m.id).collect::<Vec<i64>>(), vec![ids[1], ids[2]]);
+        assert_eq!(in_range[0].sugar_mmol_per_l, Rational32::new(11, 2));
+
+        let first_page = BloodSugarMeasurement::get_range(&storage, 1, &TimeRange::new(None, None), &Page::new(None, Some(3)))
+            .await.unwrap();
+        assert_eq!(first_page.len(), 3);
+        let cursor = PageCursor::new(first_page[2].timestamp, first_page[2].id);
+        let second_page = BloodSugarMeasurement::get_range(&storage, 1, &TimeRange::new(None, None), &Page::new(Some(cursor), Some(3)))
+            .await.unwrap();
+        assert_eq!(second_page.iter().map(|m| m.id).collect::<Vec<i64>>(), vec![ids[3]]);
+    }
+
+    #[tokio::test]
+    async fn custom_measurement_round_trip() {
+        let storage = open_in_memory().await;
+        let timestamp = Local.with_ymd_and_hms(2024, 3, 1, 8, 30, 0).unwrap();
+        let mut values = BTreeMap::new();
+        values.insert("pef".to_owned(), Rational32::from_integer(450));
+        values.insert("variability".to_owned(), Rational32::new(1234, 10_000));
+        let measurement_id = storage.add_custom_measurement(1, "peak-flow", &CustomMeasurement::new(0, timestamp, values.clone()))
+            .await.unwrap();
+
+        let mut measurement = storage.get_custom_measurement(1, "peak-flow", measurement_id)
+            .await.unwrap().unwrap();
+        assert_eq!(measurement, CustomMeasurement::new(measurement_id, timestamp, values));
+        assert_eq!(storage.get_custom_measurement(1, "inr", measurement_id).await.unwrap(), None);
+
+        measurement.values.remove("variability");
+        storage.update_custom_measurement(1, "peak-flow", &measurement)
+            .await.unwrap();
+        let all_measurements = storage.get_custom_measurements(1, "peak-flow", &TimeRange::new(None, None), &Page::all())
+            .await.unwrap();
+        assert_eq!(all_measurements, vec![measurement]);
+
+        storage.remove_custom_measurement(1, "peak-flow", measurement_id)
+            .await.unwrap();
+        assert_eq!(storage.get_custom_measurement(1, "peak-flow", measurement_id).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,271 @@
+use std::cmp::Ordering;
+
+use num_rational::Rational32;
+use num_traits::ToPrimitive;
+use serde::{Serialize, Serializer};
+use serde::ser::SerializeMap;
+
+use crate::measurement::{Measurement, MeasurementField};
+
+
+/// The linearly interpolated percentile (0 to 100) of the values, which must be sorted in ascending
+/// order. This is the method used by most spreadsheets (Excel's `PERCENTILE.INC`, R's type 7).
+pub(crate) fn percentile(sorted_values: &[f64], percent: f64) -> Option<f64> {
+    if sorted_values.is_empty() {
+        return None;
+    }
+    let position = (sorted_values.len() - 1) as f64 * percent.clamp(0.0, 100.0) / 100.0;
+    let lower_index = position.floor() as usize;
+    let upper_index = position.ceil() as usize;
+    let fraction = position - lower_index as f64;
+    let lower = sorted_values[lower_index];
+    let upper = sorted_values[upper_index];
+    Some(lower + (upper - lower) * fraction)
+}
+
+
+/// Statistics about the values of one field.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub(crate) struct FieldStatistics {
+    /// The number of measurements that have a value for this field.
+    pub count: usize,
+    pub minimum: f64,
+    pub percentile_25: f64,
+    pub median: f64,
+    pub percentile_75: f64,
+    pub maximum: f64,
+    pub average: f64,
+    /// The sample standard deviation, or `None` if there are fewer than two values.
+    pub standard_deviation: Option<f64>,
+    /// The standard deviation divided by the average, or `None` if either is unknown or zero.
+    pub coefficient_of_variation: Option<f64>,
+}
+impl FieldStatistics {
+    /// Calculates the statistics of the values, or returns `None` if there are none.
+    pub fn calculate(values: &[Rational32]) -> Option<Self> {
+        let mut sorted_values: Vec<f64> = values.iter()
+            .filter_map(|v| v.to_f64())
+            .collect();
+        if sorted_values.is_empty() {
+            return None;
+        }
+        sorted_values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+        let count = sorted_values.len();
+        let count_f64 = count as f64;
+        let average = sorted_values.iter().sum::<f64>() / count_f64;
+        let standard_deviation = if count > 1 {
+            let squared_deviations: f64 = sorted_values.iter()
+                .map(|v| (v - average) * (v - average))
+                .sum();
+            Some((squared_deviations / (count_f64 - 1.0)).sqrt())
+        } else {
+            None
+        };
+        let coefficient_of_variation = standard_deviation
+            .filter(|_| average != 0.0)
+            .map(|sd| sd / average.abs());
+
+        Some(Self {
+            count,
+            minimum: sorted_values[0],
+            percentile_25: percentile(&sorted_values, 25.0).unwrap(),
+            median: percentile(&sorted_values, 50.0).unwrap(),
+            percentile_75: percentile(&sorted_values, 75.0).unwrap(),
+            maximum: sorted_values[count - 1],
+            average,
+            standard_deviation,
+            coefficient_of_variation,
+        })
+    }
+}
+
+
+/// A row of the statistics table, with the formatted value of each field.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct StatisticsRow {
+    pub class: &'static str,
+    pub title: &'static str,
+    /// The CSS class and the formatted value of each field.
+    pub cells: Vec<(String, String)>,
+}
+
+
+/// Statistics about each field of a list of measurements.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MeasurementStatistics {
+    /// The number of measurements.
+    pub count: usize,
+    /// Each field with its statistics, or `None` if no measurement has a value for it.
+    pub fields: Vec<(MeasurementField, Option<FieldStatistics>)>,
+}
+impl MeasurementStatistics {
+    pub fn calculate<M: Measurement>(measurements: &[M]) -> Self {
+        let all_values: Vec<Vec<Option<Rational32>>> = measurements.iter()
+            .map(|m| m.values())
+            .collect();
+        Self::calculate_values(M::FIELDS, &all_values)
+    }
+
+    /// Calculates the statistics from the values of each measurement, given in the order of
+    /// `fields`.
+    pub fn calculate_values(fields: &[MeasurementField], all_values: &[Vec<Option<Rational32>>]) -> Self {
+        let fields = fields.iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let values: Vec<Rational32> = all_values.iter()
+                    .filter_map(|vs| vs.get(i).copied().flatten())
+                    .collect();
+                (field.clone(), FieldStatistics::calculate(&values))
+            })
+            .collect();
+        Self {
+            count: all_values.len(),
+            fields,
+        }
+    }
+
+    fn row<F: Fn(&MeasurementField, &FieldStatistics) -> Option<String>>(&self, class: &'static str, title: &'static str, format_value: F) -> StatisticsRow {
+        let cells = self.fields.iter()
+            .map(|(field, stats)| {
+                let value = stats.as_ref()
+                    .and_then(|s| format_value(field, s))
+                    .unwrap_or_default();
+                (field.class.to_string(), value)
+            })
+            .collect();
+        StatisticsRow {
+            class,
+            title,
+            cells,
+        }
+    }
+
+    /// The rows of the statistics table, from the largest to the smallest value and followed by
+    /// the spread of the values.
+    pub fn rows(&self) -> Vec<StatisticsRow> {
+        let decimal = |value: f64, decimal_places: usize| Some(format!("{:.*}", decimal_places, value));
+        vec![
+            self.row("count", "number of values", |_, s| Some(s.count.to_string())),
+            self.row("maximum", "maximum", |f, s| decimal(s.maximum, f.decimal_places)),
+            self.row("quartile-3", "3rd quartile", |f, s| decimal(s.percentile_75, f.decimal_places)),
+            self.row("average", "average", |f, s| decimal(s.average, f.decimal_places)),
+            self.row("median", "median", |f, s| decimal(s.median, f.decimal_places)),
+            self.row("quartile-1", "1st quartile", |f, s| decimal(s.percentile_25, f.decimal_places)),
+            self.row("minimum", "minimum", |f, s| decimal(s.minimum, f.decimal_places)),
+            self.row("standard-deviation", "standard deviation", |f, s| s.standard_deviation.and_then(|sd| decimal(sd, f.decimal_places + 1))),
+            self.row("coefficient-of-variation", "coefficient of variation", |_, s| s.coefficient_of_variation.map(|cv| format!("{:.1}%", cv * 100.0))),
+        ]
+    }
+}
+impl Serialize for MeasurementStatistics {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct Fields<'a>(&'a [(MeasurementField, Option<FieldStatistics>)]);
+        impl<'a> Serialize for Fields<'a> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut map = serializer.serialize_map(Some(self.0.len()))?;
+                for (field, stats) in self.0 {
+                    map.serialize_entry(&field.key, stats)?;
+                }
+                map.end()
+            }
+        }
+
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("count", &self.count)?;
+        map.serialize_entry("fields", &Fields(&self.fields))?;
+        map.end()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{Local, TimeZone};
+
+    use crate::model::{BloodPressureMeasurement, BodyTemperatureMeasurement};
+
+    fn bp(systolic_mmhg: i32, spo2_percent: Option<i32>) -> BloodPressureMeasurement {
+        let timestamp = Local.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap();
+        BloodPressureMeasurement::new(-1, timestamp, systolic_mmhg, 80, 60, spo2_percent)
+    }
+
+    #[test]
+    fn percentiles_interpolated() {
+        let values = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(percentile(&values, 0.0), Some(1.0));
+        assert_eq!(percentile(&values, 25.0), Some(1.75));
+        assert_eq!(percentile(&values, 50.0), Some(2.5));
+        assert_eq!(percentile(&values, 75.0), Some(3.25));
+        assert_eq!(percentile(&values, 100.0), Some(4.0));
+        assert_eq!(percentile(&[7.0], 30.0), Some(7.0));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn field_statistics() {
+        let values: Vec<Rational32> = [2, 4, 4, 4, 5, 5, 7, 9].iter()
+            .map(|v| Rational32::from_integer(*v))
+            .collect();
+        let stats = FieldStatistics::calculate(&values).unwrap();
+        assert_eq!(stats.count, 8);
+        assert_eq!(stats.minimum, 2.0);
+        assert_eq!(stats.maximum, 9.0);
+        assert_eq!(stats.average, 5.0);
+        assert_eq!(stats.median, 4.5);
+        assert_eq!(stats.percentile_25, 4.0);
+        assert_eq!(stats.percentile_75, 5.5);
+        let sd = stats.standard_deviation.unwrap();
+        assert!((sd - (32.0f64 / 7.0).sqrt()).abs() < 1e-12);
+        assert!((stats.coefficient_of_variation.unwrap() - sd / 5.0).abs() < 1e-12);
+
+        let single = FieldStatistics::calculate(&[Rational32::new(73, 10)]).unwrap();
+        assert_eq!(single.median, 7.3);
+        assert_eq!(single.standard_deviation, None);
+        assert_eq!(single.coefficient_of_variation, None);
+
+        assert_eq!(FieldStatistics::calculate(&[]), None);
+    }
+
+    #[test]
+    fn measurement_statistics() {
+        let measurements = vec![bp(120, None), bp(130, Some(97)), bp(141, None)];
+        let stats = MeasurementStatistics::calculate(&measurements);
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.fields[0].0.key, "systolic_mmhg");
+        assert!((stats.fields[0].1.unwrap().average - 391.0 / 3.0).abs() < 1e-12);
+        assert_eq!(stats.fields[3].1.unwrap().count, 1);
+
+        let rows = stats.rows();
+        let median_row = rows.iter().find(|r| r.class == "median").unwrap();
+        assert_eq!(median_row.cells[0], (String::from("systolic"), String::from("130")));
+        assert_eq!(median_row.cells[3], (String::from("spo2"), String::from("97")));
+
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["count"], 3);
+        assert_eq!(json["fields"]["systolic_mmhg"]["maximum"], 141.0);
+        assert_eq!(json["fields"]["spo2_percent"]["standard_deviation"], serde_json::Value::Null);
+
+        let no_spo2 = MeasurementStatistics::calculate(&[bp(120, None)]);
+        assert_eq!(no_spo2.fields[3].1, None);
+        assert_eq!(no_spo2.rows()[0].cells[3], (String::from("spo2"), String::new()));
+    }
+
+    #[test]
+    fn temperature_minimum() {
+        // the minimum used to be taken as the maximum of the temperatures
+        let timestamp = Local.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap();
+        let measurements: Vec<BodyTemperatureMeasurement> = [3720, 3650, 3810].iter()
+            .map(|t| BodyTemperatureMeasurement::new(-1, timestamp, 1, Rational32::new(*t, 100)))
+            .collect();
+        let stats = MeasurementStatistics::calculate(&measurements);
+        let temperature = stats.fields[0].1.unwrap();
+        assert_eq!(temperature.minimum, 36.5);
+        assert_eq!(temperature.maximum, 38.1);
+
+        let minimum_row = stats.rows().into_iter().find(|r| r.class == "minimum").unwrap();
+        assert_eq!(minimum_row.cells[0], (String::from("temperature"), String::from("36.50")));
+    }
+}
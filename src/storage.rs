@@ -0,0 +1,193 @@
+use std::convert::TryFrom;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use num_rational::Rational32;
+use once_cell::sync::OnceCell;
+
+use crate::config::{Config, StorageBackend};
+use crate::database::{DatabaseError, PostgresStorage};
+use crate::memory::MemoryStorage;
+use crate::migrations::MigrationError;
+use crate::model::{
+    BodyHeight, BodyTemperatureLocation, CustomMeasurement, Page, Profile, Session, ShareToken,
+    TimeRange, User,
+};
+use crate::sqlite::SqliteStorage;
+
+
+static STORAGE: OnceCell<Box<dyn Storage>> = OnceCell::new();
+
+
+/// The type of a value column of a measurement table.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum ColumnType {
+    Integer,
+    /// A decimal number, stored with two decimal places by the database backends.
+    Decimal,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct MeasurementColumn {
+    pub name: &'static str,
+    pub column_type: ColumnType,
+}
+impl MeasurementColumn {
+    pub const fn integer(name: &'static str) -> Self {
+        Self { name, column_type: ColumnType::Integer }
+    }
+
+    pub const fn decimal(name: &'static str) -> Self {
+        Self { name, column_type: ColumnType::Decimal }
+    }
+}
+
+/// The table storing the measurements of a built-in type. Besides the value columns, each table has
+/// the columns `id`, `user_id` and `timestamp`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct MeasurementTable {
+    pub name: &'static str,
+    pub columns: &'static [MeasurementColumn],
+}
+
+/// A value in a value column of a measurement table.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum ColumnValue {
+    Integer(i64),
+    Decimal(Rational32),
+}
+
+/// A row of a measurement table, with the values in the order of the table's columns and `None` for
+/// NULL.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct MeasurementRow {
+    pub id: i64,
+    pub timestamp: DateTime<Local>,
+    pub values: Vec<Option<ColumnValue>>,
+}
+impl MeasurementRow {
+    pub fn opt_integer(&self, index: usize) -> Result<Option<i64>, DatabaseError> {
+        match self.values.get(index) {
+            Some(Some(ColumnValue::Integer(i))) => Ok(Some(*i)),
+            Some(None) => Ok(None),
+            _ => Err(DatabaseError::InvalidColumnValue(index)),
+        }
+    }
+
+    pub fn integer(&self, index: usize) -> Result<i64, DatabaseError> {
+        self.opt_integer(index)?
+            .ok_or(DatabaseError::InvalidColumnValue(index))
+    }
+
+    pub fn opt_i32(&self, index: usize) -> Result<Option<i32>, DatabaseError> {
+        self.opt_integer(index)?
+            .map(|i| i32::try_from(i).map_err(|_| DatabaseError::InvalidColumnValue(index)))
+            .transpose()
+    }
+
+    pub fn i32(&self, index: usize) -> Result<i32, DatabaseError> {
+        self.opt_i32(index)?
+            .ok_or(DatabaseError::InvalidColumnValue(index))
+    }
+
+    pub fn opt_decimal(&self, index: usize) -> Result<Option<Rational32>, DatabaseError> {
+        match self.values.get(index) {
+            Some(Some(ColumnValue::Decimal(d))) => Ok(Some(*d)),
+            Some(None) => Ok(None),
+            _ => Err(DatabaseError::InvalidColumnValue(index)),
+        }
+    }
+
+    pub fn decimal(&self, index: usize) -> Result<Rational32, DatabaseError> {
+        self.opt_decimal(index)?
+            .ok_or(DatabaseError::InvalidColumnValue(index))
+    }
+}
+
+
+/// Persistence of users, their measurements and the temperature locations shared by all users. The
+/// measurements of the built-in types are stored in the table described by their type; see
+/// [`crate::measurement::Measurement`].
+#[async_trait]
+pub(crate) trait Storage: Send + Sync {
+    /// Applies all pending schema migrations, returning the versions that have been applied.
+    async fn run_migrations(&self) -> Result<Vec<i32>, MigrationError>;
+
+    /// Ensures that the schema is at exactly the version expected by this program.
+    async fn check_schema_version(&self) -> Result<(), MigrationError>;
+
+    /// Returns the user with the given name, adding it if it does not exist yet.
+    async fn get_or_add_user(&self, name: &str) -> Result<User, DatabaseError>;
+
+    /// The user's profile, including all recorded heights.
+    async fn get_profile(&self, user_id: i64) -> Result<Profile, DatabaseError>;
+    /// Updates the user's birth date and sex; the heights are left unchanged.
+    async fn update_profile(&self, user_id: i64, profile: &Profile) -> Result<(), DatabaseError>;
+    /// Records a height from its effective date, replacing the height recorded for the same date.
+    async fn set_height(&self, user_id: i64, height: &BodyHeight) -> Result<i64, DatabaseError>;
+    async fn remove_height(&self, user_id: i64, height_id: i64) -> Result<(), DatabaseError>;
+
+    async fn add_share_token(&self, user_id: i64, share_token: &ShareToken) -> Result<i64, DatabaseError>;
+    /// The user's share tokens, including expired and revoked ones, newest first.
+    async fn get_share_tokens(&self, user_id: i64) -> Result<Vec<ShareToken>, DatabaseError>;
+    /// The share token with the given hash and the user who created it, even if it has expired or
+    /// been revoked.
+    async fn get_share_token_by_hash(&self, token_sha256: &str) -> Result<Option<(User, ShareToken)>, DatabaseError>;
+    async fn revoke_share_token(&self, user_id: i64, share_token_id: i64, revoked: DateTime<Local>) -> Result<(), DatabaseError>;
+
+    async fn add_session(&self, user_id: i64, session: &Session) -> Result<i64, DatabaseError>;
+    /// The session with the given hash and the user who started it, even if it has expired.
+    async fn get_session_by_hash(&self, session_sha256: &str) -> Result<Option<(User, Session)>, DatabaseError>;
+    async fn remove_session(&self, session_sha256: &str) -> Result<(), DatabaseError>;
+    /// Removes all sessions that have expired by the given time.
+    async fn remove_expired_sessions(&self, now: DateTime<Local>) -> Result<(), DatabaseError>;
+
+    /// Adds all the given measurements to the table in a single transaction, returning their IDs. The
+    /// IDs in the rows are ignored.
+    async fn add_measurements(&self, user_id: i64, table: &MeasurementTable, rows: &[MeasurementRow]) -> Result<Vec<i64>, DatabaseError>;
+    async fn remove_measurement(&self, user_id: i64, table: &MeasurementTable, measurement_id: i64) -> Result<(), DatabaseError>;
+    async fn update_measurement(&self, user_id: i64, table: &MeasurementTable, row: &MeasurementRow) -> Result<(), DatabaseError>;
+    async fn get_measurement(&self, user_id: i64, table: &MeasurementTable, measurement_id: i64) -> Result<Option<MeasurementRow>, DatabaseError>;
+    /// The measurements within the range, ordered by timestamp and ID.
+    async fn get_measurements(&self, user_id: i64, table: &MeasurementTable, range: &TimeRange, page: &Page) -> Result<Vec<MeasurementRow>, DatabaseError>;
+
+    async fn add_temperature_location(&self, loc: &BodyTemperatureLocation) -> Result<i64, DatabaseError>;
+    async fn remove_temperature_location(&self, loc_id: i64) -> Result<(), DatabaseError>;
+    async fn update_temperature_location(&self, loc: &BodyTemperatureLocation) -> Result<(), DatabaseError>;
+    async fn get_temperature_locations(&self) -> Result<Vec<BodyTemperatureLocation>, DatabaseError>;
+
+    /// Adds a measurement of the custom type with the given name along with its values.
+    async fn add_custom_measurement(&self, user_id: i64, type_name: &str, measurement: &CustomMeasurement) -> Result<i64, DatabaseError>;
+    async fn remove_custom_measurement(&self, user_id: i64, type_name: &str, measurement_id: i64) -> Result<(), DatabaseError>;
+    /// Updates the timestamp of the measurement and replaces its values.
+    async fn update_custom_measurement(&self, user_id: i64, type_name: &str, measurement: &CustomMeasurement) -> Result<(), DatabaseError>;
+    async fn get_custom_measurement(&self, user_id: i64, type_name: &str, measurement_id: i64) -> Result<Option<CustomMeasurement>, DatabaseError>;
+    async fn get_custom_measurements(&self, user_id: i64, type_name: &str, range: &TimeRange, page: &Page) -> Result<Vec<CustomMeasurement>, DatabaseError>;
+}
+
+
+/// Sets up the storage backend selected in the configuration.
+pub(crate) fn init_storage(config: &Config) -> Result<(), DatabaseError> {
+    let storage: Box<dyn Storage> = match config.storage {
+        StorageBackend::Postgres => Box::new(PostgresStorage::new(config)?),
+        StorageBackend::Sqlite => {
+            let path = config.sqlite_path.as_ref()
+                .ok_or(DatabaseError::MissingConfigValue("sqlite_path"))?;
+            Box::new(SqliteStorage::open(path)?)
+        },
+        StorageBackend::Memory => Box::new(MemoryStorage::new()),
+    };
+
+    if STORAGE.set(storage).is_err() {
+        panic!("storage already initialized");
+    }
+    Ok(())
+}
+
+/// The storage backend selected in the configuration.
+pub(crate) fn storage() -> &'static dyn Storage {
+    STORAGE
+        .get().expect("storage not initialized")
+        .as_ref()
+}
+
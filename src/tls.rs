@@ -0,0 +1,406 @@
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::pki_types::pem::PemObject;
+use tokio_postgres::config::SslMode;
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use crate::config::{DbSslMode, DbTlsConfig};
+
+
+#[derive(Debug)]
+pub(crate) enum TlsSetupError {
+    ReadingCaFile(PathBuf, rustls::pki_types::pem::Error),
+    AddingCaCertificate(PathBuf, rustls::Error),
+    LoadingSystemCertificates(Vec<rustls_native_certs::Error>),
+    ReadingClientCertFile(PathBuf, rustls::pki_types::pem::Error),
+    ReadingClientKeyFile(PathBuf, rustls::pki_types::pem::Error),
+    IncompleteClientIdentity,
+    CreatingVerifier(rustls::client::VerifierBuilderError),
+    CreatingConfig(rustls::Error),
+}
+impl fmt::Display for TlsSetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsSetupError::ReadingCaFile(path, e)
+                => write!(f, "error reading CA file {}: {}", path.display(), e),
+            TlsSetupError::AddingCaCertificate(path, e)
+                => write!(f, "error adding CA certificate from {}: {}", path.display(), e),
+            TlsSetupError::LoadingSystemCertificates(errors) => {
+                write!(f, "error loading system certificates:")?;
+                for e in errors {
+                    write!(f, " {}", e)?;
+                }
+                Ok(())
+            },
+            TlsSetupError::ReadingClientCertFile(path, e)
+                => write!(f, "error reading client certificate file {}: {}", path.display(), e),
+            TlsSetupError::ReadingClientKeyFile(path, e)
+                => write!(f, "error reading client key file {}: {}", path.display(), e),
+            TlsSetupError::IncompleteClientIdentity
+                => write!(f, "client certificate and client key must be configured together"),
+            TlsSetupError::CreatingVerifier(e)
+                => write!(f, "error creating certificate verifier: {}", e),
+            TlsSetupError::CreatingConfig(e)
+                => write!(f, "error creating TLS configuration: {}", e),
+        }
+    }
+}
+impl Error for TlsSetupError {
+}
+
+
+/// Accepts any server certificate. Only the handshake signatures are verified.
+#[derive(Debug)]
+struct NoCertificateVerification {
+    provider: Arc<CryptoProvider>,
+}
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+
+/// Verifies that the server certificate has been issued by a trusted CA, but not that it has been
+/// issued for the host name being connected to (`sslmode=verify-ca`).
+#[derive(Debug)]
+struct CaOnlyVerification {
+    inner: Arc<WebPkiServerVerifier>,
+}
+impl ServerCertVerifier for CaOnlyVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let result = self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now);
+        match result {
+            Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName))
+                | Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForNameContext { .. }))
+                => Ok(ServerCertVerified::assertion()),
+            other => other,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+
+/// Accepts a server certificate that is identical to one of the pinned certificates and verifies
+/// the others using the inner verifier.
+///
+/// This allows the CA file to contain the server's own certificate, as is common for self-signed
+/// certificates (and accepted by libpq as `root.crt`), which webpki otherwise refuses as a CA
+/// certificate used as an end-entity certificate. As the certificate is trusted individually, its
+/// host name is not checked.
+#[derive(Debug)]
+struct PinnedCertificateVerification {
+    pinned: Vec<CertificateDer<'static>>,
+    inner: Arc<dyn ServerCertVerifier>,
+}
+impl ServerCertVerifier for PinnedCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if self.pinned.iter().any(|p| p.as_ref() == end_entity.as_ref()) {
+            return Ok(ServerCertVerified::assertion());
+        }
+        self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+
+fn read_ca_file(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsSetupError> {
+    CertificateDer::pem_file_iter(path)
+        .map_err(|e| TlsSetupError::ReadingCaFile(path.to_owned(), e))?
+        .collect::<Result<_, _>>()
+        .map_err(|e| TlsSetupError::ReadingCaFile(path.to_owned(), e))
+}
+
+fn load_root_certs(ca_file: Option<&Path>) -> Result<RootCertStore, TlsSetupError> {
+    let mut root_store = RootCertStore::empty();
+    match ca_file {
+        Some(path) => {
+            for cert in read_ca_file(path)? {
+                root_store.add(cert)
+                    .map_err(|e| TlsSetupError::AddingCaCertificate(path.to_owned(), e))?;
+            }
+        },
+        None => {
+            let native_certs = rustls_native_certs::load_native_certs();
+            if native_certs.certs.is_empty() && !native_certs.errors.is_empty() {
+                return Err(TlsSetupError::LoadingSystemCertificates(native_certs.errors));
+            }
+            // certificates that the TLS library cannot parse are skipped
+            root_store.add_parsable_certificates(native_certs.certs);
+        },
+    }
+    Ok(root_store)
+}
+
+/// The value of `sslmode` to pass to the PostgreSQL client for the given mode.
+fn postgres_ssl_mode(mode: DbSslMode) -> SslMode {
+    match mode {
+        DbSslMode::Disable => SslMode::Disable,
+        DbSslMode::Prefer => SslMode::Prefer,
+        DbSslMode::Require|DbSslMode::VerifyCa|DbSslMode::VerifyFull => SslMode::Require,
+    }
+}
+
+/// Applies the configured mode to the PostgreSQL configuration and returns it or, if no mode is
+/// configured, returns the mode of the PostgreSQL configuration (i.e. its connection string).
+pub(crate) fn apply_ssl_mode(config: &DbTlsConfig, pg_config: &mut tokio_postgres::Config) -> DbSslMode {
+    match config.ssl_mode {
+        Some(mode) => {
+            pg_config.ssl_mode(postgres_ssl_mode(mode));
+            mode
+        },
+        None => match pg_config.get_ssl_mode() {
+            SslMode::Disable => DbSslMode::Disable,
+            SslMode::Prefer => DbSslMode::Prefer,
+            _ => DbSslMode::Require,
+        },
+    }
+}
+
+/// Creates the TLS connector for PostgreSQL connections using the given mode.
+pub(crate) fn make_tls_connect(config: &DbTlsConfig, ssl_mode: DbSslMode) -> Result<MakeRustlsConnect, TlsSetupError> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let verifier: Arc<dyn ServerCertVerifier> = match ssl_mode {
+        DbSslMode::Disable|DbSslMode::Prefer|DbSslMode::Require => {
+            Arc::new(NoCertificateVerification {
+                provider: Arc::clone(&provider),
+            })
+        },
+        DbSslMode::VerifyCa|DbSslMode::VerifyFull => {
+            let root_store = load_root_certs(config.ca_file.as_deref())?;
+            let webpki_verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(root_store), Arc::clone(&provider))
+                .build()
+                .map_err(TlsSetupError::CreatingVerifier)?;
+            let ca_verifier: Arc<dyn ServerCertVerifier> = if ssl_mode == DbSslMode::VerifyCa {
+                Arc::new(CaOnlyVerification {
+                    inner: webpki_verifier,
+                })
+            } else {
+                webpki_verifier
+            };
+            match &config.ca_file {
+                Some(path) => Arc::new(PinnedCertificateVerification {
+                    pinned: read_ca_file(path)?,
+                    inner: ca_verifier,
+                }),
+                None => ca_verifier,
+            }
+        },
+    };
+
+    let config_builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(TlsSetupError::CreatingConfig)?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
+
+    let client_config = match (&config.client_cert_file, &config.client_key_file) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert_chain: Vec<CertificateDer<'static>> = CertificateDer::pem_file_iter(cert_path)
+                .map_err(|e| TlsSetupError::ReadingClientCertFile(cert_path.clone(), e))?
+                .collect::<Result<_, _>>()
+                .map_err(|e| TlsSetupError::ReadingClientCertFile(cert_path.clone(), e))?;
+            let key = PrivateKeyDer::from_pem_file(key_path)
+                .map_err(|e| TlsSetupError::ReadingClientKeyFile(key_path.clone(), e))?;
+            config_builder.with_client_auth_cert(cert_chain, key)
+                .map_err(TlsSetupError::CreatingConfig)?
+        },
+        (None, None) => config_builder.with_no_client_auth(),
+        _ => return Err(TlsSetupError::IncompleteClientIdentity),
+    };
+
+    Ok(MakeRustlsConnect::new(client_config))
+}
+
+/// Creates the TLS configuration for HTTPS requests, which verifies servers against the system
+/// certificates.
+pub(crate) fn make_https_client_config() -> Result<ClientConfig, TlsSetupError> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let root_store = load_root_certs(None)?;
+    let client_config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(TlsSetupError::CreatingConfig)?
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+    Ok(client_config)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // These tests need a PostgreSQL server with a self-signed certificate, which
+    // contrib/tls-test-postgres.sh sets up, e.g.:
+    //   BEEPEE_TLS_TEST_DB="host=localhost port=54329 user=postgres dbname=postgres"
+    //   BEEPEE_TLS_TEST_CA=/path/to/data/server.crt
+    // and are run with `cargo test tls:: -- --ignored`.
+
+    async fn ssl_in_use(ssl_mode: DbSslMode, ca_file: Option<PathBuf>) -> Result<bool, tokio_postgres::Error> {
+        let conn_string = std::env::var("BEEPEE_TLS_TEST_DB")
+            .expect("BEEPEE_TLS_TEST_DB not set");
+        let tls_config = DbTlsConfig {
+            ssl_mode: Some(ssl_mode),
+            ca_file,
+            client_cert_file: None,
+            client_key_file: None,
+        };
+
+        let mut pg_config: tokio_postgres::Config = conn_string.parse()
+            .expect("invalid BEEPEE_TLS_TEST_DB");
+        let ssl_mode = apply_ssl_mode(&tls_config, &mut pg_config);
+        let tls_connect = make_tls_connect(&tls_config, ssl_mode)
+            .expect("failed to set up TLS");
+        let (client, connection) = pg_config.connect(tls_connect)
+            .await?;
+        tokio::spawn(connection);
+
+        let row = client
+            .query_one("SELECT ssl FROM pg_stat_ssl WHERE pid = pg_backend_pid()", &[])
+            .await?;
+        Ok(row.get(0))
+    }
+
+    #[test]
+    fn ssl_mode_from_conn_string() {
+        let unset = DbTlsConfig::default();
+        let mut pg_config: tokio_postgres::Config = "host=localhost sslmode=require".parse().unwrap();
+        assert_eq!(apply_ssl_mode(&unset, &mut pg_config), DbSslMode::Require);
+        assert_eq!(pg_config.get_ssl_mode(), SslMode::Require);
+
+        let mut pg_config: tokio_postgres::Config = "host=localhost".parse().unwrap();
+        assert_eq!(apply_ssl_mode(&unset, &mut pg_config), DbSslMode::Prefer);
+
+        let verify_full = DbTlsConfig {
+            ssl_mode: Some(DbSslMode::VerifyFull),
+            ..DbTlsConfig::default()
+        };
+        let mut pg_config: tokio_postgres::Config = "host=localhost sslmode=disable".parse().unwrap();
+        assert_eq!(apply_ssl_mode(&verify_full, &mut pg_config), DbSslMode::VerifyFull);
+        assert_eq!(pg_config.get_ssl_mode(), SslMode::Require);
+    }
+
+    fn test_ca_file() -> PathBuf {
+        std::env::var("BEEPEE_TLS_TEST_CA")
+            .expect("BEEPEE_TLS_TEST_CA not set")
+            .into()
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn self_signed_unverified() {
+        assert!(ssl_in_use(DbSslMode::Prefer, None).await.unwrap());
+        assert!(ssl_in_use(DbSslMode::Require, None).await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn self_signed_verified() {
+        assert!(ssl_in_use(DbSslMode::VerifyCa, Some(test_ca_file())).await.unwrap());
+        assert!(ssl_in_use(DbSslMode::VerifyFull, Some(test_ca_file())).await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn self_signed_untrusted() {
+        // the system does not trust the self-signed certificate
+        assert!(ssl_in_use(DbSslMode::VerifyCa, None).await.is_err());
+        assert!(ssl_in_use(DbSslMode::VerifyFull, None).await.is_err());
+    }
+}
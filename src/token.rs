@@ -0,0 +1,188 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use once_cell::sync::Lazy;
+use ring::{digest, hmac};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+
+const ALGORITHM_NAME: &str = "hmac-sha256";
+const SALT_LENGTH: usize = 16;
+const GENERATED_TOKEN_LENGTH: usize = 32;
+
+/// Key for comparing plaintext tokens in constant time; it only has to stay the same while the
+/// program is running.
+static COMPARISON_KEY: Lazy<hmac::Key> = Lazy::new(|| {
+    hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+        .expect("failed to generate token comparison key")
+});
+
+
+#[derive(Debug)]
+pub(crate) enum ParseTokenHashError {
+    WrongPartCount(usize),
+    UnknownAlgorithm(String),
+    InvalidHex(String),
+}
+impl fmt::Display for ParseTokenHashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongPartCount(count)
+                => write!(f, "expected 3 parts separated by \"$\", found {}", count),
+            Self::UnknownAlgorithm(name)
+                => write!(f, "unknown hash algorithm {:?}; expected {:?}", name, ALGORITHM_NAME),
+            Self::InvalidHex(value)
+                => write!(f, "{:?} is not a hexadecimal string", value),
+        }
+    }
+}
+impl Error for ParseTokenHashError {
+}
+
+
+/// A salted hash of a token, written as `hmac-sha256$SALT$HASH` with the salt and the hash encoded
+/// in hexadecimal. The hash is HMAC-SHA256 of the token keyed with the salt; tokens are long random
+/// strings, so a slow password hash is not necessary.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct TokenHash {
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+impl TokenHash {
+    pub fn new(token: &str, salt: Vec<u8>) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &salt);
+        let hash = hmac::sign(&key, token.as_bytes())
+            .as_ref()
+            .to_vec();
+        Self {
+            salt,
+            hash,
+        }
+    }
+
+    /// Whether this is the hash of the given token. The comparison takes constant time.
+    pub fn verify(&self, token: &str) -> bool {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &self.salt);
+        hmac::verify(&key, token.as_bytes(), &self.hash).is_ok()
+    }
+}
+impl fmt::Display for TokenHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}${}${}", ALGORITHM_NAME, to_hex(&self.salt), to_hex(&self.hash))
+    }
+}
+impl FromStr for TokenHash {
+    type Err = ParseTokenHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('$').collect();
+        if parts.len() != 3 {
+            return Err(ParseTokenHashError::WrongPartCount(parts.len()));
+        }
+        if parts[0] != ALGORITHM_NAME {
+            return Err(ParseTokenHashError::UnknownAlgorithm(parts[0].to_owned()));
+        }
+        Ok(Self {
+            salt: from_hex(parts[1])?,
+            hash: from_hex(parts[2])?,
+        })
+    }
+}
+impl TryFrom<String> for TokenHash {
+    type Error = ParseTokenHashError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+impl From<TokenHash> for String {
+    fn from(value: TokenHash) -> Self {
+        value.to_string()
+    }
+}
+
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, ParseTokenHashError> {
+    let invalid = || ParseTokenHashError::InvalidHex(hex.to_owned());
+    // from_str_radix would also accept a sign
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => u8::from_str_radix(&format!("{}{}", *high as char, *low as char), 16)
+                .map_err(|_| invalid()),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+
+/// Compares two plaintext tokens in constant time.
+pub(crate) fn tokens_equal(expected: &str, actual: &str) -> bool {
+    let expected_tag = hmac::sign(&COMPARISON_KEY, expected.as_bytes());
+    hmac::verify(&COMPARISON_KEY, actual.as_bytes(), expected_tag.as_ref()).is_ok()
+}
+
+/// The unsalted SHA-256 hash of a token in hexadecimal, used to look up share tokens in storage.
+pub(crate) fn sha256_hex(token: &str) -> String {
+    to_hex(digest::digest(&digest::SHA256, token.as_bytes()).as_ref())
+}
+
+/// Generates a new random token.
+pub(crate) fn generate_token_value() -> Result<String, ring::error::Unspecified> {
+    let mut token_bytes = [0u8; GENERATED_TOKEN_LENGTH];
+    SystemRandom::new().fill(&mut token_bytes)?;
+    Ok(to_hex(&token_bytes))
+}
+
+/// Generates a new random token and its hash.
+pub(crate) fn generate_token() -> Result<(String, TokenHash), ring::error::Unspecified> {
+    let token = generate_token_value()?;
+    let mut salt = vec![0u8; SALT_LENGTH];
+    SystemRandom::new().fill(&mut salt)?;
+
+    let hash = TokenHash::new(&token, salt);
+    Ok((token, hash))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_token_verifies() {
+        let (token, hash) = generate_token().unwrap();
+        let parsed: TokenHash = hash.to_string().parse().unwrap();
+        assert!(parsed.verify(&token));
+        assert!(!parsed.verify("wrong"));
+        assert!(!parsed.verify(&token[1..]));
+    }
+
+    #[test]
+    fn invalid_hashes_rejected() {
+        assert!("sha256$00$00".parse::<TokenHash>().is_err());
+        assert!("hmac-sha256$0g$00".parse::<TokenHash>().is_err());
+        assert!("hmac-sha256$000$00".parse::<TokenHash>().is_err());
+        assert!("hmac-sha256$00".parse::<TokenHash>().is_err());
+    }
+
+    #[test]
+    fn plaintext_comparison() {
+        assert!(tokens_equal("secret", "secret"));
+        assert!(!tokens_equal("secret", "secreT"));
+        assert!(!tokens_equal("secret", "secret2"));
+    }
+}